@@ -1,41 +1,10 @@
-use std::{path::PathBuf, io, collections::{BTreeMap, BTreeSet}, time::Instant};
-use git_reader::{ioerr, object_id::{hex_u128_to_str, PartialOid, hash_str_to_oid, Oid}};
-use git_reader::{printoid, object_database::{LightObjectDB, FoundObjectLocation, loose::{commit_object_parsing::CommitFull, ParsedObject, UnparsedObject, ParseEverythingBlobStringsLossy}, state::{State, MinState}}, eprintoid, ioerre};
+use std::io;
+use git_reader::{ioerr, object_id::hash_str_to_oid};
+use git_reader::object_database::{LightObjectDB, DEFAULT_MIN_ABBREV, loose::{ParsedObject, ParseEverythingBlobStringsLossy}, state::MinState};
 
 /// Like git-cat-file, but it defaults to "-p", ie: it just
 /// prints the contents of the object found via its OID.
 
-pub fn disambiguate<S: State>(
-    ambiguous_oid: &String,
-    odb: &LightObjectDB,
-    state: &mut S,
-) -> io::Result<(Oid, FoundObjectLocation)> {
-    let partial_oid =  PartialOid::from_hash(ambiguous_oid)?;
-    let mut found_set = BTreeMap::new();
-    odb.find_matching_oids_with_locations(partial_oid, state, |oid, location| {
-        found_set.insert(oid, location);
-    })?;
-
-    let found_len = found_set.len();
-    if found_len == 1 {
-        let (oid, _) = found_set.iter().next().unwrap();
-        let oid = *oid;
-        let location = found_set.remove(&oid).unwrap();
-        return Ok((oid, location))
-    }
-
-    if found_len == 0 {
-        return ioerre!("Failed to find object matching {}", ambiguous_oid);
-    } else {
-        let mut err_str = format!("Error: '{}' is too ambiguous", ambiguous_oid);
-        err_str = format!("{}\nhint: The candidates are:", err_str);
-        for (found_oid, _) in found_set.iter() {
-            err_str = format!("{}\n{:032x}", err_str, found_oid);
-        }
-        return ioerre!("{}", err_str);
-    }
-}
-
 pub fn realmain() -> io::Result<()> {
     let args = std::env::args().collect::<Vec<_>>();
     let path = args.get(1)
@@ -50,7 +19,7 @@ pub fn realmain() -> io::Result<()> {
         // if its not a full oid, we need
         // to disambiguate, so traverse everything,
         // and find all matches:
-        let (oid, location) = disambiguate(ambiguous_oid, &odb, &mut state)?;
+        let (oid, location) = odb.resolve_short(ambiguous_oid, DEFAULT_MIN_ABBREV, &mut state)?;
         (oid, Some(location))
     } else {
         // if its already 32 hex chars or longer,
@@ -1,8 +1,8 @@
 use std::io;
 
-use git_reader::{ioerr, object_id::{PartialOid, Oid, OidFull, oid_parts_to_full, get_first_byte_of_oid}};
+use git_reader::{ioerr, object_id::{PartialOid, Oid, OidFull, oid_parts_to_full, get_first_byte_of_oid, write_oid_hex}};
 use git_reader::{object_database::{LightObjectDB, loose::{commit_object_parsing, ParsedObject, ParseObject, blob_object_parsing, tree_object_parsing}, packed, state::{State, MinState}}, ioerre};
-use git_reader::{object_database::{FoundPackedLocation, Location, oidmap_u128::{defaults::{B10, B14}, OidMap}}};
+use git_reader::{object_database::{Location, oidmap_u128::{defaults::{B10, B14}, OidMap}}};
 use packed::{PackFile, IDXFileLight, open_pack_file};
 use io::{Write, stdout, StdoutLock};
 
@@ -210,18 +210,12 @@ impl OidMap2 {
                 let object: ParsedObject<MyCustomParser> = odb.get_loose_object_from_oid_full(oid_full, state)?;
                 object
             }
-            OidLocationType::Packed(idx_file_index, packfile_offset) => {
-                let idx_file = &self.searched_packs[idx_file_index];
-                let idx_file_id = idx_file.id;
-                let location = FoundPackedLocation {
-                    id: idx_file_id,
-                    object_starts_at: packfile_offset,
-                    oid_index: 0, // we dont need this
-                };
-                // TODO: this state should have access to our oid map, otherwise
-                // it will unnecessarily read another index file...
-                let pack = &idx_file.pack_file;
-                let object: ParsedObject<MyCustomParser> = odb.get_packed_object_packfile_loaded(&location, &pack, state)?;
+            OidLocationType::Packed(idx_file_index, _) => {
+                // we already have the idx file open, so we give it to the odb
+                // instead of letting the state read another one:
+                let searched = &self.searched_packs[idx_file_index];
+                let object: ParsedObject<MyCustomParser> = odb.get_packed_object_with_idx_and_pack(
+                    oid, &searched.idx_file.file, &searched.pack_file, state)?;
                 object
             }
         };
@@ -403,13 +397,14 @@ pub fn lo7_log(
     this_oid: &mut Oid,
 ) -> bool {
     if let ParsedObject::Commit(ref c) = obj {
-        let _ = writeln!(handle, "{:032x} {}", this_oid, c.message);
+        let _ = write_oid_hex(*this_oid, handle);
+        let _ = writeln!(handle, " {}", c.message);
         // TODO: using handle.write_all is a bit better i think
-        if c.parent_one == 0 {
+        let next_parent_id = match c.parents().next() {
+            Some(p) => p,
             // initial commit, we are done
-            return true;
-        }
-        let next_parent_id = c.parent_one;
+            None => return true,
+        };
         *this_oid = next_parent_id;
         return false;
     }
@@ -1,5 +1,5 @@
 use std::{path::PathBuf, io, collections::{BTreeMap, HashMap}, convert::TryInto};
-use git_reader::{ioerr, object_database, ioerre, object_id::{hex_u128_to_str, PartialOid, hash_str_to_oid, Oid, full_oid_to_u128_oid}};
+use git_reader::{ioerr, object_database, ioerre, object_id::{hex_u128_to_str, PartialOid, hash_str_to_oid, Oid, full_oid_to_u128_oid, OidFull, oid_full_to_string}};
 use object_database::{loose::{tree_object_parsing::TreeObject, commit_object_parsing::{ParseCommit, CommitOnlyTreeAndParents}}, packed::{open_pack_file_ex, DATA_STARTS_AT, open_idx_file_light, IDXFileLight, PackFile}};
 use object_database::{LightObjectDB, loose::UnparsedObjectType};
 
@@ -65,28 +65,16 @@ pub fn realmain() -> io::Result<()> {
     // first, get the idx entry map.
     // we want to traverse the packed objects in order
     // they appear in the packfile, but in the idx file
-    // they have a different order. a simple solution
-    // is to store their indices in a BTreeMap so we can
-    // then iterate in order:
-    let mut idx_map = BTreeMap::new();
-    idxfile.walk_all_oids_with_index_and_from(None, |oid, fanout_index| {
-        // this should be safe to unwrap because we know this oid is
-        // in the packfile... if this unwrap fails, we either have
-        // an invalid packfile (which should have been caught when we opened it)
-        // or otherwise our parsing/searching code is wrong...
-        let packfile_index = idxfile.find_packfile_index_from_fanout_index(fanout_index)
-            .unwrap();
-        idx_map.insert(packfile_index as usize, oid);
-        false
-    });
+    // they have a different order:
+    let pack_order = idxfile.full_oids_in_pack_order();
 
     // this map stores packfile indices and maps to
     // the base object that it refers to. a delta ofset
     // always points to a past object. so if we
     // see a delta offset, we know we can lookup its
     // base in this map:
-    let mut delta_map: BTreeMap<usize, (Oid, &'static str)> = BTreeMap::new();
-    let mut ite = idx_map.iter().peekable();
+    let mut delta_map: BTreeMap<usize, (OidFull, &'static str)> = BTreeMap::new();
+    let mut ite = pack_order.iter().map(|(i, oid)| (*i as usize, oid)).peekable();
     loop {
         let (packfile_index, oid) = match ite.next() {
             Some(po) => po,
@@ -96,9 +84,9 @@ pub fn realmain() -> io::Result<()> {
             obj_type,
             obj_decompressed_size,
             _,
-        ) = packfile.get_object_type_and_len_at_index(*packfile_index)?;
+        ) = packfile.get_object_type_and_len_at_index(packfile_index)?;
         let next_index = match ite.peek() {
-            Some((i, _)) => **i,
+            Some((i, _)) => *i,
             None => {
                 // if there is no next index, instead
                 // we use the length of the file:
@@ -107,7 +95,7 @@ pub fn realmain() -> io::Result<()> {
         };
         let size_in_packfile = next_index - packfile_index;
 
-        let (typestr, base_oid): (&'static str, Option<Oid>) = match obj_type {
+        let (typestr, base_oid): (&'static str, Option<OidFull>) = match obj_type {
             object_database::packed::PackFileObjectType::Commit => (COMMIT, None),
             object_database::packed::PackFileObjectType::Tree => (TREE, None),
             object_database::packed::PackFileObjectType::Blob => (BLOB, None),
@@ -121,17 +109,16 @@ pub fn realmain() -> io::Result<()> {
                 (base_type_str, Some(*base_oid))
             }
             object_database::packed::PackFileObjectType::RefDelta(base_oid) => {
-                let base_oid = full_oid_to_u128_oid(base_oid);
-                let base_type_str = resolve_to_base_type(&mut idxfile, &packfile, base_oid)?;
+                let base_type_str = resolve_to_base_type(&mut idxfile, &packfile, full_oid_to_u128_oid(base_oid))?;
                 (base_type_str, Some(base_oid))
             }
         };
-        delta_map.insert(*packfile_index, (*oid, typestr));
+        delta_map.insert(packfile_index, (*oid, typestr));
 
         if let Some(base_oid) = base_oid {
-            println!("{:032x} {}\t{} {} {} ? {:032x}", oid, typestr, obj_decompressed_size, size_in_packfile, packfile_index, base_oid);
+            println!("{} {}\t{} {} {} ? {}", oid_full_to_string(*oid), typestr, obj_decompressed_size, size_in_packfile, packfile_index, oid_full_to_string(base_oid));
         } else {
-            println!("{:032x} {}\t{} {} {}", oid, typestr, obj_decompressed_size, size_in_packfile, packfile_index);
+            println!("{} {}\t{} {} {}", oid_full_to_string(*oid), typestr, obj_decompressed_size, size_in_packfile, packfile_index);
         }
     }
 
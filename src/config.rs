@@ -0,0 +1,244 @@
+//! just enough of git's config format to read the settings that change
+//! how objects are read. This is not a full config implementation: only
+//! the repo's own `config` file is read (not the global or system ones),
+//! and `[include]` / `[includeIf]` sections are ignored, so settings
+//! that are only in an included file are not seen. See:
+//! https://git-scm.com/docs/git-config#_configuration_file
+
+use std::{io, fs, path::Path, convert::TryFrom};
+use crate::{ioerr, ioerre, error::GitReaderError, repository::HashAlgo};
+
+/// the highest `core.repositoryformatversion` we know how to read.
+/// Version 1 is the same as 0, except that it's allowed to have `[extensions]`.
+pub const MAX_REPOSITORY_FORMAT_VERSION: u32 = 1;
+
+/// the config settings that matter for finding and reading objects.
+/// Load one with `load_config`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ObjectConfig {
+    /// `core.repositoryformatversion`, 0 if it's not set.
+    pub repository_format_version: u32,
+    /// `extensions.objectformat`. None means sha1.
+    pub object_format: Option<HashAlgo>,
+    /// `core.bigFileThreshold` in bytes. git stores files bigger than
+    /// this without deltas, and streams them instead of reading them all at once.
+    pub big_file_threshold: Option<u64>,
+    /// `core.bare`, None if it's not set.
+    pub bare: Option<bool>,
+}
+
+impl ObjectConfig {
+    /// parse the contents of a config file. If a key is in
+    /// there more than once, the last one wins like in git.
+    pub fn parse(contents: &str) -> io::Result<ObjectConfig> {
+        let mut config = ObjectConfig::default();
+        // None when we are in a section we dont care about, or
+        // in a subsection, ie: `[remote "origin"]`
+        let mut section: Option<String> = None;
+        let mut lines = contents.lines();
+        while let Some(line) = lines.next() {
+            let mut line = line.trim().to_string();
+            // a value can continue on the next line:
+            while line.ends_with('\\') && !line.ends_with("\\\\") {
+                line.pop();
+                match lines.next() {
+                    Some(next) => line.push_str(next),
+                    None => break,
+                }
+            }
+            let mut rest = line.as_str();
+            if rest.starts_with('[') {
+                let end = rest.find(']')
+                    .ok_or_else(|| ioerr!("Failed to parse config section '{}'", line))?;
+                let header = rest[1..end].trim();
+                let has_subsection = header.contains(|c: char| c == '"' || c == '.' || c.is_whitespace());
+                section = if has_subsection { None } else { Some(header.to_ascii_lowercase()) };
+                // git allows a key on the same line as the section:
+                rest = rest[end + 1..].trim();
+            }
+            if rest.is_empty() || rest.starts_with('#') || rest.starts_with(';') {
+                continue;
+            }
+            let section = match &section {
+                Some(s) => s.as_str(),
+                None => continue,
+            };
+            let (key, value) = match rest.split_once('=') {
+                Some((key, value)) => (key.trim(), parse_config_value(value.trim())?),
+                // a key without a value is a boolean true.
+                None => (rest, "true".to_string()),
+            };
+            match (section, key.to_ascii_lowercase().as_str()) {
+                ("core", "repositoryformatversion") => {
+                    config.repository_format_version = value.parse()
+                        .map_err(|_| ioerr!("Invalid core.repositoryformatversion '{}'", value))?;
+                }
+                ("core", "bigfilethreshold") => {
+                    config.big_file_threshold = Some(parse_config_size(&value)
+                        .ok_or_else(|| ioerr!("Invalid core.bigFileThreshold '{}'", value))?);
+                }
+                ("core", "bare") => {
+                    config.bare = Some(parse_config_bool(&value)
+                        .ok_or_else(|| ioerr!("Invalid core.bare '{}'", value))?);
+                }
+                ("extensions", "objectformat") => {
+                    config.object_format = match HashAlgo::from_name(&value) {
+                        Some(algo) => Some(algo),
+                        None => return ioerre!("Unknown extensions.objectformat '{}'", value),
+                    };
+                }
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+
+    /// the hash the objects are named with. sha1 unless the config says otherwise.
+    pub fn hash_algo(&self) -> HashAlgo {
+        self.object_format.unwrap_or(HashAlgo::Sha1)
+    }
+
+    /// an error if this repo is a newer format than we can read,
+    /// or if its objects are not named with sha1.
+    pub fn check_supported(&self) -> io::Result<()> {
+        if self.repository_format_version > MAX_REPOSITORY_FORMAT_VERSION {
+            return Err(GitReaderError::UnsupportedVersion(self.repository_format_version).into());
+        }
+        if self.hash_algo() != HashAlgo::Sha1 {
+            return ioerre!("Repository uses {:?} object ids, only sha1 is supported", self.hash_algo());
+        }
+        Ok(())
+    }
+
+    /// `core.bigFileThreshold` as a `State::max_object_size`, if it's set.
+    /// Objects bigger than that are the ones git would stream, so
+    /// we shouldn't read them into memory either.
+    pub fn max_object_size(&self) -> Option<usize> {
+        self.big_file_threshold.map(|t| usize::try_from(t).unwrap_or(usize::MAX))
+    }
+}
+
+/// load `config` from the git dir. Its not an error if it doesn't
+/// exist, you just get the defaults.
+pub fn load_config<P: AsRef<Path>>(git_dir: P) -> io::Result<ObjectConfig> {
+    let config_path = git_dir.as_ref().join("config");
+    match fs::read_to_string(&config_path) {
+        Ok(contents) => ObjectConfig::parse(&contents)
+            .map_err(|e| ioerr!("Failed to parse {:?}\n{}", config_path, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ObjectConfig::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// remove the comments and quotes of a value, and unescape it.
+fn parse_config_value(value: &str) -> io::Result<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut in_quotes = false;
+    // so trailing whitespace that was in quotes is kept:
+    let mut keep_len = 0;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '#' | ';' if !in_quotes => break,
+            '\\' => {
+                let escaped = match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('b') => '\u{8}',
+                    Some(c @ '"') | Some(c @ '\\') => c,
+                    _ => return ioerre!("Invalid escape in config value '{}'", value),
+                };
+                out.push(escaped);
+            }
+            c => out.push(c),
+        }
+        if in_quotes || !c.is_whitespace() {
+            keep_len = out.len();
+        }
+    }
+    if in_quotes {
+        return ioerre!("Config value '{}' is missing a closing quote", value);
+    }
+    out.truncate(keep_len);
+    Ok(out)
+}
+
+/// a config boolean. Like git, an empty value is false.
+fn parse_config_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// a config integer, which can end with k, m, or g (ie: "512m").
+fn parse_config_size(value: &str) -> Option<u64> {
+    let (digits, multiplier) = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => (&value[..value.len() - 1], 1024),
+        'm' => (&value[..value.len() - 1], 1024 * 1024),
+        'g' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    digits.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_config_parsing_works() {
+        let contents = "\
+# a comment
+[core]
+\trepositoryformatversion = 1
+\tbare
+\tBigFileThreshold = 512m ; a comment
+[include]
+\tpath = other.config
+[remote \"origin\"]
+\tbigfilethreshold = not even a number
+[extensions] objectFormat = \"sha1\"
+";
+        let config = ObjectConfig::parse(contents).unwrap();
+        assert_eq!(config.repository_format_version, 1);
+        assert_eq!(config.big_file_threshold, Some(512 * 1024 * 1024));
+        assert_eq!(config.max_object_size(), Some(512 * 1024 * 1024));
+        assert_eq!(config.object_format, Some(HashAlgo::Sha1));
+        assert_eq!(config.bare, Some(true));
+        config.check_supported().unwrap();
+
+        // the last value wins:
+        let config = ObjectConfig::parse("[core]\nbigFileThreshold = 1k\n[core]\nbigFileThreshold = 20\n").unwrap();
+        assert_eq!(config.big_file_threshold, Some(20));
+        assert_eq!(ObjectConfig::parse("").unwrap(), ObjectConfig::default());
+
+        let config = ObjectConfig::parse("[core]\nrepositoryformatversion = 2\n").unwrap();
+        let err = config.check_supported().unwrap_err();
+        assert!(matches!(GitReaderError::from_io_error(&err), Some(GitReaderError::UnsupportedVersion(2))));
+        let config = ObjectConfig::parse("[extensions]\nobjectformat = sha256\n").unwrap();
+        assert_eq!(config.hash_algo(), HashAlgo::Sha256);
+        assert!(config.check_supported().is_err());
+
+        assert!(ObjectConfig::parse("[core]\nbigFileThreshold = lots\n").is_err());
+        assert!(ObjectConfig::parse("[extensions]\nobjectformat = md5\n").is_err());
+        assert!(ObjectConfig::parse("[core\n").is_err());
+    }
+
+    #[test]
+    fn config_values_work() {
+        assert_eq!(parse_config_value("abc # comment").unwrap(), "abc");
+        assert_eq!(parse_config_value("\"a # b\" c").unwrap(), "a # b c");
+        assert_eq!(parse_config_value("\"keep \"  ").unwrap(), "keep ");
+        assert_eq!(parse_config_value("a\\tb\\\\").unwrap(), "a\tb\\");
+        assert!(parse_config_value("\"open").is_err());
+        assert_eq!(parse_config_size("10"), Some(10));
+        assert_eq!(parse_config_size("2G"), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_config_size("k"), None);
+        assert_eq!(parse_config_bool("Yes"), Some(true));
+        assert_eq!(parse_config_bool(""), Some(false));
+        assert_eq!(parse_config_bool("maybe"), None);
+    }
+}
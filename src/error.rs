@@ -0,0 +1,170 @@
+use std::{io, fmt, error::Error, path::PathBuf};
+use crate::object_id::Oid;
+
+/// Most of this library returns `io::Result` so that `?` works
+/// everywhere. But for errors that callers might want to handle
+/// differently (ie: an object not existing vs a corrupt pack file)
+/// we create the io::Error from one of these, so callers can get
+/// it back out with `GitReaderError::from_io_error`.
+#[derive(Debug)]
+pub enum GitReaderError {
+    /// some other io error that we dont know anything more about
+    Io(io::Error),
+    /// we looked everywhere, and this oid does not exist
+    ObjectNotFound(Oid),
+    /// a partial oid matched more than one object
+    Ambiguous(Vec<Oid>),
+    CorruptPack { path: PathBuf, reason: String },
+    CorruptIdx { path: PathBuf, reason: String },
+    /// a pack file or idx file with a version we dont know how to read
+    UnsupportedVersion(u32),
+    /// a path that we cannot use, ie: its too long
+    BadPath(String),
+    /// this object does not exist locally, but a promisor pack
+    /// (from a partial clone) says it can be fetched from the remote.
+    PromisedObjectMissing(Oid),
+}
+
+impl GitReaderError {
+    /// if this io error was created from a `GitReaderError`, get
+    /// a reference to it.
+    pub fn from_io_error(e: &io::Error) -> Option<&GitReaderError> {
+        e.get_ref()?.downcast_ref::<GitReaderError>()
+    }
+
+    /// the io::ErrorKind we use when converting this into an io::Error
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            GitReaderError::Io(e) => e.kind(),
+            GitReaderError::ObjectNotFound(_) => io::ErrorKind::NotFound,
+            GitReaderError::Ambiguous(_) => io::ErrorKind::Other,
+            GitReaderError::CorruptPack { .. } |
+            GitReaderError::CorruptIdx { .. } |
+            GitReaderError::UnsupportedVersion(_) => io::ErrorKind::InvalidData,
+            GitReaderError::BadPath(_) => io::ErrorKind::InvalidInput,
+            GitReaderError::PromisedObjectMissing(_) => io::ErrorKind::NotFound,
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, GitReaderError::ObjectNotFound(_))
+    }
+
+    pub fn is_promised_missing(&self) -> bool {
+        matches!(self, GitReaderError::PromisedObjectMissing(_))
+    }
+}
+
+impl fmt::Display for GitReaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitReaderError::Io(e) => write!(f, "{}", e),
+            GitReaderError::ObjectNotFound(oid) => write!(f, "Failed to find object {:032x}", oid),
+            GitReaderError::Ambiguous(oids) => {
+                write!(f, "Ambiguous oid, found {} matches:", oids.len())?;
+                for oid in oids {
+                    write!(f, "\n{:032x}", oid)?;
+                }
+                Ok(())
+            }
+            GitReaderError::CorruptPack { path, reason } => write!(f, "Pack file {:?} is corrupt: {}", path, reason),
+            GitReaderError::CorruptIdx { path, reason } => write!(f, "IDX file {:?} is corrupt: {}", path, reason),
+            GitReaderError::UnsupportedVersion(v) => write!(f, "Unsupported version number '{}'", v),
+            GitReaderError::BadPath(p) => write!(f, "Bad path '{}'", p),
+            GitReaderError::PromisedObjectMissing(oid) => write!(f, "Object {:032x} is missing, but was promised by a promisor pack. It needs to be fetched from the promisor remote", oid),
+        }
+    }
+}
+
+impl Error for GitReaderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GitReaderError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// an io::Error can't be cloned, so a cloned `GitReaderError::Io` is a new
+/// io::Error with the same kind and message.
+impl Clone for GitReaderError {
+    fn clone(&self) -> Self {
+        match self {
+            GitReaderError::Io(e) => GitReaderError::Io(io::Error::new(e.kind(), e.to_string())),
+            GitReaderError::ObjectNotFound(oid) => GitReaderError::ObjectNotFound(*oid),
+            GitReaderError::Ambiguous(oids) => GitReaderError::Ambiguous(oids.clone()),
+            GitReaderError::CorruptPack { path, reason } => GitReaderError::CorruptPack { path: path.clone(), reason: reason.clone() },
+            GitReaderError::CorruptIdx { path, reason } => GitReaderError::CorruptIdx { path: path.clone(), reason: reason.clone() },
+            GitReaderError::UnsupportedVersion(v) => GitReaderError::UnsupportedVersion(*v),
+            GitReaderError::BadPath(p) => GitReaderError::BadPath(p.clone()),
+            GitReaderError::PromisedObjectMissing(oid) => GitReaderError::PromisedObjectMissing(*oid),
+        }
+    }
+}
+
+impl GitReaderError {
+    /// a copy of `e`, with the same kind and message. If it was made from
+    /// a `GitReaderError`, so is the copy, so `from_io_error` still works on it.
+    pub fn copy_io_error(e: &io::Error) -> io::Error {
+        match GitReaderError::from_io_error(e) {
+            Some(ours) => ours.clone().into(),
+            None => io::Error::new(e.kind(), e.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for GitReaderError {
+    fn from(e: io::Error) -> Self {
+        // if it was one of ours to begin with, unwrap it
+        // instead of nesting it:
+        if GitReaderError::from_io_error(&e).is_none() {
+            return GitReaderError::Io(e);
+        }
+        match e.into_inner().map(|inner| inner.downcast::<GitReaderError>()) {
+            Some(Ok(ours)) => *ours,
+            _ => unreachable!("we just checked that this io error contains a GitReaderError"),
+        }
+    }
+}
+
+impl From<GitReaderError> for io::Error {
+    fn from(e: GitReaderError) -> Self {
+        match e {
+            GitReaderError::Io(e) => e,
+            other => io::Error::new(other.kind(), other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_reader_error_roundtrips_through_io_error() {
+        let e: io::Error = GitReaderError::ObjectNotFound(1).into();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert!(GitReaderError::from_io_error(&e).unwrap().is_not_found());
+        let back = GitReaderError::from(e);
+        assert!(matches!(back, GitReaderError::ObjectNotFound(1)));
+
+        let plain = io::Error::other("plain");
+        assert!(GitReaderError::from_io_error(&plain).is_none());
+        assert!(matches!(GitReaderError::from(plain), GitReaderError::Io(_)));
+    }
+
+    #[test]
+    fn copy_io_error_keeps_kind_and_variant() {
+        let e: io::Error = GitReaderError::CorruptPack { path: "pack-0.pack".into(), reason: "bad".into() }.into();
+        let copy = GitReaderError::copy_io_error(&e);
+        assert_eq!(copy.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(copy.to_string(), e.to_string());
+        assert!(matches!(GitReaderError::from_io_error(&copy), Some(GitReaderError::CorruptPack { .. })));
+
+        let plain = io::Error::new(io::ErrorKind::PermissionDenied, "plain");
+        let copy = GitReaderError::copy_io_error(&plain);
+        assert_eq!(copy.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(copy.to_string(), "plain");
+        assert!(GitReaderError::from_io_error(&copy).is_none());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{path::Path, fs, io};
+use std::{path::Path, fs, io, ops::Deref};
 use fs::{OpenOptions, DirEntry, File};
 use memmap2::{Mmap, MmapOptions};
 
@@ -82,6 +82,47 @@ pub fn get_mmapped_file<P: AsRef<Path>>(
     Ok(mmapped_file)
 }
 
+/// how the contents of a pack/idx file should be held in memory.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum ReadMode {
+    /// mmap the file. this is what we do by default.
+    #[default]
+    Mmap,
+    /// read the entire file into a vec. Can be better for very small
+    /// files, or on network filesystems where mmap behaves badly.
+    Heap,
+}
+
+/// the contents of a file, either mmapped, or read into memory.
+/// derefs to a byte slice either way, so code reading it
+/// doesn't need to care which one it is.
+pub enum Backing {
+    Mmap(Mmap),
+    Heap(Vec<u8>),
+}
+
+impl Deref for Backing {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Backing::Mmap(m) => m,
+            Backing::Heap(v) => v,
+        }
+    }
+}
+
+pub fn get_file_backing<P: AsRef<Path>>(
+    path: P,
+    mode: ReadMode,
+) -> io::Result<Backing> {
+    let backing = match mode {
+        ReadMode::Mmap => Backing::Mmap(get_mmapped_file(path)?),
+        ReadMode::Heap => Backing::Heap(fs::read(path)?),
+    };
+    Ok(backing)
+}
+
 pub fn get_readonly_handle<P: AsRef<Path>>(
     path: P
 ) -> io::Result<File> {
@@ -0,0 +1,266 @@
+//! gitignore style pattern matching. This only knows about the patterns
+//! themselves, not where the .gitignore files are. See:
+//! https://git-scm.com/docs/gitignore#_pattern_format
+
+/// one line of a .gitignore file
+#[derive(Debug, Clone, PartialEq)]
+pub struct IgnorePattern {
+    /// the glob, without the leading `!`, leading `/`, or trailing `/`
+    pub pattern: String,
+    /// started with `!`, so a match re-includes the path
+    pub negated: bool,
+    /// ended with `/`, so it only matches directories
+    pub dir_only: bool,
+    /// had a `/` at the start or in the middle, so it matches
+    /// the full path instead of just the last component.
+    pub anchored: bool,
+}
+
+impl IgnorePattern {
+    /// parse one line. blank lines and comments are None.
+    pub fn parse(line: &str) -> Option<IgnorePattern> {
+        let mut line = line.trim_end_matches(['\n', '\r'].as_ref());
+        // trailing spaces are ignored unless they are escaped:
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[0..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+        Some(IgnorePattern { pattern: line.to_string(), negated, dir_only, anchored })
+    }
+
+    /// does this pattern match `path`? `path` is relative
+    /// to the directory of the .gitignore file, ie: "src/main.rs"
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let target = if self.anchored {
+            path
+        } else {
+            match path.rfind('/') {
+                Some(i) => &path[i + 1..],
+                None => path,
+            }
+        };
+        wildmatch(self.pattern.as_bytes(), 0, target.as_bytes(), 0)
+    }
+}
+
+/// the patterns of a .gitignore file (or several, in order of
+/// increasing priority). Like git, the last pattern that matches
+/// a path decides if it is ignored.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Gitignore {
+    pub patterns: Vec<IgnorePattern>,
+}
+
+impl Gitignore {
+    /// ie: `Gitignore::parse(contents.lines())`
+    pub fn parse<'a, I: IntoIterator<Item = &'a str>>(lines: I) -> Gitignore {
+        Gitignore {
+            patterns: lines.into_iter().filter_map(IgnorePattern::parse).collect(),
+        }
+    }
+
+    /// Some(true) if the last pattern that matches ignores the path,
+    /// Some(false) if it re-includes it, and None if nothing matches.
+    /// This does not check the parent directories, see `is_ignored`.
+    pub fn matched(&self, path: &str, is_dir: bool) -> Option<bool> {
+        self.patterns.iter().rev()
+            .find(|p| p.matches(path, is_dir))
+            .map(|p| !p.negated)
+    }
+
+    /// true if `path` is ignored. Like git, if one of its parent
+    /// directories is ignored then so is everything inside of it, even if
+    /// a later pattern would re-include the path itself.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let path = path.trim_matches('/');
+        for (i, b) in path.bytes().enumerate() {
+            if b == b'/' && self.matched(&path[0..i], true) == Some(true) {
+                return true;
+            }
+        }
+        self.matched(path, is_dir).unwrap_or(false)
+    }
+}
+
+/// match the glob `p` (from index `pi`) against `t` (from index `ti`).
+/// `*` and `?` do not match a `/`, but a `**` that is its own
+/// path component matches any number of directories.
+fn wildmatch(p: &[u8], pi: usize, t: &[u8], ti: usize) -> bool {
+    if pi == p.len() {
+        return ti == t.len();
+    }
+    match p[pi] {
+        b'*' => {
+            let is_double = p.get(pi + 1) == Some(&b'*');
+            let starts_component = pi == 0 || p[pi - 1] == b'/';
+            let after = pi + 2;
+            if is_double && starts_component && (after == p.len() || p[after] == b'/') {
+                if after == p.len() {
+                    // trailing `**`: everything that is left
+                    return true;
+                }
+                // `**/`: zero or more directories
+                let rest = after + 1;
+                return (ti..=t.len())
+                    .filter(|&i| i == ti || t[i - 1] == b'/')
+                    .any(|i| wildmatch(p, rest, t, i));
+            }
+            // any other amount of stars is the same as one
+            let mut rest = pi + 1;
+            while p.get(rest) == Some(&b'*') {
+                rest += 1;
+            }
+            let mut i = ti;
+            loop {
+                if wildmatch(p, rest, t, i) {
+                    return true;
+                }
+                if i == t.len() || t[i] == b'/' {
+                    return false;
+                }
+                i += 1;
+            }
+        }
+        b'?' => ti < t.len() && t[ti] != b'/' && wildmatch(p, pi + 1, t, ti + 1),
+        b'[' => {
+            if ti == t.len() || t[ti] == b'/' {
+                return false;
+            }
+            match match_class(p, pi, t[ti]) {
+                Some((true, next)) => wildmatch(p, next, t, ti + 1),
+                Some((false, _)) => false,
+                // no closing ], so the [ is just a character:
+                None => t[ti] == b'[' && wildmatch(p, pi + 1, t, ti + 1),
+            }
+        }
+        b'\\' if pi + 1 < p.len() => {
+            ti < t.len() && t[ti] == p[pi + 1] && wildmatch(p, pi + 2, t, ti + 1)
+        }
+        c => ti < t.len() && t[ti] == c && wildmatch(p, pi + 1, t, ti + 1),
+    }
+}
+
+/// match `c` against the `[...]` class starting at `p[pi]`.
+/// returns if it matched, and the index after the class.
+/// None if the class is never closed.
+fn match_class(p: &[u8], pi: usize, c: u8) -> Option<(bool, usize)> {
+    let mut i = pi + 1;
+    let negated = matches!(p.get(i), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut start = *p.get(i)?;
+        // a ] right at the start is part of the class
+        if start == b']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if start == b'\\' {
+            i += 1;
+            start = *p.get(i)?;
+        }
+        if p.get(i + 1) == Some(&b'-') && p.get(i + 2).is_some_and(|e| *e != b']') {
+            let mut end = p[i + 2];
+            i += 2;
+            if end == b'\\' {
+                i += 1;
+                end = *p.get(i)?;
+            }
+            if start <= c && c <= end {
+                matched = true;
+            }
+        } else if start == c {
+            matched = true;
+        }
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gitignore_basic_patterns_work() {
+        let ig = Gitignore::parse("# comment\n*.o\n/build\ndocs/*.html\nlogs/\n\\#hash\nspace\\ \n".lines());
+        assert!(ig.is_ignored("main.o", false));
+        assert!(ig.is_ignored("src/deep/main.o", false));
+        assert!(!ig.is_ignored("main.c", false));
+        // anchored to the root:
+        assert!(ig.is_ignored("build", true));
+        assert!(!ig.is_ignored("src/build", true));
+        // a single * does not cross directories:
+        assert!(ig.is_ignored("docs/index.html", false));
+        assert!(!ig.is_ignored("docs/api/index.html", false));
+        // trailing / only matches directories, and their contents:
+        assert!(ig.is_ignored("src/logs", true));
+        assert!(!ig.is_ignored("src/logs", false));
+        assert!(ig.is_ignored("logs/today.txt", false));
+        assert!(ig.is_ignored("#hash", false));
+        assert!(ig.is_ignored("space ", false));
+        assert!(!ig.is_ignored("space", false));
+    }
+
+    #[test]
+    fn gitignore_double_star_works() {
+        let ig = Gitignore::parse("**/foo\nbar/**\na/**/b\n".lines());
+        assert!(ig.is_ignored("foo", false));
+        assert!(ig.is_ignored("x/y/foo", true));
+        assert!(ig.is_ignored("bar/x", false));
+        assert!(ig.is_ignored("bar/x/y", false));
+        // bar/** is whats inside of bar, not bar itself:
+        assert!(!ig.is_ignored("bar", true));
+        assert!(ig.is_ignored("a/b", false));
+        assert!(ig.is_ignored("a/x/y/b", false));
+        assert!(!ig.is_ignored("a/xb", false));
+    }
+
+    #[test]
+    fn gitignore_negation_works() {
+        let ig = Gitignore::parse(vec!["*.log", "!important.log", "tmp/", "!tmp/keep"]);
+        assert!(ig.is_ignored("debug.log", false));
+        assert!(!ig.is_ignored("important.log", false));
+        assert!(!ig.is_ignored("src/important.log", false));
+        // cant re-include a file inside an ignored directory:
+        assert!(ig.is_ignored("tmp/keep", false));
+
+        // but re-including the directory itself works:
+        let ig = Gitignore::parse(vec!["/*", "!/src", "src/*.tmp"]);
+        assert!(ig.is_ignored("README", false));
+        assert!(!ig.is_ignored("src/main.rs", false));
+        assert!(ig.is_ignored("src/x.tmp", false));
+    }
+
+    #[test]
+    fn gitignore_classes_work() {
+        let ig = Gitignore::parse(vec!["file[0-9].txt", "[!a]bc", "x[]]y", "?z"]);
+        assert!(ig.is_ignored("file3.txt", false));
+        assert!(!ig.is_ignored("filex.txt", false));
+        assert!(ig.is_ignored("zbc", false));
+        assert!(!ig.is_ignored("abc", false));
+        assert!(ig.is_ignored("x]y", false));
+        assert!(ig.is_ignored("qz", false));
+        assert!(!ig.is_ignored("qqz", false));
+    }
+}
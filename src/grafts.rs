@@ -0,0 +1,228 @@
+use std::{io, fs, path::Path, collections::{BTreeMap, BTreeSet}};
+use crate::{ioerr, ioerre, object_id::{Oid, hash_str_to_oid}, repository::{parse_ref_contents, Ref}};
+
+/// git follows replace refs that point to other replaced objects,
+/// but only up to this many times.
+pub const MAX_REPLACE_DEPTH: usize = 5;
+
+/// overrides of history that git applies when reading objects.
+/// grafts (`.git/info/grafts`) change the parents of a commit, and
+/// replace refs (`refs/replace/<oid>`) make an object be read from
+/// the contents of a different object. Load one with `load_grafts`.
+#[derive(Debug, Default)]
+pub struct GraftTable {
+    /// commit -> the parents it should have instead
+    pub grafts: BTreeMap<Oid, Vec<Oid>>,
+    /// object -> the object whose contents should be used instead
+    pub replacements: BTreeMap<Oid, Oid>,
+}
+
+impl GraftTable {
+    pub fn is_empty(&self) -> bool {
+        self.grafts.is_empty() && self.replacements.is_empty()
+    }
+
+    /// the oid whose contents should be read when someone asks
+    /// for `oid`. If it is not replaced, this is just `oid`.
+    pub fn replacement_for(&self, oid: Oid) -> Oid {
+        let mut current = oid;
+        for _ in 0..MAX_REPLACE_DEPTH {
+            match self.replacements.get(&current) {
+                Some(replaced_with) => current = *replaced_with,
+                None => break,
+            }
+        }
+        current
+    }
+
+    /// the parents this commit should have, if it was grafted.
+    pub fn grafted_parents(&self, oid: Oid) -> Option<&[Oid]> {
+        self.grafts.get(&oid).map(|p| p.as_slice())
+    }
+
+    /// parse the contents of a grafts file. each line is
+    /// a commit followed by the parents it should have, ie:
+    /// `<commit> [<parent>]*`. An empty parent list makes it a root commit.
+    pub fn add_grafts_from_str(&mut self, contents: &str) -> io::Result<()> {
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut hashes = line.split_whitespace();
+            let commit = match hashes.next() {
+                Some(c) => hash_str_to_oid(c)?,
+                None => continue,
+            };
+            let parents = hashes.map(hash_str_to_oid).collect::<io::Result<Vec<Oid>>>()?;
+            self.grafts.insert(commit, parents);
+        }
+        Ok(())
+    }
+}
+
+/// load `info/grafts` and every `refs/replace/<oid>` ref, both loose
+/// and packed. Its not an error if none of these exist, you just
+/// get an empty table.
+pub fn load_grafts<P: AsRef<Path>>(git_dir: P) -> io::Result<GraftTable> {
+    let git_dir = git_dir.as_ref();
+    let mut table = GraftTable::default();
+
+    let grafts_path = git_dir.join("info").join("grafts");
+    match fs::read_to_string(&grafts_path) {
+        Ok(contents) => table.add_grafts_from_str(&contents)
+            .map_err(|e| ioerr!("Failed to parse {:?}\n{}", grafts_path, e))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    // packed ones first, so loose refs take priority:
+    let packed_refs_path = git_dir.join("packed-refs");
+    match fs::read_to_string(&packed_refs_path) {
+        Ok(contents) => {
+            for line in contents.lines() {
+                if line.starts_with('#') || line.starts_with('^') {
+                    continue;
+                }
+                let (hash, ref_name) = match line.split_once(' ') {
+                    Some(hr) => hr,
+                    None => continue,
+                };
+                if let Some(replaced) = ref_name.strip_prefix("refs/replace/") {
+                    table.replacements.insert(hash_str_to_oid(replaced)?, hash_str_to_oid(hash)?);
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let replace_dir = git_dir.join("refs").join("replace");
+    let entries = match fs::read_dir(&replace_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(table),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let replaced = match file_name.to_str() {
+            Some(name) if name.len() == 40 => hash_str_to_oid(name)?,
+            // not a replace ref, ignore it:
+            _ => continue,
+        };
+        let contents = fs::read_to_string(entry.path())?;
+        match parse_ref_contents(&contents)? {
+            Ref::Direct(replaced_with) => {
+                table.replacements.insert(replaced, replaced_with);
+            }
+            Ref::Symbolic(target) => {
+                return ioerre!("Replace ref {:?} is a symbolic ref to '{}', which is not supported", entry.path(), target);
+            }
+        }
+    }
+    Ok(table)
+}
+
+/// the commits of a shallow clone (`.git/shallow`) whose parents
+/// were intentionally not fetched. git treats them as root commits,
+/// so a walk should stop at them instead of looking for their parents.
+/// Load one with `load_shallow`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ShallowSet {
+    pub commits: BTreeSet<Oid>,
+}
+
+impl ShallowSet {
+    /// parse the contents of a shallow file, one commit per line.
+    pub fn parse(contents: &str) -> io::Result<ShallowSet> {
+        let mut commits = BTreeSet::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            commits.insert(hash_str_to_oid(line)?);
+        }
+        Ok(ShallowSet { commits })
+    }
+
+    pub fn contains(&self, oid: Oid) -> bool {
+        self.commits.contains(&oid)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commits.is_empty()
+    }
+}
+
+/// load `shallow` from the git dir. Its not an error if it doesn't
+/// exist, that just means this is not a shallow clone, and you get an empty set.
+pub fn load_shallow<P: AsRef<Path>>(git_dir: P) -> io::Result<ShallowSet> {
+    let shallow_path = git_dir.as_ref().join("shallow");
+    match fs::read_to_string(&shallow_path) {
+        Ok(contents) => ShallowSet::parse(&contents)
+            .map_err(|e| ioerr!("Failed to parse {:?}\n{}", shallow_path, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ShallowSet::default()),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::TempDir;
+
+    #[test]
+    fn grafts_parsing_works() {
+        let a = "a".repeat(40);
+        let b = "b".repeat(40);
+        let c = "c".repeat(40);
+        let contents = format!("# comment\n{} {} {}\n\n{}\n", a, b, c, b);
+        let mut table = GraftTable::default();
+        table.add_grafts_from_str(&contents).unwrap();
+        let a = hash_str_to_oid(&a).unwrap();
+        let b = hash_str_to_oid(&b).unwrap();
+        let c = hash_str_to_oid(&c).unwrap();
+        assert_eq!(table.grafted_parents(a), Some(&[b, c][..]));
+        // a graft with no parents makes it a root:
+        assert_eq!(table.grafted_parents(b), Some(&[][..]));
+        assert_eq!(table.grafted_parents(c), None);
+
+        table.replacements.insert(a, b);
+        table.replacements.insert(b, c);
+        assert_eq!(table.replacement_for(a), c);
+        assert_eq!(table.replacement_for(c), c);
+    }
+
+    #[test]
+    fn shallow_parsing_works() {
+        let a = "a".repeat(40);
+        let b = "b".repeat(40);
+        let shallow = ShallowSet::parse(&format!("{}\n{}\n\n", a, b)).unwrap();
+        assert!(shallow.contains(hash_str_to_oid(&a).unwrap()));
+        assert!(shallow.contains(hash_str_to_oid(&b).unwrap()));
+        assert!(!shallow.contains(0));
+        assert!(ShallowSet::parse("not a hash\n").is_err());
+        // not a shallow clone:
+        assert!(load_shallow("/nonexistent/.git").unwrap().is_empty());
+    }
+
+    #[test]
+    fn load_shallow_reads_the_shallow_file() {
+        let dir = TempDir::new("load_shallow");
+        // no shallow file means nothing is shallow:
+        assert!(load_shallow(dir.path()).unwrap().is_empty());
+
+        let a = "a".repeat(40);
+        fs::write(dir.join("shallow"), format!("{}\n\n", a)).unwrap();
+        let shallow = load_shallow(dir.path()).unwrap();
+        assert!(shallow.contains(hash_str_to_oid(&a).unwrap()));
+        assert!(!shallow.contains(0));
+
+        fs::write(dir.join("shallow"), format!("{}\nnot a hash\n", a)).unwrap();
+        let err = load_shallow(dir.path()).unwrap_err();
+        // the error says which file was bad:
+        assert!(err.to_string().contains(&format!("{:?}", dir.join("shallow"))), "{}", err);
+    }
+}
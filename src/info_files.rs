@@ -0,0 +1,87 @@
+//! readers for the files that git writes for the "dumb" http
+//! protocol (`git update-server-info`), and the other files
+//! in `objects/info/`. See:
+//! https://git-scm.com/docs/gitrepository-layout
+
+use std::{io, fs, path::{Path, PathBuf}};
+use crate::{ioerr, ioerre, object_id::{Oid, OidFull, hash_str_to_oid}, object_database::packed::parse_pack_or_idx_id};
+
+/// parses `objects/info/packs`, which has one line
+/// per pack file like: `P pack-{40 hex chars}.pack`.
+/// The file is only updated when someone runs `git update-server-info`
+/// so it can be stale: packs that are listed but no longer exist
+/// (either the .pack or the .idx is missing) are skipped. Packs
+/// that exist but were created after the file was written will
+/// not be returned.
+pub fn read_info_packs<P: AsRef<Path>>(objects_dir: P) -> io::Result<Vec<OidFull>> {
+    let objects_dir = objects_dir.as_ref();
+    let info_packs_path = objects_dir.join("info").join("packs");
+    let contents = fs::read_to_string(&info_packs_path)
+        .map_err(|e| ioerr!("Failed to read {:?}\n{}", info_packs_path, e))?;
+    let pack_dir = objects_dir.join("pack");
+    let mut out = vec![];
+    for line in contents.lines() {
+        // other lines can exist, but the only one
+        // git writes is P:
+        let pack_name = match line.strip_prefix("P ") {
+            Some(p) => p.trim(),
+            None => continue,
+        };
+        let pack_id = match parse_pack_or_idx_id(pack_name) {
+            Some(id) => id,
+            None => return ioerre!("Failed to parse pack id from line '{}' in {:?}", line, info_packs_path),
+        };
+        let pack_path = pack_dir.join(pack_name);
+        if !pack_path.is_file() || !pack_path.with_extension("idx").is_file() {
+            continue;
+        }
+        out.push(pack_id);
+    }
+    Ok(out)
+}
+
+/// parses `info/refs`, which has one line per ref like:
+/// `{40 hex chars}\t{ref name}`. Annotated tags also have
+/// a line for what they peel to, with a ref name ending in `^{}`,
+/// those are returned as is.
+pub fn read_info_refs<P: AsRef<Path>>(git_dir: P) -> io::Result<Vec<(Oid, String)>> {
+    let info_refs_path = git_dir.as_ref().join("info").join("refs");
+    let contents = fs::read_to_string(&info_refs_path)
+        .map_err(|e| ioerr!("Failed to read {:?}\n{}", info_refs_path, e))?;
+    let mut out = vec![];
+    for line in contents.lines() {
+        if line.is_empty() {
+            continue;
+        }
+        let (hash, ref_name) = line.split_once('\t')
+            .ok_or_else(|| ioerr!("Failed to parse line '{}' in {:?}", line, info_refs_path))?;
+        out.push((hash_str_to_oid(hash)?, ref_name.to_string()));
+    }
+    Ok(out)
+}
+
+/// parses `objects/info/alternates`, which lists other objects
+/// directories (one per line) that this repository can read objects from,
+/// ie: a clone made with `git clone --shared`. Relative paths are relative
+/// to `objects_dir`. Returns an empty list if the file doesn't exist.
+pub fn read_info_alternates<P: AsRef<Path>>(objects_dir: P) -> io::Result<Vec<PathBuf>> {
+    let objects_dir = objects_dir.as_ref();
+    let alternates_path = objects_dir.join("info").join("alternates");
+    let contents = match fs::read_to_string(&alternates_path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(ioerr!("Failed to read {:?}\n{}", alternates_path, e)),
+    };
+    let mut out = vec![];
+    for line in contents.lines() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('"') {
+            return ioerre!("Quoted path '{}' in {:?} is not supported", line, alternates_path);
+        }
+        out.push(objects_dir.join(line));
+    }
+    Ok(out)
+}
@@ -4,9 +4,20 @@ pub mod repository;
 pub mod object_database;
 pub mod fs_helpers;
 pub mod object_id;
+pub mod sha1;
+pub mod error;
+pub mod info_files;
+pub mod config;
+pub mod grafts;
+pub mod sparse;
+pub mod gitignore;
+#[cfg(test)]
+pub(crate) mod test_helpers;
 
 /// returns the absolute path of the actual .git/ folder
-/// from your search path
+/// from your search path. The search path can be a worktree
+/// (that has a .git/ folder), the .git/ folder itself,
+/// or a bare repository (ie: `repo.git/`)
 pub fn get_repository_directory<P: AsRef<Path>>(
     search_path: P
 ) -> io::Result<PathBuf> {
@@ -14,11 +25,12 @@ pub fn get_repository_directory<P: AsRef<Path>>(
     // and use that if one exists.
     let mut search_path = search_path.as_ref().to_path_buf();
     search_path.push(".git/");
-    let _search_path = if search_path.is_dir() {
+    let search_path = if search_path.is_dir() {
         // search_path/.git/ exists, use this
         search_path
     } else {
-        // maybe the search path is already the .git/ dir?
+        // maybe the search path is already the .git/ dir,
+        // or a bare repo?
         search_path.pop();
         if !search_path.exists() {
             return ioerre!("{:?} does not exist", search_path);
@@ -29,8 +41,33 @@ pub fn get_repository_directory<P: AsRef<Path>>(
     // we know search_path exists, now check if
     // its actually a git dir, ie: does it have the
     // necessary files to make it a git dir?
+    if !search_path.join("HEAD").is_file() {
+        return ioerre!("{:?} is not a git directory: it does not have a HEAD file", search_path);
+    }
+    if !search_path.join("objects").is_dir() {
+        return ioerre!("{:?} is not a git directory: it does not have an objects directory", search_path);
+    }
+    if !is_git_dir(&search_path)? {
+        return ioerre!("{:?} is not a git directory: it does not have a refs directory, and its config does not have core.bare = true", search_path);
+    }
 
-    panic!()
+    search_path.canonicalize()
+}
+
+/// true if `path` has the layout of a git dir, ie: the .git/ folder
+/// of a worktree, or a bare repository. That is: a HEAD file, and
+/// objects/ and refs/ folders. The refs/ folder can be missing if the
+/// config has `core.bare = true` (ie: a bare repo that was copied
+/// without its empty folders).
+pub fn is_git_dir<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    let path = path.as_ref();
+    if !path.join("HEAD").is_file() || !path.join("objects").is_dir() {
+        return Ok(false);
+    }
+    if path.join("refs").is_dir() {
+        return Ok(true);
+    }
+    Ok(config::load_config(path)?.bare == Some(true))
 }
 
 
@@ -57,8 +94,55 @@ macro_rules! ioerre {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn bare_repositories_are_found() {
+        use crate::{repository::Repo, object_database::{LightObjectDB, state::MinState, loose::{UnparsedObject, UnparsedObjectType}}};
+
+        // a bare repo like `git init --bare` makes, with one commit:
+        let bare = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_fixtures").join("bare.git");
+        assert!(is_git_dir(&bare).unwrap());
+        let git_dir = get_repository_directory(&bare).unwrap();
+        assert_eq!(git_dir, bare.canonicalize().unwrap());
+
+        let repo = Repo::open(&bare).unwrap();
+        assert!(repo.is_bare().unwrap());
+        let head = repo.head_oid().unwrap().unwrap();
+        let odb = LightObjectDB::open(&bare).unwrap();
+        let mut state = MinState::new(odb.path_to_db.as_ref()).unwrap();
+        let obj: UnparsedObject = odb.get_object_by_oid(head, &mut state).unwrap();
+        assert_eq!(obj.object_type, UnparsedObjectType::Commit);
+
+        // without core.bare, it needs a refs/ folder to be a git dir:
+        let root = crate::test_helpers::TempDir::new("bare");
+        std::fs::create_dir_all(root.join("objects")).unwrap();
+        std::fs::write(root.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        assert!(!is_git_dir(&root).unwrap());
+        assert!(get_repository_directory(&root).is_err());
+        std::fs::create_dir_all(root.join("refs")).unwrap();
+        assert!(is_git_dir(&root).unwrap());
+        assert_eq!(get_repository_directory(&root).unwrap(), root.path().canonicalize().unwrap());
+        // its a git dir, but not named .git, so its bare:
+        assert!(Repo::open(&root).unwrap().is_bare().unwrap());
+
+        // git doesn't keep empty folders, so a bare repo that was copied
+        // (ie: committed to another repo) can be missing refs/. With
+        // core.bare = true thats still a git dir:
+        let copied = crate::test_helpers::TempDir::new("bare_no_refs");
+        std::fs::create_dir_all(copied.join("objects")).unwrap();
+        std::fs::write(copied.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        std::fs::write(copied.join("config"), "[core]\n\tbare = false\n").unwrap();
+        assert!(!is_git_dir(&copied).unwrap());
+        std::fs::write(copied.join("config"), "[core]\n\tbare = true\n").unwrap();
+        assert!(is_git_dir(&copied).unwrap());
+        assert!(!copied.join("refs").exists());
+        assert_eq!(get_repository_directory(&copied).unwrap(), copied.path().canonicalize().unwrap());
+        assert!(Repo::open(&copied).unwrap().is_bare().unwrap());
+    }
 }
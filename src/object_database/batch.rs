@@ -0,0 +1,88 @@
+use std::io::{self, BufRead, Write};
+use crate::{ioerr, error::GitReaderError, object_id::{PartialOid, OidFull, oid_full_to_string_no_alloc, hash_object_file_and_folder_full}};
+use super::{LightObjectDB, FoundObjectLocation, state::State, loose::{UnparsedObject, read_raw_object_limited}};
+
+/// git does not look up abbreviated oids shorter than this.
+pub const MIN_BATCH_OID_LEN: usize = 4;
+
+/// like `git cat-file --batch`: reads one oid per line from `reader`,
+/// and for each of them writes `<oid> <type> <size>\n<content>\n`
+/// to `writer`. The oids can be partial (at least `MIN_BATCH_OID_LEN` hex chars)
+/// as long as they only match one object.
+/// If an oid does not exist, `<input> missing\n` is written instead, and
+/// if a partial oid matches several objects, `<input> ambiguous\n` is written.
+/// Neither of those stop the batch, only errors reading objects
+/// or writing to `writer` do.
+pub fn batch_cat<R: BufRead, W: Write, S: State>(
+    odb: &LightObjectDB,
+    reader: R,
+    writer: &mut W,
+    state: &mut S,
+) -> io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        let input = line.trim();
+        if input.is_empty() {
+            continue;
+        }
+        if PartialOid::from_hash(input).is_err() {
+            writeln!(writer, "{} missing", input)?;
+            continue;
+        }
+        let location = match odb.resolve_short(input, MIN_BATCH_OID_LEN, state) {
+            Ok((_, location)) => location,
+            Err(e) => {
+                if let Some(GitReaderError::Ambiguous(_)) = GitReaderError::from_io_error(&e) {
+                    writeln!(writer, "{} ambiguous", input)?;
+                    continue;
+                }
+                match e.kind() {
+                    // doesnt exist, or too short to look up:
+                    io::ErrorKind::NotFound | io::ErrorKind::InvalidInput => {
+                        writeln!(writer, "{} missing", input)?;
+                        continue;
+                    }
+                    _ => return Err(e),
+                }
+            }
+        };
+
+        let oid_full = full_oid_at_location(odb, &location)?;
+        let object = match location {
+            // loose objects are normally read without their blob
+            // contents, but we want everything here:
+            FoundObjectLocation::FoundLoose(path) => {
+                let max_object_size = state.max_object_size();
+                let decompressor = state.get_decompressor();
+                decompressor.reset(true);
+                read_raw_object_limited(path, true, decompressor, max_object_size)?
+            }
+            location => odb.get_object_from_location::<UnparsedObject, _>(location, state)?,
+        };
+        writer.write_all(&oid_full_to_string_no_alloc(oid_full))?;
+        writeln!(writer, " {} {}", object.object_type.as_str(), object.payload.len())?;
+        writer.write_all(&object.payload)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// our `Oid`s are truncated, so to print the oid like git does we
+/// get the full one back from where the object was found.
+fn full_oid_at_location(odb: &LightObjectDB, location: &FoundObjectLocation) -> io::Result<OidFull> {
+    match location {
+        FoundObjectLocation::FoundLoose(path) => {
+            let file_name = path.file_name().and_then(|f| f.to_str());
+            let folder = path.parent().and_then(|p| p.file_name()).and_then(|f| f.to_str());
+            match (folder, file_name) {
+                (Some(folder), Some(file_name)) => hash_object_file_and_folder_full(folder, file_name),
+                _ => Err(ioerr!("Failed to get an oid from the loose object path {:?}", path)),
+            }
+        }
+        FoundObjectLocation::FoundPacked(packed) => {
+            let idx = odb.open_idx(packed.id)?;
+            idx.get_full_oid_at_fanout_index(packed.oid_index)
+                .ok_or_else(|| ioerr!("Failed to read the oid at index {} of the idx file", packed.oid_index))
+        }
+    }
+}
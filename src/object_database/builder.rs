@@ -0,0 +1,279 @@
+use std::{io, sync::Arc, time::SystemTime};
+use flate2::Decompress;
+use crate::{ioerr, config::ObjectConfig, info_files::read_info_alternates, object_id::{Oid, OidFull}};
+use super::{LightObjectDB, OwnedLightObjectDB, MAX_PATH_TO_DB_LEN, MAX_DELTA_DEPTH, DEFAULT_MAX_OBJECT_SIZE};
+use super::packed::{IDXFileLight, PackFile, PackOffsetMap, OidBloomFilter};
+use super::state::{State, MinState, CachingState, OwnedOrBorrowedMut};
+
+/// git follows the alternates of alternates, but only this deep.
+pub const MAX_ALTERNATES_DEPTH: usize = 5;
+
+/// collects the options of an object db, and then makes the
+/// `LightObjectDB` and a `State` to read it with. The defaults are the same
+/// as `LightObjectDB::new_owned` and `MinState::new`, ie:
+/// ```no_run
+/// use git_reader::object_database::builder::ObjectDbBuilder;
+///
+/// let (odb, mut state) = ObjectDbBuilder::new("../.git/objects/")
+///     .with_alternates(true)
+///     .max_object_size(1 << 30)
+///     .delta_depth_limit(50)
+///     .caching(true)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ObjectDbBuilder {
+    path: String,
+    alternates: bool,
+    max_object_size: usize,
+    delta_depth_limit: usize,
+    caching: bool,
+}
+
+impl ObjectDbBuilder {
+    /// `path` is the objects directory, ie: ".git/objects/"
+    pub fn new<S: Into<String>>(path: S) -> ObjectDbBuilder {
+        ObjectDbBuilder {
+            path: path.into(),
+            alternates: false,
+            max_object_size: DEFAULT_MAX_OBJECT_SIZE,
+            delta_depth_limit: MAX_DELTA_DEPTH,
+            caching: false,
+        }
+    }
+
+    /// also read objects from the object dbs listed in
+    /// `objects/info/alternates`. Off by default.
+    pub fn with_alternates(mut self, alternates: bool) -> ObjectDbBuilder {
+        self.alternates = alternates;
+        self
+    }
+
+    /// see `State::max_object_size`.
+    pub fn max_object_size(mut self, max_object_size: usize) -> ObjectDbBuilder {
+        self.max_object_size = max_object_size;
+        self
+    }
+
+    /// use the limits that the repo's config asks for,
+    /// ie: `core.bigFileThreshold` as the max object size.
+    pub fn with_config(mut self, config: &ObjectConfig) -> ObjectDbBuilder {
+        if let Some(max_object_size) = config.max_object_size() {
+            self.max_object_size = max_object_size;
+        }
+        self
+    }
+
+    /// see `LightObjectDB::max_delta_depth`.
+    pub fn delta_depth_limit(mut self, delta_depth_limit: usize) -> ObjectDbBuilder {
+        self.delta_depth_limit = delta_depth_limit;
+        self
+    }
+
+    /// keep idx and pack files open between reads, see `CachingState`.
+    /// Off by default, which gives you a `MinState`.
+    pub fn caching(mut self, caching: bool) -> ObjectDbBuilder {
+        self.caching = caching;
+        self
+    }
+
+    pub fn build(self) -> io::Result<(OwnedLightObjectDB, ObjectDbState)> {
+        let odb = self.open_db(self.path.clone(), 0)?;
+        let mut min_state = MinState::new(&self.path)?;
+        min_state.max_object_size = self.max_object_size;
+        let state = ObjectDbState {
+            state: CachingState::from_min_state(min_state),
+            caching: self.caching,
+        };
+        Ok((odb, state))
+    }
+
+    /// open the db at `path`, and if we want alternates, its alternates too.
+    fn open_db(&self, path: String, depth: usize) -> io::Result<OwnedLightObjectDB> {
+        let mut odb = LightObjectDB::new_owned(path)?;
+        odb.max_delta_depth = self.delta_depth_limit;
+        // past the max depth, the alternates are ignored like git does.
+        // this also stops alternates that point back at each other:
+        if !self.alternates || depth >= MAX_ALTERNATES_DEPTH {
+            return Ok(odb);
+        }
+        for alternate in read_info_alternates(odb.path_to_db.as_ref())? {
+            let alternate = alternate.into_os_string().into_string()
+                .map_err(|p| ioerr!("Alternate objects directory {:?} is not valid utf8", p))?;
+            odb = odb.with_alternate(self.open_db(alternate, depth + 1)?);
+        }
+        Ok(odb)
+    }
+}
+
+/// the state made by `ObjectDbBuilder::build`. It reads like a
+/// `CachingState` or a `MinState`, depending on `ObjectDbBuilder::caching`.
+pub struct ObjectDbState {
+    state: CachingState,
+    caching: bool,
+}
+
+impl ObjectDbState {
+    pub fn is_caching(&self) -> bool {
+        self.caching
+    }
+
+    /// the `CachingState` we read with, ie: to change its
+    /// limits. None if this state is not caching.
+    pub fn caching_state(&mut self) -> Option<&mut CachingState> {
+        if self.caching { Some(&mut self.state) } else { None }
+    }
+
+    /// the `MinState` that we read with. If we are caching,
+    /// this is the state inside of the `CachingState`.
+    pub fn min_state(&mut self) -> &mut MinState {
+        &mut self.state.inner
+    }
+}
+
+// the loose objects, the packs and the limits all come from the
+// `MinState`, only the per pack caches depend on if we are caching.
+impl State for ObjectDbState {
+    type Idx = IDXFileLight;
+
+    fn get_decompressor(&mut self) -> &mut Decompress {
+        self.state.inner.get_decompressor()
+    }
+
+    fn max_object_size(&self) -> usize {
+        self.state.inner.max_object_size()
+    }
+
+    fn get_idx_file(&mut self, id: OidFull) -> io::Result<OwnedOrBorrowedMut<'_, Self::Idx>> {
+        if self.caching {
+            self.state.get_idx_file(id)
+        } else {
+            self.state.inner.get_idx_file(id)
+        }
+    }
+
+    fn get_pack_file(&mut self, id: OidFull) -> io::Result<Arc<PackFile>> {
+        if self.caching {
+            self.state.get_pack_file(id)
+        } else {
+            self.state.inner.get_pack_file(id)
+        }
+    }
+
+    fn get_cached_blob(&mut self, oid: Oid) -> Option<Arc<[u8]>> {
+        if self.caching {
+            self.state.get_cached_blob(oid)
+        } else {
+            self.state.inner.get_cached_blob(oid)
+        }
+    }
+
+    fn cache_blob(&mut self, oid: Oid, blob: Arc<[u8]>) {
+        if self.caching {
+            self.state.cache_blob(oid, blob)
+        } else {
+            self.state.inner.cache_blob(oid, blob)
+        }
+    }
+
+    fn get_pack_bloom_filter(&mut self) -> io::Result<Option<Arc<OidBloomFilter>>> {
+        if self.caching {
+            self.state.get_pack_bloom_filter()
+        } else {
+            self.state.inner.get_pack_bloom_filter()
+        }
+    }
+
+    fn get_pack_offset_map(&mut self, id: OidFull) -> io::Result<Option<Arc<PackOffsetMap>>> {
+        if self.caching {
+            self.state.get_pack_offset_map(id)
+        } else {
+            self.state.inner.get_pack_offset_map(id)
+        }
+    }
+
+    fn iter_loose_folder<F>(&mut self, folder_byte: u8, cb: &mut F) -> io::Result<()>
+        where F: FnMut(Oid, &str, &str) -> bool
+    {
+        self.state.inner.iter_loose_folder(folder_byte, cb)
+    }
+
+    fn iter_loose_folder_if_changed<F>(
+        &mut self,
+        folder_byte: u8,
+        changed_since: SystemTime,
+        cb: &mut F
+    ) -> io::Result<bool>
+        where F: FnMut(Oid, &str, &str) -> bool
+    {
+        if self.caching {
+            self.state.iter_loose_folder_if_changed(folder_byte, changed_since, cb)
+        } else {
+            self.state.inner.iter_loose_folder_if_changed(folder_byte, changed_since, cb)
+        }
+    }
+
+    fn iter_known_packs<F>(&mut self, cb: &mut F) -> io::Result<()>
+        where F: FnMut(&mut Self, OidFull) -> bool
+    {
+        // the callback wants us, not the state inside of us,
+        // so we find the packs first:
+        let mut ids = vec![];
+        self.state.inner.iter_known_packs(&mut |_, id| {
+            ids.push(id);
+            false
+        })?;
+        for id in ids {
+            if cb(self, id) { break; }
+        }
+        Ok(())
+    }
+
+    fn get_path_to_db_as_bytes(&self) -> (usize, [u8; MAX_PATH_TO_DB_LEN]) {
+        self.state.inner.get_path_to_db_as_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::GitReaderError, object_id::full_oid_to_u128_oid};
+    use crate::object_database::loose::{write_raw_object, UnparsedObject, UnparsedObjectType};
+    use crate::test_helpers::{TempDir, CommitFixture};
+
+    #[test]
+    fn builder_reads_alternates() {
+        let root = TempDir::new("builder");
+        let shared = root.join("shared");
+        let local = root.join("local");
+        std::fs::create_dir_all(shared.join("pack")).unwrap();
+        std::fs::create_dir_all(local.join("pack")).unwrap();
+        std::fs::create_dir_all(local.join("info")).unwrap();
+        // relative to the objects dir that lists it:
+        std::fs::write(local.join("info").join("alternates"), "# comment\n../shared\n").unwrap();
+        let commit = CommitFixture::new().message("shared").to_bytes();
+        let commit_oid = full_oid_to_u128_oid(write_raw_object(&shared, &UnparsedObjectType::Commit, &commit).unwrap());
+        let local_str = local.to_str().unwrap();
+
+        let (odb, mut state) = ObjectDbBuilder::new(local_str).build().unwrap();
+        assert!(!state.is_caching());
+        let err = odb.get_object_by_oid::<UnparsedObject, _>(commit_oid, &mut state).unwrap_err();
+        assert!(GitReaderError::from_io_error(&err).unwrap().is_not_found());
+
+        let (odb, mut state) = ObjectDbBuilder::new(local_str)
+            .with_alternates(true)
+            .caching(true)
+            .max_object_size(1000)
+            .delta_depth_limit(7)
+            .build()
+            .unwrap();
+        assert!(state.is_caching());
+        assert_eq!(state.max_object_size(), 1000);
+        assert_eq!(odb.max_delta_depth, 7);
+        let obj: UnparsedObject = odb.get_object_by_oid(commit_oid, &mut state).unwrap();
+        assert_eq!(obj.payload, &commit[..]);
+        // its read from the alternate, not copied:
+        assert_eq!(std::fs::read_dir(&local).unwrap().count(), 2);
+    }
+}
@@ -0,0 +1,172 @@
+use std::{io, path::Path, convert::TryInto};
+use crate::{ioerr, ioerre, error::GitReaderError, object_id::{Oid, OidFull, full_oid_to_u128_oid, oid_full_to_loose_relpath, hash_object_file_and_folder_full}};
+use super::{LightObjectDB, FoundObjectLocation, FoundPackedLocation, is_object_not_found, state::{State, IDXState}};
+
+/// how many bytes `LocationKey::to_bytes` makes: a tag byte,
+/// the 20 byte id, and an 8 byte offset.
+pub const LOCATION_KEY_LEN: usize = 1 + 20 + 8;
+
+/// where an object is, in a form that can be saved to disk and
+/// used again later, ie: a cache of oid -> location that a tool
+/// keeps between runs. Unlike `FoundObjectLocation`, this doesn't have
+/// any paths in it, so it stays valid if the repo is moved.
+/// Make one with `from_location`, and turn it back with `to_location`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LocationKey {
+    /// a loose object. its path comes from its oid.
+    Loose(OidFull),
+    /// an object in "pack-{pack_id}.pack" that starts at `offset`.
+    Packed { pack_id: OidFull, offset: u64 },
+}
+
+impl LocationKey {
+    /// errors if the location is a loose object whose file
+    /// name is not an oid, ie: it wasn't found by us.
+    pub fn from_location(location: &FoundObjectLocation) -> io::Result<LocationKey> {
+        match location {
+            FoundObjectLocation::FoundLoose(path) => {
+                let (folder, filename) = path.parent()
+                    .and_then(|p| p.file_name())
+                    .and_then(|f| f.to_str())
+                    .zip(path.file_name().and_then(|f| f.to_str()))
+                    .ok_or_else(|| ioerr!("Loose object path {:?} is not objects/xx/<rest of the oid>", path))?;
+                Ok(LocationKey::Loose(hash_object_file_and_folder_full(folder, filename)?))
+            }
+            FoundObjectLocation::FoundPacked(info) => Ok(LocationKey::Packed {
+                pack_id: info.id,
+                offset: info.object_starts_at,
+            }),
+        }
+    }
+
+    /// turn this back into a location in `odb`. `oid` is the object this
+    /// key was made for, its needed to find the object's entry in the idx file.
+    /// A saved key can be stale (ie: the object was packed, or the pack was
+    /// changed since), so this checks that the object is still where the key
+    /// says it is, and if not you get a `GitReaderError::ObjectNotFound`. If the
+    /// pack doesn't exist anymore, or its idx file is corrupt, you get the
+    /// error from opening or searching that idx file.
+    pub fn to_location<S: State>(
+        &self,
+        oid: Oid,
+        odb: &LightObjectDB,
+        state: &mut S,
+    ) -> io::Result<FoundObjectLocation> {
+        match self {
+            LocationKey::Loose(oid_full) => {
+                let path = Path::new(odb.path_to_db.as_ref()).join(oid_full_to_loose_relpath(*oid_full));
+                if full_oid_to_u128_oid(*oid_full) != oid || !path.is_file() {
+                    return Err(GitReaderError::ObjectNotFound(oid).into());
+                }
+                Ok(FoundObjectLocation::FoundLoose(path))
+            }
+            LocationKey::Packed { pack_id, offset } => {
+                let mut idx_file = state.get_idx_file(*pack_id)?;
+                let idx_file = idx_file.as_mut();
+                // only an oid that isn't in the idx means the key is stale,
+                // an idx that we can't read is its own error:
+                let oid_index = match idx_file.find_oid_and_fanout_index(oid) {
+                    Ok(i) => i,
+                    Err(e) if is_object_not_found(&e) => return Err(GitReaderError::ObjectNotFound(oid).into()),
+                    Err(e) => return Err(e),
+                };
+                if idx_file.find_packfile_index_from_fanout_index(oid_index) != Some(*offset) {
+                    return Err(GitReaderError::ObjectNotFound(oid).into());
+                }
+                Ok(FoundObjectLocation::FoundPacked(FoundPackedLocation {
+                    id: *pack_id,
+                    object_starts_at: *offset,
+                    oid_index,
+                }))
+            }
+        }
+    }
+
+    /// a fixed size encoding of this key: 0 or 1 for loose or packed,
+    /// then the oid or pack id, then the offset as big endian (0 if loose).
+    pub fn to_bytes(&self) -> [u8; LOCATION_KEY_LEN] {
+        let mut out = [0; LOCATION_KEY_LEN];
+        let (tag, id, offset) = match self {
+            LocationKey::Loose(oid_full) => (0, oid_full, 0),
+            LocationKey::Packed { pack_id, offset } => (1, pack_id, *offset),
+        };
+        out[0] = tag;
+        out[1..21].copy_from_slice(id);
+        out[21..].copy_from_slice(&offset.to_be_bytes());
+        out
+    }
+
+    /// the reverse of `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<LocationKey> {
+        if bytes.len() != LOCATION_KEY_LEN {
+            return ioerre!("Expected a location key of {} bytes, instead found {} bytes", LOCATION_KEY_LEN, bytes.len());
+        }
+        let mut id = OidFull::default();
+        id.copy_from_slice(&bytes[1..21]);
+        let offset = u64::from_be_bytes(bytes[21..].try_into().unwrap());
+        match bytes[0] {
+            0 if offset == 0 => Ok(LocationKey::Loose(id)),
+            1 => Ok(LocationKey::Packed { pack_id: id, offset }),
+            tag => ioerre!("Invalid location key with tag {} and offset {}", tag, offset),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_database::{state::MinState, loose::{write_raw_object, UnparsedObjectType}};
+    use crate::object_id::{full_oid_from_str, hash_str_to_oid};
+    use crate::test_helpers::{TempDir, copy_packed_fixture_objects, REF_DELTA_PACK, PACKED_COMMITS};
+
+    #[test]
+    fn location_key_round_trips() {
+        let packed = LocationKey::Packed { pack_id: [7; 20], offset: 0x0012_3456_789a };
+        assert_eq!(LocationKey::from_bytes(&packed.to_bytes()).unwrap(), packed);
+        let loose = LocationKey::Loose([9; 20]);
+        assert_eq!(LocationKey::from_bytes(&loose.to_bytes()).unwrap(), loose);
+        assert!(LocationKey::from_bytes(&packed.to_bytes()[1..]).is_err());
+        let mut bad = packed.to_bytes();
+        bad[0] = 2;
+        assert!(LocationKey::from_bytes(&bad).is_err());
+
+        let objects_dir = TempDir::objects_dir("location_key");
+        let oid_full = write_raw_object(&objects_dir, &UnparsedObjectType::Blob, b"where am i\n").unwrap();
+        let oid = full_oid_to_u128_oid(oid_full);
+        let objects_dir_str = objects_dir.path_str();
+        let odb = LightObjectDB::new(objects_dir_str).unwrap();
+        let mut state = MinState::new(objects_dir_str).unwrap();
+
+        let (_, location) = odb.try_find_first_matching_oid_with_location(oid, &mut state).unwrap().unwrap();
+        let key = LocationKey::from_location(&location).unwrap();
+        assert_eq!(key, LocationKey::Loose(oid_full));
+        match key.to_location(oid, &odb, &mut state).unwrap() {
+            FoundObjectLocation::FoundLoose(path) => assert!(path.ends_with(oid_full_to_loose_relpath(oid_full))),
+            _ => panic!("expected a loose location"),
+        }
+        // a key for some other oid is stale:
+        let err = key.to_location(oid + 1, &odb, &mut state).unwrap_err();
+        assert!(GitReaderError::from_io_error(&err).unwrap().is_not_found());
+    }
+
+    #[test]
+    fn truncated_idx_is_not_a_stale_key() {
+        let objects_dir = copy_packed_fixture_objects("location_key_truncated_idx");
+        let objects_dir_str = objects_dir.path_str();
+        let odb = LightObjectDB::new(objects_dir_str).unwrap();
+        let oid = hash_str_to_oid(PACKED_COMMITS[4]).unwrap();
+        let mut state = MinState::new(objects_dir_str).unwrap();
+        let (_, location) = odb.try_find_first_matching_oid_with_location(oid, &mut state).unwrap().unwrap();
+        let key = LocationKey::from_location(&location).unwrap();
+        let pack_id = full_oid_from_str(REF_DELTA_PACK).unwrap();
+        assert!(matches!(key, LocationKey::Packed { pack_id: id, .. } if id == pack_id));
+        assert!(key.to_location(oid, &odb, &mut state).is_ok());
+
+        // the header and fanout table are there, but only the first 2 oids:
+        let idx_path = objects_dir.join("pack").join(format!("pack-{}.idx", REF_DELTA_PACK));
+        let idx = std::fs::read(&idx_path).unwrap();
+        std::fs::write(&idx_path, &idx[..8 + 256 * 4 + 40]).unwrap();
+        let err = key.to_location(oid, &odb, &mut state).unwrap_err();
+        assert!(matches!(GitReaderError::from_io_error(&err), Some(GitReaderError::CorruptIdx { .. })), "{}", err);
+    }
+}
@@ -35,6 +35,53 @@ pub struct BlobObjStringLossy {
     pub s: String,
 }
 
+/// Don't keep the blob's data, only its size and whether or not
+/// it looks like a binary file. This uses the same heuristic
+/// as git: a blob is binary if there is a NUL byte in its
+/// first 8000 bytes.
+/// Useful for cheaply classifying blobs, eg: `cat-file --batch-check`
+pub struct BlobObjMeta {
+    pub len: usize,
+    pub is_binary: bool,
+}
+
+/// what kind of line endings a blob uses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NewlineStyle {
+    /// there are no newlines at all
+    None,
+    Lf,
+    Crlf,
+    /// some lines end in \r\n, others in just \n
+    Mixed,
+}
+
+/// Don't keep the blob's data, only some stats about its lines.
+/// Useful for counting lines of an entire repo cheaply.
+/// `lines` counts the last line even if it doesn't end
+/// in a newline, so "a\nb" has 2 lines, and an empty blob has 0.
+/// If the blob is binary (see `looks_binary`) then we stop
+/// counting lines at the first NUL byte.
+pub struct BlobObjLineStats {
+    pub len: usize,
+    pub lines: usize,
+    pub newline_style: NewlineStyle,
+    /// true if the blob is not empty and does not end in \n
+    pub missing_trailing_newline: bool,
+    pub is_binary: bool,
+}
+
+/// git only checks this many bytes when deciding
+/// if a blob is binary.
+pub const BINARY_CHECK_FIRST_N_BYTES: usize = 8000;
+
+/// returns true if there is a NUL byte in the first
+/// `BINARY_CHECK_FIRST_N_BYTES` of this data.
+pub fn looks_binary(raw: &[u8]) -> bool {
+    let check_to = raw.len().min(BINARY_CHECK_FIRST_N_BYTES);
+    raw[0..check_to].contains(&0)
+}
+
 impl Display for BlobObjectNone {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "blob")
@@ -59,6 +106,25 @@ impl Display for BlobObjStringOrError {
     }
 }
 
+impl Display for BlobObjMeta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.len)
+    }
+}
+
+impl Display for BlobObjLineStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_binary {
+            return write!(f, "{} bytes, binary", self.len);
+        }
+        write!(f, "{} bytes, {} lines, {:?}", self.len, self.lines, self.newline_style)?;
+        if self.missing_trailing_newline {
+            write!(f, ", no newline at end of file")?;
+        }
+        Ok(())
+    }
+}
+
 impl ParseBlob for BlobObjectNone {
     fn parse(_raw: &[u8]) -> io::Result<Self> where Self: Sized {
         Ok(BlobObjectNone {})
@@ -71,6 +137,52 @@ impl ParseBlob for BlobObjRaw {
     }
 }
 
+impl ParseBlob for BlobObjMeta {
+    fn parse(raw: &[u8]) -> io::Result<Self> where Self: Sized {
+        Ok(BlobObjMeta { len: raw.len(), is_binary: looks_binary(raw) })
+    }
+}
+
+impl ParseBlob for BlobObjLineStats {
+    fn parse(raw: &[u8]) -> io::Result<Self> where Self: Sized {
+        let is_binary = looks_binary(raw);
+        let count_to = if is_binary {
+            raw.iter().position(|&b| b == 0).unwrap_or(raw.len())
+        } else {
+            raw.len()
+        };
+        let mut lf = 0;
+        let mut crlf = 0;
+        for (i, b) in raw[0..count_to].iter().enumerate() {
+            if *b != b'\n' { continue; }
+            if i > 0 && raw[i - 1] == b'\r' {
+                crlf += 1;
+            } else {
+                lf += 1;
+            }
+        }
+        let newline_style = match (lf, crlf) {
+            (0, 0) => NewlineStyle::None,
+            (_, 0) => NewlineStyle::Lf,
+            (0, _) => NewlineStyle::Crlf,
+            _ => NewlineStyle::Mixed,
+        };
+        let counted = &raw[0..count_to];
+        let missing_trailing_newline = !raw.is_empty() && raw.last() != Some(&b'\n');
+        let mut lines = lf + crlf;
+        if !counted.is_empty() && counted.last() != Some(&b'\n') {
+            lines += 1;
+        }
+        Ok(BlobObjLineStats {
+            len: raw.len(),
+            lines,
+            newline_style,
+            missing_trailing_newline,
+            is_binary,
+        })
+    }
+}
+
 impl ParseBlob for BlobObjStringLossy {
     fn parse(raw: &[u8]) -> io::Result<Self> where Self: Sized {
         let cow_str = String::from_utf8_lossy(raw);
@@ -85,3 +197,34 @@ impl ParseBlob for BlobObjStringOrError {
         Ok(BlobObjStringOrError { s: raw_str.to_owned() })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blob_line_stats_works() {
+        let stats = BlobObjLineStats::parse(b"").unwrap();
+        assert_eq!(stats.lines, 0);
+        assert_eq!(stats.newline_style, NewlineStyle::None);
+        assert!(!stats.missing_trailing_newline);
+
+        let stats = BlobObjLineStats::parse(b"a\nb\n").unwrap();
+        assert_eq!(stats.lines, 2);
+        assert_eq!(stats.newline_style, NewlineStyle::Lf);
+        assert!(!stats.missing_trailing_newline);
+
+        let stats = BlobObjLineStats::parse(b"a\r\nb").unwrap();
+        assert_eq!(stats.lines, 2);
+        assert_eq!(stats.newline_style, NewlineStyle::Crlf);
+        assert!(stats.missing_trailing_newline);
+
+        let stats = BlobObjLineStats::parse(b"a\r\nb\nc\n").unwrap();
+        assert_eq!(stats.newline_style, NewlineStyle::Mixed);
+
+        let stats = BlobObjLineStats::parse(b"a\nb\0c\nd\n").unwrap();
+        assert!(stats.is_binary);
+        assert_eq!(stats.lines, 2);
+        assert_eq!(stats.len, 8);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{ioerre, object_id::{Oid, hex_u128_to_str, OidTruncated, OID_TRUNC_ZERO, hex_u128_trunc_to_str, trunc_oid_from_hex_bytes}, ioerr};
+use crate::{ioerre, object_id::{Oid, hex_u128_to_str, OidTruncated, OID_TRUNC_ZERO, hex_u128_trunc_to_str, trunc_oid_from_hex_bytes, hash_str_to_oid}, ioerr};
 use std::{fmt::Display, io};
 
 pub trait ParseCommit: Display {
@@ -11,6 +11,14 @@ pub trait ParseCommit: Display {
         let mut index = 0;
         Self::parse_inner(raw, &mut index)
     }
+
+    /// the oid of the commit's tree, if this variant keeps it.
+    /// Lets generic code (ie: something that walks commits and
+    /// then their trees) ask for the tree without knowing which
+    /// variant it was given. Variants that skip the tree return None.
+    fn tree_oid(&self) -> Option<Oid> {
+        None
+    }
 }
 
 /// The reason we use `parent_one`, `parent_two`
@@ -31,6 +39,32 @@ pub struct CommitFull {
     pub message: String,
 }
 
+/// how to turn the author, committer, and message bytes
+/// of a commit into strings.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Utf8Strategy {
+    /// replace invalid utf8 with U+FFFD. This is what
+    /// `CommitFull` does.
+    Lossy,
+    /// error if any of them are not valid utf8.
+    Strict,
+}
+
+/// Like `CommitFull`, but the author, committer, and message
+/// are kept as the bytes that are in the commit. Useful for
+/// repos that use a legacy (non utf8) `encoding`, where you
+/// want to decode the strings yourself.
+#[derive(Debug, Default)]
+pub struct CommitRawBytes {
+    pub tree: Oid,
+    pub parent_one: Oid,
+    pub parent_two: Oid,
+    pub extra_parents: Vec<Oid>,
+    pub author: Vec<u8>,
+    pub committer: Vec<u8>,
+    pub message: Vec<u8>,
+}
+
 /// Unlike `CommitFull` this will actually parse the commit message
 /// and commit summary seperately, where the CommitFull just includes
 /// the entire message as one string.
@@ -101,6 +135,35 @@ pub struct CommitOnlyTreeAndParents {
     pub extra_parents: Vec<Oid>,
 }
 
+/// Like `CommitOnlyTreeAndParents`, but it also reads the time of
+/// the committer line. Nothing is allocated for the author, committer,
+/// or message, so this is for tools that sort or stop by date (ie: blame)
+/// and also need the tree.
+#[derive(Debug, Default)]
+pub struct CommitTreeAndCommitterDate {
+    pub tree: Oid,
+    pub parent_one: Oid,
+    pub parent_two: Oid,
+    pub extra_parents: Vec<Oid>,
+    /// see `Signature::time`
+    pub committer_time: i64,
+    /// see `Signature::tz_offset_minutes`
+    pub committer_tz_offset_minutes: i32,
+}
+
+/// every header of the commit as it is, without assuming which headers
+/// there are or what order they are in. Unlike the other variants, this
+/// doesn't break if a commit has a header we don't know about.
+/// The value of a header that continues on the next lines (ie: `gpgsig`)
+/// has those lines joined with '\n', without their leading space.
+/// Get the ones you care about with `get` and `get_all`.
+#[derive(Debug, Default)]
+pub struct CommitHeaders {
+    /// (key, value) in the order they are in the commit.
+    pub headers: Vec<(String, String)>,
+    pub message: String,
+}
+
 pub struct CommitOnlyParentsAndMessage {
     pub parent_one: Oid,
     pub parent_two: Oid,
@@ -115,6 +178,33 @@ pub struct CommitOnlyParentsAndMessageOidTrunc {
     pub message: String,
 }
 
+/// adds a `parents()` method that yields all of the parents in order,
+/// so callers dont need to know about the `parent_one`, `parent_two`,
+/// `extra_parents` split. zero parents (no parent) are skipped.
+macro_rules! impl_parents {
+    ($oid_type:ty, $zero:expr, $($commit_type:ty),+) => {
+        $(
+            impl $commit_type {
+                pub fn parents(&self) -> impl Iterator<Item = $oid_type> + '_ {
+                    std::iter::once(self.parent_one)
+                        .chain(std::iter::once(self.parent_two))
+                        .filter(|p| *p != $zero)
+                        .chain(self.extra_parents.iter().copied())
+                }
+            }
+        )+
+    };
+}
+
+impl_parents!(Oid, 0,
+    CommitFull, CommitRawBytes, CommitFullMessageAndDescription, CommitFullOnlyMessage,
+    CommitOnlyMessageNoAuthorOrCommitter, CommitOnlyParents, CommitNoMessage,
+    CommitOnlyTreeAndParents, CommitTreeAndCommitterDate, CommitOnlyParentsAndMessage
+);
+impl_parents!(OidTruncated, OID_TRUNC_ZERO,
+    CommitOnlyParentsOidTrunc, CommitOnlyParentsAndMessageOidTrunc
+);
+
 impl Display for CommitFull {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let tree_id_str = hex_u128_to_str(self.tree);
@@ -135,6 +225,24 @@ impl Display for CommitFull {
     }
 }
 
+/// invalid utf8 is displayed lossily.
+impl Display for CommitRawBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tree_and_parents = CommitOnlyTreeAndParents {
+            tree: self.tree,
+            parent_one: self.parent_one,
+            parent_two: self.parent_two,
+            extra_parents: self.extra_parents.clone(),
+        };
+        write!(f, "{}author {}\ncommitter {}\n\n{}",
+            tree_and_parents,
+            String::from_utf8_lossy(&self.author),
+            String::from_utf8_lossy(&self.committer),
+            String::from_utf8_lossy(&self.message),
+        )
+    }
+}
+
 impl Display for CommitOnlyParents {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let parent_str = if self.parent_one == 0 {
@@ -291,6 +399,29 @@ impl Display for CommitOnlyTreeAndParents {
     }
 }
 
+impl Display for CommitTreeAndCommitterDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let tree_and_parents = CommitOnlyTreeAndParents {
+            tree: self.tree,
+            parent_one: self.parent_one,
+            parent_two: self.parent_two,
+            extra_parents: self.extra_parents.clone(),
+        };
+        let tz = self.committer_tz_offset_minutes;
+        let sign = if tz < 0 { '-' } else { '+' };
+        writeln!(f, "{}committer date {} {}{:02}{:02}", tree_and_parents, self.committer_time, sign, tz.abs() / 60, tz.abs() % 60)
+    }
+}
+
+impl Display for CommitHeaders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (key, value) in self.headers.iter() {
+            writeln!(f, "{} {}", key, value.replace('\n', "\n "))?;
+        }
+        write!(f, "\n{}", self.message)
+    }
+}
+
 impl ParseCommit for CommitFull {
     fn parse_inner(
         raw: &[u8],
@@ -302,17 +433,11 @@ impl ParseCommit for CommitFull {
         // and message
         let author = parse_author(raw, current_index, true)?;
         let committer = parse_committer(raw, current_index, true)?;
-        let rest_of_data = &raw[*current_index..];
+        let rest_of_data = raw.get(*current_index..)
+            .ok_or_else(|| ioerr!("Commit object ended before its message"))?;
         // the rest of the data should be the commit message.
-        // we dont want trailing newlines though, so we do this:
-        let mut last_index = rest_of_data.len() - 1;
-        let mut last_char = rest_of_data[last_index];
-        while last_char == b'\n' {
-            last_index -= 1;
-            last_char = *rest_of_data.get(last_index)
-                .ok_or_else(|| ioerr!("Failed to trim newlines from commit message. Does your commit message consist entirely of new lines?"))?;
-        }
-        let commit_message_raw = &rest_of_data[0..last_index + 1];
+        // we dont want trailing newlines though.
+        let commit_message_raw = trim_trailing_newlines(rest_of_data);
         let message = String::from_utf8_lossy(commit_message_raw);
 
         let obj = CommitFull {
@@ -326,6 +451,68 @@ impl ParseCommit for CommitFull {
         };
         Ok(obj)
     }
+
+    fn tree_oid(&self) -> Option<Oid> {
+        Some(self.tree)
+    }
+}
+
+impl CommitFull {
+    /// Like `parse`, but lets you choose what happens
+    /// when the author, committer, or message is not valid utf8.
+    pub fn parse_with_strategy(raw: &[u8], strategy: Utf8Strategy) -> io::Result<CommitFull> {
+        CommitRawBytes::parse(raw)?.into_commit_full(strategy)
+    }
+}
+
+impl CommitRawBytes {
+    pub fn into_commit_full(self, strategy: Utf8Strategy) -> io::Result<CommitFull> {
+        let to_string = |bytes: Vec<u8>, field: &str| -> io::Result<String> {
+            match strategy {
+                Utf8Strategy::Lossy => Ok(String::from_utf8_lossy(&bytes).into()),
+                Utf8Strategy::Strict => String::from_utf8(bytes)
+                    .map_err(|e| ioerr!("Commit {} is not valid utf8: {}", field, e)),
+            }
+        };
+        Ok(CommitFull {
+            tree: self.tree,
+            parent_one: self.parent_one,
+            parent_two: self.parent_two,
+            extra_parents: self.extra_parents,
+            author: to_string(self.author, "author")?,
+            committer: to_string(self.committer, "committer")?,
+            message: to_string(self.message, "message")?,
+        })
+    }
+}
+
+impl ParseCommit for CommitRawBytes {
+    fn parse_inner(
+        raw: &[u8],
+        current_index: &mut usize
+    ) -> io::Result<Self> where Self: Sized {
+        let only_tree_and_parents = CommitOnlyTreeAndParents::parse_inner(raw, current_index)?;
+        let author = parse_author_bytes(raw, current_index)?.to_vec();
+        let committer = parse_committer_bytes(raw, current_index)?.to_vec();
+        let rest_of_data = raw.get(*current_index..)
+            .ok_or_else(|| ioerr!("Commit object ended before its message"))?;
+        let message = trim_trailing_newlines(rest_of_data).to_vec();
+
+        let obj = CommitRawBytes {
+            tree: only_tree_and_parents.tree,
+            parent_one: only_tree_and_parents.parent_one,
+            parent_two: only_tree_and_parents.parent_two,
+            extra_parents: only_tree_and_parents.extra_parents,
+            author,
+            committer,
+            message,
+        };
+        Ok(obj)
+    }
+
+    fn tree_oid(&self) -> Option<Oid> {
+        Some(self.tree)
+    }
 }
 
 impl ParseCommit for CommitFullOnlyMessage {
@@ -339,7 +526,8 @@ impl ParseCommit for CommitFullOnlyMessage {
         // and message
         let author = parse_author(raw, current_index, true)?;
         let committer = parse_committer(raw, current_index, true)?;
-        let rest_of_data = &raw[*current_index..];
+        let rest_of_data = raw.get(*current_index..)
+            .ok_or_else(|| ioerr!("Commit object ended before its message"))?;
         // for the only message mode, we wish to only allocate for the
         // first part of the commit message, so we read up to
         // the first newline we find. if we don't find the newline, then
@@ -363,6 +551,10 @@ impl ParseCommit for CommitFullOnlyMessage {
         };
         Ok(obj)
     }
+
+    fn tree_oid(&self) -> Option<Oid> {
+        Some(self.tree)
+    }
 }
 
 impl ParseCommit for CommitOnlyMessageNoAuthorOrCommitter {
@@ -376,7 +568,8 @@ impl ParseCommit for CommitOnlyMessageNoAuthorOrCommitter {
         // and message
         let _ = parse_author(raw, current_index, false)?;
         let _ = parse_committer(raw, current_index, false)?;
-        let rest_of_data = &raw[*current_index..];
+        let rest_of_data = raw.get(*current_index..)
+            .ok_or_else(|| ioerr!("Commit object ended before its message"))?;
         // for the only message mode, we wish to only allocate for the
         // first part of the commit message, so we read up to
         // the first newline we find. if we don't find the newline, then
@@ -398,6 +591,10 @@ impl ParseCommit for CommitOnlyMessageNoAuthorOrCommitter {
         };
         Ok(obj)
     }
+
+    fn tree_oid(&self) -> Option<Oid> {
+        Some(self.tree)
+    }
 }
 
 impl ParseCommit for CommitOnlyParentsAndMessage {
@@ -408,7 +605,8 @@ impl ParseCommit for CommitOnlyParentsAndMessage {
         let only_parents = CommitOnlyParents::parse_inner(raw, current_index)?;
         let _ = parse_author(raw, current_index, false)?;
         let _ = parse_committer(raw, current_index, false)?;
-        let rest_of_data = &raw[*current_index..];
+        let rest_of_data = raw.get(*current_index..)
+            .ok_or_else(|| ioerr!("Commit object ended before its message"))?;
         // for the only message mode, we wish to only allocate for the
         // first part of the commit message, so we read up to
         // the first newline we find. if we don't find the newline, then
@@ -439,7 +637,8 @@ impl ParseCommit for CommitOnlyParentsAndMessageOidTrunc {
         let only_parents = CommitOnlyParentsOidTrunc::parse_inner(raw, current_index)?;
         let _ = parse_author(raw, current_index, false)?;
         let _ = parse_committer(raw, current_index, false)?;
-        let rest_of_data = &raw[*current_index..];
+        let rest_of_data = raw.get(*current_index..)
+            .ok_or_else(|| ioerr!("Commit object ended before its message"))?;
         // for the only message mode, we wish to only allocate for the
         // first part of the commit message, so we read up to
         // the first newline we find. if we don't find the newline, then
@@ -501,6 +700,10 @@ impl ParseCommit for CommitFullMessageAndDescription {
         };
         Ok(new_obj)
     }
+
+    fn tree_oid(&self) -> Option<Oid> {
+        Some(self.tree)
+    }
 }
 
 impl ParseCommit for CommitOnlyTreeAndParents {
@@ -541,6 +744,90 @@ impl ParseCommit for CommitOnlyTreeAndParents {
 
         Ok(out)
     }
+
+    fn tree_oid(&self) -> Option<Oid> {
+        Some(self.tree)
+    }
+}
+
+impl ParseCommit for CommitTreeAndCommitterDate {
+    fn parse_inner(
+        raw: &[u8],
+        current_index: &mut usize
+    ) -> io::Result<Self> where Self: Sized {
+        let only_tree_and_parents = CommitOnlyTreeAndParents::parse_inner(raw, current_index)?;
+        let _ = parse_author_bytes(raw, current_index)?;
+        // this also skips any mergetag or gpgsig after the committer,
+        // so current_index ends up at the start of the message:
+        let committer_line = parse_committer_bytes(raw, current_index)?;
+        let committer = Signature::parse(committer_line)?;
+        Ok(CommitTreeAndCommitterDate {
+            tree: only_tree_and_parents.tree,
+            parent_one: only_tree_and_parents.parent_one,
+            parent_two: only_tree_and_parents.parent_two,
+            extra_parents: only_tree_and_parents.extra_parents,
+            committer_time: committer.time,
+            committer_tz_offset_minutes: committer.tz_offset_minutes,
+        })
+    }
+
+    fn tree_oid(&self) -> Option<Oid> {
+        Some(self.tree)
+    }
+}
+
+impl ParseCommit for CommitHeaders {
+    fn parse_inner(
+        raw: &[u8],
+        current_index: &mut usize
+    ) -> io::Result<Self> where Self: Sized {
+        let mut out = CommitHeaders::default();
+        loop {
+            let rest_of_data = raw.get(*current_index..).unwrap_or_default();
+            let newline_index = rest_of_data.iter().position(|&b| b == b'\n')
+                .ok_or_else(|| ioerr!("Commit object ended before the blank line after its headers"))?;
+            let line = &rest_of_data[0..newline_index];
+            *current_index += newline_index + 1;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(continued) = line.strip_prefix(b" ") {
+                let (_, value) = out.headers.last_mut()
+                    .ok_or_else(|| ioerr!("Commit headers start with a continuation line"))?;
+                value.push('\n');
+                value.push_str(&String::from_utf8_lossy(continued));
+                continue;
+            }
+            let (key, value) = match line.iter().position(|&b| b == b' ') {
+                Some(space_index) => (&line[0..space_index], &line[space_index + 1..]),
+                None => (line, &b""[..]),
+            };
+            out.headers.push((String::from_utf8_lossy(key).into(), String::from_utf8_lossy(value).into()));
+        }
+        let rest_of_data = raw.get(*current_index..).unwrap_or_default();
+        out.message = String::from_utf8_lossy(trim_trailing_newlines(rest_of_data)).into();
+        Ok(out)
+    }
+
+    fn tree_oid(&self) -> Option<Oid> {
+        self.get("tree").and_then(|tree| hash_str_to_oid(tree).ok())
+    }
+}
+
+impl CommitHeaders {
+    /// the value of the first header named `key`, ie: "author".
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// the values of every header named `key`, in order, ie: "parent".
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers.iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
 }
 
 impl ParseCommit for CommitOnlyParents {
@@ -629,6 +916,21 @@ pub fn parse_author(
     curr_index: &mut usize,
     should_allocate: bool,
 ) -> io::Result<String> {
+    let author_line = parse_author_bytes(raw, curr_index)?;
+    let author_str = if should_allocate {
+        String::from_utf8_lossy(author_line).into()
+    } else {
+        String::with_capacity(0)
+    };
+    Ok(author_str)
+}
+
+/// Like `parse_author`, but returns the raw bytes
+/// of the author line without any utf8 conversion.
+pub fn parse_author_bytes<'a>(
+    raw: &'a [u8],
+    curr_index: &mut usize,
+) -> io::Result<&'a [u8]> {
     let start_index = *curr_index;
     let desired_range = start_index..(start_index + 7);
     let line = raw.get(desired_range)
@@ -643,13 +945,8 @@ pub fn parse_author(
         .ok_or_else(|| ioerr!("Failed to find newline when parsing author line"))?;
 
     let author_line = &rest_of_data[0..newline_index];
-    let author_str = if should_allocate {
-        String::from_utf8_lossy(author_line).into()
-    } else {
-        String::with_capacity(0)
-    };
     *curr_index = start_index + 7 + newline_index + 1;
-    Ok(author_str)
+    Ok(author_line)
 }
 
 /// this function is a misnomer. its purpose is to SKIP the mergetag string
@@ -680,7 +977,8 @@ pub fn parse_mergetag(
 
     // for now we dont do any merge tag parsing, we just want to
     // advance the current index to the end of the merge tag.
-    let current_data = &raw[start_index..];
+    let current_data = raw.get(start_index..)
+        .ok_or_else(|| ioerr!("Commit object ended before its extra headers"))?;
     let mut skip_chars = 0;
     let mut last_char_was_newline = false;
     for byte in current_data {
@@ -730,6 +1028,21 @@ pub fn parse_committer(
     curr_index: &mut usize,
     should_allocate: bool,
 ) -> io::Result<String> {
+    let committer_line = parse_committer_bytes(raw, curr_index)?;
+    let committer_str = if should_allocate {
+        String::from_utf8_lossy(committer_line).into()
+    } else {
+        String::with_capacity(0)
+    };
+    Ok(committer_str)
+}
+
+/// Like `parse_committer`, but returns the raw bytes
+/// of the committer line without any utf8 conversion.
+pub fn parse_committer_bytes<'a>(
+    raw: &'a [u8],
+    curr_index: &mut usize,
+) -> io::Result<&'a [u8]> {
     let start_index = *curr_index;
     let desired_range = start_index..(start_index + 10);
     let line = raw.get(desired_range)
@@ -744,16 +1057,13 @@ pub fn parse_committer(
         .ok_or_else(|| ioerr!("Failed to find newline when parsing committer line"))?;
 
     let committer_line = &rest_of_data[0..newline_index];
-    let committer_str = if should_allocate {
-        String::from_utf8_lossy(committer_line).into()
-    } else {
-        String::with_capacity(0)
-    };
 
     // at the end of the committer line, there should be 2 newlines.
     // we verify that here. If there is not 2 newlines, then
     // this should be a mergetag object
-    if rest_of_data[newline_index + 1] != b'\n' {
+    let next_byte = *rest_of_data.get(newline_index + 1)
+        .ok_or_else(|| ioerr!("Commit object ended after the committer line, expected a blank line before the message"))?;
+    if next_byte != b'\n' {
         // we add 1 here to skip the one newline that we DID find above,
         // so now the current index should point to the beginning of the merge
         // tag object.
@@ -766,7 +1076,97 @@ pub fn parse_committer(
         // if we did find 2 trailing newlines, we add 2
         *curr_index = start_index + 10 + newline_index + 2;
     }
-    Ok(committer_str)
+    Ok(committer_line)
+}
+
+/// an author or committer line, split into its parts, ie:
+/// `Jane Doe <jane@example.com> 1618000000 -0500`
+/// The time is kept as the raw unix seconds and timezone offset
+/// so that we don't need a datetime dependency. Pass them to whatever
+/// datetime type you use, or turn on the `chrono` feature
+/// to get them as a chrono datetime (see `timestamp_datetime`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature<'a> {
+    pub name: &'a [u8],
+    pub email: &'a [u8],
+    /// seconds since the unix epoch
+    pub time: i64,
+    /// minutes east of UTC, ie: -0500 is -300 and +0530 is 330
+    pub tz_offset_minutes: i32,
+}
+
+impl<'a> Signature<'a> {
+    /// parse the part of an author/committer line after the "author "
+    /// or "committer ", ie: what `parse_author_bytes` returns.
+    pub fn parse(line: &'a [u8]) -> io::Result<Signature<'a>> {
+        let email_start = line.iter().position(|&b| b == b'<')
+            .ok_or_else(|| ioerr!("Signature is missing the '<' before the email"))?;
+        let email_end = email_start + line[email_start..].iter().position(|&b| b == b'>')
+            .ok_or_else(|| ioerr!("Signature is missing the '>' after the email"))?;
+        let name = &line[0..email_start];
+        let name = name.strip_suffix(b" ").unwrap_or(name);
+        let email = &line[email_start + 1..email_end];
+
+        let rest = std::str::from_utf8(&line[email_end + 1..])
+            .map_err(|_| ioerr!("Signature date is not valid utf8"))?;
+        let mut parts = rest.split_ascii_whitespace();
+        let time = parts.next()
+            .ok_or_else(|| ioerr!("Signature is missing its timestamp"))?;
+        let time: i64 = time.parse()
+            .map_err(|_| ioerr!("Signature timestamp '{}' is not a number", time))?;
+        let tz = parts.next()
+            .ok_or_else(|| ioerr!("Signature is missing its timezone"))?;
+        let tz_offset_minutes = parse_tz_offset(tz)?;
+        Ok(Signature { name, email, time, tz_offset_minutes })
+    }
+
+    /// the timezone offset in seconds east of UTC
+    pub fn tz_offset_seconds(&self) -> i32 {
+        self.tz_offset_minutes * 60
+    }
+
+    /// the time in the signature's own timezone. Errors if
+    /// the offset is a day or more, or the time is too far
+    /// away for chrono to represent.
+    #[cfg(feature = "chrono")]
+    pub fn timestamp_datetime(&self) -> io::Result<chrono::DateTime<chrono::FixedOffset>> {
+        use chrono::TimeZone;
+        let offset = chrono::FixedOffset::east_opt(self.tz_offset_seconds())
+            .ok_or_else(|| ioerr!("Signature timezone offset of {} minutes is out of range", self.tz_offset_minutes))?;
+        offset.timestamp_opt(self.time, 0).single()
+            .ok_or_else(|| ioerr!("Signature timestamp {} is out of range", self.time))
+    }
+}
+
+/// parse a git timezone like `+0000`, `-0500`, or `+0530`
+/// into minutes east of UTC.
+pub fn parse_tz_offset(tz: &str) -> io::Result<i32> {
+    let bytes = tz.as_bytes();
+    if bytes.len() != 5 || !bytes[1..].iter().all(|b| b.is_ascii_digit()) {
+        return ioerre!("Invalid timezone '{}', expected something like +0000", tz);
+    }
+    let sign = match bytes[0] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return ioerre!("Invalid timezone '{}', expected something like +0000", tz),
+    };
+    let digit = |i: usize| (bytes[i] - b'0') as i32;
+    let hours = digit(1) * 10 + digit(2);
+    let minutes = digit(3) * 10 + digit(4);
+    if minutes >= 60 {
+        return ioerre!("Invalid timezone '{}', it has more than 59 minutes", tz);
+    }
+    Ok(sign * (hours * 60 + minutes))
+}
+
+/// the message without any newlines at the end of it. A message
+/// can be empty (`git commit --allow-empty-message`) or be
+/// only newlines, in which case this returns an empty slice.
+pub fn trim_trailing_newlines(message: &[u8]) -> &[u8] {
+    let keep = message.iter().rposition(|&b| b != b'\n')
+        .map(|last_index| last_index + 1)
+        .unwrap_or(0);
+    &message[0..keep]
 }
 
 pub fn parse_tree(
@@ -776,6 +1176,9 @@ pub fn parse_tree(
     if !should_allocate {
         // this just assumes a tree is here, and skips
         // to index 46 (past the tree line)
+        if raw.len() < 46 {
+            return ioerre!("First line not long enough to contain a tree id");
+        }
         return Ok((0, 46));
     }
     // a tree line should be 5 bytes for the string "tree "
@@ -821,7 +1224,7 @@ pub fn parse_parent(raw: &[u8], curr_index: &mut usize) -> io::Result<Option<Oid
     
     // otherwise, we expect this to be a parent line
     if &line[0..7] != b"parent " {
-        return ioerre!("Expected first line of commit object to be 'tree '");
+        return ioerre!("Expected a 'parent ' or 'author ' line after the tree line");
     }
     // now, lets get the rest of the line, which should just be the hash
     // and a new line, so 40 + 1 chars:
@@ -904,7 +1307,7 @@ pub fn parse_parent_oid_trunc(
     
     // otherwise, we expect this to be a parent line
     if &line[0..7] != b"parent " {
-        return ioerre!("Expected first line of commit object to be 'tree '");
+        return ioerre!("Expected a 'parent ' or 'author ' line after the tree line");
     }
     // now, lets get the rest of the line, which should just be the hash
     // and a new line, so 40 + 1 chars:
@@ -937,6 +1340,99 @@ mod tests {
         assert_eq!(size, 72);
     }
 
+    #[test]
+    fn utf8_strategies_work() {
+        let commit = b"tree 0000000000000000000000000000000100000000\nauthor me\xff <me> 1 -0000\ncommitter me <me> 1 -0000\n\nhello \xe9\n";
+        let raw = CommitRawBytes::parse(commit).unwrap();
+        assert_eq!(raw.author, b"me\xff <me> 1 -0000");
+        assert_eq!(raw.message, b"hello \xe9");
+
+        let lossy = CommitFull::parse_with_strategy(commit, Utf8Strategy::Lossy).unwrap();
+        assert_eq!(lossy.message, "hello \u{FFFD}");
+        assert_eq!(lossy.author, CommitFull::parse(commit).unwrap().author);
+        assert!(CommitFull::parse_with_strategy(commit, Utf8Strategy::Strict).is_err());
+
+        let valid = b"tree 0000000000000000000000000000000100000000\nauthor me <me> 1 -0000\ncommitter me <me> 1 -0000\n\nhello\n";
+        let strict = CommitFull::parse_with_strategy(valid, Utf8Strategy::Strict).unwrap();
+        assert_eq!(strict.message, "hello");
+    }
+
+    #[test]
+    fn parents_iter_works() {
+        let mut commit = CommitOnlyParents::default();
+        assert_eq!(commit.parents().next(), None);
+        commit.parent_one = 1;
+        assert_eq!(commit.parents().collect::<Vec<_>>(), [1]);
+        commit.parent_two = 2;
+        commit.extra_parents = vec![3, 4];
+        assert_eq!(commit.parents().collect::<Vec<_>>(), [1, 2, 3, 4]);
+
+        let mut trunc = CommitOnlyParentsOidTrunc::default();
+        assert_eq!(trunc.parents().count(), 0);
+        trunc.parent_one = [1; 16];
+        assert_eq!(trunc.parents().collect::<Vec<_>>(), [[1; 16]]);
+    }
+
+    /// run every commit parser on `raw`, returning which ones succeeded.
+    /// the point is that none of them panic.
+    fn parse_all_commit_kinds(raw: &[u8]) -> Vec<(&'static str, bool)> {
+        vec![
+            ("CommitFull", CommitFull::parse(raw).is_ok()),
+            ("CommitRawBytes", CommitRawBytes::parse(raw).is_ok()),
+            ("CommitFullOnlyMessage", CommitFullOnlyMessage::parse(raw).is_ok()),
+            ("CommitOnlyMessageNoAuthorOrCommitter", CommitOnlyMessageNoAuthorOrCommitter::parse(raw).is_ok()),
+            ("CommitOnlyParentsAndMessage", CommitOnlyParentsAndMessage::parse(raw).is_ok()),
+            ("CommitOnlyParentsAndMessageOidTrunc", CommitOnlyParentsAndMessageOidTrunc::parse(raw).is_ok()),
+            ("CommitFullMessageAndDescription", CommitFullMessageAndDescription::parse(raw).is_ok()),
+            ("CommitOnlyTreeAndParents", CommitOnlyTreeAndParents::parse(raw).is_ok()),
+            ("CommitTreeAndCommitterDate", CommitTreeAndCommitterDate::parse(raw).is_ok()),
+            ("CommitHeaders", CommitHeaders::parse(raw).is_ok()),
+            ("CommitOnlyParents", CommitOnlyParents::parse(raw).is_ok()),
+            ("CommitOnlyParentsOidTrunc", CommitOnlyParentsOidTrunc::parse(raw).is_ok()),
+        ]
+    }
+
+    #[test]
+    fn truncated_commits_error_instead_of_panicking() {
+        let tree = "a".repeat(40);
+        let parent_one = "b".repeat(40);
+        let parent_two = "c".repeat(40);
+        let headers = format!(
+            "tree {}\nparent {}\nparent {}\nauthor a <a> 1 +0000\ncommitter c <c> 2 +0000\n\n",
+            tree, parent_one, parent_two
+        );
+        let raw = format!("{}message\n\ndescription\n", headers);
+        let raw = raw.as_bytes();
+        for (name, ok) in parse_all_commit_kinds(raw) {
+            assert!(ok, "{} failed to parse the whole commit", name);
+        }
+
+        let parents_end = 46 + 48 * 2;
+        for cut in 0..raw.len() {
+            let results = parse_all_commit_kinds(&raw[0..cut]);
+            for (name, ok) in results {
+                // the parents only parsers stop at the author line,
+                // but everything else needs all of the headers:
+                let needs_headers = !matches!(name, "CommitOnlyTreeAndParents" | "CommitOnlyParents" | "CommitOnlyParentsOidTrunc");
+                let needed = if needs_headers { headers.len() } else { parents_end + 7 };
+                if cut < needed {
+                    assert!(!ok, "{} parsed a commit cut off at {} bytes", name, cut);
+                }
+            }
+        }
+
+        // one with extra headers should not panic either:
+        let with_sig = format!(
+            "tree {}\nparent {}\nauthor a <a> 1 +0000\ncommitter c <c> 2 +0000\ngpgsig -----BEGIN-----\n abc\n -----END-----\n\nmessage\n",
+            tree, parent_one
+        );
+        let with_sig = with_sig.as_bytes();
+        assert!(CommitFull::parse(with_sig).is_ok());
+        for cut in 0..with_sig.len() {
+            parse_all_commit_kinds(&with_sig[0..cut]);
+        }
+    }
+
     #[test]
     fn parse_tree_line_works() {
         // our OIDs only take first 32 hex chars
@@ -984,6 +1480,54 @@ mod tests {
         // assert_eq!(obj.description, "Here is the description of this commit.");
     }
 
+    #[test]
+    fn committer_date_skips_mergetags() {
+        let mergetag = include_bytes!("../../../../test_fixtures/mergetag.test");
+        let mut index = 0;
+        let obj = CommitTreeAndCommitterDate::parse_inner(mergetag, &mut index).unwrap();
+        assert_eq!(obj.parents().collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(obj.committer_time, 1613332394);
+        assert_eq!(obj.committer_tz_offset_minutes, -480);
+        // we should be past the mergetag, at the start of the message:
+        assert!(mergetag[index..].starts_with(b"This is a merge tag commit message"));
+
+        let raw = b"tree 0000000000000000000000000000000100000000\nauthor a <a> 5 +0000\ncommitter c <c> 1618000000 +0530\n\nmsg\n";
+        let obj = CommitTreeAndCommitterDate::parse(raw).unwrap();
+        assert_eq!(obj.tree, 1);
+        assert_eq!(obj.committer_time, 1618000000);
+        assert_eq!(obj.committer_tz_offset_minutes, 330);
+        assert!(obj.to_string().ends_with("committer date 1618000000 +0530\n"));
+        assert!(CommitTreeAndCommitterDate::parse(b"tree 0000000000000000000000000000000100000000\nauthor a <a> 5 +0000\ncommitter c <c> soon +0000\n\n").is_err());
+    }
+
+    #[test]
+    fn commit_headers_parsing_works() {
+        let gpgsig = include_bytes!("../../../../test_fixtures/gpgsig.test");
+        let obj = CommitHeaders::parse(gpgsig).unwrap();
+        let keys: Vec<&str> = obj.headers.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["tree", "parent", "author", "committer", "gpgsig"]);
+        assert_eq!(obj.get("committer"), Some("Person A1 <a1@person.org> 1625572310 +0100"));
+        let sig = obj.get("gpgsig").unwrap();
+        assert!(sig.starts_with("-----BEGIN PGP SIGNATURE-----\n\naQJg"));
+        assert!(sig.ends_with("\n-----END PGP SIGNATURE-----"));
+        assert_eq!(obj.message, CommitFull::parse(gpgsig).unwrap().message);
+        assert_eq!(obj.tree_oid(), Some(0));
+        // displaying it gives back the commit:
+        assert_eq!(obj.to_string(), std::str::from_utf8(gpgsig).unwrap().trim_end());
+
+        // headers we dont know about, in an order we dont expect:
+        let raw = b"tree 0000000000000000000000000000000100000000\nfuture-header some\n value\nparent 0000000000000000000000000000000200000000\nparent 0000000000000000000000000000000300000000\nencoding ISO-8859-1\nflag\n\nmsg\n";
+        assert!(CommitFull::parse(raw).is_err());
+        let obj = CommitHeaders::parse(raw).unwrap();
+        assert_eq!(obj.get("future-header"), Some("some\nvalue"));
+        assert_eq!(obj.get_all("parent").count(), 2);
+        assert_eq!(obj.get("encoding"), Some("ISO-8859-1"));
+        assert_eq!(obj.get("flag"), Some(""));
+        assert_eq!(obj.get("author"), None);
+        assert_eq!(obj.message, "msg");
+        assert!(CommitHeaders::parse(b" starts with a continuation\n\n").is_err());
+    }
+
     #[test]
     fn can_parse_mergetags_that_are_gpgsig() {
         let mergetag = include_bytes!("../../../../test_fixtures/gpgsig.test");
@@ -993,4 +1537,80 @@ mod tests {
         // TODO: description shouldnt have leading newline...
         // assert_eq!(obj.description, "This is the description...");
     }
+
+    #[test]
+    fn empty_commit_messages_work() {
+        let header = b"tree 0000000000000000000000000000000100000000\nauthor me <me> 1 -0000\ncommitter me <me> 1 -0000\n\n";
+        let obj = CommitFull::parse(header).unwrap();
+        assert_eq!(obj.message, "");
+
+        let mut all_newlines = header.to_vec();
+        all_newlines.extend_from_slice(b"\n\n\n");
+        let obj = CommitFull::parse(&all_newlines).unwrap();
+        assert_eq!(obj.message, "");
+
+        let mut normal = header.to_vec();
+        normal.extend_from_slice(b"hello\n\nworld\n\n");
+        let obj = CommitFull::parse(&normal).unwrap();
+        assert_eq!(obj.message, "hello\n\nworld");
+    }
+
+    #[test]
+    fn signature_parsing_works() {
+        let sig = Signature::parse(b"Jane Doe <jane@example.com> 1618000000 -0500").unwrap();
+        assert_eq!(sig.name, b"Jane Doe");
+        assert_eq!(sig.email, b"jane@example.com");
+        assert_eq!(sig.time, 1618000000);
+        assert_eq!(sig.tz_offset_minutes, -300);
+        assert_eq!(sig.tz_offset_seconds(), -18000);
+
+        let sig = Signature::parse(b"x <> 0 +0000").unwrap();
+        assert_eq!(sig.email, b"");
+        assert_eq!(sig.tz_offset_minutes, 0);
+        assert_eq!(Signature::parse(b"a <a> 1 +0530").unwrap().tz_offset_minutes, 330);
+        assert_eq!(parse_tz_offset("-0930").unwrap(), -570);
+
+        assert!(Signature::parse(b"no email 1 +0000").is_err());
+        assert!(Signature::parse(b"a <a> notanumber +0000").is_err());
+        assert!(Signature::parse(b"a <a> 1").is_err());
+        assert!(parse_tz_offset("0500").is_err());
+        assert!(parse_tz_offset("+05").is_err());
+        assert!(parse_tz_offset("+0575").is_err());
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn signature_datetime_works() {
+        let datetime = |line: &[u8]| Signature::parse(line).unwrap().timestamp_datetime().unwrap().to_rfc3339();
+        assert_eq!(datetime(b"a <a> 0 +0000"), "1970-01-01T00:00:00+00:00");
+        assert_eq!(datetime(b"a <a> 1618000000 -0500"), "2021-04-09T15:26:40-05:00");
+        assert_eq!(datetime(b"a <a> 1618000000 +0530"), "2021-04-10T01:56:40+05:30");
+        assert_eq!(datetime(b"a <a> -1 +0000"), "1969-12-31T23:59:59+00:00");
+
+        let sig = Signature::parse(b"a <a> 1618000000 -0500").unwrap();
+        assert_eq!(sig.timestamp_datetime().unwrap().timestamp(), sig.time);
+        assert!(Signature::parse(b"a <a> 0 +2400").unwrap().timestamp_datetime().is_err());
+        assert!(Signature::parse(b"a <a> 9223372036854775807 +0000").unwrap().timestamp_datetime().is_err());
+    }
+
+    #[test]
+    fn tree_oid_works_for_every_variant() {
+        let raw = &crate::test_helpers::CommitFixture::new().to_bytes()[..];
+        let tree = 0x4b825dc642cb6eb9a060e54bf8d69288;
+        fn tree_of<C: ParseCommit>(raw: &[u8]) -> Option<Oid> {
+            C::parse(raw).unwrap().tree_oid()
+        }
+        assert_eq!(tree_of::<CommitFull>(raw), Some(tree));
+        assert_eq!(tree_of::<CommitRawBytes>(raw), Some(tree));
+        assert_eq!(tree_of::<CommitFullOnlyMessage>(raw), Some(tree));
+        assert_eq!(tree_of::<CommitOnlyMessageNoAuthorOrCommitter>(raw), Some(tree));
+        assert_eq!(tree_of::<CommitFullMessageAndDescription>(raw), Some(tree));
+        assert_eq!(tree_of::<CommitOnlyTreeAndParents>(raw), Some(tree));
+        assert_eq!(tree_of::<CommitTreeAndCommitterDate>(raw), Some(tree));
+        assert_eq!(tree_of::<CommitHeaders>(raw), Some(tree));
+        assert_eq!(tree_of::<CommitOnlyParents>(raw), None);
+        assert_eq!(tree_of::<CommitOnlyParentsOidTrunc>(raw), None);
+        assert_eq!(tree_of::<CommitOnlyParentsAndMessage>(raw), None);
+        assert_eq!(tree_of::<CommitOnlyParentsAndMessageOidTrunc>(raw), None);
+    }
 }
@@ -6,9 +6,11 @@ use super::{UnparsedObject, UnparsedObjectType};
 pub mod commit_object_parsing;
 pub mod tree_object_parsing;
 pub mod blob_object_parsing;
+pub mod tag_object_parsing;
 
 use tree_object_parsing::ParseTree;
 use blob_object_parsing::ParseBlob;
+pub use tag_object_parsing::TagObject;
 
 /// A trait thats used to define how you want
 /// your objects parsed. this lets you avoid
@@ -89,12 +91,6 @@ impl ParseObject for ParseBareMinimal {
     type Tree = tree_object_parsing::TreeObject;
 }
 
-/// TODO: care about tags?
-#[derive(Debug)]
-pub struct TagObject {
-
-}
-
 #[derive(Debug)]
 pub enum ParsedObject<T: ParseObject> {
     Commit(T::Commit),
@@ -109,7 +105,7 @@ impl<T: ParseObject> Display for ParsedObject<T> {
             ParsedObject::Commit(c) => write!(f, "{}", c),
             ParsedObject::Tree(t) => write!(f, "{}", t),
             ParsedObject::Blob(b) => write!(f, "{}", b),
-            ParsedObject::Tag(_) => write!(f, "tags not impl yet"),
+            ParsedObject::Tag(t) => write!(f, "{}", t),
         }
     }
 }
@@ -132,7 +128,8 @@ impl<T: ParseObject> TryFrom<UnparsedObject> for ParsedObject<T> {
                 ParsedObject::Blob(blob_obj)
             }
             UnparsedObjectType::Tag => {
-                ParsedObject::Tag(TagObject {})
+                let tag_obj = TagObject::parse(&unparsed.payload)?;
+                ParsedObject::Tag(tag_obj)
             }
         };
         Ok(obj)
@@ -0,0 +1,95 @@
+use crate::{ioerr, ioerre, object_id::{Oid, hash_str_to_oid, hex_u128_to_str}};
+use std::{io, fmt::Display, str::FromStr};
+use super::super::UnparsedObjectType;
+
+/// An annotated tag object. looks like:
+/// ```text
+/// object <40 hex chars>
+/// type <commit|tree|blob|tag>
+/// tag <name>
+/// tagger <name> <email> <time> <tz>
+///
+/// <message>
+/// ```
+/// the tagger line is optional, very old tags don't have it.
+#[derive(Debug, Default)]
+pub struct TagObject {
+    /// the object this tag points to
+    pub object: Oid,
+    /// the type of `object`. if this is a tag, then this
+    /// tag points to another tag.
+    pub object_type: Option<UnparsedObjectType>,
+    pub tag: String,
+    pub tagger: String,
+    pub message: String,
+}
+
+impl TagObject {
+    pub fn parse(raw: &[u8]) -> io::Result<TagObject> {
+        let mut out = TagObject::default();
+        let mut index = 0;
+        // read header lines until we reach the empty line
+        // that separates the headers from the message:
+        while index < raw.len() {
+            let line_end = raw[index..].iter().position(|&b| b == b'\n')
+                .map(|i| index + i)
+                .unwrap_or(raw.len());
+            let line = &raw[index..line_end];
+            index = line_end + 1;
+            if line.is_empty() {
+                break;
+            }
+            let line = std::str::from_utf8(line)
+                .map_err(|e| ioerr!("Failed to parse tag header line as utf8: {}", e))?;
+            let (key, value) = match line.split_once(' ') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            match key {
+                "object" => out.object = hash_str_to_oid(value)?,
+                "type" => out.object_type = Some(UnparsedObjectType::from_str(value)?),
+                "tag" => out.tag = value.to_string(),
+                "tagger" => out.tagger = value.to_string(),
+                // theres other possible headers, ie: gpgsig
+                // that we dont care about
+                _ => {}
+            }
+        }
+        if out.object_type.is_none() {
+            return ioerre!("Failed to parse tag: missing object type");
+        }
+        if index < raw.len() {
+            out.message = String::from_utf8_lossy(&raw[index..]).to_string();
+        }
+        Ok(out)
+    }
+}
+
+impl Display for TagObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let type_str = self.object_type.as_ref().map(|t| t.as_str()).unwrap_or("");
+        write!(f, "object {}\ntype {}\ntag {}\ntagger {}\n\n{}", hex_u128_to_str(self.object), type_str, self.tag, self.tagger, self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_parse_works() {
+        let raw = b"object 0000000000000000000000000000000f00000000\ntype commit\ntag v1.0\ntagger me <me@me.com> 1623986985 -0500\n\nrelease!\n";
+        let tag = TagObject::parse(raw).unwrap();
+        assert_eq!(tag.object, 15);
+        assert_eq!(tag.object_type, Some(UnparsedObjectType::Commit));
+        assert_eq!(tag.tag, "v1.0");
+        assert_eq!(tag.tagger, "me <me@me.com> 1623986985 -0500");
+        assert_eq!(tag.message, "release!\n");
+
+        // no tagger:
+        let raw = b"object 0000000000000000000000000000000f00000000\ntype tag\ntag old\n\nmsg";
+        let tag = TagObject::parse(raw).unwrap();
+        assert_eq!(tag.object_type, Some(UnparsedObjectType::Tag));
+        assert_eq!(tag.tagger, "");
+    }
+}
@@ -1,6 +1,6 @@
 
-use crate::{ioerr, object_id::{OidTruncated, Oid, trunc_oid_to_u128_oid, hex_u128_to_str}, ioerre};
-use std::{convert::TryFrom, io, fmt::Display};
+use crate::{ioerr, object_id::{OidTruncated, Oid, OidFull, trunc_oid_to_u128_oid, full_oid_to_u128_oid, hex_u128_to_str}, ioerre};
+use std::{convert::TryFrom, io, fmt::Display, cmp::Ordering, borrow::Cow};
 
 pub trait ParseTree: Display {
     fn parse(raw: &[u8]) -> io::Result<Self> where Self: Sized;
@@ -22,6 +22,10 @@ pub enum TreeMode {
     SymLink,
     /// 160000
     GitLink,
+    /// any other mode. old versions of git (and other tools) wrote modes
+    /// like 100775, so instead of failing to parse the whole tree
+    /// we keep the octal value around.
+    Unknown(u32),
 }
 
 impl TreeMode {
@@ -34,14 +38,86 @@ impl TreeMode {
             TreeMode::SymLink => {
                 true
             }
-            // TODO: get rid of panic once you find out...
-            TreeMode::GitLink => {
-                panic!("I DONT KNOW IF A GITLINK IS A BLOB OR NOT");
+            // a gitlink points to a commit in a submodule,
+            // which is not in our object DB:
+            TreeMode::GitLink => false,
+            TreeMode::Unknown(bits) => {
+                let file_type = bits & 0o170000;
+                file_type == 0o100000 || file_type == 0o120000
             }
         }
     }
+
+    /// true if this entry is a subtree. Unknown modes
+    /// are directories if their file type bits say so.
+    pub fn is_dir(&self) -> bool {
+        match self {
+            TreeMode::Directory => true,
+            TreeMode::Unknown(bits) => bits & 0o170000 == 0o040000,
+            _ => false,
+        }
+    }
+
+    pub fn is_gitlink(&self) -> bool {
+        *self == TreeMode::GitLink
+    }
+
+    /// the full octal mode git stores for this entry,
+    /// ie: 0o100644 for a regular non executable file.
+    pub fn mode_bits(&self) -> u32 {
+        match self {
+            TreeMode::Directory => 0o040000,
+            TreeMode::RegularNonEx => 0o100644,
+            TreeMode::RegularNonExGroupWrite => 0o100664,
+            TreeMode::RegularEx => 0o100755,
+            TreeMode::SymLink => 0o120000,
+            TreeMode::GitLink => 0o160000,
+            TreeMode::Unknown(bits) => *bits,
+        }
+    }
+
+    /// just the unix permission bits of this mode, ie: 0o755 or 0o644.
+    /// git does not store permissions for directories,
+    /// symlinks, or gitlinks, so those are 0.
+    pub fn permissions(&self) -> u32 {
+        self.mode_bits() & 0o777
+    }
+
+    pub fn is_executable(&self) -> bool {
+        *self == TreeMode::RegularEx
+    }
 }
 
+/// Create a tree mode from a numeric mode, like one you get from
+/// a stat() call. Like git, regular files are normalized to either
+/// 100755 (if the owner executable bit is set) or 100644, with the
+/// exception of 100664 which git also knows about.
+impl TryFrom<u32> for TreeMode {
+    type Error = io::Error;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        let out = match value & 0o170000 {
+            0o040000 => TreeMode::Directory,
+            0o120000 => TreeMode::SymLink,
+            0o160000 => TreeMode::GitLink,
+            0o100000 => {
+                if value & 0o777 == 0o664 {
+                    TreeMode::RegularNonExGroupWrite
+                } else if value & 0o100 != 0 {
+                    TreeMode::RegularEx
+                } else {
+                    TreeMode::RegularNonEx
+                }
+            }
+            _ => return ioerre!("Failed to find appropriate tree mode for: {:o}", value),
+        };
+        Ok(out)
+    }
+}
+
+/// Note: we cant return a str of an unknown mode's digits,
+/// so those are "unknown". Use the Display impl if you
+/// want the actual octal value.
 impl AsRef<str> for TreeMode {
     fn as_ref(&self) -> &str {
         match self {
@@ -51,10 +127,17 @@ impl AsRef<str> for TreeMode {
             TreeMode::RegularEx => "100755",
             TreeMode::SymLink => "120000",
             TreeMode::GitLink => "160000",
+            TreeMode::Unknown(_) => "unknown",
         }
     }
 }
 
+impl Display for TreeMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:06o}", self.mode_bits())
+    }
+}
+
 impl Default for TreeMode {
     fn default() -> Self {
         TreeMode::Directory
@@ -73,17 +156,43 @@ impl TryFrom<&[u8]> for TreeMode {
             b"100755" => TreeMode::RegularEx,
             b"120000" => TreeMode::SymLink,
             b"160000" => TreeMode::GitLink,
+            // its going to be at most 6 octal digits, but it
+            // needs to fit into a u32 regardless:
+            other if !other.is_empty() && other.len() <= 10 && other.iter().all(|b| (b'0'..=b'7').contains(b)) => {
+                let bits = other.iter().fold(0u32, |acc, b| (acc << 3) | (b - b'0') as u32);
+                TreeMode::Unknown(bits)
+            }
             _ => return ioerre!("Failed to find appropriate tree mode for: {:?}", value),
         };
         Ok(out)
     }
 }
 
+/// the mode of a tree entry exactly as it was written in the tree:
+/// the octal value, and how many digits it was written with.
+/// See `TreeEntry::raw_mode`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RawTreeMode {
+    pub bits: u32,
+    pub digits: u8,
+}
+
 #[derive(Debug, Default)]
 pub struct TreeEntry {
-    pub id: Oid,
-    pub path_component: String,
+    /// all 20 bytes of the entry's oid, which you need to write
+    /// the tree back out. See `id` for the `Oid` of it.
+    pub id_full: OidFull,
+    /// the name of this entry, as the bytes that are in the tree.
+    /// git doesn't care what encoding a file name is in, so
+    /// this is not always valid utf8. See `display_name`
+    pub path_component: Vec<u8>,
     pub entry_mode: TreeMode,
+    /// None if the mode was written the way git writes `entry_mode`.
+    /// Otherwise, the mode as it was written, ie: "100640" (which is
+    /// a `TreeMode::RegularNonEx`), or "040000" with a leading zero.
+    /// Old versions of git wrote modes like that, and we need them to
+    /// write the tree back out with the same oid.
+    pub raw_mode: Option<RawTreeMode>,
 }
 
 /// Warning, using this will make your object DB not traversible...
@@ -99,16 +208,106 @@ pub struct TreeObject {
     pub entries: Vec<TreeEntry>,
 }
 
+impl TreeEntry {
+    pub fn id(&self) -> Oid {
+        full_oid_to_u128_oid(self.id_full)
+    }
+
+    /// the name of this entry as a string, with anything that isn't
+    /// valid utf8 replaced with U+FFFD. Only use this for printing,
+    /// two different names can have the same display name.
+    pub fn display_name(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.path_component)
+    }
+
+    /// compares the path components of two entries the same way git does
+    /// when it sorts tree entries: byte by byte, but directories
+    /// are compared as if their name has a trailing '/'.
+    /// ie: "dir.txt" comes before the directory "dir", because '.' < '/'
+    pub fn canonical_cmp(&self, other: &TreeEntry) -> Ordering {
+        let trailing = |e: &TreeEntry| if e.entry_mode.is_dir() {
+            Some(b'/')
+        } else {
+            None
+        };
+        let a = self.path_component.iter().copied().chain(trailing(self));
+        let b = other.path_component.iter().copied().chain(trailing(other));
+        a.cmp(b)
+    }
+}
+
+impl TreeObject {
+    /// true if the entries are in the order git would write them in.
+    /// If this is false, hashing this tree would not give the same oid
+    /// that git would.
+    pub fn is_canonically_sorted(&self) -> bool {
+        self.entries.windows(2).all(|w| w[0].canonical_cmp(&w[1]) != Ordering::Greater)
+    }
+
+    /// sort the entries into the order git would write them in.
+    pub fn sort_canonical(&mut self) {
+        self.entries.sort_by(|a, b| a.canonical_cmp(b));
+    }
+
+    /// the tree as git stores it: `<mode> <name>\0<20 byte oid>` for
+    /// every entry, in canonical order (see `sort_canonical`). Hashing this
+    /// as a "tree" gives you the tree's oid. The modes are written like git
+    /// writes them, so directories are "40000", unless the entry has
+    /// a `raw_mode`, so a parsed tree always gets its original bytes back.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut entries: Vec<&TreeEntry> = self.entries.iter().collect();
+        if !self.is_canonically_sorted() {
+            entries.sort_by(|a, b| a.canonical_cmp(b));
+        }
+        let len = entries.iter().map(|e| e.path_component.len() + 8 + 20).sum();
+        let mut out = Vec::with_capacity(len);
+        for entry in entries {
+            let mode = match entry.raw_mode {
+                Some(raw) => format!("{:0width$o} ", raw.bits, width = raw.digits as usize),
+                None => format!("{:o} ", entry.entry_mode.mode_bits()),
+            };
+            out.extend_from_slice(mode.as_bytes());
+            out.extend_from_slice(&entry.path_component);
+            out.push(0);
+            out.extend_from_slice(&entry.id_full);
+        }
+        out
+    }
+
+    /// iterate only the entries whose mode matches the predicate.
+    pub fn entries_of_mode(&self, predicate: impl Fn(&TreeMode) -> bool) -> impl Iterator<Item = &TreeEntry> {
+        self.entries.iter().filter(move |e| predicate(&e.entry_mode))
+    }
+
+    /// the entries that are subtrees.
+    pub fn dirs(&self) -> impl Iterator<Item = &TreeEntry> {
+        self.entries_of_mode(TreeMode::is_dir)
+    }
+
+    /// the entries that are blobs, ie: regular files and symlinks.
+    /// gitlinks are not included.
+    pub fn blobs(&self) -> impl Iterator<Item = &TreeEntry> {
+        self.entries_of_mode(TreeMode::is_blob)
+    }
+
+    /// the entries that are submodule commits.
+    pub fn gitlinks(&self) -> impl Iterator<Item = &TreeEntry> {
+        self.entries_of_mode(TreeMode::is_gitlink)
+    }
+}
+
 impl ToString for TreeEntry {
     fn to_string(&self) -> String {
-        let mode_str = self.entry_mode.as_ref();
-        let blob_or_tree = if self.entry_mode.is_blob() {
-            "blob"
-        } else {
+        let mode_str = self.entry_mode.to_string();
+        let blob_or_tree = if self.entry_mode.is_dir() {
             "tree"
+        } else if self.entry_mode.is_gitlink() {
+            "commit"
+        } else {
+            "blob"
         };
-        let id_str = hex_u128_to_str(self.id);
-        format!("{} {} {}\t{}", mode_str, blob_or_tree, id_str, self.path_component)
+        let id_str = hex_u128_to_str(self.id());
+        format!("{} {} {}\t{}", mode_str, blob_or_tree, id_str, self.display_name())
     }
 }
 
@@ -126,7 +325,9 @@ impl Display for TreeObject {
     }
 }
 
-pub fn get_tree_entry(raw: &[u8], curr: &mut usize) -> io::Result<TreeEntry> {
+/// Like `get_tree_entry`, but doesn't allocate: the path component
+/// is borrowed from `raw`.
+pub fn get_tree_entry_borrowed<'a>(raw: &'a [u8], curr: &mut usize) -> io::Result<(&'a [u8], Oid, TreeMode)> {
     // get everything up to the null byte:
     let raw = &raw[*curr..];
     let null_byte_index = raw.iter().position(|&b| b == 0)
@@ -137,10 +338,6 @@ pub fn get_tree_entry(raw: &[u8], curr: &mut usize) -> io::Result<TreeEntry> {
     let mode = &string_part[0..space_index];
     let tree_mode = TreeMode::try_from(mode)?;
     let path_component = &string_part[(space_index + 1)..];
-    let path_component = std::str::from_utf8(path_component)
-        .map_err(|e| ioerr!("Failed to parse path component: {}", e))?;
-    // dont heap allocate the string until
-    // we verify that the hash is valid:
     let desired_range = (null_byte_index + 1)..(null_byte_index + 1 + 20);
     let last_segment = raw.get(desired_range)
         .ok_or_else(|| ioerr!("Failed to find sha hash of tree entry"))?;
@@ -149,20 +346,75 @@ pub fn get_tree_entry(raw: &[u8], curr: &mut usize) -> io::Result<TreeEntry> {
     let mut oid = OidTruncated::default();
     oid[..].copy_from_slice(&last_segment[0..16]);
     let oid = trunc_oid_to_u128_oid(oid);
-    
+
     // if we got this far, we successfully parsed this entry,
     // so adjust the current index:
     let this_entry_len = null_byte_index + 1 + 20;
-    *curr = *curr + this_entry_len;
+    *curr += this_entry_len;
+    Ok((path_component, oid, tree_mode))
+}
+
+pub fn get_tree_entry(raw: &[u8], curr: &mut usize) -> io::Result<TreeEntry> {
+    let mut next_index = *curr;
+    let (path_component, _, tree_mode) = get_tree_entry_borrowed(raw, &mut next_index)?;
+    // the full oid is the last 20 bytes of the entry,
+    // and the mode is everything before the space:
+    let mut id_full = OidFull::default();
+    id_full.copy_from_slice(&raw[(next_index - 20)..next_index]);
+    let mode_len = raw[*curr..].iter().position(|&b| b == b' ').unwrap_or_default();
+    let raw_mode = raw_mode_if_not_canonical(&tree_mode, &raw[*curr..(*curr + mode_len)]);
+    *curr = next_index;
     let tree_entry = TreeEntry {
-        id: oid,
+        id_full,
         entry_mode: tree_mode,
         path_component: path_component.to_owned(),
+        raw_mode,
     };
 
     Ok(tree_entry)
 }
 
+/// the mode as it was written, if its not how git would write `mode`.
+/// `written` was already parsed into `mode`, so its all octal digits.
+fn raw_mode_if_not_canonical(mode: &TreeMode, written: &[u8]) -> Option<RawTreeMode> {
+    let bits = written.iter().fold(0u32, |acc, b| (acc << 3) | (b - b'0') as u32);
+    if bits == mode.mode_bits() && written.first() != Some(&b'0') {
+        return None;
+    }
+    Some(RawTreeMode { bits, digits: written.len() as u8 })
+}
+
+/// iterates the entries of a raw tree object without allocating,
+/// yielding (path component, oid, mode). The path component is borrowed
+/// from the raw tree data, ie: the payload of an `UnparsedObject`. Useful
+/// if you only need to scan a tree once. After an error, the iterator
+/// doesn't return anything else.
+pub struct TreeEntryIter<'a> {
+    raw: &'a [u8],
+    index: usize,
+}
+
+impl<'a> TreeEntryIter<'a> {
+    pub fn new(raw: &'a [u8]) -> TreeEntryIter<'a> {
+        TreeEntryIter { raw, index: 0 }
+    }
+}
+
+impl<'a> Iterator for TreeEntryIter<'a> {
+    type Item = io::Result<(&'a [u8], Oid, TreeMode)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.raw.len() {
+            return None;
+        }
+        let entry = get_tree_entry_borrowed(self.raw, &mut self.index);
+        if entry.is_err() {
+            self.index = self.raw.len();
+        }
+        Some(entry)
+    }
+}
+
 impl ParseTree for TreeObject {
     fn parse(raw: &[u8]) -> io::Result<Self> where Self: Sized {
         let mut index = 0;
@@ -188,6 +440,79 @@ mod tests {
     use super::*;
     use crate::object_id::OidFull;
 
+    #[test]
+    fn tree_mode_permissions_work() {
+        assert_eq!(TreeMode::RegularNonEx.permissions(), 0o644);
+        assert_eq!(TreeMode::RegularNonExGroupWrite.permissions(), 0o664);
+        assert_eq!(TreeMode::RegularEx.permissions(), 0o755);
+        assert_eq!(TreeMode::SymLink.mode_bits(), 0o120000);
+        assert!(TreeMode::RegularEx.is_executable());
+        assert!(!TreeMode::SymLink.is_executable());
+
+        assert_eq!(TreeMode::try_from(0o100664u32).unwrap(), TreeMode::RegularNonExGroupWrite);
+        assert_eq!(TreeMode::try_from(0o100644u32).unwrap(), TreeMode::RegularNonEx);
+        assert_eq!(TreeMode::try_from(0o100600u32).unwrap(), TreeMode::RegularNonEx);
+        assert_eq!(TreeMode::try_from(0o100700u32).unwrap(), TreeMode::RegularEx);
+        assert_eq!(TreeMode::try_from(0o040755u32).unwrap(), TreeMode::Directory);
+        assert!(TreeMode::try_from(0o010644u32).is_err());
+    }
+
+    #[test]
+    fn tree_entries_of_mode_works() {
+        let entry = |name: &str, entry_mode| TreeEntry {
+            path_component: name.as_bytes().to_vec(),
+            entry_mode,
+            ..Default::default()
+        };
+        let tree = TreeObject {
+            entries: vec![
+                entry("dir", TreeMode::Directory),
+                entry("file", TreeMode::RegularNonEx),
+                entry("link", TreeMode::SymLink),
+                entry("submodule", TreeMode::GitLink),
+            ],
+        };
+        let names = |it: &mut dyn Iterator<Item = &TreeEntry>| it.map(|e| e.display_name().into_owned()).collect::<Vec<_>>();
+        assert_eq!(names(&mut tree.dirs()), ["dir"]);
+        assert_eq!(names(&mut tree.blobs()), ["file", "link"]);
+        assert_eq!(names(&mut tree.gitlinks()), ["submodule"]);
+        assert_eq!(names(&mut tree.entries_of_mode(|m| *m != TreeMode::Directory)).len(), 3);
+        assert!(tree.entries[3].to_string().starts_with("160000 commit "));
+    }
+
+    #[test]
+    fn tree_canonical_sort_works() {
+        let entry = |name: &str, entry_mode| TreeEntry {
+            path_component: name.as_bytes().to_vec(),
+            entry_mode,
+            ..Default::default()
+        };
+        let mut tree = TreeObject {
+            entries: vec![
+                entry("dir", TreeMode::Directory),
+                entry("dir.txt", TreeMode::RegularNonEx),
+                entry("a", TreeMode::RegularNonEx),
+                entry("dir0", TreeMode::RegularNonEx),
+            ],
+        };
+        assert!(!tree.is_canonically_sorted());
+        tree.sort_canonical();
+        assert!(tree.is_canonically_sorted());
+        let names: Vec<&[u8]> = tree.entries.iter().map(|e| e.path_component.as_slice()).collect();
+        // a plain byte sort would put "dir" before "dir.txt",
+        // but git compares the directory as "dir/", and '.' < '/' < '0'
+        assert_eq!(names, vec![&b"a"[..], b"dir.txt", b"dir", b"dir0"]);
+
+        // a file named "dir" on the other hand does come first:
+        let tree = TreeObject {
+            entries: vec![
+                entry("dir", TreeMode::RegularNonEx),
+                entry("dir.txt", TreeMode::RegularNonEx),
+            ],
+        };
+        assert!(tree.is_canonically_sorted());
+    }
+
     #[test]
     fn tree_parse_works() {
         let mut oid_full_1 = OidFull::default();
@@ -203,21 +528,151 @@ mod tests {
         assert_eq!(parsed.entries.len(), 2);
         let first_entry = &parsed.entries[0];
         let second_entry = &parsed.entries[1];
-        assert_eq!(first_entry.id, 1);
-        assert_eq!(second_entry.id, 2);
-        assert_eq!(first_entry.path_component, "dir1");
-        assert_eq!(second_entry.path_component, "somefile");
+        assert_eq!(first_entry.id(), 1);
+        assert_eq!(second_entry.id(), 2);
+        assert_eq!(first_entry.id_full, oid_full_1);
+        assert_eq!(first_entry.raw_mode, None);
+        assert_eq!(first_entry.path_component, b"dir1");
+        assert_eq!(second_entry.path_component, b"somefile");
         assert_eq!(first_entry.entry_mode, TreeMode::Directory);
         assert_eq!(second_entry.entry_mode, TreeMode::RegularNonEx);
     }
 
+    #[test]
+    fn tree_to_bytes_round_trips() {
+        use crate::{sha1::hash_object, object_id::{full_oid_from_str, oid_full_to_string}};
+        let hello = full_oid_from_str("ce013625030ba8dba906f756967f9e9ca394464a").unwrap();
+        let empty_tree = full_oid_from_str("4b825dc642cb6eb9a060e54bf8d69288fbee4904").unwrap();
+        let mut raw = b"100644 a.txt\0".to_vec();
+        raw.extend(&hello);
+        raw.extend(b"100755 dir.txt\0");
+        raw.extend(&hello);
+        raw.extend(b"40000 dir\0");
+        raw.extend(&empty_tree);
+
+        let mut parsed = TreeObject::parse(&raw).unwrap();
+        assert_eq!(parsed.entries[0].id_full, hello);
+        assert_eq!(parsed.entries[2].id_full, empty_tree);
+        assert_eq!(parsed.to_bytes(), raw);
+        // same as `git mktree` of these entries:
+        assert_eq!(oid_full_to_string(hash_object("tree", &parsed.to_bytes())), "03d79c26cdce3c5b38216aa4b4e5cfeb51fe796c");
+
+        // the bytes are always in canonical order:
+        parsed.entries.reverse();
+        assert_eq!(parsed.to_bytes(), raw);
+        assert!(TreeObject::default().to_bytes().is_empty());
+    }
+
+    #[test]
+    fn tree_to_bytes_keeps_modes_we_normalize() {
+        use crate::sha1::hash_object;
+        // old versions of git wrote these:
+        let mut raw = b"100640 a.txt\0".to_vec();
+        raw.extend(&[1; 20]);
+        raw.extend(b"040000 dir\0");
+        raw.extend(&[2; 20]);
+        raw.extend(b"100644 e.txt\0");
+        raw.extend(&[3; 20]);
+
+        let parsed = TreeObject::parse(&raw).unwrap();
+        assert_eq!(parsed.entries[0].entry_mode, TreeMode::RegularNonEx);
+        assert_eq!(parsed.entries[0].raw_mode, Some(RawTreeMode { bits: 0o100640, digits: 6 }));
+        assert!(parsed.entries[1].entry_mode.is_dir());
+        assert_eq!(parsed.entries[1].raw_mode, Some(RawTreeMode { bits: 0o40000, digits: 6 }));
+        assert_eq!(parsed.entries[2].raw_mode, None);
+        assert_eq!(parsed.to_bytes(), raw);
+        assert_eq!(hash_object("tree", &parsed.to_bytes()), hash_object("tree", &raw));
+
+        // an entry we make ourselves is written the way git would:
+        let tree = TreeObject {
+            entries: vec![TreeEntry {
+                path_component: b"dir".to_vec(),
+                entry_mode: TreeMode::Directory,
+                ..Default::default()
+            }],
+        };
+        assert!(tree.to_bytes().starts_with(b"40000 dir\0"));
+    }
+
+    #[test]
+    fn tree_parse_unknown_modes_works() {
+        let mut tree_vec = b"100600 oldfile\0".to_vec();
+        tree_vec.extend(&[1; 20]);
+        tree_vec.extend(b"40755 olddir\0");
+        tree_vec.extend(&[2; 20]);
+        tree_vec.extend(b"100644 somefile\0");
+        tree_vec.extend(&[3; 20]);
+
+        let parsed = TreeObject::parse(&tree_vec[..]).unwrap();
+        assert_eq!(parsed.entries.len(), 3);
+        let old_file = &parsed.entries[0].entry_mode;
+        let old_dir = &parsed.entries[1].entry_mode;
+        assert_eq!(*old_file, TreeMode::Unknown(0o100600));
+        assert!(old_file.is_blob());
+        assert!(!old_file.is_dir());
+        assert_eq!(*old_dir, TreeMode::Unknown(0o40755));
+        assert!(old_dir.is_dir());
+        assert!(!old_dir.is_blob());
+        assert_eq!(parsed.dirs().count(), 1);
+        assert_eq!(old_file.to_string(), "100600");
+        assert!(parsed.entries[1].to_string().starts_with("040755 tree "));
+        assert_eq!(parsed.entries[2].entry_mode, TreeMode::RegularNonEx);
+
+        // not octal is still an error:
+        assert!(TreeMode::try_from(&b"10064x"[..]).is_err());
+        assert!(TreeMode::try_from(&b"100648"[..]).is_err());
+    }
+
+    #[test]
+    fn tree_parse_non_utf8_names_works() {
+        // "caf\xe9" is latin-1, and "\x83e\x83X\x83g" is shift-jis:
+        let mut tree_vec = b"100644 caf\xe9.txt\0".to_vec();
+        tree_vec.extend(&[1; 20]);
+        tree_vec.extend(b"40000 \x83e\x83X\x83g\0");
+        tree_vec.extend(&[2; 20]);
+        tree_vec.extend(b"100644 ok.txt\0");
+        tree_vec.extend(&[3; 20]);
+
+        let parsed = TreeObject::parse(&tree_vec[..]).unwrap();
+        assert_eq!(parsed.entries.len(), 3);
+        assert_eq!(parsed.entries[0].path_component, b"caf\xe9.txt");
+        assert_eq!(parsed.entries[0].display_name(), "caf\u{FFFD}.txt");
+        assert_eq!(parsed.entries[1].path_component, b"\x83e\x83X\x83g");
+        assert!(parsed.entries[1].entry_mode.is_dir());
+        assert_eq!(parsed.entries[2].display_name(), "ok.txt");
+        assert!(parsed.to_string().contains("\tcaf\u{FFFD}.txt"));
+    }
+
     #[test]
     fn size_test() {
+        // Unknown(u32) makes this bigger than a byte:
         let size = std::mem::size_of::<TreeMode>();
-        assert_eq!(size, 1);
+        assert_eq!(size, 8);
         let size = std::mem::size_of::<TreeEntry>();
-        assert_eq!(size, 48);
+        assert_eq!(size, 64);
         let size = std::mem::size_of::<TreeObject>();
         assert_eq!(size, 24);
     }
+
+    #[test]
+    fn tree_entry_iter_works() {
+        let mut raw = vec![];
+        raw.extend_from_slice(b"100644 a.txt\0");
+        raw.extend_from_slice(&[1; 20]);
+        raw.extend_from_slice(b"40000 dir\0");
+        raw.extend_from_slice(&[2; 20]);
+        let entries = TreeEntryIter::new(&raw).collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, b"a.txt");
+        assert_eq!(entries[0].2, TreeMode::RegularNonEx);
+        assert_eq!(entries[1].0, b"dir");
+        assert_eq!(entries[1].1, u128::from_be_bytes([2; 16]));
+        assert_eq!(entries[1].2, TreeMode::Directory);
+
+        // a truncated entry errors once, then stops:
+        let mut iter = TreeEntryIter::new(&raw[0..raw.len() - 1]);
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
 }
@@ -1,4 +1,4 @@
-use crate::{fs_helpers, ioerr, ioerre};
+use crate::{fs_helpers, ioerr, ioerre, object_database::{check_object_size, DEFAULT_MAX_OBJECT_SIZE}};
 use std::{io, path::Path, fs::File, fmt::Debug, str::FromStr};
 use flate2::{Decompress, Status, FlushDecompress};
 use io::{BufRead, Read};
@@ -53,6 +53,24 @@ pub struct FirstReadInfo {
     pub decompressed_state: Status,
 }
 
+/// true if data starts with a zlib header that we can decompress,
+/// ie: the CMF byte says its deflate with a window of at most 32KB,
+/// the FLG byte's check bits are valid, and there is no preset dictionary.
+/// (git always writes 0x78 as the CMF byte)
+// `is_multiple_of` is newer than the compilers we support:
+#[allow(unknown_lints, clippy::manual_is_multiple_of)]
+pub fn is_valid_zlib_header(data: &[u8]) -> bool {
+    let (cmf, flg) = match data {
+        [cmf, flg, ..] => (*cmf, *flg),
+        _ => return false,
+    };
+    let is_deflate = cmf & 0x0f == 8;
+    let window_ok = cmf >> 4 <= 7;
+    let check_ok = (((cmf as u16) << 8) | flg as u16) % 31 == 0;
+    let no_dict = flg & 0x20 == 0;
+    is_deflate && window_ok && check_ok && no_dict
+}
+
 pub fn read_and_extract_header<D: Debug>(
     file: &mut File,
     filename: D,
@@ -74,11 +92,17 @@ pub fn read_and_extract_header<D: Debug>(
     };
     file.read_exact(&mut buf)
         .map_err(|e| ioerr!("Failed to read file {:?}\n{}", filename, e))?;
+    // check this ourselves, because otherwise all we get from
+    // the decompressor is a vague "corrupt deflate stream"
+    if !is_valid_zlib_header(&buf) {
+        return ioerre!("Object {:?} does not begin with a valid zlib header", filename);
+    }
     // the header should only need 128 bytes (even less..)
     // to be properly parsed. Once we parse the header, we decide what to do next
     let mut header_buf = [0; 128];
     let decompressed_state = decompressor.decompress(
-        &buf, &mut header_buf, FlushDecompress::None)?;
+        &buf, &mut header_buf, FlushDecompress::None)
+        .map_err(|e| ioerr!("Failed to decompress object {:?}\n{}", filename, e))?;
     let (
         object_type,
         payload_size,
@@ -99,6 +123,42 @@ pub fn read_and_extract_header<D: Debug>(
 }
 
 
+/// inflate an entire zlib stream from `compressed` into `out`, and return
+/// how many bytes were written to `out`. It is an error if the stream does not fit
+/// into `out`, or if `compressed` ends before the stream does.
+/// `compressed` can have extra data after the end of the zlib stream, ie:
+/// you can pass the rest of a pack file starting from where an object's data starts.
+pub fn inflate_into(
+    compressed: &[u8],
+    out: &mut [u8],
+    decompressor: &mut Decompress,
+) -> io::Result<usize> {
+    decompressor.reset(true);
+    loop {
+        let total_in = decompressor.total_in() as usize;
+        let total_out = decompressor.total_out() as usize;
+        let input = compressed.get(total_in..).unwrap_or(&[]);
+        let output = &mut out[total_out..];
+        let status = decompressor.decompress(input, output, FlushDecompress::None)
+            .map_err(|e| ioerr!("Failed to inflate data\n{}", e))?;
+        if status == Status::StreamEnd {
+            return Ok(decompressor.total_out() as usize);
+        }
+        let made_progress = decompressor.total_in() as usize != total_in ||
+            decompressor.total_out() as usize != total_out;
+        if made_progress {
+            continue;
+        }
+        if total_out == out.len() {
+            return ioerre!("Output buffer of {} bytes is too small to inflate data into", out.len());
+        }
+        if total_in >= compressed.len() {
+            return ioerre!("Compressed data ended after {} bytes before the end of the zlib stream", compressed.len());
+        }
+        return ioerre!("Failed to inflate data: zlib made no progress");
+    }
+}
+
 /// I couldnt figure out the decompress logic, so I stole this from:
 /// https://github.com/Byron/gitoxide/blob/057016e2df3138992c4857f9b65bf19dc2c9a097/git-features/src/zlib/stream/inflate.rs#L22
 /// Read bytes from `rd` and decompress them using
@@ -145,14 +205,75 @@ pub fn decompress_remaining(
 }
 
 
+/// reads only as much of a loose object as is needed to find the
+/// header `<type> <size>\0`, and returns the type and size.
+/// The payload itself is never decompressed, so this
+/// is cheap even for huge blobs.
+pub fn read_loose_header<P: AsRef<Path>>(
+    path: P,
+    decompressor: &mut Decompress,
+) -> io::Result<(UnparsedObjectType, usize)> {
+    let mut file = fs_helpers::get_readonly_handle(&path)?;
+    decompressor.reset(true);
+    // headers are tiny, so we read/decompress
+    // a small amount at a time until we find the null byte.
+    let mut compressed_buf = [0; 64];
+    let mut header_buf = [0; 128];
+    loop {
+        let num_read = file.read(&mut compressed_buf)
+            .map_err(|e| ioerr!("Failed to read file {:?}\n{}", path.as_ref(), e))?;
+        if num_read == 0 {
+            return ioerre!("Reached end of file {:?} before finding the end of its header", path.as_ref());
+        }
+        let mut input = &compressed_buf[0..num_read];
+        while !input.is_empty() {
+            let out_so_far = decompressor.total_out() as usize;
+            if out_so_far >= header_buf.len() {
+                return ioerre!("Failed to find end of header in the first {} bytes of {:?}", header_buf.len(), path.as_ref());
+            }
+            let in_before = decompressor.total_in();
+            let status = decompressor.decompress(
+                input, &mut header_buf[out_so_far..], FlushDecompress::None)
+                .map_err(|e| ioerr!("Failed to decompress header of {:?}\n{}", path.as_ref(), e))?;
+            let consumed = (decompressor.total_in() - in_before) as usize;
+            input = &input[consumed..];
+            let out_now = decompressor.total_out() as usize;
+            if header_buf[out_so_far..out_now].contains(&0) {
+                let (object_type, payload_size, _) = decode_object_header_res(&header_buf[0..out_now], path.as_ref())?;
+                return Ok((UnparsedObjectType::from_str(object_type)?, payload_size));
+            }
+            if status == Status::StreamEnd {
+                return ioerre!("Reached end of compressed stream of {:?} before finding the end of its header", path.as_ref());
+            }
+            if consumed == 0 && out_now == out_so_far {
+                // no progress, need more input
+                break;
+            }
+        }
+    }
+}
+
 pub fn read_raw_object<P: AsRef<Path>>(
     path: P,
     should_read_blobs: bool,
     decompressor: &mut Decompress,
+) -> io::Result<UnparsedObject> {
+    read_raw_object_limited(path, should_read_blobs, decompressor, DEFAULT_MAX_OBJECT_SIZE)
+}
+
+/// Like `read_raw_object`, but errors if the object's header
+/// says its larger than `max_object_size` instead of trying to allocate it.
+pub fn read_raw_object_limited<P: AsRef<Path>>(
+    path: P,
+    should_read_blobs: bool,
+    decompressor: &mut Decompress,
+    max_object_size: usize,
 ) -> io::Result<UnparsedObject> {
     let mut file = fs_helpers::get_readonly_handle(&path)?;
 
     let first_read_info = read_and_extract_header(&mut file, path.as_ref(), decompressor)?;
+    check_object_size(first_read_info.payload_size, max_object_size)
+        .map_err(|e| ioerr!("{:?}: {}", path.as_ref(), e))?;
     if !should_read_blobs && first_read_info.object_type == UnparsedObjectType::Blob {
         // this is a blob, and the user did not want to
         // read it, so we just return with an empty vec:
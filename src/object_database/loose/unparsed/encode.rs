@@ -0,0 +1,52 @@
+use crate::{ioerr, object_id::{OidFull, oid_full_to_string_no_alloc}, sha1::hash_object};
+use std::{io::{self, Write}, fs, path::Path, sync::atomic::{AtomicUsize, Ordering}};
+use flate2::{write::ZlibEncoder, Compression};
+use super::UnparsedObjectType;
+
+/// makes the temporary file names unique within this process.
+static TMP_OBJECT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// the bytes of a loose object before it is compressed: the
+/// header (ie: "blob 12\0") followed by the payload.
+pub fn encode_loose_object(object_type: &UnparsedObjectType, payload: &[u8]) -> Vec<u8> {
+    let mut out = format!("{} {}\0", object_type.as_str(), payload.len()).into_bytes();
+    out.extend_from_slice(payload);
+    out
+}
+
+/// write an object as a loose object in `objects_dir`
+/// (ie: `.git/objects/`), and return its full oid.
+/// Like git, the object is written to a temporary file first and then
+/// renamed into place, so a reader never sees half of an object. If the
+/// object already exists, it is not written again.
+pub fn write_raw_object<P: AsRef<Path>>(
+    objects_dir: P,
+    object_type: &UnparsedObjectType,
+    payload: &[u8],
+) -> io::Result<OidFull> {
+    let oid_full = hash_object(object_type.as_str(), payload);
+    let hex = oid_full_to_string_no_alloc(oid_full);
+    let hex = std::str::from_utf8(&hex)
+        .map_err(|_| ioerr!("Failed to convert oid into string"))?;
+    let folder = objects_dir.as_ref().join(&hex[0..2]);
+    let object_path = folder.join(&hex[2..]);
+    if object_path.is_file() {
+        return Ok(oid_full);
+    }
+    fs::create_dir_all(&folder)
+        .map_err(|e| ioerr!("Failed to create loose object folder {:?}\n{}", folder, e))?;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&encode_loose_object(object_type, payload))?;
+    let compressed = encoder.finish()?;
+
+    let tmp_name = format!("tmp_obj_{}_{}", std::process::id(), TMP_OBJECT_COUNTER.fetch_add(1, Ordering::Relaxed));
+    let tmp_path = folder.join(tmp_name);
+    fs::write(&tmp_path, &compressed)
+        .map_err(|e| ioerr!("Failed to write temporary object file {:?}\n{}", tmp_path, e))?;
+    if let Err(e) = fs::rename(&tmp_path, &object_path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(ioerr!("Failed to move {:?} to {:?}\n{}", tmp_path, object_path, e));
+    }
+    Ok(oid_full)
+}
@@ -3,8 +3,10 @@ use crate::ioerre;
 
 pub mod decode;
 pub use decode::*;
+pub mod encode;
+pub use encode::*;
 
-#[derive(Debug, PartialOrd, PartialEq)]
+#[derive(Debug, PartialOrd, PartialEq, Clone, Copy)]
 pub enum UnparsedObjectType {
     Tree,
     Blob,
@@ -27,6 +29,19 @@ impl FromStr for UnparsedObjectType {
     }
 }
 
+impl UnparsedObjectType {
+    /// the name git uses for this object type, ie:
+    /// what would be in the header of a loose object.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnparsedObjectType::Tree => "tree",
+            UnparsedObjectType::Blob => "blob",
+            UnparsedObjectType::Commit => "commit",
+            UnparsedObjectType::Tag => "tag",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct UnparsedObject {
     pub object_type: UnparsedObjectType,
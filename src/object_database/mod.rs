@@ -1,17 +1,27 @@
-use std::{path::{PathBuf, Path}, io, convert::{TryInto, TryFrom}};
-use crate::{ioerre, object_id::{Oid, PartialOid, full_oid_to_u128_oid, get_first_byte_of_oid, HEX_BYTES, OidFull, oid_full_to_string_no_alloc}, ioerr, fs_helpers};
+use std::{path::{PathBuf, Path}, io, convert::{TryInto, TryFrom}, borrow::Cow, collections::{BTreeMap, VecDeque}, time::SystemTime, sync::Arc};
+use crate::{ioerre, get_repository_directory, error::GitReaderError, object_id::{Oid, PartialOid, full_oid_to_u128_oid, get_first_byte_of_oid, HEX_BYTES, OidFull, oid_full_to_string_no_alloc, oid_full_to_string}, ioerr, fs_helpers};
 
 pub mod loose;
 use loose::*;
+use loose::{commit_object_parsing::{ParseCommit, CommitOnlyTreeAndParents}, tree_object_parsing::{ParseTree, TreeObject}};
 
 pub mod packed;
 use packed::*;
 use state::{State, IDXState};
 
 pub mod state;
+pub mod revwalk;
+pub mod batch;
+pub mod tree_walk;
+pub mod object_source;
+pub mod reachable;
+pub mod builder;
+pub mod location_key;
+use object_source::FallbackSource;
 
 pub mod oidmap_trunc;
 pub mod oidmap_u128;
+use oidmap_u128::{OidMap, defaults::B14};
 
 /// A trait used to see if 2 Oids match.
 /// if both of the Oids are actually Oids then
@@ -62,6 +72,32 @@ pub fn main_sep_byte() -> u8 {
     }
 }
 
+/// the maximum number of ref delta bases we will follow
+/// when resolving a single packed object before giving up.
+/// git itself wont create chains longer than 4095.
+pub const MAX_DELTA_DEPTH: usize = 4095;
+
+/// the default for `State::max_object_size`, and `PackFile::max_object_size`.
+/// we refuse to allocate room for an object larger than this, because
+/// a corrupt (or malicious) object can claim any size it wants.
+pub const DEFAULT_MAX_OBJECT_SIZE: usize = 2 * 1024 * 1024 * 1024;
+
+/// errors if an object of `size` bytes is bigger than `max_size`.
+pub fn check_object_size(size: usize, max_size: usize) -> io::Result<()> {
+    if size > max_size {
+        return ioerre!("Object claims to be {} bytes, which is larger than the max object size of {} bytes. If this is a legitimately large object, increase the max object size", size, max_size);
+    }
+    Ok(())
+}
+
+/// like git, by default we dont try to resolve
+/// an abbreviated oid shorter than this.
+pub const DEFAULT_MIN_ABBREV: usize = 4;
+
+/// the maximum number of tags we will follow when peeling
+/// an object before assuming theres a cycle.
+pub const MAX_PEEL_DEPTH: usize = 64;
+
 /// The LightObjectDB tries to minimize allocations at the cost of potentially
 /// slightly slower performance.
 /// if used repeatedly, this would amount to significantly more calls
@@ -74,9 +110,23 @@ pub fn main_sep_byte() -> u8 {
 /// that you are holding on to.
 pub struct LightObjectDB<'a> {
     /// Should be absolute path to /.../.git/objects/
-    pub path_to_db: &'a str,
+    /// This is borrowed if created via `new`, or owned if created via
+    /// `new_owned`.
+    pub path_to_db: Cow<'a, str>,
     pub path_to_db_bytes: [u8; MAX_PATH_TO_DB_LEN],
     pub path_to_db_bytes_start: usize,
+    /// if set, objects that are not in this object db are read from
+    /// here instead, and then written to this object db as loose objects
+    /// so the next lookup finds them locally. See `with_fallback`.
+    pub fallback: Option<Box<dyn FallbackSource + Send + Sync + 'a>>,
+    /// other object dbs that objects are read from if we don't have
+    /// them (see `objects/info/alternates`). Unlike the fallback, nothing
+    /// is copied from them. Only `get_object_by_oid` looks at these, searching
+    /// for partial oids does not. See `with_alternate`.
+    pub alternates: Vec<OwnedLightObjectDB>,
+    /// how many ref delta bases we follow for one object before giving
+    /// up. Defaults to `MAX_DELTA_DEPTH`.
+    pub max_delta_depth: usize,
 }
 
 /// a struct describing the information necessary
@@ -97,6 +147,36 @@ pub struct FoundPackedLocation {
     pub oid_index: usize,
 }
 
+/// read a loose object, and unlike `LightObjectDB::get_loose_object`,
+/// also read the payload of blobs.
+fn read_loose_object_in_full<S: State, P: AsRef<Path>>(path: P, state: &mut S) -> io::Result<UnparsedObject> {
+    let max_object_size = state.max_object_size();
+    let decompressor = state.get_decompressor();
+    decompressor.reset(true);
+    read_raw_object_limited(path, true, decompressor, max_object_size)
+}
+
+/// whether this error is a `GitReaderError::ObjectNotFound`, ie: an
+/// idx file that doesn't have the oid, as opposed to one we couldn't read.
+fn is_object_not_found(e: &io::Error) -> bool {
+    GitReaderError::from_io_error(e).map(|g| g.is_not_found()).unwrap_or(false)
+}
+
+/// where `oid` is in `pack`, according to its `idx`.
+/// Ok(None) if its not in this idx file.
+fn find_location_in_idx(oid: Oid, idx: &IDXFileLight, pack: &PackFile) -> io::Result<Option<FoundPackedLocation>> {
+    let oid_index = match idx.find_oid_and_fanout_index(oid) {
+        Ok(i) => i,
+        Err(e) if is_object_not_found(&e) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let object_starts_at = idx.find_packfile_index_from_fanout_index(oid_index)
+        .ok_or_else(|| ioerr!("Failed to find packfile index from fanout index {}", oid_index))
+        .and_then(|offset| check_pack_offset(offset, pack.mmapped_file.len()))
+        .map_err(|e| ioerr!("Found oid index, but failed to find packfile index offset for {:032x}\n{}", oid, e))?;
+    Ok(Some(FoundPackedLocation { id: idx.id, object_starts_at, oid_index }))
+}
+
 /// An enum of where we could have possibly found an object.
 #[derive(Debug, Clone)]
 pub enum FoundObjectLocation {
@@ -107,14 +187,79 @@ pub enum FoundObjectLocation {
     FoundPacked(FoundPackedLocation),
 }
 
+/// totals returned by `LightObjectDB::object_counts`,
+/// similar to `git count-objects -v`. sizes are in bytes.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ObjectCounts {
+    pub loose_count: usize,
+    pub loose_size_bytes: u64,
+    pub pack_count: usize,
+    /// the number of objects in all of the idx files. Objects
+    /// that exist in more than one pack are counted more than once.
+    pub in_pack_count: usize,
+    /// the size of every .pack file, not including the .idx files.
+    pub pack_size_bytes: u64,
+}
+
+/// A LightObjectDB that owns its path, so it can be
+/// stored in a struct or moved into another thread without worrying
+/// about the lifetime of the path string. Create one with
+/// `LightObjectDB::new_owned`.
+pub type OwnedLightObjectDB = LightObjectDB<'static>;
+
+impl LightObjectDB<'static> {
+    pub fn new_owned<S: Into<String>>(p: S) -> io::Result<OwnedLightObjectDB> {
+        LightObjectDB::new_from_cow(Cow::Owned(p.into()))
+    }
+
+    /// Open the object DB of a repository, where repo_path can be
+    /// either the root of a worktree (which contains a .git/ folder)
+    /// or the git dir itself (ie: a bare repository)
+    pub fn open<P: AsRef<Path>>(repo_path: P) -> io::Result<OwnedLightObjectDB> {
+        let git_dir = get_repository_directory(repo_path)?;
+        let objects_dir = git_dir.join("objects").canonicalize()?;
+        let objects_dir = objects_dir.into_os_string().into_string()
+            .map_err(|p| ioerr!("Objects directory {:?} is not valid utf8", p))?;
+        LightObjectDB::new_owned(objects_dir)
+    }
+}
+
+impl FoundObjectLocation {
+    /// the id of the pack file (and idx file) this object was found in.
+    /// None if the object is loose.
+    pub fn pack_id(&self) -> Option<OidFull> {
+        match self {
+            FoundObjectLocation::FoundLoose(_) => None,
+            FoundObjectLocation::FoundPacked(info) => Some(info.id),
+        }
+    }
+
+    /// a human readable description of where this object is,
+    /// useful for debugging/diagnostics.
+    pub fn describe(&self) -> String {
+        match self {
+            FoundObjectLocation::FoundLoose(path) => {
+                format!("loose at {:?}", path)
+            }
+            FoundObjectLocation::FoundPacked(info) => {
+                format!("packed in pack-{}.pack at offset {}", oid_full_to_string(info.id), info.object_starts_at)
+            }
+        }
+    }
+}
+
 impl<'a> LightObjectDB<'a> {
     pub fn new(p: &'a str) -> io::Result<LightObjectDB<'a>> {
+        LightObjectDB::new_from_cow(Cow::Borrowed(p))
+    }
+
+    fn new_from_cow(p: Cow<'a, str>) -> io::Result<LightObjectDB<'a>> {
         // hard to imagine a path would be longer than this right?...
         let p_len = p.len();
         // we probably wont extend the path_to_db by more than 60 chars ever...
         let max_extend_by = 60;
         if p_len >= MAX_PATH_TO_DB_LEN - max_extend_by {
-            return ioerre!("Path '{}' is too long for us to represent it without allocations", p);
+            return Err(GitReaderError::BadPath(p.into_owned()).into());
         }
         // we create a static array that contains the utf8 bytes
         // of the path string. We do this so that
@@ -129,10 +274,63 @@ impl<'a> LightObjectDB<'a> {
             path_to_db: p,
             path_to_db_bytes,
             path_to_db_bytes_start: p_len + 1,
+            fallback: None,
+            alternates: vec![],
+            max_delta_depth: MAX_DELTA_DEPTH,
         };
         Ok(out)
     }
 
+    /// read objects that we don't have from `fallback`, ie: a slower
+    /// object store that is backed by a remote. Every object we get
+    /// from it is written to our loose objects, so it is only fetched once.
+    pub fn with_fallback<F: FallbackSource + Send + Sync + 'a>(mut self, fallback: F) -> LightObjectDB<'a> {
+        self.fallback = Some(Box::new(fallback));
+        self
+    }
+
+    /// read objects that we don't have from `alternate` as well. Alternates
+    /// are checked in the order they were added, before the fallback.
+    pub fn with_alternate(mut self, alternate: OwnedLightObjectDB) -> LightObjectDB<'a> {
+        self.alternates.push(alternate);
+        self
+    }
+
+    /// write an object to this object db as a loose object,
+    /// and return its oid. Does nothing if the object is already loose.
+    pub fn write_loose_object(&self, object_type: &UnparsedObjectType, payload: &[u8]) -> io::Result<Oid> {
+        let oid_full = write_raw_object(self.path_to_db.as_ref(), object_type, payload)?;
+        Ok(full_oid_to_u128_oid(oid_full))
+    }
+
+    /// get `oid` from our fallback, and write it to our loose objects.
+    /// Ok(None) if we dont have a fallback, or it doesnt have it either.
+    fn fetch_from_fallback(&self, oid: Oid) -> io::Result<Option<UnparsedObject>> {
+        let fallback = match &self.fallback {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        let obj = match fallback.fetch_object(oid)? {
+            Some(obj) => obj,
+            None => return Ok(None),
+        };
+        let written = self.write_loose_object(&obj.object_type, &obj.payload)?;
+        if written != oid {
+            return ioerre!("Fallback object source returned {:032x} when asked for {:032x}", written, oid);
+        }
+        Ok(Some(obj))
+    }
+
+    /// get `oid` from the first of our alternates that has it.
+    fn fetch_from_alternates(&self, oid: Oid) -> io::Result<Option<UnparsedObject>> {
+        for alternate in self.alternates.iter() {
+            if let Some(obj) = alternate.fetch_object(oid)? {
+                return Ok(Some(obj));
+            }
+        }
+        Ok(None)
+    }
+
     /// extend_by should be valid utf-8 slice.
     /// we extend our self.path_to_db_bytes by the extend by slice
     /// and return an array that can be turned into a stack
@@ -147,6 +345,18 @@ impl<'a> LightObjectDB<'a> {
         (stack_arr, take_slice_to)
     }
 
+    /// Like `get_static_path_str`, but returns an error instead of
+    /// panicking if the path would not fit in `MAX_PATH_TO_DB_LEN` bytes.
+    /// use this when `extend_by` is not one of our own constant length paths.
+    pub fn try_get_static_path_str(&self, extend_by: &[u8]) -> io::Result<([u8; MAX_PATH_TO_DB_LEN], usize)> {
+        let take_slice_to = self.path_to_db_bytes_start + extend_by.len();
+        if take_slice_to > MAX_PATH_TO_DB_LEN {
+            let path = format!("{}{}", self.path_to_db, String::from_utf8_lossy(extend_by));
+            return Err(GitReaderError::BadPath(path).into());
+        }
+        Ok(self.get_static_path_str(extend_by))
+    }
+
     #[inline(always)]
     fn get_loose_item_str_array(&self, oid_full: OidFull) -> io::Result<([u8; MAX_PATH_TO_DB_LEN], usize)> {
         let oid_full_str = oid_full_to_string_no_alloc(oid_full);
@@ -217,9 +427,10 @@ impl<'a> LightObjectDB<'a> {
               F::Error: ToString,
               S: State,
     {
+        let max_object_size = state.max_object_size();
         let decompressor = state.get_decompressor();
         decompressor.reset(true);
-        let resolved_obj = read_raw_object(loose_obj_path, false, decompressor)?;
+        let resolved_obj = read_raw_object_limited(loose_obj_path, false, decompressor, max_object_size)?;
         let transformed = F::try_from(resolved_obj)
             .map_err(|e| ioerr!("Failed to get loose object\n{}", e.to_string()))?;
         Ok(transformed)
@@ -261,8 +472,8 @@ impl<'a> LightObjectDB<'a> {
         // this is the fanout index we use to find the
         // actual packfile index:
         let oid_index = idx_file.find_oid_and_fanout_index(oid)?;
-        let pack_index = idx_file.find_packfile_index_from_fanout_index(oid_index)
-            .ok_or_else(|| ioerr!("Found oid index, but failed to find packfile index offset for {:032x}", oid))?;
+        let pack_index = idx_file.find_packfile_index_checked(oid_index, pack_file.mmapped_file.len())
+            .map_err(|e| ioerr!("Found oid index, but failed to find packfile index offset for {:032x}\n{}", oid, e))?;
         let object_starts_at = pack_index;
         let location_info = FoundPackedLocation {
             id: idx_file.id(),
@@ -272,6 +483,28 @@ impl<'a> LightObjectDB<'a> {
         self.get_packed_object_packfile_loaded(&location_info, pack_file, state)
     }
 
+    /// read an object from an idx file and its pack file that you already
+    /// have open, ie: because you keep them around yourself. The idx is used
+    /// to find the object, and to find the base of a ref delta in this pack,
+    /// so the state's idx file is never opened. The state is still used to
+    /// decompress, and to look outside of this pack for a thin pack's bases.
+    /// It is an error if the oid is not in this idx file.
+    pub fn get_packed_object_with_idx_and_pack<F, S>(
+        &self,
+        oid: Oid,
+        idx: &IDXFileLight,
+        pack: &PackFile,
+        state: &mut S,
+    ) -> io::Result<F>
+        where F: TryFrom<UnparsedObject>,
+              F::Error: ToString,
+              S: State,
+    {
+        let location_info = find_location_in_idx(oid, idx, pack)?
+            .ok_or(GitReaderError::ObjectNotFound(oid))?;
+        self.get_packed_object_packfile_loaded_with_depth(&location_info, pack, Some(idx), state, 0)
+    }
+
     /// Like `get_packed_object` but takes a pack file that has
     /// already been loaded
     pub fn get_packed_object_packfile_loaded<F, S>(
@@ -284,6 +517,31 @@ impl<'a> LightObjectDB<'a> {
               F::Error: ToString,
               S: State,
     {
+        self.get_packed_object_packfile_loaded_with_depth(packed_info, pack, None, state, 0)
+    }
+
+    /// the actual implementation of `get_packed_object_packfile_loaded`.
+    /// depth is how many ref delta bases deep we currently are. every time
+    /// we have to go find a ref delta's base object, we increase the depth
+    /// and error once we pass `max_delta_depth`. This
+    /// protects us from cycles, ie: a thin pack whose object's base
+    /// points back to itself. If we are given the idx file of the pack,
+    /// its used to find ref delta bases instead of asking the state for it.
+    fn get_packed_object_packfile_loaded_with_depth<F, S>(
+        &self,
+        packed_info: &FoundPackedLocation,
+        pack: &PackFile,
+        idx: Option<&IDXFileLight>,
+        state: &mut S,
+        depth: usize,
+    ) -> io::Result<F>
+        where F: TryFrom<UnparsedObject>,
+              F::Error: ToString,
+              S: State,
+    {
+        if depth > self.max_delta_depth {
+            return ioerre!("Exceeded max delta depth of {} while resolving object at index {}", self.max_delta_depth, packed_info.object_starts_at);
+        }
         let obj_index: usize = packed_info.object_starts_at.try_into()
             .map_err(|_| ioerr!("Failed to convert u64 into usize in order to index the packfile. Your architecture might not allow {} to be represented as a usize.", packed_info.object_starts_at))?;
         let (
@@ -293,6 +551,7 @@ impl<'a> LightObjectDB<'a> {
         // obj size also needs to be converted to usize.
         let obj_size: usize = obj_size.try_into()
             .map_err(|_| ioerr!("Failed to convert u128 into usize in order to get object size. Your architecture might not allow {} to be represented as a usize.", obj_size))?;
+        check_object_size(obj_size, state.max_object_size())?;
 
         // if anything but Ref delta, we should be safe to just
         // call the pack and resolve it:
@@ -311,10 +570,56 @@ impl<'a> LightObjectDB<'a> {
         // from the .idx file to get the index of
         // where its ref base object starts, and then try again.
         let base_oid = full_oid_to_u128_oid(ref_id);
+        // first we try to find the base in the same pack, either with
+        // the state's offset map of this pack, or by searching its idx file.
+        // but if the pack is thin, the base might live outside of it
+        // (loose, or in some other pack), so if its not in our pack
+        // we fall back to a full lookup of the whole object db.
+        let base_location = if let Some(idx) = idx.filter(|idx| idx.id == packed_info.id) {
+            find_location_in_idx(base_oid, idx, pack)?
+        } else if let Some(offsets) = state.get_pack_offset_map(packed_info.id)? {
+            pack.find_object_offset_by_oid(ref_id, &offsets)
+                .zip(offsets.get(ref_id))
+                .map(|(object_starts_at, (_, oid_index))| FoundPackedLocation {
+                    id: packed_info.id,
+                    object_starts_at: object_starts_at as u64,
+                    oid_index,
+                })
+        } else {
+            let mut idx_file = state.get_idx_file(packed_info.id)?;
+            let idx_file = idx_file.as_mut();
+            match idx_file.find_oid_and_fanout_index(base_oid) {
+                Ok(oid_index) => {
+                    let object_starts_at = idx_file.find_packfile_index_checked(oid_index, pack.mmapped_file.len())
+                        .map_err(|e| ioerr!("Found oid index, but failed to find packfile index offset for {:032x}\n{}", base_oid, e))?;
+                    Some(FoundPackedLocation {
+                        id: packed_info.id,
+                        object_starts_at,
+                        oid_index,
+                    })
+                }
+                Err(e) if is_object_not_found(&e) => None,
+                Err(e) => return Err(e),
+            }
+        };
         // we want the unparsed data, so we make sure
         // to specify that:
-        let unparsed_object = self.get_packed_object_from_oid::<UnparsedObject, S>(
-            base_oid, &pack, packed_info.id, state)?;
+        let unparsed_object: UnparsedObject = match base_location {
+            Some(location) => self.get_packed_object_packfile_loaded_with_depth(
+                &location, pack, idx, state, depth + 1)?,
+            None => match self.read_ref_delta_base(base_oid, state, depth + 1) {
+                Ok(obj) => obj,
+                Err(e) => {
+                    // in a partial clone, the base might legitimately
+                    // be missing, and needs to be fetched:
+                    let is_not_found = GitReaderError::from_io_error(&e).map(|g| g.is_not_found()).unwrap_or(false);
+                    if is_not_found && self.pack_flags(packed_info.id)?.promisor {
+                        return Err(GitReaderError::PromisedObjectMissing(base_oid).into());
+                    }
+                    return ioerre!("Failed to find base object {:032x} of ref delta\n{}", base_oid, e);
+                }
+            },
+        };
         // now that we have resolved the base object, we load our object:
         let base_object_data = unparsed_object.payload;
         let base_object_type = unparsed_object.object_type;
@@ -331,6 +636,7 @@ impl<'a> LightObjectDB<'a> {
         let (our_size, num_read) = find_encoded_length(&this_object_data)
             .ok_or_else(|| ioerr!("Failed to find size of object"))?;
         let this_object_data = &this_object_data[num_read..];
+        check_object_size(our_size, state.max_object_size())?;
 
         let data_out = apply_delta(&base_object_data, this_object_data, our_size)?;
         let unparsed_obj = UnparsedObject {
@@ -351,16 +657,7 @@ impl<'a> LightObjectDB<'a> {
               F::Error: ToString,
               S: State,
     {
-        // we need to first construct the path of this pack file:
-        let (
-            packfile_path_str_array, take_index
-        ) = self.get_pack_file_str_array(packed_info.id);
-        // make it into a string:
-        let search_path_str = std::str::from_utf8(&packfile_path_str_array[0..take_index])
-            .map_err(|e| ioerr!("Failed to convert path string to utf8...\n{}", e))?;
-
-        // now read that file:
-        let pack = open_pack_file(search_path_str, packed_info.id)?;
+        let pack = state.get_pack_file(packed_info.id)?;
         self.get_packed_object_packfile_loaded(packed_info, &pack, state)
     }
 
@@ -390,6 +687,121 @@ impl<'a> LightObjectDB<'a> {
         }
     }
 
+    /// the type of the object at `location`, without decompressing its
+    /// payload, ie: for `git cat-file -t`. For a loose object only its header
+    /// is read. For a packed object only its type is read, and if its a delta,
+    /// the types of its bases until one isn't a delta, since a delta is
+    /// always the same type as its base.
+    pub fn object_type_at_location<S: State>(
+        &self,
+        location: &FoundObjectLocation,
+        state: &mut S,
+    ) -> io::Result<UnparsedObjectType> {
+        match location {
+            FoundObjectLocation::FoundLoose(path) => {
+                let (object_type, _) = read_loose_header(path, state.get_decompressor())?;
+                Ok(object_type)
+            }
+            FoundObjectLocation::FoundPacked(info) => {
+                self.packed_object_type_with_depth(info, state, 0)
+            }
+        }
+    }
+
+    /// the actual implementation of `object_type_at_location` for packed objects.
+    /// `depth` is how many ref delta bases deep we are, the same as in
+    /// `get_packed_object_packfile_loaded_with_depth`.
+    fn packed_object_type_with_depth<S: State>(
+        &self,
+        packed_info: &FoundPackedLocation,
+        state: &mut S,
+        mut depth: usize,
+    ) -> io::Result<UnparsedObjectType> {
+        let pack = state.get_pack_file(packed_info.id)?;
+        let mut obj_index: usize = packed_info.object_starts_at.try_into()
+            .map_err(|_| ioerr!("Failed to convert u64 into usize in order to index the packfile. Your architecture might not allow {} to be represented as a usize.", packed_info.object_starts_at))?;
+        loop {
+            if depth > self.max_delta_depth {
+                return ioerre!("Exceeded max delta depth of {} while resolving the type of the object at index {}", self.max_delta_depth, packed_info.object_starts_at);
+            }
+            let (obj_type, _, _) = pack.get_object_type_and_len_at_index(obj_index)?;
+            let ref_id = match obj_type {
+                PackFileObjectType::OfsDelta(base_starts_at) => {
+                    // the base has to be before the delta, which also
+                    // means a broken pack cant make us loop forever:
+                    if base_starts_at >= obj_index {
+                        return ioerre!("Ofs delta at index {} has a base at index {} that does not come before it", obj_index, base_starts_at);
+                    }
+                    obj_index = base_starts_at;
+                    continue;
+                }
+                PackFileObjectType::RefDelta(i) => i,
+                simple => return simple.into_unparsed_type()
+                    .ok_or_else(|| ioerr!("Object at index {} is a delta without a type", obj_index)),
+            };
+            // a ref delta's base can be anywhere in the pack, or
+            // outside of it if the pack is thin, so these count
+            // towards the depth like they do when reading the object:
+            depth += 1;
+            let base_oid = full_oid_to_u128_oid(ref_id);
+            let base_starts_at = if let Some(offsets) = state.get_pack_offset_map(packed_info.id)? {
+                pack.find_object_offset_by_oid(ref_id, &offsets)
+            } else {
+                let mut idx_file = state.get_idx_file(packed_info.id)?;
+                let idx_file = idx_file.as_mut();
+                match idx_file.find_oid_and_fanout_index(base_oid) {
+                    Ok(oid_index) => {
+                        let offset = idx_file.find_packfile_index_checked(oid_index, pack.mmapped_file.len())
+                            .map_err(|e| ioerr!("Found oid index, but failed to find packfile index offset for {:032x}\n{}", base_oid, e))?;
+                        Some(offset as usize)
+                    }
+                    Err(e) if is_object_not_found(&e) => None,
+                    Err(e) => return Err(e),
+                }
+            };
+            match base_starts_at {
+                Some(base_starts_at) => obj_index = base_starts_at,
+                None => return self.ref_delta_base_type(packed_info, base_oid, state, depth),
+            }
+        }
+    }
+
+    /// like `read_ref_delta_base`, but only for the type of the base.
+    /// We only have to read a base in full if its in an alternate
+    /// or the fallback.
+    fn ref_delta_base_type<S: State>(
+        &self,
+        packed_info: &FoundPackedLocation,
+        base_oid: Oid,
+        state: &mut S,
+        depth: usize,
+    ) -> io::Result<UnparsedObjectType> {
+        let found = match self.try_find_first_matching_oid_with_location(base_oid, state)? {
+            Some((_, FoundObjectLocation::FoundLoose(path))) => {
+                return read_loose_header(path, state.get_decompressor()).map(|(object_type, _)| object_type);
+            }
+            Some((_, FoundObjectLocation::FoundPacked(info))) => {
+                return self.packed_object_type_with_depth(&info, state, depth);
+            }
+            None => match self.fetch_from_alternates(base_oid)? {
+                Some(obj) => Some(obj),
+                None => self.fetch_from_fallback(base_oid)?,
+            },
+        };
+        match found {
+            Some(obj) => Ok(obj.object_type),
+            // in a partial clone, the base might legitimately
+            // be missing, and needs to be fetched:
+            None if self.pack_flags(packed_info.id)?.promisor => {
+                Err(GitReaderError::PromisedObjectMissing(base_oid).into())
+            }
+            None => ioerre!("Failed to find base object {:032x} of ref delta\n{}", base_oid, GitReaderError::ObjectNotFound(base_oid)),
+        }
+    }
+
+    /// If the oid does not exist, the returned error is a
+    /// `GitReaderError::ObjectNotFound`, which you can check for
+    /// with `GitReaderError::from_io_error`.
     pub fn get_object_by_oid<F, S>(
         &self,
         oid: Oid,
@@ -399,8 +811,206 @@ impl<'a> LightObjectDB<'a> {
               F::Error: ToString,
               S: State,
     {
-        let (_, location) = self.find_first_matching_oid_with_location(oid, state)?;
-        self.get_object_from_location(location, state)
+        self.get_object_by_oid_with_depth(oid, state, 0)
+    }
+
+    /// like `get_object_by_oid`, but you also get the payload of the
+    /// object (ie: the exact bytes of a commit, which you need to re-sign
+    /// or re-write it) from the same read. The payload is copied before
+    /// it is converted into `F`, so this costs one more allocation of the
+    /// whole object. If you only need one of the two, use `get_object_by_oid`.
+    /// Unlike `get_object_by_oid`, blobs that are loose objects are read in full.
+    pub fn get_object_with_raw<F, S>(
+        &self,
+        oid: Oid,
+        state: &mut S,
+    ) -> io::Result<(F, Vec<u8>)>
+        where F: TryFrom<UnparsedObject>,
+              F::Error: ToString,
+              S: State,
+    {
+        let obj = self.read_object_in_full(oid, state)?;
+        let raw = obj.payload.clone();
+        let transformed = F::try_from(obj)
+            .map_err(|e| ioerr!("Failed to get object\n{}", e.to_string()))?;
+        Ok((transformed, raw))
+    }
+
+    /// read an object with its whole payload, even if it is a loose blob.
+    fn read_object_in_full<S: State>(&self, oid: Oid, state: &mut S) -> io::Result<UnparsedObject> {
+        match self.try_find_first_matching_oid_with_location(oid, state)? {
+            Some((_, FoundObjectLocation::FoundLoose(path))) => read_loose_object_in_full(path, state),
+            Some((_, location)) => self.get_object_from_location(location, state),
+            // not ours, but our fallback might have it:
+            None => self.get_object_by_oid(oid, state),
+        }
+    }
+
+    /// find the base of a ref delta that is not in the delta's own pack.
+    /// It can be loose, in any other pack, or in an alternate or the fallback.
+    /// A loose base is read in full even if its a blob, since we need
+    /// its bytes to apply the delta to. `depth` is the depth of the base,
+    /// so a chain of ref deltas across packs still stops at `max_delta_depth`.
+    fn read_ref_delta_base<S: State>(&self, base_oid: Oid, state: &mut S, depth: usize) -> io::Result<UnparsedObject> {
+        match self.try_find_first_matching_oid_with_location(base_oid, state)? {
+            Some((_, FoundObjectLocation::FoundLoose(path))) => read_loose_object_in_full(path, state),
+            Some((_, FoundObjectLocation::FoundPacked(info))) => {
+                let pack = state.get_pack_file(info.id)?;
+                self.get_packed_object_packfile_loaded_with_depth(&info, &pack, None, state, depth)
+            }
+            None => match self.fetch_from_alternates(base_oid)? {
+                Some(obj) => Ok(obj),
+                None => self.fetch_from_fallback(base_oid)?
+                    .ok_or_else(|| GitReaderError::ObjectNotFound(base_oid).into()),
+            },
+        }
+    }
+
+    /// the contents of a blob. If the state keeps blobs (ie: a `CachingState`),
+    /// reading the same blob again doesn't decompress it again, and everyone
+    /// who asked for it shares the same bytes. Errors if `oid` is not a blob.
+    pub fn get_blob_bytes<S: State>(&self, oid: Oid, state: &mut S) -> io::Result<Arc<[u8]>> {
+        if let Some(blob) = state.get_cached_blob(oid) {
+            return Ok(blob);
+        }
+        let obj = self.read_object_in_full(oid, state)?;
+        if obj.object_type != UnparsedObjectType::Blob {
+            return ioerre!("Expected {:032x} to be a blob, but it is a {}", oid, obj.object_type.as_str());
+        }
+        let blob: Arc<[u8]> = obj.payload.into();
+        state.cache_blob(oid, blob.clone());
+        Ok(blob)
+    }
+
+    /// follow the chain of tags starting at oid until we reach an object of the target type.
+    /// if oid is already of the target type, then it is returned as is. (ie: a lightweight
+    /// tag pointing to a commit). Like git, if we reach a commit but want a tree, we
+    /// return the commit's tree.
+    /// This is `git rev-parse <oid>^{<target>}`
+    pub fn peel_to_type<S: State>(
+        &self,
+        oid: Oid,
+        target: UnparsedObjectType,
+        state: &mut S,
+    ) -> io::Result<Oid> {
+        let mut current = oid;
+        for _ in 0..MAX_PEEL_DEPTH {
+            let obj: UnparsedObject = self.get_object_by_oid(current, state)?;
+            if obj.object_type == target {
+                return Ok(current);
+            }
+            current = match obj.object_type {
+                UnparsedObjectType::Tag => TagObject::parse(&obj.payload)?.object,
+                UnparsedObjectType::Commit if target == UnparsedObjectType::Tree => {
+                    let (tree, _) = commit_object_parsing::parse_tree(&obj.payload, true)?;
+                    tree
+                }
+                other => {
+                    return ioerre!("Cannot peel {:032x} to a {}, it is a {}", oid, target.as_str(), other.as_str());
+                }
+            };
+        }
+        ioerre!("Failed to peel {:032x}: exceeded max depth of {} while following tags", oid, MAX_PEEL_DEPTH)
+    }
+
+    /// `git rev-parse <oid>^{commit}`. See `peel_to_type`
+    pub fn peel_to_commit<S: State>(&self, oid: Oid, state: &mut S) -> io::Result<Oid> {
+        self.peel_to_type(oid, UnparsedObjectType::Commit, state)
+    }
+
+    /// Fetch many objects at once. Instead of calling `get_object_by_oid`
+    /// for each oid (which would open a pack file every time) we first find
+    /// where every oid lives, and then open each pack file only once, resolving
+    /// all of the objects that live in that pack (in the order they appear in the pack)
+    /// before moving on to the next pack.
+    /// The callback is called once per oid with the result of reading that
+    /// object, so one bad oid does not stop the rest of the batch.
+    /// Note that the callback is not necessarily called in the same order as `oids`.
+    pub fn get_objects_batch<F, S, C>(
+        &self,
+        oids: &[Oid],
+        state: &mut S,
+        cb: &mut C,
+    )
+        where F: TryFrom<UnparsedObject>,
+              F::Error: ToString,
+              S: State,
+              C: FnMut(Oid, io::Result<F>),
+    {
+        let mut packed_by_id: BTreeMap<OidFull, Vec<(Oid, FoundPackedLocation)>> = BTreeMap::new();
+        for oid in oids.iter() {
+            let oid = *oid;
+            match self.try_find_first_matching_oid_with_location(oid, state) {
+                Ok(Some((_, FoundObjectLocation::FoundLoose(path)))) => {
+                    cb(oid, self.get_loose_object(&path, state));
+                }
+                Ok(Some((_, FoundObjectLocation::FoundPacked(info)))) => {
+                    packed_by_id.entry(info.id).or_default().push((oid, info));
+                }
+                Ok(None) => cb(oid, Err(GitReaderError::ObjectNotFound(oid).into())),
+                Err(e) => cb(oid, Err(e)),
+            }
+        }
+
+        for (pack_id, mut objects) in packed_by_id {
+            let pack = match state.get_pack_file(pack_id) {
+                Ok(p) => p,
+                Err(e) => {
+                    // every object in this pack fails the same way. The first
+                    // gets the error, the rest get copies of it:
+                    let mut objects = objects.into_iter();
+                    if let Some((oid, _)) = objects.next() {
+                        let copies = objects.map(|(oid, _)| (oid, GitReaderError::copy_io_error(&e))).collect::<Vec<_>>();
+                        cb(oid, Err(e));
+                        for (oid, copy) in copies {
+                            cb(oid, Err(copy));
+                        }
+                    }
+                    continue;
+                }
+            };
+            // reading in pack order is friendlier to the page cache
+            objects.sort_unstable_by_key(|(_, info)| info.object_starts_at);
+            for (oid, info) in objects {
+                cb(oid, self.get_packed_object_packfile_loaded(&info, &pack, state));
+            }
+        }
+    }
+
+    /// like `get_object_by_oid`, but keeps track of the delta depth
+    /// so that if this object is a ref delta base of some other object,
+    /// we can still terminate.
+    fn get_object_by_oid_with_depth<F, S>(
+        &self,
+        oid: Oid,
+        state: &mut S,
+        depth: usize,
+    ) -> io::Result<F>
+        where F: TryFrom<UnparsedObject>,
+              F::Error: ToString,
+              S: State,
+    {
+        let location = match self.try_find_first_matching_oid_with_location(oid, state)? {
+            Some((_, location)) => location,
+            None => {
+                let obj = match self.fetch_from_alternates(oid)? {
+                    Some(obj) => obj,
+                    None => self.fetch_from_fallback(oid)?
+                        .ok_or(GitReaderError::ObjectNotFound(oid))?,
+                };
+                return F::try_from(obj)
+                    .map_err(|e| ioerr!("Failed to get object from alternate or fallback\n{}", e.to_string()));
+            }
+        };
+        match location {
+            FoundObjectLocation::FoundLoose(path) => {
+                self.get_loose_object(&path, state)
+            }
+            FoundObjectLocation::FoundPacked(info) => {
+                let pack = state.get_pack_file(info.id)?;
+                self.get_packed_object_packfile_loaded_with_depth(&info, &pack, None, state, depth)
+            }
+        }
     }
 
     pub fn find_matching_oids_loose<F, S>(
@@ -461,9 +1071,10 @@ impl<'a> LightObjectDB<'a> {
         // our file name should be at least 45 chars long:
         // pack-{40hexchars}.idx
         // we want just the 40 hex chars:
-        let idx_hex_str = idx_file_name.get(5..45)
-            .ok_or_else(|| ioerr!("Failed to extract hex chars from idx file name: {}", idx_file_name))?;
-        let (idx_str_array, take_to) = self.get_idx_file_str_array_from_hash(idx_hex_str.as_bytes());
+        let idx_id = try_parse_pack_or_idx_id(idx_file_name)?
+            .ok_or_else(|| ioerr!("Failed to extract hex chars from idx file name: {} is not named like an idx file", idx_file_name))?;
+        let idx_hex_str = oid_full_to_string_no_alloc(idx_id);
+        let (idx_str_array, take_to) = self.get_idx_file_str_array_from_hash(&idx_hex_str);
         let search_path_str = std::str::from_utf8(&idx_str_array[0..take_to])
             .map_err(|e| ioerr!("Failed to convert path string to utf8...\n{}", e))?;
         // println!("reading idx file: {}", search_path_str);
@@ -484,6 +1095,39 @@ impl<'a> LightObjectDB<'a> {
         Ok(idx_file)
     }
 
+    /// open the pack file "pack/pack-{id}.pack" of this object db.
+    pub fn open_pack(&self, id: OidFull) -> io::Result<PackFile> {
+        let (pack_str_array, take_to) = self.get_pack_file_str_array(id);
+        let search_path_str = std::str::from_utf8(&pack_str_array[0..take_to])
+            .map_err(|e| ioerr!("Failed to convert path string to utf8...\n{}", e))?;
+        open_pack_file(search_path_str, id)
+    }
+
+    /// whether the pack with this id is kept and/or is a promisor pack.
+    pub fn pack_flags(&self, id: OidFull) -> io::Result<PackFlags> {
+        let (pack_str_array, take_to) = self.get_pack_file_str_array(id);
+        let pack_path_str = std::str::from_utf8(&pack_str_array[0..take_to])
+            .map_err(|e| ioerr!("Failed to convert path string to utf8...\n{}", e))?;
+        Ok(read_pack_flags(pack_path_str))
+    }
+
+    /// the id of every pack this state knows about, along with
+    /// whether its kept or a promisor pack.
+    pub fn list_packs_with_flags<S: State>(&self, state: &mut S) -> io::Result<Vec<(OidFull, PackFlags)>> {
+        let mut ids = vec![];
+        state.iter_known_packs(&mut |_, id| {
+            ids.push(id);
+            false
+        })?;
+        ids.into_iter().map(|id| Ok((id, self.pack_flags(id)?))).collect()
+    }
+
+    /// open the idx file "pack/pack-{id}.idx" of this object db.
+    /// this is the same as `read_idx_file_from_id`.
+    pub fn open_idx(&self, id: OidFull) -> io::Result<IDXFileLight> {
+        self.read_idx_file_from_id(id)
+    }
+
     pub fn find_matching_oids_packed<F, S>(
         &self,
         partial_oid: PartialOid,
@@ -521,6 +1165,46 @@ impl<'a> LightObjectDB<'a> {
         })
     }
 
+    /// like `find_matching_oids_packed` but if any idx file
+    /// fails to be read, we stop searching, and return an error
+    /// that contains the name of the idx file that failed.
+    /// Useful for integrity checking, where you don't want a
+    /// corrupt pack to silently be skipped.
+    pub fn find_matching_oids_packed_strict<F, S>(
+        &self,
+        partial_oid: PartialOid,
+        state: &mut S,
+        cb: &mut F,
+    ) -> io::Result<()>
+        where F: FnMut(Oid),
+              S: State,
+    {
+        let partial_oid_first_byte = partial_oid.get_first_byte();
+        let mut had_error = Ok(());
+        state.iter_known_packs(&mut |state2, idx_id| {
+            let mut idx_file = match state2.get_idx_file(idx_id) {
+                Ok(f) => f,
+                Err(e) => {
+                    let idx_hex_str = oid_full_to_string_no_alloc(idx_id);
+                    let idx_hex_str = String::from_utf8_lossy(&idx_hex_str);
+                    had_error = ioerre!("Failed to read idx file pack-{}.idx\n{}", idx_hex_str, e);
+                    // stop iterating, we report the error below
+                    return true;
+                }
+            };
+            let idx_file = idx_file.as_mut();
+            idx_file.walk_all_oids_from(Some(partial_oid_first_byte), |oid| {
+                let found_oid_first_byte = get_first_byte_of_oid(oid);
+                if partial_oid.matches(oid) {
+                    cb(oid);
+                }
+                found_oid_first_byte > partial_oid_first_byte
+            });
+            false
+        })?;
+        had_error
+    }
+
     /// The callback should return true if you want to stop
     /// searching.
     pub fn find_matching_oids_packed_with_locations<F, M, S>(
@@ -589,11 +1273,48 @@ impl<'a> LightObjectDB<'a> {
         Ok(())
     }
 
-    pub fn find_first_matching_oid_with_location<M, S>(
+    /// resolve an abbreviated hex oid like git does. It is an error
+    /// (of kind `InvalidInput`) if the prefix is shorter than `min_len`
+    /// (see `DEFAULT_MIN_ABBREV`), or shorter than 2 hex characters, since
+    /// we look objects up by their first byte.
+    /// If it matches more than one object, the error is a `GitReaderError::Ambiguous`
+    /// with every candidate, and if nothing matches the error has a
+    /// kind of `io::ErrorKind::NotFound`.
+    pub fn resolve_short<S: State>(
+        &self,
+        prefix: &str,
+        min_len: usize,
+        state: &mut S,
+    ) -> io::Result<(Oid, FoundObjectLocation)> {
+        if prefix.len() < min_len.max(2) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("Short object id '{}' is too short, it must be at least {} hex characters", prefix, min_len.max(2))));
+        }
+        let partial_oid = PartialOid::from_hash(prefix)?;
+        // the same object can be both loose and packed, so we
+        // dedupe by oid:
+        let mut found: BTreeMap<Oid, FoundObjectLocation> = BTreeMap::new();
+        self.find_matching_oids_with_locations(partial_oid, state, |oid, location| {
+            found.entry(oid).or_insert(location);
+        })?;
+        if found.len() > 1 {
+            return Err(GitReaderError::Ambiguous(found.keys().copied().collect()).into());
+        }
+        match found.into_iter().next() {
+            Some(f) => Ok(f),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("Failed to find object matching '{}'", prefix))),
+        }
+    }
+
+    /// Find the first object matching the partial oid, looking through
+    /// loose objects first, and then packed objects.
+    /// If nothing matches, this returns Ok(None), so you can tell
+    /// apart an object not existing (Ok(None)) from failing to read something (Err).
+    pub fn try_find_first_matching_oid_with_location<M, S>(
         &self,
         partial_oid: M,
         state: &mut S,
-    ) -> io::Result<(Oid, FoundObjectLocation)>
+    ) -> io::Result<Option<(Oid, FoundObjectLocation)>>
         where M: DoesMatch,
               S: State,
     {
@@ -603,8 +1324,8 @@ impl<'a> LightObjectDB<'a> {
             true
         };
         self.find_matching_oids_loose_with_locations(partial_oid, state, &mut cb_wrapper)?;
-        if let Some(f) = found {
-            return Ok(f);
+        if found.is_some() {
+            return Ok(found);
         }
         let mut found: Option<(Oid, FoundObjectLocation)> = None;
         let mut cb_wrapper = |oid, location| {
@@ -612,12 +1333,25 @@ impl<'a> LightObjectDB<'a> {
             true
         };
         self.find_matching_oids_packed_with_locations(partial_oid, state, &mut cb_wrapper)?;
-        match found {
+        Ok(found)
+    }
+
+    /// same as `try_find_first_matching_oid_with_location` but it is an
+    /// error if nothing matches. that error has a kind of `io::ErrorKind::NotFound`.
+    pub fn find_first_matching_oid_with_location<M, S>(
+        &self,
+        partial_oid: M,
+        state: &mut S,
+    ) -> io::Result<(Oid, FoundObjectLocation)>
+        where M: DoesMatch,
+              S: State,
+    {
+        match self.try_find_first_matching_oid_with_location(partial_oid, state)? {
             Some(f) => Ok(f),
             None => {
                 // TODO: should add debug requirement for M so we can print which
                 // one we failed to find...
-                return ioerre!("Failed to find a matching oid/location");
+                Err(io::Error::new(io::ErrorKind::NotFound, "Failed to find a matching oid/location"))
             }
         }
     }
@@ -715,9 +1449,611 @@ impl<'a> LightObjectDB<'a> {
         })?;
         Ok(())
     }
+
+    /// count the loose objects and the pack files (and their objects)
+    /// of this object db. Useful for deciding when to repack.
+    pub fn object_counts<S: State>(&self, state: &mut S) -> io::Result<ObjectCounts> {
+        let mut counts = ObjectCounts::default();
+        let mut had_error = Ok(());
+        for folder_byte in 0u8..=255 {
+            state.iter_loose_folder(folder_byte, &mut |_oid, folder_path, filename| {
+                let file_path = Path::new(folder_path).join(filename);
+                match std::fs::metadata(&file_path) {
+                    Ok(m) => {
+                        counts.loose_count += 1;
+                        counts.loose_size_bytes += m.len();
+                        false
+                    }
+                    Err(e) => {
+                        had_error = ioerre!("Failed to read metadata of {:?}\n{}", file_path, e);
+                        true
+                    }
+                }
+            })?;
+            had_error?;
+            had_error = Ok(());
+        }
+        state.iter_known_packs(&mut |state2, idx_id| {
+            let idx_file = state2.get_idx_file(idx_id);
+            let mut idx_file = match idx_file {
+                Ok(f) => f,
+                Err(e) => {
+                    had_error = Err(e);
+                    return true;
+                }
+            };
+            counts.in_pack_count += idx_file.as_mut().num_objects();
+            let (pack_str_array, take_to) = self.get_pack_file_str_array(idx_id);
+            let pack_path = String::from_utf8_lossy(&pack_str_array[0..take_to]);
+            match std::fs::metadata(pack_path.as_ref()) {
+                Ok(m) => {
+                    counts.pack_count += 1;
+                    counts.pack_size_bytes += m.len();
+                    false
+                }
+                Err(e) => {
+                    had_error = ioerre!("Failed to read metadata of {}\n{}", pack_path, e);
+                    true
+                }
+            }
+        })?;
+        had_error?;
+        Ok(counts)
+    }
+
+    /// how many objects have an oid starting with each byte, ie:
+    /// `histogram[0xab]` is how many oids start with "ab". For packs, this
+    /// is read from the fanout tables of their idx files, so it doesn't
+    /// read any oids. Loose objects are counted from their folders.
+    /// An object that is in several packs (or is both loose and packed)
+    /// is counted several times, so treat this as an estimate.
+    pub fn first_byte_histogram<S: State>(&self, state: &mut S) -> io::Result<[u64; 256]> {
+        let mut histogram = [0u64; 256];
+        for folder_byte in 0u8..=255 {
+            state.iter_loose_folder(folder_byte, &mut |_oid, _folder_path, _filename| {
+                histogram[folder_byte as usize] += 1;
+                false
+            })?;
+        }
+        let mut had_error = Ok(());
+        state.iter_known_packs(&mut |state2, idx_id| {
+            let mut idx_file = match state2.get_idx_file(idx_id) {
+                Ok(f) => f,
+                Err(e) => {
+                    had_error = Err(e);
+                    return true;
+                }
+            };
+            let idx_file = idx_file.as_mut();
+            for (first_byte, count) in histogram.iter_mut().enumerate() {
+                *count += idx_file.num_objects_with_first_byte(first_byte as u8) as u64;
+            }
+            false
+        })?;
+        had_error?;
+        Ok(histogram)
+    }
+
+    /// the ids of every pack in `pack/`, sorted and deduped. Only
+    /// the filenames of the .idx files are read, nothing is opened,
+    /// so this is cheap even for a repo with many packs. A repo without
+    /// a `pack/` folder has no packs.
+    pub fn list_pack_ids<S: State>(&self, state: &mut S) -> io::Result<Vec<OidFull>> {
+        let (take_index, big_str_array) = state.try_get_static_path_str(b"pack")?;
+        let search_path_str = std::str::from_utf8(&big_str_array[0..take_index])
+            .map_err(|e| ioerr!("Failed to convert path string to utf8...\n{}", e))?;
+        let mut pack_ids = vec![];
+        fs_helpers::search_folder_out_missing_ok(search_path_str, |entry| {
+            let filename = entry.file_name();
+            if let Some(idx_id) = filename.to_str()
+                .filter(|f| f.ends_with(".idx"))
+                .and_then(parse_pack_or_idx_id)
+            {
+                pack_ids.push(idx_id);
+            }
+            Ok(())
+        })?;
+        pack_ids.sort_unstable();
+        pack_ids.dedup();
+        Ok(pack_ids)
+    }
+
+    /// the size and modification time of every pack, and the modification
+    /// time of every loose folder. Nothing is opened, this only reads
+    /// the metadata of the files. Keep the result around and compare it
+    /// with a later one to find out if the packs changed (ie: a repack)
+    /// or if new loose objects were written, see `ObjectsMetadata`.
+    pub fn objects_metadata<S: State>(&self, state: &mut S) -> io::Result<ObjectsMetadata> {
+        let mut out = ObjectsMetadata::default();
+        let (take_index, big_str_array) = state.try_get_static_path_str(b"pack")?;
+        let search_path_str = std::str::from_utf8(&big_str_array[0..take_index])
+            .map_err(|e| ioerr!("Failed to convert path string to utf8...\n{}", e))?;
+        fs_helpers::search_folder_out_missing_ok(search_path_str, |entry| {
+            let filename = entry.file_name();
+            let id = match filename.to_str().filter(|f| f.ends_with(".pack")).and_then(parse_pack_or_idx_id) {
+                Some(id) => id,
+                None => return Ok(()),
+            };
+            let metadata = entry.metadata()?;
+            out.packs.push(PackMetadata { id, size: metadata.len(), modified: metadata.modified()? });
+            Ok(())
+        })?;
+        out.packs.sort_unstable_by_key(|p| p.id);
+
+        let (folder_starts_at, mut path_buf) = state.get_path_to_db_as_bytes();
+        let take_to = folder_starts_at + 2;
+        for (folder_byte, hex_first_byte) in HEX_BYTES.iter().enumerate() {
+            path_buf[folder_starts_at..take_to].copy_from_slice(hex_first_byte);
+            let folder_path = std::str::from_utf8(&path_buf[0..take_to])
+                .map_err(|e| ioerr!("Failed to convert path string to utf8...\n{}", e))?;
+            match std::fs::metadata(folder_path) {
+                Ok(m) => out.loose_folders.push((folder_byte as u8, m.modified()?)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(out)
+    }
+
+    /// find every object (loose and packed) whose hash starts
+    /// with `prefix`, which must be 1 to 8 hex characters, ie: "ab".
+    /// The returned oids are sorted and deduped. Useful
+    /// for things like shell-completion of object ids.
+    pub fn oids_with_prefix<S: State>(&self, prefix: &str, state: &mut S) -> io::Result<Vec<Oid>> {
+        if prefix.is_empty() || prefix.len() > 8 {
+            return ioerre!("Prefix '{}' must be between 1 and 8 hex characters", prefix);
+        }
+        if !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return ioerre!("Prefix '{}' contains non hex characters", prefix);
+        }
+        let prefix = prefix.to_ascii_lowercase();
+        let mut out = vec![];
+        if prefix.len() == 1 {
+            // the loose folders and the idx walks are per first *byte*,
+            // but a single hex char only covers half of one, ie: "a" is
+            // every byte from 0xa0 to 0xaf, so we search each of them:
+            for second_char in "0123456789abcdef".chars() {
+                let byte_prefix = format!("{}{}", prefix, second_char);
+                let partial_oid = PartialOid::from_hash(&byte_prefix)?;
+                self.find_matching_oids(partial_oid, state, |oid| out.push(oid))?;
+            }
+        } else {
+            let partial_oid = PartialOid::from_hash(&prefix)?;
+            self.find_matching_oids(partial_oid, state, |oid| out.push(oid))?;
+        }
+        out.sort_unstable();
+        out.dedup();
+        Ok(out)
+    }
+
+    /// collect every oid in the object db. every loose object, and every
+    /// object in every pack file. An oid will only appear once, even
+    /// if it exists both loose and packed, or in multiple packs.
+    /// The returned oids are sorted.
+    pub fn all_oids<S: State>(&self, state: &mut S) -> io::Result<Vec<Oid>> {
+        // we use the map only to dedup, the oid is all we care about.
+        let mut seen: OidMap<(), B14> = OidMap::default();
+        state.iter_all_loose(&mut |oid, _oid_full| {
+            if !seen.contains_key(&oid) {
+                seen.insert(oid, ());
+            }
+            false
+        })?;
+        let mut pack_ids = vec![];
+        self.get_all_packs(&mut |idx_id| {
+            pack_ids.push(idx_id);
+        })?;
+        for idx_id in pack_ids {
+            let mut idx_file = state.get_idx_file(idx_id)?;
+            let idx_file = idx_file.as_mut();
+            idx_file.walk_all_oids_from(None, |oid| {
+                if !seen.contains_key(&oid) {
+                    seen.insert(oid, ());
+                }
+                false
+            });
+        }
+        // iterating the map gives us the oids in sorted order:
+        let out = seen.iter().map(|(oid, _)| *oid).collect();
+        Ok(out)
+    }
+
+    /// same as `all_oids` but returns an iterator instead of a vec for
+    /// convenience. Note that all of the oids are still collected
+    /// before this returns, the iteration itself cannot fail.
+    pub fn iter_all_oids<S: State>(&self, state: &mut S) -> io::Result<std::vec::IntoIter<Oid>> {
+        Ok(self.all_oids(state)?.into_iter())
+    }
+
+    /// every object reachable from `root`, including itself: for a commit,
+    /// its tree, every subtree and blob of that tree, and then the same
+    /// for each of its parents, all the way back. `root` can also be a tag
+    /// (its target is followed), a tree, or a blob.
+    /// Gitlinks are not followed, since their commits are in other repos.
+    /// Objects are visited breadth first, and each one is only returned once.
+    /// Blobs are never read, we know they are blobs from the tree they are in.
+    /// Like `iter_all_oids`, every oid is collected before this returns.
+    /// This is what `git rev-list --objects` walks.
+    pub fn reachable_objects<S: State>(&self, root: Oid, state: &mut S) -> io::Result<std::vec::IntoIter<Oid>> {
+        let mut seen: OidMap<(), B14> = OidMap::default();
+        let mut out = vec![];
+        // objects to read. None means we dont know what type it
+        // is yet (the root, or the target of a tag).
+        let mut queue: VecDeque<(Oid, Option<UnparsedObjectType>)> = VecDeque::new();
+        seen.insert(root, ());
+        queue.push_back((root, None));
+        while let Some((oid, known_type)) = queue.pop_front() {
+            out.push(oid);
+            if known_type == Some(UnparsedObjectType::Blob) {
+                continue;
+            }
+            self.visit_object_links(oid, known_type, state, |next, next_type| {
+                if !seen.contains_key(&next) {
+                    seen.insert(next, ());
+                    queue.push_back((next, next_type));
+                }
+            })?;
+        }
+        Ok(out.into_iter())
+    }
+
+    /// read the object `oid` (which is not a blob) and call `visit` with
+    /// every object it points to, and what type that object is if we know it:
+    /// a commit's tree and parents, a tree's subtrees and blobs, or a tag's target.
+    /// If `known_type` is given, its an error for the object to be any other type.
+    pub(crate) fn visit_object_links<S, F>(
+        &self,
+        oid: Oid,
+        known_type: Option<UnparsedObjectType>,
+        state: &mut S,
+        mut visit: F,
+    ) -> io::Result<()>
+        where S: State,
+              F: FnMut(Oid, Option<UnparsedObjectType>),
+    {
+        let obj: UnparsedObject = self.get_object_by_oid(oid, state)?;
+        if let Some(expected) = known_type {
+            if expected != obj.object_type {
+                return ioerre!("Expected {:032x} to be a {}, but it is a {}", oid, expected.as_str(), obj.object_type.as_str());
+            }
+        }
+        match obj.object_type {
+            UnparsedObjectType::Commit => {
+                let commit = CommitOnlyTreeAndParents::parse(&obj.payload)?;
+                visit(commit.tree, Some(UnparsedObjectType::Tree));
+                for parent in commit.parents() {
+                    visit(parent, Some(UnparsedObjectType::Commit));
+                }
+            }
+            UnparsedObjectType::Tree => {
+                let tree = TreeObject::parse(&obj.payload)?;
+                for entry in tree.entries.iter() {
+                    if entry.entry_mode.is_dir() {
+                        visit(entry.id(), Some(UnparsedObjectType::Tree));
+                    } else if entry.entry_mode.is_blob() {
+                        visit(entry.id(), Some(UnparsedObjectType::Blob));
+                    }
+                }
+            }
+            UnparsedObjectType::Tag => {
+                let tag = TagObject::parse(&obj.payload)?;
+                visit(tag.object, None);
+            }
+            UnparsedObjectType::Blob => {}
+        }
+        Ok(())
+    }
+}
+
+/// one pack file, as seen by `LightObjectDB::objects_metadata`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackMetadata {
+    pub id: OidFull,
+    /// size of the .pack file in bytes
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// a snapshot of the files of an object db, see `LightObjectDB::objects_metadata`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectsMetadata {
+    /// sorted by id
+    pub packs: Vec<PackMetadata>,
+    /// the first byte of each loose folder that exists, and its
+    /// modification time. Sorted by the first byte.
+    pub loose_folders: Vec<(u8, SystemTime)>,
+}
+
+impl ObjectsMetadata {
+    /// true if a pack was added, removed, or modified
+    /// between `previous` and this snapshot.
+    pub fn packs_changed(&self, previous: &ObjectsMetadata) -> bool {
+        self.packs != previous.packs
+    }
+
+    /// the loose folders that were created or modified since `previous`.
+    /// Writing or deleting a loose object modifies its folder, so
+    /// these are the only folders that need to be read again.
+    pub fn changed_loose_folders(&self, previous: &ObjectsMetadata) -> Vec<u8> {
+        self.loose_folders.iter()
+            .filter(|folder| !previous.loose_folders.contains(folder))
+            .map(|(folder_byte, _)| *folder_byte)
+            .collect()
+    }
 }
 
 pub enum Location {
     Loose(Oid, u32),
     Packed(OidFull),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Decompress;
+    use state::{MinState, OwnedOrBorrowedMut};
+    use crate::object_id::{full_oid_from_str, hash_str_to_oid};
+    use crate::test_helpers::{packed_fixture, copy_packed_fixture_objects, OFS_DELTA_PACK, REF_DELTA_PACK, PACKED_COMMITS};
+
+    /// a state that counts how many times something asked it for
+    /// an idx file, and never gives it one.
+    struct NoIdxState {
+        inner: MinState,
+        idx_requests: usize,
+    }
+
+    impl State for NoIdxState {
+        type Idx = IDXFileLight;
+
+        fn get_decompressor(&mut self) -> &mut Decompress {
+            self.inner.get_decompressor()
+        }
+
+        fn get_idx_file(&mut self, id: OidFull) -> io::Result<OwnedOrBorrowedMut<'_, Self::Idx>> {
+            self.idx_requests += 1;
+            ioerre!("Asked for the idx file of {}", oid_full_to_string(id))
+        }
+
+        fn iter_loose_folder<F>(&mut self, folder_byte: u8, cb: &mut F) -> io::Result<()>
+            where F: FnMut(Oid, &str, &str) -> bool
+        {
+            self.inner.iter_loose_folder(folder_byte, cb)
+        }
+
+        fn iter_known_packs<F>(&mut self, _cb: &mut F) -> io::Result<()>
+            where F: FnMut(&mut Self, OidFull) -> bool
+        {
+            Ok(())
+        }
+
+        fn get_path_to_db_as_bytes(&self) -> (usize, [u8; MAX_PATH_TO_DB_LEN]) {
+            self.inner.get_path_to_db_as_bytes()
+        }
+    }
+
+    #[test]
+    fn given_idx_is_used_for_ref_delta_bases() {
+        let objects_dir = packed_fixture().join("objects");
+        let objects_dir = objects_dir.to_str().unwrap();
+        let odb = LightObjectDB::new(objects_dir).unwrap();
+        let pack_id = full_oid_from_str(REF_DELTA_PACK).unwrap();
+        let idx = odb.open_idx(pack_id).unwrap();
+        let pack = odb.open_pack(pack_id).unwrap();
+        let mut state = NoIdxState { inner: MinState::new(objects_dir).unwrap(), idx_requests: 0 };
+        let mut min_state = MinState::new(objects_dir).unwrap();
+
+        // file.txt of the last commit, a ref delta of the one before it:
+        let delta = hash_str_to_oid("e174d2e582b1073ec45f9ff5638e1fd4c633fdfa").unwrap();
+        let location = find_location_in_idx(delta, &idx, &pack).unwrap().unwrap();
+        let (obj_type, _, _) = pack.get_object_type_and_len_at_index(location.object_starts_at as usize).unwrap();
+        assert!(matches!(obj_type, PackFileObjectType::RefDelta(_)));
+
+        let obj: UnparsedObject = odb.get_packed_object_with_idx_and_pack(delta, &idx, &pack, &mut state).unwrap();
+        let expected: UnparsedObject = odb.get_object_by_oid(delta, &mut min_state).unwrap();
+        assert_eq!(obj.object_type, UnparsedObjectType::Blob);
+        assert!(obj.payload == expected.payload);
+        assert_eq!(state.idx_requests, 0);
+
+        // and without the idx, the state is asked for it:
+        let err = odb.get_packed_object_packfile_loaded::<UnparsedObject, _>(&location, &pack, &mut state).unwrap_err();
+        assert!(err.to_string().contains("Asked for the idx file"), "{}", err);
+        assert_eq!(state.idx_requests, 1);
+    }
+
+    /// an idx that we can't search, ie: its file is unreadable.
+    struct BrokenIdx(IDXFileLight);
+
+    impl IDXState for BrokenIdx {
+        fn find_oid_and_fanout_index(&mut self, oid: Oid) -> io::Result<usize> {
+            ioerre!("Broken idx can't search for {:032x}", oid)
+        }
+
+        fn find_packfile_index_from_fanout_index(&mut self, fanout_index: usize) -> Option<u64> {
+            self.0.find_packfile_index_from_fanout_index(fanout_index)
+        }
+
+        fn walk_all_oids_from<F>(&mut self, start_byte: Option<u8>, cb: F)
+            where F: FnMut(Oid) -> bool
+        {
+            self.0.walk_all_oids_from(start_byte, cb)
+        }
+
+        fn get_partial_matches_with_locations<F, P>(&mut self, start_byte: Option<u8>, partial: P, cb: &mut F)
+            where F: FnMut(Oid, FoundObjectLocation) -> bool,
+                  P: DoesMatch
+        {
+            IDXState::get_partial_matches_with_locations(&mut self.0, start_byte, partial, cb)
+        }
+
+        fn id(&self) -> OidFull {
+            self.0.id
+        }
+
+        fn num_objects(&self) -> usize {
+            self.0.num_objects
+        }
+    }
+
+    struct BrokenIdxState {
+        inner: MinState,
+    }
+
+    impl State for BrokenIdxState {
+        type Idx = BrokenIdx;
+
+        fn get_decompressor(&mut self) -> &mut Decompress {
+            self.inner.get_decompressor()
+        }
+
+        fn get_idx_file(&mut self, id: OidFull) -> io::Result<OwnedOrBorrowedMut<'_, Self::Idx>> {
+            Ok(OwnedOrBorrowedMut::Owned(BrokenIdx(self.inner.open_idx_file(id)?)))
+        }
+
+        fn iter_loose_folder<F>(&mut self, folder_byte: u8, cb: &mut F) -> io::Result<()>
+            where F: FnMut(Oid, &str, &str) -> bool
+        {
+            self.inner.iter_loose_folder(folder_byte, cb)
+        }
+
+        fn iter_known_packs<F>(&mut self, _cb: &mut F) -> io::Result<()>
+            where F: FnMut(&mut Self, OidFull) -> bool
+        {
+            Ok(())
+        }
+
+        fn get_path_to_db_as_bytes(&self) -> (usize, [u8; MAX_PATH_TO_DB_LEN]) {
+            self.inner.get_path_to_db_as_bytes()
+        }
+    }
+
+    #[test]
+    fn idx_errors_are_not_treated_as_missing_bases() {
+        let objects_dir = packed_fixture().join("objects");
+        let objects_dir = objects_dir.to_str().unwrap();
+        let odb = LightObjectDB::new(objects_dir).unwrap();
+        let pack_id = full_oid_from_str(REF_DELTA_PACK).unwrap();
+        let idx = odb.open_idx(pack_id).unwrap();
+        let pack = odb.open_pack(pack_id).unwrap();
+        let delta = hash_str_to_oid("e174d2e582b1073ec45f9ff5638e1fd4c633fdfa").unwrap();
+        let location = find_location_in_idx(delta, &idx, &pack).unwrap().unwrap();
+
+        // the base is in the same pack, but we can't search its idx. That
+        // should be the error, not a failed lookup of the base elsewhere:
+        let mut state = BrokenIdxState { inner: MinState::new(objects_dir).unwrap() };
+        let err = odb.get_packed_object_packfile_loaded::<UnparsedObject, _>(&location, &pack, &mut state).unwrap_err();
+        assert!(err.to_string().contains("Broken idx"), "{}", err);
+        assert!(!is_object_not_found(&err));
+        // but an oid that the idx doesn't have is still not found:
+        assert!(is_object_not_found(&idx.find_oid_and_fanout_index(1).unwrap_err()));
+    }
+
+    #[test]
+    fn ref_delta_bases_outside_of_the_pack_work() {
+        use crate::object_database::{loose::write_raw_object, packed::make_delta};
+        use crate::object_id::{OidFull, full_oid_to_u128_oid, oid_full_to_string};
+        use crate::sha1::hash_object;
+        use crate::test_helpers::{TempDir, PackObject, pack_bytes, v1_idx_bytes};
+        let objects_dir = TempDir::objects_dir("thin_pack");
+        // a pack of a single ref delta, and a v1 idx for it:
+        let write_thin_pack = |pack_id: OidFull, base: &[u8], target: &[u8]| {
+            let delta = make_delta(base, target);
+            let objects = [PackObject::RefDelta(hash_object("blob", base), &delta)];
+            let (pack, offsets) = pack_bytes(&objects);
+            let oid = hash_object("blob", target);
+            let idx = v1_idx_bytes(&[(oid, offsets[0] as u32)]);
+            let name = objects_dir.join("pack").join(format!("pack-{}", oid_full_to_string(pack_id)));
+            std::fs::write(name.with_extension("pack"), pack).unwrap();
+            std::fs::write(name.with_extension("idx"), idx).unwrap();
+            full_oid_to_u128_oid(oid)
+        };
+        let loose_base = b"the base is a loose blob, its bytes have to be read\n";
+        let middle = b"the base is a loose blob, its bytes have to be read!\n";
+        let top = b"the base is a loose blob, its bytes have to be read!!\n";
+        write_raw_object(&objects_dir, &UnparsedObjectType::Blob, loose_base).unwrap();
+        // top is a delta of middle, which is a delta in another pack of the loose blob:
+        let middle_oid = write_thin_pack([0xaa; 20], loose_base, middle);
+        let top_oid = write_thin_pack([0xbb; 20], middle, top);
+
+        let objects_dir_str = objects_dir.path_str();
+        let mut state = MinState::new(objects_dir_str).unwrap();
+        let mut odb = LightObjectDB::new(objects_dir_str).unwrap();
+        let obj: UnparsedObject = odb.get_object_by_oid(middle_oid, &mut state).unwrap();
+        assert_eq!(obj.payload, &middle[..]);
+        let obj: UnparsedObject = odb.get_object_by_oid(top_oid, &mut state).unwrap();
+        assert_eq!(obj.payload, &top[..]);
+        assert_eq!(obj.object_type, UnparsedObjectType::Blob);
+
+        // middle's base doesnt need another delta, but top's does:
+        odb.max_delta_depth = 0;
+        assert!(odb.get_object_by_oid::<UnparsedObject, _>(middle_oid, &mut state).is_ok());
+        let err = odb.get_object_by_oid::<UnparsedObject, _>(top_oid, &mut state).unwrap_err();
+        assert!(err.to_string().contains("Exceeded max delta depth of 0"));
+
+        // the types come from the headers of the bases, which stop at the same depth:
+        let (_, top_location) = odb.find_first_matching_oid_with_location(top_oid, &mut state).unwrap();
+        let err = odb.object_type_at_location(&top_location, &mut state).unwrap_err();
+        assert!(err.to_string().contains("Exceeded max delta depth of 0"));
+        odb.max_delta_depth = 2;
+        assert_eq!(odb.object_type_at_location(&top_location, &mut state).unwrap(), UnparsedObjectType::Blob);
+        let (_, loose_location) = odb.find_first_matching_oid_with_location(
+            full_oid_to_u128_oid(hash_object("blob", loose_base)), &mut state).unwrap();
+        assert!(matches!(loose_location, FoundObjectLocation::FoundLoose(_)));
+        assert_eq!(odb.object_type_at_location(&loose_location, &mut state).unwrap(), UnparsedObjectType::Blob);
+    }
+
+    #[test]
+    fn strict_oid_matching_reports_corrupt_idx_files() {
+        let objects_dir = copy_packed_fixture_objects("strict_matching");
+        let objects_dir_str = objects_dir.path_str();
+        let idx_name = format!("pack-{}.idx", OFS_DELTA_PACK);
+        std::fs::write(objects_dir.join("pack").join(&idx_name), b"not an idx").unwrap();
+        let odb = LightObjectDB::new(objects_dir_str).unwrap();
+        let mut state = MinState::new(objects_dir_str).unwrap();
+        // the last commit is in the pack that is fine:
+        let partial = PartialOid::from_hash(&PACKED_COMMITS[4][..6]).unwrap();
+        let expected = hash_str_to_oid(PACKED_COMMITS[4]).unwrap();
+
+        let mut found = vec![];
+        odb.find_matching_oids_packed(partial, &mut state, &mut |oid| found.push(oid)).unwrap();
+        assert_eq!(found, vec![expected]);
+
+        let err = odb.find_matching_oids_packed_strict(partial, &mut state, &mut |_| {}).unwrap_err();
+        assert!(err.to_string().contains(&idx_name), "{}", err);
+    }
+
+    #[test]
+    fn contains_oid_with_a_bloom_filter_works() {
+        use crate::object_database::{object_source::ObjectSource, state::CachingState, loose::write_raw_object};
+        use crate::object_id::{OidFull, full_oid_to_u128_oid, oid_full_to_string};
+        use crate::test_helpers::{TempDir, v1_idx_bytes};
+        let objects_dir = TempDir::objects_dir("bloom");
+        // contains_oid only needs the idx files, so thats all we write:
+        let write_idx = |pack_id: OidFull, oids: &[OidFull]| {
+            let entries: Vec<_> = oids.iter().enumerate().map(|(i, oid)| (*oid, 12 + i as u32)).collect();
+            let name = format!("pack-{}.idx", oid_full_to_string(pack_id));
+            std::fs::write(objects_dir.join("pack").join(name), v1_idx_bytes(&entries)).unwrap();
+        };
+        let first = [[0x10; 20], [0x80; 20]];
+        write_idx([0xaa; 20], &first);
+        let loose = full_oid_to_u128_oid(write_raw_object(&objects_dir, &UnparsedObjectType::Blob, b"loose\n").unwrap());
+
+        let objects_dir_str = objects_dir.path_str();
+        let odb = LightObjectDB::new(objects_dir_str).unwrap();
+        let mut state = CachingState::new(objects_dir_str).unwrap();
+        assert!(state.get_pack_bloom_filter().unwrap().is_none());
+        state.use_bloom_filter = true;
+        for oid in first.iter() {
+            assert!(odb.contains_oid(full_oid_to_u128_oid(*oid), &mut state).unwrap());
+        }
+        assert!(odb.contains_oid(loose, &mut state).unwrap());
+        assert!(!odb.contains_oid(full_oid_to_u128_oid([0x30; 20]), &mut state).unwrap());
+        let filter = state.get_pack_bloom_filter().unwrap().unwrap();
+        assert_eq!(filter.len(), 2);
+        // its kept until the packs change:
+        assert!(std::sync::Arc::ptr_eq(&filter, &state.get_pack_bloom_filter().unwrap().unwrap()));
+
+        write_idx([0xbb; 20], &[[0x30; 20]]);
+        assert!(odb.contains_oid(full_oid_to_u128_oid([0x30; 20]), &mut state).unwrap());
+        assert_eq!(state.get_pack_bloom_filter().unwrap().unwrap().len(), 3);
+    }
+}
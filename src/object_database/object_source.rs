@@ -0,0 +1,288 @@
+use std::{io, convert::TryFrom, collections::BTreeMap};
+use crate::{error::GitReaderError, object_id::{Oid, full_oid_to_u128_oid}, sha1::hash_object};
+use super::{LightObjectDB, FoundObjectLocation, DoesMatch, state::{State, MinState}, loose::{UnparsedObject, UnparsedObjectType}};
+
+/// something we can read objects out of. This is implemented
+/// by `LightObjectDB` (the objects in a repository), and by
+/// `MemObjectStore` (objects that only exist in memory), so things
+/// like `RevWalk` and the tree walking functions can work with either.
+/// The state is only used by the `LightObjectDB`, but it is part of
+/// every method so that both can be used the same way.
+pub trait ObjectSource {
+    /// where an object was found. For a `LightObjectDB` this
+    /// is a loose file or a position in a pack.
+    type Location;
+
+    /// If the oid does not exist, the returned error is a
+    /// `GitReaderError::ObjectNotFound`.
+    fn get_object_by_oid<F, S>(&self, oid: Oid, state: &mut S) -> io::Result<F>
+        where F: TryFrom<UnparsedObject>,
+              F::Error: ToString,
+              S: State;
+
+    fn contains_oid<S: State>(&self, oid: Oid, state: &mut S) -> io::Result<bool>;
+
+    /// errors with a kind of `io::ErrorKind::NotFound` if nothing matches.
+    fn find_first_matching_oid_with_location<M, S>(
+        &self,
+        partial_oid: M,
+        state: &mut S,
+    ) -> io::Result<(Oid, Self::Location)>
+        where M: DoesMatch,
+              S: State;
+}
+
+impl<'a> ObjectSource for LightObjectDB<'a> {
+    type Location = FoundObjectLocation;
+
+    fn get_object_by_oid<F, S>(&self, oid: Oid, state: &mut S) -> io::Result<F>
+        where F: TryFrom<UnparsedObject>,
+              F::Error: ToString,
+              S: State,
+    {
+        LightObjectDB::get_object_by_oid(self, oid, state)
+    }
+
+    /// if the state has a `State::get_pack_bloom_filter`, oids that
+    /// it rules out are only looked for in the loose objects.
+    /// Like `get_object_by_oid`, the objects of our alternates count too.
+    /// The fallback doesn't: asking it about an object means fetching it.
+    fn contains_oid<S: State>(&self, oid: Oid, state: &mut S) -> io::Result<bool> {
+        let not_packed = state.get_pack_bloom_filter()?
+            .is_some_and(|filter| !filter.might_contain(oid));
+        let found = if not_packed {
+            let mut found = false;
+            self.find_matching_oids_loose_with_locations(oid, state, &mut |_, _| {
+                found = true;
+                true
+            })?;
+            found
+        } else {
+            self.try_find_first_matching_oid_with_location(oid, state)?.is_some()
+        };
+        if found {
+            return Ok(true);
+        }
+        for alternate in self.alternates.iter() {
+            let mut alternate_state = MinState::new(alternate.path_to_db.as_ref())?;
+            if alternate.contains_oid(oid, &mut alternate_state)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn find_first_matching_oid_with_location<M, S>(
+        &self,
+        partial_oid: M,
+        state: &mut S,
+    ) -> io::Result<(Oid, Self::Location)>
+        where M: DoesMatch,
+              S: State,
+    {
+        LightObjectDB::find_first_matching_oid_with_location(self, partial_oid, state)
+    }
+}
+
+/// where a `LightObjectDB` gets objects that it doesn't have.
+/// Unlike `ObjectSource` this is object safe, so a
+/// `LightObjectDB` can hold any kind of one.
+pub trait FallbackSource {
+    /// Ok(None) if this source doesn't have the object either.
+    fn fetch_object(&self, oid: Oid) -> io::Result<Option<UnparsedObject>>;
+}
+
+/// another object db can be a fallback, ie: the objects of
+/// a (slow) network mount of a repository.
+impl<'a> FallbackSource for LightObjectDB<'a> {
+    fn fetch_object(&self, oid: Oid) -> io::Result<Option<UnparsedObject>> {
+        let mut state = MinState::new(self.path_to_db.as_ref())?;
+        // whoever asks us has no way to read a loose blob
+        // again, so we read them in full:
+        match self.read_object_in_full(oid, &mut state) {
+            Ok(obj) => Ok(Some(obj)),
+            Err(e) if GitReaderError::from_io_error(&e).is_some_and(|g| g.is_not_found()) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// objects kept in memory instead of in a repository. Useful
+/// for tests, or for objects you made but don't want to write to disk.
+/// Objects are kept in oid order, so the first match of a partial
+/// oid is always the smallest matching oid.
+#[derive(Debug, Default)]
+pub struct MemObjectStore {
+    pub objects: BTreeMap<Oid, (UnparsedObjectType, Vec<u8>)>,
+}
+
+impl MemObjectStore {
+    pub fn new() -> MemObjectStore {
+        MemObjectStore::default()
+    }
+
+    /// hash the object like git would, store it, and return its oid.
+    /// inserting the same object twice just replaces it with itself.
+    pub fn insert(&mut self, object_type: UnparsedObjectType, payload: Vec<u8>) -> Oid {
+        let oid = full_oid_to_u128_oid(hash_object(object_type.as_str(), &payload));
+        self.objects.insert(oid, (object_type, payload));
+        oid
+    }
+
+    /// store an object under an oid of your choosing. The oid
+    /// is not checked against the payload.
+    pub fn insert_with_oid(&mut self, oid: Oid, object_type: UnparsedObjectType, payload: Vec<u8>) {
+        self.objects.insert(oid, (object_type, payload));
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+}
+
+impl ObjectSource for MemObjectStore {
+    /// there isn't really a location in memory, so you just get the oid.
+    type Location = ();
+
+    fn get_object_by_oid<F, S>(&self, oid: Oid, _state: &mut S) -> io::Result<F>
+        where F: TryFrom<UnparsedObject>,
+              F::Error: ToString,
+              S: State,
+    {
+        let (object_type, payload) = self.objects.get(&oid)
+            .ok_or(GitReaderError::ObjectNotFound(oid))?;
+        let obj = UnparsedObject { object_type: *object_type, payload: payload.clone() };
+        F::try_from(obj).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    fn contains_oid<S: State>(&self, oid: Oid, _state: &mut S) -> io::Result<bool> {
+        Ok(self.objects.contains_key(&oid))
+    }
+
+    fn find_first_matching_oid_with_location<M, S>(
+        &self,
+        partial_oid: M,
+        _state: &mut S,
+    ) -> io::Result<(Oid, Self::Location)>
+        where M: DoesMatch,
+              S: State,
+    {
+        self.objects.keys().find(|oid| partial_oid.matches(**oid))
+            .map(|oid| (*oid, ()))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Failed to find a matching oid/location"))
+    }
+}
+
+impl FallbackSource for MemObjectStore {
+    fn fetch_object(&self, oid: Oid) -> io::Result<Option<UnparsedObject>> {
+        Ok(self.objects.get(&oid).map(|(object_type, payload)| UnparsedObject {
+            object_type: *object_type,
+            payload: payload.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_id::PartialOid;
+    use crate::object_database::revwalk::RevWalk;
+    use crate::test_helpers::{TempDir, CommitFixture, EMPTY_TREE};
+
+    #[test]
+    fn mem_object_store_works() {
+        let mut state = MinState::new("/nonexistent/.git/objects").unwrap();
+        let mut store = MemObjectStore::new();
+        let blob = store.insert(UnparsedObjectType::Blob, b"hello\n".to_vec());
+        // same as `echo hello | git hash-object --stdin`
+        assert_eq!(format!("{:032x}", blob), "ce013625030ba8dba906f756967f9e9c");
+        assert!(store.contains_oid(blob, &mut state).unwrap());
+        assert!(!store.contains_oid(1, &mut state).unwrap());
+
+        let obj: UnparsedObject = store.get_object_by_oid(blob, &mut state).unwrap();
+        assert_eq!(obj.object_type, UnparsedObjectType::Blob);
+        assert_eq!(obj.payload, b"hello\n");
+        let err = store.get_object_by_oid::<UnparsedObject, _>(1, &mut state).unwrap_err();
+        assert!(GitReaderError::from_io_error(&err).unwrap().is_not_found());
+
+        let partial = PartialOid::from_hash("ce0136").unwrap();
+        let (found, _) = store.find_first_matching_oid_with_location(partial, &mut state).unwrap();
+        assert_eq!(found, blob);
+    }
+
+    #[test]
+    fn revwalk_over_mem_store_works() {
+        let mut state = MinState::new("/nonexistent/.git/objects").unwrap();
+        let mut store = MemObjectStore::new();
+        let tree = EMPTY_TREE;
+        let first = store.insert(UnparsedObjectType::Commit, CommitFixture::new().to_bytes());
+        let first_hex = crate::object_id::oid_full_to_string(
+            hash_object("commit", &store.objects[&first].1));
+        let second = store.insert(UnparsedObjectType::Commit, CommitFixture::new().parent(first_hex).to_bytes());
+
+        let mut walk = RevWalk::new(second);
+        let mut visited = vec![];
+        while let Some((oid, c)) = walk.next_commit(&store, &mut state).unwrap() {
+            visited.push(oid);
+            assert_eq!(format!("{:032x}", c.tree), &tree[0..32]);
+        }
+        assert_eq!(visited, vec![second, first]);
+    }
+
+    #[test]
+    fn fallback_objects_are_written_locally() {
+        let objects_dir = TempDir::objects_dir("fallback");
+        let objects_dir_str = objects_dir.path_str();
+        let mut state = MinState::new(objects_dir_str).unwrap();
+
+        let mut remote = MemObjectStore::new();
+        let blob = remote.insert(UnparsedObjectType::Blob, b"from far away\n".to_vec());
+        let odb = LightObjectDB::new(objects_dir_str).unwrap().with_fallback(remote);
+        assert!(!odb.contains_oid(blob, &mut state).unwrap());
+        let obj: UnparsedObject = odb.get_object_by_oid(blob, &mut state).unwrap();
+        assert_eq!(obj.payload, b"from far away\n");
+        // its now one of our loose objects, even without the fallback:
+        let odb = LightObjectDB::new(objects_dir_str).unwrap();
+        assert!(odb.contains_oid(blob, &mut state).unwrap());
+        let obj: UnparsedObject = odb.get_object_by_oid(blob, &mut state).unwrap();
+        assert_eq!(obj.object_type, UnparsedObjectType::Blob);
+
+        // and something neither of them have is still not found:
+        let err = odb.get_object_by_oid::<UnparsedObject, _>(1, &mut state).unwrap_err();
+        assert!(GitReaderError::from_io_error(&err).unwrap().is_not_found());
+    }
+
+    #[test]
+    fn contains_oid_checks_alternates() {
+        use crate::object_database::{state::CachingState, loose::write_raw_object};
+        let root = TempDir::new("contains_alternates");
+        let (local, shared) = (root.join("local"), root.join("shared"));
+        std::fs::create_dir_all(local.join("pack")).unwrap();
+        std::fs::create_dir_all(shared.join("pack")).unwrap();
+        let shared_blob = full_oid_to_u128_oid(write_raw_object(&shared, &UnparsedObjectType::Blob, b"shared\n").unwrap());
+        let local_str = local.to_str().unwrap();
+        let alternate = LightObjectDB::new_owned(shared.to_str().unwrap()).unwrap();
+        let odb = LightObjectDB::new(local_str).unwrap().with_alternate(alternate);
+
+        let mut state = MinState::new(local_str).unwrap();
+        assert!(odb.contains_oid(shared_blob, &mut state).unwrap());
+        assert!(!odb.contains_oid(1, &mut state).unwrap());
+        // we have no packs, so the bloom filter rules it out, but that
+        // only means its not in our packs:
+        let mut state = CachingState::new(local_str).unwrap();
+        state.use_bloom_filter = true;
+        assert!(odb.contains_oid(shared_blob, &mut state).unwrap());
+        assert!(state.get_pack_bloom_filter().unwrap().unwrap().is_empty());
+
+        // the fallback is not asked, it would have to fetch the object:
+        let mut remote = MemObjectStore::new();
+        let far = remote.insert(UnparsedObjectType::Blob, b"far\n".to_vec());
+        let odb = odb.with_fallback(remote);
+        assert!(!odb.contains_oid(far, &mut state).unwrap());
+        assert!(odb.contains_oid(shared_blob, &mut state).unwrap());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::object_id::Oid;
+
+/// how many bits of the filter each oid gets. With `BLOOM_NUM_HASHES`
+/// this is about a 1% chance that an oid that isnt there looks like it is.
+pub const BLOOM_BITS_PER_OID: usize = 10;
+pub const BLOOM_NUM_HASHES: u64 = 7;
+
+/// a bloom filter of oids: it can tell you that an oid is definitely
+/// not in a set of oids, ie: every oid of every pack, without searching
+/// any idx file. If `might_contain` is true you still have to do the real
+/// lookup, since the oid can be a false positive.
+/// `CachingState` can build and keep one for you, see `use_bloom_filter`.
+#[derive(Debug, Clone)]
+pub struct OidBloomFilter {
+    bits: Vec<u64>,
+    /// the number of bits is a power of 2, so this is that minus 1.
+    mask: u64,
+    len: usize,
+}
+
+impl OidBloomFilter {
+    /// an empty filter sized for `num_oids` oids. Adding more
+    /// than that works, but makes false positives more likely.
+    pub fn with_capacity(num_oids: usize) -> OidBloomFilter {
+        let num_bits = (num_oids.max(1) * BLOOM_BITS_PER_OID).next_power_of_two().max(64);
+        OidBloomFilter {
+            bits: vec![0; num_bits / 64],
+            mask: num_bits as u64 - 1,
+            len: 0,
+        }
+    }
+
+    pub fn insert(&mut self, oid: Oid) {
+        let (h1, h2) = bloom_hashes(oid);
+        for i in 0..BLOOM_NUM_HASHES {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) & self.mask;
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.len += 1;
+    }
+
+    /// false if `oid` was never inserted. true if it
+    /// was, or if its a false positive.
+    pub fn might_contain(&self, oid: Oid) -> bool {
+        let (h1, h2) = bloom_hashes(oid);
+        (0..BLOOM_NUM_HASHES).all(|i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) & self.mask;
+            self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0
+        })
+    }
+
+    /// how many oids were inserted.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// how much memory the bits of the filter take.
+    pub fn size_bytes(&self) -> usize {
+        self.bits.len() * 8
+    }
+}
+
+/// oids are already hashes, so we dont need to hash them again, but
+/// we do mix the two halves so that made up oids (ie: 1, 2, 3 in tests)
+/// dont all land on the same bits. The second hash is odd
+/// so it never repeats a bit before going through all of them.
+fn bloom_hashes(oid: Oid) -> (u64, u64) {
+    let high = (oid >> 64) as u64;
+    let low = oid as u64;
+    let h1 = low.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ high;
+    let h2 = (high.wrapping_mul(0xc2b2_ae3d_27d4_eb4f) ^ low) | 1;
+    (h1, h2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sha1::hash_object, object_id::full_oid_to_u128_oid};
+
+    #[test]
+    fn bloom_filter_has_no_false_negatives() {
+        let oid_of = |i: usize| full_oid_to_u128_oid(hash_object("blob", format!("{}", i).as_bytes()));
+        let mut filter = OidBloomFilter::with_capacity(5000);
+        assert!(filter.is_empty());
+        assert!(!filter.might_contain(oid_of(0)));
+        for i in 0..5000 {
+            filter.insert(oid_of(i));
+        }
+        assert_eq!(filter.len(), 5000);
+        assert!((0..5000).all(|i| filter.might_contain(oid_of(i))));
+        let false_positives = (5000..15000).filter(|i| filter.might_contain(oid_of(*i))).count();
+        assert!(false_positives < 300, "{} false positives out of 10000", false_positives);
+
+        // made up oids work too:
+        let mut filter = OidBloomFilter::with_capacity(0);
+        assert_eq!(filter.size_bytes(), 8);
+        filter.insert(1);
+        filter.insert(2);
+        assert!(filter.might_contain(1) && filter.might_contain(2));
+    }
+}
@@ -0,0 +1,258 @@
+use std::{io, collections::HashMap, convert::TryInto};
+use flate2::{Crc, Decompress};
+use crate::{ioerr, ioerre, error::GitReaderError, object_id::{Oid, OidFull, cmp_oid_full, full_oid_to_u128_oid, get_first_byte_of_oid}, sha1::hash_object};
+use crate::object_database::{DoesMatch, FoundObjectLocation, FoundPackedLocation, MAX_DELTA_DEPTH};
+use crate::object_database::loose::UnparsedObject;
+use crate::object_database::state::IDXState;
+use super::{PackFile, PackFileObjectType, apply_delta, find_encoded_length};
+
+/// An idx file that we created ourselves by reading
+/// a pack file (see `build_idx_from_pack`), rather than
+/// reading an existing .idx file.
+pub struct InMemoryIdx {
+    pub id: OidFull,
+    /// the Nth entry of the fanout table is how many
+    /// oids have a first byte <= N. same as a real .idx file.
+    pub fanout_table: [u32; 256],
+    /// sorted by oid. each entry is the full oid, the index of where
+    /// that object starts in the pack file, and the crc32 of the object's
+    /// raw bytes in the pack. The position of an entry in this vec
+    /// is its fanout index.
+    pub entries: Vec<(OidFull, u64, u32)>,
+}
+
+impl InMemoryIdx {
+    /// same as `IDXFileLight::get_crc32_from_fanout_index`
+    pub fn get_crc32_from_fanout_index(&self, fanout_index: usize) -> Option<u32> {
+        self.entries.get(fanout_index).map(|(_, _, crc)| *crc)
+    }
+
+    fn start_index_for(&self, start_byte: Option<u8>) -> usize {
+        match start_byte {
+            Some(b) if b > 0 => self.fanout_table[b as usize - 1] as usize,
+            _ => 0,
+        }
+    }
+}
+
+impl IDXState for InMemoryIdx {
+    fn find_oid_and_fanout_index(&mut self, oid: Oid) -> io::Result<usize> {
+        // our entries are sorted by full oid, which means
+        // they are also sorted by their truncated oid.
+        self.entries.binary_search_by_key(&oid, |(full, _, _)| full_oid_to_u128_oid(*full))
+            .map_err(|_| GitReaderError::ObjectNotFound(oid).into())
+    }
+
+    fn find_packfile_index_from_fanout_index(&mut self, fanout_index: usize) -> Option<u64> {
+        self.entries.get(fanout_index).map(|(_, offset, _)| *offset)
+    }
+
+    fn get_full_oid_at_fanout_index(&self, fanout_index: usize) -> Option<OidFull> {
+        self.entries.get(fanout_index).map(|(oid, _, _)| *oid)
+    }
+
+    fn walk_all_oids_from<F>(&mut self, start_byte: Option<u8>, cb: F)
+        where F: FnMut(Oid) -> bool
+    {
+        let mut cb = cb;
+        let start_at = self.start_index_for(start_byte);
+        for (full, _, _) in self.entries.iter().skip(start_at) {
+            if cb(full_oid_to_u128_oid(*full)) {
+                return;
+            }
+        }
+    }
+
+    fn get_partial_matches_with_locations<F, P>(&mut self, start_byte: Option<u8>, partial: P, cb: &mut F)
+        where F: FnMut(Oid, FoundObjectLocation) -> bool,
+              P: DoesMatch
+    {
+        let partial_oid_first_byte = partial.get_first_byte();
+        let start_at = self.start_index_for(start_byte);
+        for (oid_index, (full, object_starts_at, _)) in self.entries.iter().enumerate().skip(start_at) {
+            let oid = full_oid_to_u128_oid(*full);
+            if partial.matches(oid) {
+                let location = FoundPackedLocation {
+                    id: self.id,
+                    object_starts_at: *object_starts_at,
+                    oid_index,
+                };
+                let stop_searching = cb(oid, FoundObjectLocation::FoundPacked(location));
+                if stop_searching { return; }
+            }
+            // sorted, so once we pass the partial's first byte
+            // nothing after this can match.
+            if get_first_byte_of_oid(oid) > partial_oid_first_byte {
+                return;
+            }
+        }
+    }
+
+    fn id(&self) -> OidFull {
+        self.id
+    }
+
+    fn num_objects(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// Like running `git index-pack`, but we keep the result in memory
+/// instead of writing a .idx file. We walk every object in the pack,
+/// fully resolve it (applying ofs and ref deltas) and hash the resolved
+/// data to get its oid. This is slow since every delta chain
+/// gets decompressed again for every object in the chain, but it lets
+/// you lookup objects by oid on a pack file that has no .idx.
+/// It is an error if a ref delta's base is not in this pack (ie: a thin pack).
+pub fn build_idx_from_pack(pack: &PackFile) -> io::Result<InMemoryIdx> {
+    let mut decompressor = Decompress::new(true);
+    // (index where object starts, its type, its decompressed size)
+    let mut objects = vec![];
+    for obj in pack.iter_objects(&mut decompressor) {
+        objects.push(obj?);
+    }
+    // these are in pack order, so an object's raw bytes go until
+    // the next one starts, or until the trailer for the last one:
+    let mut crc_by_index: HashMap<usize, u32> = HashMap::with_capacity(objects.len());
+    for (i, (obj_index, _, _)) in objects.iter().enumerate() {
+        let ends_at = objects.get(i + 1).map(|o| o.0).unwrap_or_else(|| pack.data_ends_at());
+        let mut crc = Crc::new();
+        crc.update(pack.raw_compressed_object(*obj_index, ends_at)?);
+        crc_by_index.insert(*obj_index, crc.sum());
+    }
+
+    let mut oid_to_offset: HashMap<OidFull, usize> = HashMap::with_capacity(objects.len());
+    let mut entries = Vec::with_capacity(objects.len());
+    // ref deltas can point to a base that comes later in the pack,
+    // so we keep trying until we either resolve everything
+    // or stop making progress.
+    let mut unresolved = objects;
+    while !unresolved.is_empty() {
+        let mut still_unresolved = vec![];
+        let num_unresolved = unresolved.len();
+        for (obj_index, obj_type, obj_size) in unresolved {
+            match resolve_for_idx(pack, &mut decompressor, &oid_to_offset, obj_index, 0)? {
+                Some(obj) => {
+                    let id = hash_object(obj.object_type.as_str(), &obj.payload);
+                    oid_to_offset.insert(id, obj_index);
+                    entries.push((id, obj_index as u64, crc_by_index[&obj_index]));
+                }
+                None => still_unresolved.push((obj_index, obj_type, obj_size)),
+            }
+        }
+        if still_unresolved.len() == num_unresolved {
+            let (obj_index, _, _) = &still_unresolved[0];
+            return ioerre!("Failed to build idx: {} objects have ref delta bases that are not in this pack (eg: object at index {}). Is this a thin pack?", num_unresolved, obj_index);
+        }
+        unresolved = still_unresolved;
+    }
+
+    entries.sort_unstable_by(|a, b| cmp_oid_full(&a.0, &b.0));
+    let mut fanout_table = [0u32; 256];
+    for (full, _, _) in entries.iter() {
+        fanout_table[full[0] as usize] += 1;
+    }
+    let mut running_total = 0;
+    for count in fanout_table.iter_mut() {
+        running_total += *count;
+        *count = running_total;
+    }
+
+    Ok(InMemoryIdx {
+        id: pack.id,
+        fanout_table,
+        entries,
+    })
+}
+
+/// resolves the object at obj_index. Returns None if it (or one of its
+/// bases) is a ref delta whose base we have not found yet.
+pub(crate) fn resolve_for_idx(
+    pack: &PackFile,
+    decompressor: &mut Decompress,
+    oid_to_offset: &HashMap<OidFull, usize>,
+    obj_index: usize,
+    depth: usize,
+) -> io::Result<Option<UnparsedObject>> {
+    if depth > MAX_DELTA_DEPTH {
+        return ioerre!("Exceeded max delta depth of {} while resolving object at index {}", MAX_DELTA_DEPTH, obj_index);
+    }
+    let (obj_type, obj_size, obj_starts_at) = pack.get_object_type_and_len_at_index(obj_index)?;
+    let obj_size: usize = obj_size.try_into()
+        .map_err(|_| ioerr!("Failed to convert {} into a usize", obj_size))?;
+    let base_index = match obj_type {
+        PackFileObjectType::OfsDelta(base_index) => base_index,
+        PackFileObjectType::RefDelta(base_id) => match oid_to_offset.get(&base_id) {
+            Some(base_index) => *base_index,
+            None => {
+                // if we havent seen it yet it might still be later on in the pack.
+                // we let the caller decide if that's an error.
+                return Ok(None);
+            }
+        },
+        simple => {
+            let unparsed = pack.resolve_unparsed_object(obj_size, obj_starts_at, simple, decompressor)?;
+            return Ok(Some(unparsed));
+        }
+    };
+
+    let base = match resolve_for_idx(pack, decompressor, oid_to_offset, base_index, depth + 1)? {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+    decompressor.reset(true);
+    let this_object_data = pack.get_decompressed_data_from_index(obj_size, obj_starts_at, decompressor)?;
+    let (_base_size, num_read) = find_encoded_length(&this_object_data)
+        .ok_or_else(|| ioerr!("Failed to find size of base object"))?;
+    let this_object_data = &this_object_data[num_read..];
+    let (our_size, num_read) = find_encoded_length(this_object_data)
+        .ok_or_else(|| ioerr!("Failed to find size of object"))?;
+    let this_object_data = &this_object_data[num_read..];
+    let payload = apply_delta(&base.payload, this_object_data, our_size)?;
+    Ok(Some(UnparsedObject {
+        object_type: base.object_type,
+        payload,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_database::LightObjectDB;
+    use crate::object_id::full_oid_from_str;
+    use crate::test_helpers::{packed_fixture, OFS_DELTA_PACK, REF_DELTA_PACK};
+
+    #[test]
+    fn build_idx_matches_git() {
+        let objects_dir = packed_fixture().join("objects");
+        let odb = LightObjectDB::new(objects_dir.to_str().unwrap()).unwrap();
+        for (pack_id, delta_type) in [(OFS_DELTA_PACK, "ofs"), (REF_DELTA_PACK, "ref")].iter() {
+            let pack_id = full_oid_from_str(pack_id).unwrap();
+            let pack = odb.open_pack(pack_id).unwrap();
+            let git_idx = odb.open_idx(pack_id).unwrap();
+            // make sure this pack has the kind of deltas we want to test:
+            let mut decompressor = Decompress::new(true);
+            let num_deltas = pack.iter_objects(&mut decompressor).filter(|obj| matches!(
+                (&obj.as_ref().unwrap().1, *delta_type),
+                (PackFileObjectType::OfsDelta(_), "ofs") | (PackFileObjectType::RefDelta(_), "ref")
+            )).count();
+            assert!(num_deltas > 0);
+
+            let mut built = build_idx_from_pack(&pack).unwrap();
+            assert_eq!(built.id, pack_id);
+            assert_eq!(built.num_objects(), git_idx.num_objects);
+            for i in 0..git_idx.num_objects {
+                let (oid, offset, crc) = built.entries[i];
+                assert_eq!(Some(oid), git_idx.get_full_oid_at_fanout_index(i));
+                assert_eq!(Some(offset), git_idx.find_packfile_index_from_fanout_index(i));
+                assert_eq!(Some(crc), git_idx.get_crc32_from_fanout_index(i));
+                assert_eq!(built.get_crc32_from_fanout_index(i), Some(crc));
+                let found = built.find_oid_and_fanout_index(full_oid_to_u128_oid(oid)).unwrap();
+                assert_eq!(found, i);
+            }
+            for byte in 0..=255u8 {
+                assert_eq!(built.fanout_table[byte as usize] as usize, git_idx.fanout_snapshot().fanout_range(byte).1);
+            }
+        }
+    }
+}
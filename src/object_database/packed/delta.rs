@@ -1,39 +1,50 @@
-use std::io;
-use crate::ioerre;
+use std::{io, ops::Range, collections::HashMap};
+use crate::{ioerr, ioerre};
+use super::find_encoded_length;
 
-/// No clue how this works to be honest.
-/// I copied it directly from:
-/// https://github.com/speedata/gogit/blob/c5cbd8f9b7205cd5390219b532ca35d0f76b9eab/repository.go#L235
-/// I couldnt wrap my head around this.
-pub fn apply_delta(
-    base_data: &[u8],
-    delta_data: &[u8],
-    output_len: usize
-) -> io::Result<Vec<u8>> {
-    let mut output = unsafe {
-        let mut out = Vec::with_capacity(output_len);
-        out.set_len(output_len);
-        out
-    };
+/// a single instruction of a delta. A delta is a list of these
+/// that when applied in order to the base object, create the
+/// resulting object.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeltaOp {
+    /// copy `len` bytes from the base object starting at `offset`
+    Copy { offset: usize, len: usize },
+    /// insert the bytes at this range of the delta data
+    Insert(Range<usize>),
+}
 
-    let delta_len = delta_data.len();
+/// iterates the instructions of a delta. The data should
+/// be the delta without the base size and result size at the front.
+/// Insert ranges are relative to the data given to the iterator.
+pub struct DeltaOpIter<'a> {
+    delta_data: &'a [u8],
+    index: usize,
+}
 
-    let mut result_pos = 0;
-    let mut base_pos;
-    let mut index = 0;
-    while index < delta_len {
-        let mut opcode = delta_data[index];
-        index += 1;
+impl<'a> DeltaOpIter<'a> {
+    pub fn new(delta_data: &'a [u8]) -> DeltaOpIter<'a> {
+        DeltaOpIter { delta_data, index: 0 }
+    }
+
+    fn next_byte(&mut self) -> io::Result<u8> {
+        let byte = self.delta_data.get(self.index)
+            .ok_or_else(|| ioerr!("Delta data ended in the middle of a copy instruction"))?;
+        self.index += 1;
+        Ok(*byte)
+    }
 
+    fn read_op(&mut self, opcode: u8) -> io::Result<DeltaOp> {
+        let mut opcode = opcode;
         if opcode & 0x80 > 0 {
-            // copy from base to dest
+            // copy from base to dest. the lower 7 bits say which
+            // of the following bytes are present: 4 bytes of offset,
+            // then 3 bytes of length, each in little endian order.
             let mut copy_offset = 0;
             let mut copy_len = 0;
             let mut shift = 0;
             for _ in 0..4 {
                 if opcode & 0x01 > 0 {
-                    copy_offset |= (delta_data[index] as usize) << shift;
-                    index += 1;
+                    copy_offset |= (self.next_byte()? as usize) << shift;
                 }
                 opcode >>= 1;
                 shift += 8;
@@ -42,8 +53,7 @@ pub fn apply_delta(
             shift = 0;
             for _ in 0..3 {
                 if opcode & 0x01 > 0 {
-                    copy_len |= (delta_data[index] as usize) << shift;
-                    index += 1;
+                    copy_len |= (self.next_byte()? as usize) << shift;
                 }
                 opcode >>= 1;
                 shift += 8;
@@ -52,23 +62,342 @@ pub fn apply_delta(
             if copy_len == 0 {
                 copy_len = 1 << 16;
             }
-            base_pos = copy_offset;
-            for _ in 0..copy_len {
-                output[result_pos] = base_data[base_pos];
-                result_pos += 1;
-                base_pos += 1;
-            }
+            Ok(DeltaOp::Copy { offset: copy_offset, len: copy_len })
         } else if opcode > 0 {
-            // insert n bytes at the end:
-            for _ in 0..(opcode as usize) {
-                output[result_pos] = delta_data[index];
-                result_pos += 1;
-                index += 1;
+            // insert the next n bytes:
+            let insert_starts = self.index;
+            let insert_ends = insert_starts + opcode as usize;
+            if insert_ends > self.delta_data.len() {
+                return ioerre!("Delta insert instruction of {} bytes goes past the end of the delta data", opcode);
             }
+            self.index = insert_ends;
+            Ok(DeltaOp::Insert(insert_starts..insert_ends))
         } else {
-            return ioerre!("Error, opcode should not be 0");
+            ioerre!("Error, opcode should not be 0")
+        }
+    }
+}
+
+impl<'a> Iterator for DeltaOpIter<'a> {
+    type Item = io::Result<DeltaOp>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let opcode = *self.delta_data.get(self.index)?;
+        self.index += 1;
+        let op = self.read_op(opcode);
+        if op.is_err() {
+            // dont keep iterating garbage after an error:
+            self.index = self.delta_data.len();
+        }
+        Some(op)
+    }
+}
+
+/// parse an entire delta (including the base size and result size
+/// at the start of it) without applying it. Returns
+/// (base size, result size, instructions). The insert ranges
+/// are relative to `delta`. Errors if any copy instruction
+/// would read past the end of the base object.
+/// Useful for debugging corrupt deltas.
+pub fn parse_delta_instructions(delta: &[u8]) -> io::Result<(usize, usize, Vec<DeltaOp>)> {
+    if delta.is_empty() {
+        return ioerre!("Delta data is empty");
+    }
+    let (base_size, base_size_len) = find_encoded_length(delta)
+        .ok_or_else(|| ioerr!("Failed to read base size of delta"))?;
+    let rest = &delta[base_size_len..];
+    if rest.is_empty() {
+        return ioerre!("Delta data ended before the result size");
+    }
+    let (result_size, result_size_len) = find_encoded_length(rest)
+        .ok_or_else(|| ioerr!("Failed to read result size of delta"))?;
+    let instructions_start = base_size_len + result_size_len;
+
+    let mut ops = vec![];
+    for op in DeltaOpIter::new(&delta[instructions_start..]) {
+        let op = match op? {
+            DeltaOp::Copy { offset, len } => {
+                if offset + len > base_size {
+                    return ioerre!("Delta copies {} bytes from base offset {}, but the base is only {} bytes", len, offset, base_size);
+                }
+                DeltaOp::Copy { offset, len }
+            }
+            DeltaOp::Insert(range) => {
+                DeltaOp::Insert((range.start + instructions_start)..(range.end + instructions_start))
+            }
+        };
+        ops.push(op);
+    }
+    Ok((base_size, result_size, ops))
+}
+
+/// Originally copied from:
+/// https://github.com/speedata/gogit/blob/c5cbd8f9b7205cd5390219b532ca35d0f76b9eab/repository.go#L235
+/// delta_data should be the delta without the base size and result size,
+/// output_len is the result size.
+pub fn apply_delta(
+    base_data: &[u8],
+    delta_data: &[u8],
+    output_len: usize
+) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(output_len);
+    for op in DeltaOpIter::new(delta_data) {
+        let data = match op? {
+            DeltaOp::Copy { offset, len } => {
+                base_data.get(offset..offset + len)
+                    .ok_or_else(|| ioerr!("Delta copies {} bytes from base offset {}, but the base is only {} bytes", len, offset, base_data.len()))?
+            }
+            DeltaOp::Insert(range) => &delta_data[range],
+        };
+        if output.len() + data.len() > output_len {
+            return ioerre!("Applying delta produces more than the expected {} bytes", output_len);
         }
+        output.extend_from_slice(data);
+    }
+    if output.len() != output_len {
+        return ioerre!("Applying delta produced {} bytes, but expected {}", output.len(), output_len);
     }
 
     Ok(output)
 }
+
+/// how many bytes of the base we look for in the target at a time.
+/// matches shorter than this are inserted instead of copied.
+const DELTA_BLOCK_SIZE: usize = 16;
+/// git doesn't make copies bigger than this, so neither do we.
+const MAX_COPY_LEN: usize = 0x10000;
+/// an insert opcode is its own length, and the high bit means copy.
+const MAX_INSERT_LEN: usize = 0x7f;
+const ROLLING_HASH_MULTIPLIER: u32 = 31;
+/// like git's HASH_LIMIT, we only keep this many base blocks per hash.
+/// Otherwise a base that repeats itself (ie: all zeros) has one hash
+/// for every block, and every byte of the target is compared to all of them.
+const MAX_BLOCKS_PER_HASH: usize = 64;
+
+/// the size format used at the start of a delta: 7 bits at a time,
+/// least significant first, with the high bit set if there are more bytes.
+fn encode_delta_size(size: usize, out: &mut Vec<u8>) {
+    let mut size = size;
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn push_copy(offset: usize, len: usize, out: &mut Vec<u8>) {
+    let mut offset = offset;
+    let mut len = len;
+    while len > 0 {
+        let this_len = len.min(MAX_COPY_LEN);
+        let opcode_index = out.len();
+        let mut opcode = 0x80u8;
+        for i in 0..4 {
+            let byte = ((offset >> (i * 8)) & 0xff) as u8;
+            if byte != 0 {
+                opcode |= 1 << i;
+                out.push(byte);
+            }
+        }
+        // a length of 0 means MAX_COPY_LEN, so that one has no length bytes:
+        let encoded_len = if this_len == MAX_COPY_LEN { 0 } else { this_len };
+        for i in 0..3 {
+            let byte = ((encoded_len >> (i * 8)) & 0xff) as u8;
+            if byte != 0 {
+                opcode |= 1 << (4 + i);
+                out.push(byte);
+            }
+        }
+        out.insert(opcode_index, opcode);
+        offset += this_len;
+        len -= this_len;
+    }
+}
+
+fn push_insert(data: &[u8], out: &mut Vec<u8>) {
+    for chunk in data.chunks(MAX_INSERT_LEN) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+fn block_hash(block: &[u8]) -> u32 {
+    block.iter().fold(0u32, |h, b| h.wrapping_mul(ROLLING_HASH_MULTIPLIER).wrapping_add(*b as u32))
+}
+
+/// create a delta that turns `base` into `target`. The output is
+/// the full delta like it would be in a pack: the base size, the result size,
+/// then the instructions. So `apply_delta(base, &delta[n..], target.len())`
+/// gives back `target`, where `n` is the length of the two sizes
+/// (see `parse_delta_instructions`).
+/// This finds copies by hashing every `DELTA_BLOCK_SIZE` bytes of the base,
+/// and then rolling a hash of that many bytes over the target. It does not make
+/// the same deltas git would, but they are valid.
+/// Bases bigger than 4GB can only be copied from their first 4GB.
+pub fn make_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(target.len() / 2 + 16);
+    encode_delta_size(base.len(), &mut out);
+    encode_delta_size(target.len(), &mut out);
+
+    // where every block of the base starts, by the hash of that block:
+    let indexed_len = base.len().min(u32::MAX as usize);
+    let mut blocks: HashMap<u32, Vec<usize>> = HashMap::new();
+    for offset in (0..indexed_len.saturating_sub(DELTA_BLOCK_SIZE - 1)).step_by(DELTA_BLOCK_SIZE) {
+        let offsets = blocks.entry(block_hash(&base[offset..offset + DELTA_BLOCK_SIZE])).or_default();
+        if offsets.len() < MAX_BLOCKS_PER_HASH {
+            offsets.push(offset);
+        }
+    }
+
+    // multiplier^(DELTA_BLOCK_SIZE - 1), to roll the oldest byte out of the hash
+    let oldest_multiplier = (1..DELTA_BLOCK_SIZE)
+        .fold(1u32, |m, _| m.wrapping_mul(ROLLING_HASH_MULTIPLIER));
+    let mut insert_from = 0;
+    let mut i = 0;
+    let mut hash = None;
+    while i + DELTA_BLOCK_SIZE <= target.len() {
+        let h = match hash {
+            Some(h) => h,
+            None => block_hash(&target[i..i + DELTA_BLOCK_SIZE]),
+        };
+        // the longest match of any base block with the same hash. A match
+        // only has to be as long as one copy, the next one is found after
+        // it, so we dont compare more than that:
+        let mut best: Option<(usize, usize)> = None;
+        for &offset in blocks.get(&h).into_iter().flatten() {
+            let len = base[offset..indexed_len].iter().zip(&target[i..])
+                .take(MAX_COPY_LEN)
+                .take_while(|(a, b)| a == b)
+                .count();
+            match best {
+                Some((_, best_len)) if best_len >= len => {}
+                _ => best = Some((offset, len)),
+            }
+            if len == MAX_COPY_LEN {
+                break;
+            }
+        }
+
+        match best {
+            Some((offset, len)) if len >= DELTA_BLOCK_SIZE => {
+                // the match might start before our block, take
+                // as much of that from what we were going to insert:
+                let mut offset = offset;
+                let mut start = i;
+                while start > insert_from && offset > 0 && base[offset - 1] == target[start - 1] {
+                    offset -= 1;
+                    start -= 1;
+                }
+                push_insert(&target[insert_from..start], &mut out);
+                let end = i + len;
+                push_copy(offset, end - start, &mut out);
+                insert_from = end;
+                i = end;
+                hash = None;
+            }
+            _ => {
+                // roll the hash forward one byte:
+                hash = target.get(i + DELTA_BLOCK_SIZE).map(|next| {
+                    h.wrapping_sub((target[i] as u32).wrapping_mul(oldest_multiplier))
+                        .wrapping_mul(ROLLING_HASH_MULTIPLIER)
+                        .wrapping_add(*next as u32)
+                });
+                i += 1;
+            }
+        }
+    }
+    push_insert(&target[insert_from..], &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_delta_instructions_works() {
+        // base size 10, result size 7,
+        // copy 4 bytes from offset 2, then insert "abc"
+        let delta = [10, 7, 0b1001_0001, 2, 4, 3, b'a', b'b', b'c'];
+        let (base_size, result_size, ops) = parse_delta_instructions(&delta).unwrap();
+        assert_eq!(base_size, 10);
+        assert_eq!(result_size, 7);
+        assert_eq!(ops, vec![DeltaOp::Copy { offset: 2, len: 4 }, DeltaOp::Insert(6..9)]);
+        assert_eq!(&delta[6..9], b"abc");
+
+        let applied = apply_delta(b"0123456789", &delta[2..], 7).unwrap();
+        assert_eq!(applied, b"2345abc");
+
+        // copying past the end of the base is an error:
+        let delta = [4, 7, 0b1001_0001, 2, 4, 3, b'a', b'b', b'c'];
+        assert!(parse_delta_instructions(&delta).is_err());
+        assert!(apply_delta(b"0123", &delta[2..], 7).is_err());
+    }
+
+    fn roundtrip(base: &[u8], target: &[u8]) -> Vec<u8> {
+        let delta = make_delta(base, target);
+        let (base_size, result_size, _) = parse_delta_instructions(&delta).unwrap();
+        assert_eq!(base_size, base.len());
+        assert_eq!(result_size, target.len());
+        let (_, base_size_len) = find_encoded_length(&delta).unwrap();
+        let (_, result_size_len) = find_encoded_length(&delta[base_size_len..]).unwrap();
+        let applied = apply_delta(base, &delta[base_size_len + result_size_len..], target.len()).unwrap();
+        assert_eq!(applied, target);
+        delta
+    }
+
+    #[test]
+    fn make_delta_roundtrip_works() {
+        roundtrip(b"", b"");
+        roundtrip(b"", b"only inserts here");
+        roundtrip(b"the base doesnt matter", b"");
+        roundtrip(b"short", b"short");
+
+        // some pseudo random text, so blocks dont all look alike:
+        let mut seed = 12345u32;
+        let base: Vec<u8> = (0..200_000).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            b'a' + ((seed >> 16) % 26) as u8
+        }).collect();
+
+        // an identical target is almost all copies, even past MAX_COPY_LEN:
+        let delta = roundtrip(&base, &base);
+        assert!(delta.len() < 100);
+
+        let mut target = b"new start ".to_vec();
+        target.extend_from_slice(&base[1000..50_000]);
+        target.extend_from_slice(b"something in the middle");
+        target.extend_from_slice(&base[20..900]);
+        target.extend_from_slice(&base[150_000..]);
+        target.extend_from_slice(&[b'z'; 500]);
+        let delta = roundtrip(&base, &target);
+        assert!(delta.len() < 1000);
+
+        // and a target that shares nothing is just inserts:
+        let other: Vec<u8> = base.iter().map(|b| b.to_ascii_uppercase()).take(3000).collect();
+        roundtrip(&base, &other);
+    }
+
+    #[test]
+    fn make_delta_of_repetitive_data_is_fast() {
+        // every block of these has the same hash, so without a limit
+        // on blocks per hash this compares every byte to every block:
+        let base = vec![0u8; 4_000_000];
+        let mut target = base.clone();
+        target[2_000_000] = 1;
+        let start = std::time::Instant::now();
+        let delta = roundtrip(&base, &target);
+        assert!(delta.len() < 1000);
+
+        let base = b"abcd".repeat(1_000_000);
+        let mut target = b"xyz".to_vec();
+        target.extend_from_slice(&base[1..]);
+        let delta = roundtrip(&base, &target);
+        assert!(delta.len() < 1000);
+        assert!(start.elapsed() < std::time::Duration::from_secs(10), "took {:?}", start.elapsed());
+    }
+}
@@ -1,9 +1,8 @@
-use std::{path::Path, io, fmt::Debug, mem::size_of};
+use std::{path::{Path, PathBuf}, io, fmt::Debug, mem::size_of};
 use std::convert::TryInto;
 use byteorder::{BigEndian, ByteOrder};
-use crate::{ioerre, fs_helpers, object_id::{get_first_byte_of_oid, Oid, full_slice_oid_to_u128_oid, OidFull, OidTruncated}, ioerr};
-use memmap2::Mmap;
-use super::parse_pack_or_idx_id;
+use crate::{ioerre, error::GitReaderError, fs_helpers::{self, Backing, ReadMode}, object_id::{oid_full_to_string, Oid, full_slice_oid_to_u128_oid, OidFull, OidTruncated}, ioerr};
+use super::{try_parse_pack_or_idx_id, DATA_STARTS_AT};
 
 /// see: https://git-scm.com/docs/pack-format#_version_2_pack_idx_files_support_packs_larger_than_4_gib_and
 const V2_IDX_SIGNATURE: [u8; 4] = [255, b't', b'O', b'c'];
@@ -21,7 +20,7 @@ const V1_HEADER_SIZE: usize = FANOUT_LENGTH * FANOUT_ENTRY_SIZE;
 const V2_HEADER_SIZE: usize = FANOUT_ENTRY_SIZE * 2 + FANOUT_LENGTH * FANOUT_ENTRY_SIZE;
 const N64_SIZE: usize = size_of::<u64>();
 
-#[derive(Debug, PartialOrd, PartialEq)]
+#[derive(Debug, PartialOrd, PartialEq, Copy, Clone)]
 pub enum IDXVersion {
     V1,
     V2,
@@ -32,10 +31,60 @@ pub struct IDXFileLight {
     pub id: OidFull,
     pub version: IDXVersion,
     pub num_objects: usize,
-    pub file: Mmap,
+    pub file: Backing,
+}
+
+/// just the fanout table and identity of an idx file, without
+/// the file itself. It can't look up oids, but it is cheap to copy around
+/// and send between threads, so you can keep one of these around for
+/// every pack without needing to keep every idx file open.
+/// Get one with `IDXFileLight::fanout_snapshot`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FanoutSnapshot {
+    pub fanout_table: [u32; 256],
+    pub id: OidFull,
+    pub version: IDXVersion,
+    pub num_objects: usize,
+}
+
+impl FanoutSnapshot {
+    /// how many objects in this pack have an oid starting with this byte.
+    pub fn num_objects_with_first_byte(&self, first_byte: u8) -> usize {
+        let (start, end) = self.fanout_range(first_byte);
+        end - start
+    }
+
+    /// the range of fanout indices that the oids starting with
+    /// this byte occupy, ie: (start, end) where end is exclusive.
+    pub fn fanout_range(&self, first_byte: u8) -> (usize, usize) {
+        let first_byte = first_byte as usize;
+        let start = if first_byte > 0 {
+            self.fanout_table[first_byte - 1] as usize
+        } else {
+            0
+        };
+        (start, self.fanout_table[first_byte] as usize)
+    }
+}
+
+impl From<&IDXFileLight> for FanoutSnapshot {
+    fn from(idx: &IDXFileLight) -> Self {
+        idx.fanout_snapshot()
+    }
 }
 
 impl IDXFileLight {
+    /// copies everything about this idx file except the file
+    /// itself, so the snapshot can outlive it.
+    pub fn fanout_snapshot(&self) -> FanoutSnapshot {
+        FanoutSnapshot {
+            fanout_table: self.fanout_table,
+            id: self.id,
+            version: self.version,
+            num_objects: self.num_objects,
+        }
+    }
+
     /// For V2 idx files, the oid starts at every index, and has an offset of 20 bytes.
     /// ie: there is no padding or anything. you just read 20 bytes at a time, and each
     /// 20 bytes is an Oid. the V2 idx file looks like:
@@ -90,8 +139,15 @@ impl IDXFileLight {
     /// the entry looks like:
     /// [4 byte packfile offset][oid_03] // 4 + 20 bytes.
     /// so here we want to read these first 4 bytes in network order.
+    /// NOTE: V1 offsets are only 32 bits, so a V1 idx cannot describe
+    /// a packfile larger than 4GB. We dont check the offset here, if you
+    /// have the pack file, use `check_pack_offset` to make sure
+    /// the offset we read actually points inside of it.
     #[inline(always)]
     pub fn find_packfile_index_from_fanout_index_v1(&self, fanout_index: usize) -> Option<u64> {
+        if fanout_index >= self.num_objects {
+            return None;
+        }
         let oid_start = self.get_oid_starting_index_from_fanout_index_v1(fanout_index);
         // we subtract 4 because we dont want the oid index, but the 4 bytes before the oid:
         let offset_start = oid_start - FANOUT_ENTRY_SIZE;
@@ -108,6 +164,11 @@ impl IDXFileLight {
     /// which has 8 byte offsets.
     #[inline(always)]
     pub fn find_packfile_index_from_fanout_index_v2(&self, fanout_index: usize) -> Option<u64> {
+        // without this, an index past the end reads into
+        // the other tables, or the trailer of an empty idx:
+        if fanout_index >= self.num_objects {
+            return None;
+        }
         let oid_table_starts_at = V2_HEADER_SIZE;
         let crc_table_starts_at = oid_table_starts_at + (self.num_objects * SHA1_SIZE);
         let four_byte_offset_table_starts_at = crc_table_starts_at + (self.num_objects * FANOUT_ENTRY_SIZE);
@@ -150,6 +211,9 @@ impl IDXFileLight {
     /// CRC32 values... If you want to use a function that will check
     /// for V2, then use: `get_crc32_from_fanout_index`
     pub fn get_crc32_from_fanout_index_unchecked(&self, fanout_index: usize) -> Option<u32> {
+        if fanout_index >= self.num_objects {
+            return None;
+        }
         let oid_table_starts_at = V2_HEADER_SIZE;
         let crc_table_starts_at = oid_table_starts_at + (self.num_objects * SHA1_SIZE);
         let this_entry_starts = crc_table_starts_at + (fanout_index * FANOUT_ENTRY_SIZE);
@@ -159,8 +223,17 @@ impl IDXFileLight {
         Some(crc_value)
     }
 
+    /// the checksum of the pack file this idx is for, from the trailer
+    /// of the idx. Its the same as the last 20 bytes of that pack file.
+    pub fn pack_checksum(&self) -> Option<OidFull> {
+        let starts_at = self.file.len().checked_sub(IDX_TRAILER_SIZE)?;
+        self.file.get(starts_at..starts_at + SHA1_SIZE)?.try_into().ok()
+    }
+
     /// Returns None if not on V2 idx, otherwise
-    /// calls `get_crc32_from_fanout_index_unchecked`
+    /// calls `get_crc32_from_fanout_index_unchecked`.
+    /// Like the offset functions, this is None if `fanout_index`
+    /// is not less than `num_objects`.
     pub fn get_crc32_from_fanout_index(&self, fanout_index: usize) -> Option<u32> {
         if let IDXVersion::V2 = self.version {
             self.get_crc32_from_fanout_index_unchecked(fanout_index)
@@ -302,29 +375,151 @@ impl IDXFileLight {
         }
     }
 
+    /// every (pack file offset, full oid) in this idx file, sorted
+    /// by where the object is in the pack file rather than by oid.
+    /// This is the order you want to read a pack in, because
+    /// ofs deltas always point to an earlier offset.
+    /// Entries whose offset can't be read are skipped.
+    pub fn full_oids_in_pack_order(&self) -> Vec<(u64, OidFull)> {
+        let mut out = Vec::with_capacity(self.num_objects);
+        self.walk_all_full_oids_from(None, |oid, fanout_index| {
+            if let Some(offset) = self.find_packfile_index_from_fanout_index(fanout_index) {
+                out.push((offset, oid));
+            }
+            false
+        });
+        out.sort_unstable_by_key(|(offset, _)| *offset);
+        out
+    }
+
+    /// calls `cb` with (pack file offset, oid) of every object
+    /// in the order that they are in the pack file.
+    /// See `full_oids_in_pack_order`.
+    pub fn iter_in_pack_order(&self, cb: impl FnMut(u64, Oid)) {
+        let mut cb = cb;
+        for (offset, oid) in self.full_oids_in_pack_order() {
+            cb(offset, full_slice_oid_to_u128_oid(&oid));
+        }
+    }
+
+    /// Like `walk_all_oid_slices_with_index_and_from`, but passes the entire
+    /// 20 byte sha instead of just the first 16 bytes. Use this
+    /// if you need to print/use the full hash of every object.
+    pub fn walk_all_full_oids_from(
+        &self,
+        start_byte: Option<u8>,
+        cb: impl FnMut(OidFull, usize) -> bool
+    ) {
+        let mut cb = cb;
+        let start_fanout_index = match start_byte {
+            Some(first_byte) => {
+                let first_byte = first_byte as usize;
+                if first_byte > 0 {
+                    self.fanout_table[first_byte - 1]
+                } else {
+                    0
+                }
+            }
+            None => 0,
+        };
+
+        let start_fanout_index = start_fanout_index as usize;
+        let mut start_index = self.get_oid_starting_index_from_fanout_index(start_fanout_index);
+        let seek_up = match self.version {
+            IDXVersion::V1 => FANOUT_ENTRY_SIZE + SHA1_SIZE,
+            IDXVersion::V2 => SHA1_SIZE,
+        };
+
+        for current_fanout_index in start_fanout_index..self.num_objects {
+            let sha_bytes = match self.file.get(start_index..(start_index + SHA1_SIZE)) {
+                Some(b) => b,
+                None => { break; }
+            };
+            let mut oid_full = OidFull::default();
+            oid_full.copy_from_slice(sha_bytes);
+            let should_stop_iterating = cb(oid_full, current_fanout_index);
+            if should_stop_iterating { break; }
+
+            start_index += seek_up;
+        }
+    }
+
+    /// get the full 20 byte oid of the nth entry of the idx file.
+    pub fn get_full_oid_at_fanout_index(&self, fanout_index: usize) -> Option<OidFull> {
+        if fanout_index >= self.num_objects {
+            return None;
+        }
+        let oid_starts_at = self.get_oid_starting_index_from_fanout_index(fanout_index);
+        let sha_bytes = self.file.get(oid_starts_at..(oid_starts_at + SHA1_SIZE))?;
+        let mut oid_full = OidFull::default();
+        oid_full.copy_from_slice(sha_bytes);
+        Some(oid_full)
+    }
+
+    /// binary search for an oid whose hash starts with `prefix`, only
+    /// looking between the fanout table entries of the prefix's first byte.
+    /// the prefix can be up to 20 bytes. If multiple oids start with the prefix,
+    /// this returns the fanout index of any one of them.
+    /// The idx is sorted by the full 20 byte hash (see `cmp_oid_full`), so we
+    /// always compare the bytes of the file, never the truncated `Oid`.
+    pub fn binary_search_oid_prefix(&self, prefix: &[u8]) -> Option<usize> {
+        self.search_oid_prefix(prefix).ok().flatten()
+    }
+
+    /// `binary_search_oid_prefix`, but if the idx file ends before the
+    /// oid we need to compare with, thats a `GitReaderError::CorruptIdx`
+    /// instead of looking like the oid isn't there.
+    fn search_oid_prefix(&self, prefix: &[u8]) -> io::Result<Option<usize>> {
+        let prefix_len = prefix.len();
+        if prefix_len == 0 || prefix_len > SHA1_SIZE {
+            return Ok(None);
+        }
+        let first_byte = prefix[0] as usize;
+        let mut low = if first_byte > 0 {
+            self.fanout_table[first_byte - 1] as usize
+        } else {
+            0
+        };
+        let mut high = (self.fanout_table[first_byte] as usize).min(self.num_objects);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let oid_starts_at = self.get_oid_starting_index_from_fanout_index(mid);
+            let sha_bytes = self.file.get(oid_starts_at..(oid_starts_at + prefix_len))
+                .ok_or_else(|| io::Error::from(GitReaderError::CorruptIdx {
+                    path: PathBuf::from(format!("pack-{}.idx", oid_full_to_string(self.id))),
+                    reason: format!("it ends before the oid at fanout index {}", mid),
+                }))?;
+            match sha_bytes.cmp(prefix) {
+                std::cmp::Ordering::Equal => return Ok(Some(mid)),
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+            }
+        }
+        Ok(None)
+    }
+
+    /// Like `find_oid_and_fanout_index`, but compares the entire 20
+    /// byte hash, so there is no ambiguity from truncating the oid.
+    pub fn find_full_oid_and_fanout_index(
+        &self,
+        oid: &OidFull,
+    ) -> io::Result<usize> {
+        self.search_oid_prefix(oid)?
+            .ok_or_else(|| GitReaderError::ObjectNotFound(full_slice_oid_to_u128_oid(oid)).into())
+    }
+
     /// Returns Ok(usize) if the Oid exists,
     /// and if we were able to find its fanout index, ie (this is
     /// the nth oid...).
+    /// This uses a binary search within the oid's fanout bucket.
+    /// if you want to walk every oid, use `walk_all_oids_with_index_and_from`.
     pub fn find_oid_and_fanout_index(
         &self,
         oid: Oid
     ) -> io::Result<usize> {
-        let mut found = None;
-        let first_byte = get_first_byte_of_oid(oid);
-        self.walk_all_oids_with_index_and_from(Some(first_byte), |found_oid, fanout_index| {
-            if found_oid == oid {
-                found = Some(fanout_index);
-                // indicate we want to stop iterating
-                return true;
-            }
-            false
-        });
-        match found {
-            Some(i) => Ok(i),
-            None => {
-                return ioerre!("Failed to find index of oid {:032x}", oid);
-            }
-        }
+        let oid_bytes = oid.to_be_bytes();
+        self.search_oid_prefix(&oid_bytes)?
+            .ok_or_else(|| GitReaderError::ObjectNotFound(oid).into())
     }
 
     /// pass a callback that takes an oid that we found,
@@ -347,12 +542,54 @@ impl IDXFileLight {
 
 pub fn open_idx_file_light<P: AsRef<Path>>(
     path: P
+) -> io::Result<IDXFileLight> {
+    open_idx_file_light_with(path, ReadMode::Mmap)
+}
+
+/// makes sure an offset we read from an idx file can actually
+/// be the start of an object in a pack file of `pack_size` bytes, ie:
+/// its after the pack header, and before the pack trailer.
+/// A bad offset usually means the idx and pack dont belong together,
+/// or the idx was misdetected as V1 (which only has 4 byte offsets)
+/// while the pack is larger than 4GB.
+pub fn check_pack_offset(offset: u64, pack_size: usize) -> io::Result<u64> {
+    let data_ends_at = pack_size.saturating_sub(SHA1_SIZE) as u64;
+    if offset < DATA_STARTS_AT as u64 || offset >= data_ends_at {
+        return ioerre!("Packfile offset {} read from idx file is outside of the pack file data (pack size is {} bytes). The idx file is either corrupt, or does not match the pack file", offset, pack_size);
+    }
+    Ok(offset)
+}
+
+/// Like `open_idx_file_light`, but you can choose if the
+/// file gets mmapped, or read entirely into memory.
+pub fn open_idx_file_light_with<P: AsRef<Path>>(
+    path: P,
+    read_mode: ReadMode,
 ) -> io::Result<IDXFileLight> {
     // let mut fhandle = fs_helpers::get_readonly_handle(&path)?;
-    let mmapped = fs_helpers::get_mmapped_file(&path)?;
-    let file_size = mmapped.len() as usize;
+    let mmapped = fs_helpers::get_file_backing(&path, read_mode)?;
+    let idx_id = try_parse_pack_or_idx_id(&path)?
+        .ok_or_else(|| ioerr!("Failed to parse idx id: {:?} is not named like an idx file", path.as_ref()))?;
+    idx_from_backing(mmapped, idx_id, path.as_ref())
+}
+
+impl IDXFileLight {
+    /// parse the header/fanout table of an idx file whose contents
+    /// are already loaded. This is what `open_idx_file_light` uses
+    /// after it reads the file. Useful if you have an idx file in memory, ie:
+    /// `Backing::Heap(bytes)`.
+    pub fn from_backing(mmapped: Backing, idx_id: OidFull) -> io::Result<IDXFileLight> {
+        idx_from_backing(mmapped, idx_id, Path::new(""))
+    }
+}
+
+fn idx_from_backing(mmapped: Backing, idx_id: OidFull, path: &Path) -> io::Result<IDXFileLight> {
+    let file_size = mmapped.len();
     if file_size < MINIMAL_IDX_FILE_SIZE {
-        return ioerre!("IDX file is too small to be a valid idx file");
+        return Err(GitReaderError::CorruptIdx {
+            path: path.to_path_buf(),
+            reason: "too small to be a valid idx file".into(),
+        }.into());
     }
 
     // read enough bytes to check for v2 and the fanout table.
@@ -363,7 +600,7 @@ pub fn open_idx_file_light<P: AsRef<Path>>(
         let version_bytes = &read_bytes[V2_IDX_SIGNATURE_LEN..(V2_IDX_SIGNATURE_LEN + V2_SKIP_VERSION_NUMBER_SIZE)];
         let version_number = BigEndian::read_u32(&version_bytes);
         if version_number != V2_IDX_VERSION_NUMBER {
-            return ioerre!("Invalid .idx version number. Expected version number of {}, found {}", V2_IDX_VERSION_NUMBER, version_number);
+            return Err(GitReaderError::UnsupportedVersion(version_number).into());
         }
         let fanout_starts = V2_IDX_SIGNATURE_LEN + V2_SKIP_VERSION_NUMBER_SIZE;
         let fanout_len = FANOUT_ENTRY_SIZE * FANOUT_LENGTH;
@@ -379,9 +616,6 @@ pub fn open_idx_file_light<P: AsRef<Path>>(
         (IDXVersion::V1, num_objects, fanout_table)
     };
 
-    let idx_id = parse_pack_or_idx_id(path)
-        .ok_or_else(|| ioerr!("Failed to parse idx idx"))?;
-
     let out = IDXFileLight {
         fanout_table,
         version,
@@ -403,3 +637,130 @@ fn fill_fan(fan: &mut [u32; FANOUT_LENGTH], d: &[u8]) -> usize {
     }
     FANOUT_LENGTH * FANOUT_ENTRY_SIZE
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_id::cmp_oid_full;
+    use crate::test_helpers::{TempDir, v1_idx_bytes};
+
+    #[test]
+    fn v1_idx_offsets_work() {
+        let mut oid_a = [0u8; 20];
+        oid_a[0] = 0x0a;
+        let mut oid_b = [0u8; 20];
+        oid_b[0] = 0xb0;
+        oid_b[19] = 1;
+        let idx_bytes = v1_idx_bytes(&[(oid_a, 12), (oid_b, 345)]);
+        let idx = IDXFileLight::from_backing(Backing::Heap(idx_bytes), [0; 20]).unwrap();
+        assert!(matches!(idx.version, IDXVersion::V1));
+        assert_eq!(idx.num_objects, 2);
+
+        let index_b = idx.find_full_oid_and_fanout_index(&oid_b).unwrap();
+        assert_eq!(index_b, 1);
+        assert_eq!(idx.find_packfile_index_from_fanout_index(index_b), Some(345));
+        let index_a = idx.find_oid_and_fanout_index(full_slice_oid_to_u128_oid(&oid_a)).unwrap();
+        assert_eq!(idx.find_packfile_index_from_fanout_index(index_a), Some(12));
+
+        // offsets have to land in between the pack header and trailer:
+        assert_eq!(check_pack_offset(345, 1000).unwrap(), 345);
+        assert!(check_pack_offset(345, 345 + SHA1_SIZE).is_err());
+        assert!(check_pack_offset(4, 1000).is_err());
+    }
+
+    #[test]
+    fn idx_search_compares_full_oids() {
+        // these share their first 16 bytes, so only the
+        // last 4 bytes decide their order in the idx:
+        let mut oids = vec![];
+        for last in [0x00, 0x01, 0x7f, 0x80, 0xff].iter() {
+            let mut oid = [0x5a; 20];
+            oid[19] = *last;
+            oids.push(oid);
+        }
+        oids[4][16] = 0x00;
+        oids.sort_unstable_by(cmp_oid_full);
+        let entries: Vec<_> = oids.iter().enumerate().map(|(i, o)| (*o, 100 + i as u32)).collect();
+        let idx = IDXFileLight::from_backing(Backing::Heap(v1_idx_bytes(&entries)), [0; 20]).unwrap();
+        for (i, oid) in oids.iter().enumerate() {
+            assert_eq!(idx.find_full_oid_and_fanout_index(oid).unwrap(), i);
+            assert_eq!(idx.get_full_oid_at_fanout_index(i), Some(*oid));
+        }
+        let mut missing = oids[0];
+        missing[19] = 0x02;
+        assert!(idx.find_full_oid_and_fanout_index(&missing).is_err());
+    }
+
+    #[test]
+    fn fanout_snapshot_outlives_idx() {
+        let mut oid_a = [0u8; 20];
+        oid_a[0] = 0x0a;
+        let mut oid_b = [0u8; 20];
+        oid_b[0] = 0x0a;
+        oid_b[19] = 1;
+        let idx_bytes = v1_idx_bytes(&[(oid_a, 12), (oid_b, 345)]);
+        let idx = IDXFileLight::from_backing(Backing::Heap(idx_bytes), [3; 20]).unwrap();
+        let snapshot = idx.fanout_snapshot();
+        drop(idx);
+        let copied = snapshot;
+        let handle = std::thread::spawn(move || copied.num_objects_with_first_byte(0x0a));
+        assert_eq!(handle.join().unwrap(), 2);
+        assert_eq!(snapshot.num_objects, 2);
+        assert_eq!(snapshot.id, [3; 20]);
+        assert_eq!(snapshot.version, IDXVersion::V1);
+        assert_eq!(snapshot.fanout_range(0x0a), (0, 2));
+        assert_eq!(snapshot.num_objects_with_first_byte(0x0b), 0);
+    }
+
+    #[test]
+    fn empty_pack_and_idx_work() {
+        use crate::object_database::{LightObjectDB, state::{CachingState, MinState}, loose::UnparsedObject};
+        use crate::object_id::PartialOid;
+        use crate::sha1::sha1;
+
+        let objects_dir = TempDir::objects_dir("empty_pack");
+        let pack_dir = objects_dir.join("pack");
+        let objects_dir_str = objects_dir.path_str();
+        // same as what `git pack-objects` writes when given no objects:
+        let mut pack = b"PACK".to_vec();
+        pack.extend_from_slice(&2u32.to_be_bytes());
+        pack.extend_from_slice(&0u32.to_be_bytes());
+        let pack_checksum = sha1(&pack);
+        pack.extend_from_slice(&pack_checksum);
+        let mut idx = V2_IDX_SIGNATURE.to_vec();
+        idx.extend_from_slice(&V2_IDX_VERSION_NUMBER.to_be_bytes());
+        idx.extend_from_slice(&[0; FANOUT_LENGTH * FANOUT_ENTRY_SIZE]);
+        idx.extend_from_slice(&pack_checksum);
+        let idx_checksum = sha1(&idx);
+        idx.extend_from_slice(&idx_checksum);
+        let name = format!("pack-{}", oid_full_to_string(pack_checksum));
+        std::fs::write(pack_dir.join(format!("{}.pack", name)), &pack).unwrap();
+        std::fs::write(pack_dir.join(format!("{}.idx", name)), &idx).unwrap();
+
+        let odb = LightObjectDB::new(objects_dir_str).unwrap();
+        let idx = odb.open_idx(pack_checksum).unwrap();
+        assert_eq!(idx.num_objects, 0);
+        assert_eq!(idx.fanout_snapshot().fanout_range(0xff), (0, 0));
+        assert_eq!(idx.get_full_oid_at_fanout_index(0), None);
+        assert_eq!(idx.find_packfile_index_from_fanout_index(0), None);
+        assert_eq!(idx.get_crc32_from_fanout_index(0), None);
+        assert!(idx.full_oids_in_pack_order().is_empty());
+        assert!(idx.find_full_oid_and_fanout_index(&[0xff; 20]).is_err());
+        let pack = odb.open_pack(pack_checksum).unwrap();
+        assert_eq!(pack.type_histogram().unwrap().total(), 0);
+
+        let mut min_state = MinState::new(objects_dir_str).unwrap();
+        let mut caching_state = CachingState::new(objects_dir_str).unwrap();
+        assert!(odb.all_oids(&mut min_state).unwrap().is_empty());
+        assert!(odb.all_oids(&mut caching_state).unwrap().is_empty());
+        assert_eq!(odb.object_counts(&mut min_state).unwrap().in_pack_count, 0);
+        let partial = PartialOid::from_hash("ab").unwrap();
+        assert!(odb.try_find_first_matching_oid_with_location(partial, &mut caching_state).unwrap().is_none());
+        for oid in [0, 1, u128::MAX].iter() {
+            let err = odb.get_object_by_oid::<UnparsedObject, _>(*oid, &mut min_state).unwrap_err();
+            assert!(GitReaderError::from_io_error(&err).unwrap().is_not_found());
+            let err = odb.get_object_by_oid::<UnparsedObject, _>(*oid, &mut caching_state).unwrap_err();
+            assert!(GitReaderError::from_io_error(&err).unwrap().is_not_found());
+        }
+    }
+}
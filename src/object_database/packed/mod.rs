@@ -1,5 +1,5 @@
-use std::path::Path;
-use crate::{object_id::{full_oid_from_str, OidFull}};
+use std::{io, path::Path};
+use crate::{ioerr, object_id::{full_oid_from_str, OidFull}};
 
 mod index;
 use index as index_file;
@@ -12,6 +12,22 @@ pub use pack_file::*;
 pub mod delta;
 pub use delta::*;
 
+pub mod build_idx;
+pub use build_idx::*;
+
+pub mod reverse_index;
+pub use reverse_index::*;
+
+pub mod verify;
+pub use verify::*;
+
+pub mod bloom;
+pub use bloom::*;
+
+/// get the id out of a file name like "pack-{40 hex chars}.idx" (or .pack).
+/// Returns None for anything else, so files that aren't packs and packs
+/// with a malformed name look the same. Use `try_parse_pack_or_idx_id`
+/// if you need to tell them apart.
 pub fn parse_pack_or_idx_id<P: AsRef<Path>>(
     path: P
 ) -> Option<OidFull> {
@@ -25,3 +41,49 @@ pub fn parse_pack_or_idx_id<P: AsRef<Path>>(
     let file_id = full_oid_from_str(file_hash)?;
     Some(file_id)
 }
+
+/// like `parse_pack_or_idx_id`, but a file that is named like a pack
+/// file (it starts with "pack-" and has an extension), except that what's
+/// between is not a 40 hex char hash, ie: "pack-short.idx", is an error
+/// that says what is wrong with the name. Files that aren't named like
+/// pack files at all are Ok(None).
+pub fn try_parse_pack_or_idx_id<P: AsRef<Path>>(
+    path: P
+) -> io::Result<Option<OidFull>> {
+    let path = path.as_ref();
+    let file_name = match path.file_name().and_then(|f| f.to_str()) {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+    let file_hash = match file_name.strip_prefix("pack-").and_then(|rest| rest.split('.').next()) {
+        Some(hash) if file_name.len() > 5 + hash.len() => hash,
+        _ => return Ok(None),
+    };
+    if file_hash.len() != 40 {
+        return Err(ioerr!("Malformed pack file name {:?}: expected pack-{{40 hex chars}}, but the hash part is {} characters long", path, file_hash.len()));
+    }
+    full_oid_from_str(file_hash)
+        .map(Some)
+        .ok_or_else(|| ioerr!("Malformed pack file name {:?}: '{}' is not a hex hash", path, file_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_file_names_are_classified() {
+        let hash = "7cf435ae239b94c7a3b2b5067336766209a1d35f";
+        let id = try_parse_pack_or_idx_id(format!("pack-{}.idx", hash)).unwrap().unwrap();
+        assert_eq!(Some(id), parse_pack_or_idx_id(format!("objects/pack/pack-{}.pack", hash)));
+        // not pack files:
+        assert_eq!(try_parse_pack_or_idx_id("multi-pack-index").unwrap(), None);
+        assert_eq!(try_parse_pack_or_idx_id("tmp_pack_abc123").unwrap(), None);
+        assert_eq!(try_parse_pack_or_idx_id("pack-").unwrap(), None);
+        // named like a pack file, but broken:
+        let err = try_parse_pack_or_idx_id("pack-short.idx").unwrap_err();
+        assert!(err.to_string().contains("5 characters long"));
+        assert!(try_parse_pack_or_idx_id(format!("pack-{}z.idx", &hash[1..])).is_err());
+        assert_eq!(parse_pack_or_idx_id("pack-short.idx"), None);
+    }
+}
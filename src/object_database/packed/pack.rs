@@ -1,9 +1,9 @@
-use std::{io, path::{Path, PathBuf}, convert::{TryInto, TryFrom}};
-use crate::{fs_helpers, object_id::{oid_full_to_string, OidFull}, ioerre, ioerr, object_database::loose::{UnparsedObjectType, UnparsedObject}};
+use std::{io, path::{Path, PathBuf}, convert::{TryInto, TryFrom}, collections::HashMap};
+use crate::{error::GitReaderError, fs_helpers::{self, Backing, ReadMode}, object_id::{oid_full_to_string, OidFull}, ioerre, ioerr, object_database::{check_object_size, DEFAULT_MAX_OBJECT_SIZE, loose::{UnparsedObjectType, UnparsedObject}}};
 use byteorder::{ByteOrder, BigEndian};
-use memmap2::Mmap;
-use super::{apply_delta, parse_pack_or_idx_id};
-use flate2::{FlushDecompress, Decompress};
+use super::{apply_delta, try_parse_pack_or_idx_id, check_pack_offset};
+use crate::object_database::state::IDXState;
+use flate2::{FlushDecompress, Decompress, Status};
 
 
 pub const PACK_SIGNATURE: &[u8; 4] = b"PACK";
@@ -16,6 +16,27 @@ pub const MINIMAL_PACK_FILE_SIZE: usize = PACK_HEADER_SIZE + 4;
 /// its just the pack header size because the header is not of variable length
 pub const DATA_STARTS_AT: usize = PACK_HEADER_SIZE;
 
+/// marker files that can sit next to a pack file, and
+/// change how it should be treated. See `read_pack_flags`
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct PackFlags {
+    /// there is a `pack-<id>.keep`, so git will not repack this pack.
+    pub keep: bool,
+    /// there is a `pack-<id>.promisor`, so this pack came from a partial
+    /// clone, and objects it references might not exist locally.
+    pub promisor: bool,
+}
+
+/// check for the `.keep` and `.promisor` files of a pack.
+/// `pack_path` is the path of the .pack (or .idx) file.
+pub fn read_pack_flags<P: AsRef<Path>>(pack_path: P) -> PackFlags {
+    let pack_path = pack_path.as_ref();
+    PackFlags {
+        keep: pack_path.with_extension("keep").is_file(),
+        promisor: pack_path.with_extension("promisor").is_file(),
+    }
+}
+
 pub enum PartiallyResolvedPackFile {
     Unresolved(PathBuf),
     Resolved(PackFile),
@@ -100,10 +121,59 @@ pub struct PackFile {
     // we don't need this other than for debugging purposes..
     pub id: OidFull,
     pub num_objects: u32,
-    pub mmapped_file: Mmap,
+    pub mmapped_file: Backing,
+    /// we refuse to decompress objects that claim to be larger than this.
+    /// defaults to `DEFAULT_MAX_OBJECT_SIZE`, increase it if your
+    /// repo has legitimately large objects.
+    pub max_object_size: usize,
 }
 
 impl PackFile {
+    /// check that this pack has as many objects as `idx` says it does,
+    /// that they have the same id, and that the checksum in this pack's
+    /// trailer is the one that `idx` has for its pack (if it has one).
+    /// If not, the idx file is for some
+    /// other pack (or one of them is corrupt) and reading objects would
+    /// give garbage, so we error with a `GitReaderError::CorruptPack`.
+    /// `pack_path` is only used for the error.
+    pub fn check_against_idx<I: IDXState, P: AsRef<Path>>(&self, idx: &I, pack_path: P) -> io::Result<()> {
+        let checksums = match (self.trailer_checksum(), idx.pack_checksum()) {
+            (Some(trailer), Some(expected)) if trailer != expected => Some((trailer, expected)),
+            _ => None,
+        };
+        let reason = if idx.id() != self.id {
+            format!("its id is {}, but its idx file is for {}", oid_full_to_string(self.id), oid_full_to_string(idx.id()))
+        } else if self.num_objects as usize != idx.num_objects() {
+            format!("its header says it has {} objects, but its idx file has {}", self.num_objects, idx.num_objects())
+        } else if let Some((trailer, expected)) = checksums {
+            format!("its trailer checksum is {}, but its idx file is for a pack with checksum {}", oid_full_to_string(trailer), oid_full_to_string(expected))
+        } else {
+            return Ok(());
+        };
+        Err(GitReaderError::CorruptPack { path: pack_path.as_ref().to_path_buf(), reason }.into())
+    }
+
+    /// the last 20 bytes of this pack, ie: the sha1 of everything before it.
+    /// None if the pack is too short to have one.
+    pub fn trailer_checksum(&self) -> Option<OidFull> {
+        let starts_at = self.mmapped_file.len().checked_sub(20)?;
+        self.mmapped_file.get(starts_at..)?.try_into().ok()
+    }
+
+    /// where the object with this oid starts in this pack, according to
+    /// `offsets`. This is the same as searching the idx file, but it is only
+    /// a hash lookup, which adds up when resolving a lot of ref deltas.
+    /// None if the object is not in this pack, if `offsets` is the map
+    /// of some other pack, or if the offset does not point inside of this pack.
+    pub fn find_object_offset_by_oid(&self, oid: OidFull, offsets: &PackOffsetMap) -> Option<usize> {
+        if offsets.pack_id != self.id {
+            return None;
+        }
+        let (object_starts_at, _) = offsets.get(oid)?;
+        let object_starts_at = check_pack_offset(object_starts_at, self.mmapped_file.len()).ok()?;
+        object_starts_at.try_into().ok()
+    }
+
     /// a helper method to very quickly find out the type of an object.
     /// See documentation for `get_object_type_and_len_at_index`
     /// for more details.
@@ -124,6 +194,17 @@ impl PackFile {
         Ok(object_type)
     }
 
+    /// returns up to `len` bytes starting at index. only returns None
+    /// if index is at or past the end of the file.
+    fn get_up_to(&self, index: usize, len: usize) -> Option<&[u8]> {
+        let file_len = self.mmapped_file.len();
+        if index >= file_len {
+            return None;
+        }
+        let end = file_len.min(index.saturating_add(len));
+        self.mmapped_file.get(index..end)
+    }
+
     /// read the pack file starting at index, and try to parse
     /// the object type and length. returns
     /// (pack file object type, length of object, index where raw object starts)
@@ -153,9 +234,11 @@ impl PackFile {
         // we consider it an error if it takes us more than 18 bytes
         // to find the length of an object
 
+        // but an object near the end of the file might
+        // not have 18 bytes after it, and thats fine as long
+        // as we find the end of the length within what is there.
         let try_read_size = 18;
-        let try_read_range = index..(index + try_read_size);
-        let try_parse_segment = self.mmapped_file.get(try_read_range)
+        let try_parse_segment = self.get_up_to(index, try_read_size)
             .ok_or_else(|| ioerr!("Failed to read packfile at index {}", index))?;
         // the first byte contains the type at the first
         // 4 bits, not including the MSB:
@@ -188,8 +271,14 @@ impl PackFile {
     
                 let least_7_bits = (byte & 0b0111_1111) as u128;
                 // we shift it by the shift amount before
-                // adding it to the length:
-                length += least_7_bits << shift;
+                // adding it to the length. With 18 bytes the last
+                // 7 bits end at bit 123, so this can't overflow today, but
+                // we check anyway instead of silently losing bits
+                // if `try_read_size` ever grows:
+                if shift >= 128 || least_7_bits.leading_zeros() < shift {
+                    return ioerre!("Object length at index {} exceeds u128", index);
+                }
+                length |= least_7_bits << shift;
                 // since now we are reading 7 bits at a time,
                 // we shift the length by 7:
                 shift += 7;
@@ -210,7 +299,7 @@ impl PackFile {
         }
 
         if !found_last_byte {
-            return ioerre!("Read {} bytes and failed to find a byte whose MSB is 0... Failed to parse object's variable length", try_read_size);
+            return ioerre!("Read {} bytes and failed to find a byte whose MSB is 0... Failed to parse object's variable length", try_parse_segment.len());
         }
 
         match object_type {
@@ -241,8 +330,7 @@ impl PackFile {
             // because to be honest im not sure where the value += 1 comes from
             // but if it works, it works.
             let desired_range_start = index + bytes_read;
-            let desired_range = desired_range_start..(desired_range_start + try_read_size);
-            let negative_offset_data = self.mmapped_file.get(desired_range)
+            let negative_offset_data = self.get_up_to(desired_range_start, try_read_size)
                 .ok_or_else(|| ioerr!("Not enough bytes to read negative offset data from a delta offset object"))?;
             let (distance, more_bytes_read) = find_negative_offset(&negative_offset_data)
                 .ok_or_else(|| ioerr!("Failed to parse negative offset data from a delta offset object"))?;
@@ -274,6 +362,108 @@ impl PackFile {
         self.mmapped_file.len()
     }
 
+    /// where the object data of the pack ends, and the trailing
+    /// 20 byte checksum begins. Use this as the `next_offset` of the
+    /// last object in the pack for `raw_compressed_object`.
+    pub fn data_ends_at(&self) -> usize {
+        self.mmapped_file.len().saturating_sub(20)
+    }
+
+    /// the compressed bytes of an object, straight from the pack file
+    /// without decompressing them. `starts_at` is where the object's data
+    /// starts (the last value returned from `get_object_type_and_len_at_index`),
+    /// and `next_offset` is where the next object in the pack starts, or
+    /// `data_ends_at()` if this is the last object. You can get the next
+    /// offset from `IDXFileLight::full_oids_in_pack_order`.
+    /// The returned bytes are only the zlib stream. They do NOT include
+    /// the type/size header bytes, or the base offset/oid of a delta.
+    pub fn raw_compressed_object(&self, starts_at: usize, next_offset: usize) -> io::Result<&[u8]> {
+        if starts_at > next_offset || next_offset > self.data_ends_at() {
+            return ioerre!("Invalid range {}..{} for an object in a pack file whose data ends at {}", starts_at, next_offset, self.data_ends_at());
+        }
+        Ok(&self.mmapped_file[starts_at..next_offset])
+    }
+
+    /// the pack file does not store the compressed length of an object,
+    /// so the only way to find where the next object begins is to run
+    /// the decompressor over this object's zlib stream until it ends, and
+    /// then see how many input bytes it consumed. `starts_at` should be
+    /// the index where the raw object data starts, ie: the last value
+    /// returned from `get_object_type_and_len_at_index`.
+    /// The decompressed output is written to a small scratch buffer
+    /// and thrown away.
+    pub fn get_compressed_len_at_index(
+        &self,
+        starts_at: usize,
+        decompressor: &mut Decompress,
+    ) -> io::Result<usize> {
+        let compressed_data = self.mmapped_file.get(starts_at..)
+            .ok_or_else(|| ioerr!("Failed to read compressed data of pack file at index {}", starts_at))?;
+        let mut scratch = [0; 4096];
+        decompressor.reset(true);
+        loop {
+            let consumed = decompressor.total_in() as usize;
+            let input = &compressed_data[consumed..];
+            let before_in = decompressor.total_in();
+            let before_out = decompressor.total_out();
+            let status = decompressor.decompress(input, &mut scratch, FlushDecompress::None)
+                .map_err(|e| ioerr!("Failed to decompress object at index {}\n{}", starts_at, e))?;
+            if let Status::StreamEnd = status {
+                break;
+            }
+            let made_progress = decompressor.total_in() != before_in
+                || decompressor.total_out() != before_out;
+            if !made_progress {
+                return ioerre!("Reached the end of the pack file before the object at index {} finished decompressing", starts_at);
+            }
+        }
+        Ok(decompressor.total_in() as usize)
+    }
+
+    /// iterate over every object in this pack file in the order
+    /// that they appear in the pack file, without needing an idx file.
+    /// Each item is (index where the object starts, type of object, decompressed size).
+    /// Note that for deltas, the decompressed size is the size of the
+    /// delta data, not the size of the object after the delta is applied.
+    /// Because the next object's index is only known after
+    /// decompressing the current object, this is not free. See
+    /// `get_compressed_len_at_index` for more details.
+    pub fn iter_objects<'a>(
+        &'a self,
+        decompressor: &'a mut Decompress,
+    ) -> PackFileObjectIter<'a> {
+        PackFileObjectIter {
+            pack: self,
+            decompressor,
+            next_index: DATA_STARTS_AT,
+            objects_remaining: self.num_objects,
+        }
+    }
+
+    /// count how many objects of each type are in this pack, ie: for
+    /// a summary like `git verify-pack --stat`. Deltas are counted as
+    /// deltas, they are not resolved to find out the type of object they
+    /// make. This walks the pack with `iter_objects`, so while nothing is
+    /// kept, every object still has to be inflated to find where the
+    /// next one starts.
+    pub fn type_histogram(&self) -> io::Result<TypeHistogram> {
+        let mut decompressor = Decompress::new(true);
+        let mut histogram = TypeHistogram::default();
+        for obj in self.iter_objects(&mut decompressor) {
+            let (_, obj_type, _) = obj?;
+            let count = match obj_type {
+                PackFileObjectType::Commit => &mut histogram.commits,
+                PackFileObjectType::Tree => &mut histogram.trees,
+                PackFileObjectType::Blob => &mut histogram.blobs,
+                PackFileObjectType::Tag => &mut histogram.tags,
+                PackFileObjectType::OfsDelta(_) => &mut histogram.ofs_deltas,
+                PackFileObjectType::RefDelta(_) => &mut histogram.ref_deltas,
+            };
+            *count += 1;
+        }
+        Ok(histogram)
+    }
+
     /// return the decompressed data from an object at a given
     /// index. the `decompressed_size` should be the size of the output vec.
     /// Note: this ONLY decompressed data at an index and outputs
@@ -286,20 +476,12 @@ impl PackFile {
         starts_at: usize,
         decompressor: &mut Decompress,
     ) -> io::Result<Vec<u8>> {
-        // Is it guaranteed that compressed data is ALWAYS smaller
-        // than the decompressed output? This is quite an assumption here...
-        // to be safe, we will extend it by 128 bytes. But then
-        // if we do that, we have to check if we reached the end of the file,
-        // and reduce it if we've gone too far:
-        let compressed_data_ends_at = starts_at + decompressed_size + 128;
-        let compressed_data_ends_at = if compressed_data_ends_at > self.mmapped_file.len() {
-            self.mmapped_file.len()
-        } else {
-            // we are good:
-            compressed_data_ends_at
-        };
-        let compressed_data_range = starts_at..compressed_data_ends_at;
-        let compressed_data = self.mmapped_file.get(compressed_data_range)
+        check_object_size(decompressed_size, self.max_object_size)?;
+        // we don't know how long the compressed data is, and it isn't
+        // always smaller than the decompressed data (ie: incompressible
+        // data in stored blocks), so we give zlib everything until the end
+        // of the file. It stops by itself at the end of the stream.
+        let compressed_data = self.mmapped_file.get(starts_at..)
             .ok_or_else(|| ioerr!("Failed to read compressed data of pack file"))?;
 
         let mut out_vec = unsafe {
@@ -307,15 +489,29 @@ impl PackFile {
             v.set_len(decompressed_size);
             v
         };
-        // TODO: need to care about this decompressed state?
-        // is it possible that we don't read into the entire
-        // out vec in one go?
         decompressor.reset(true);
-        let _decompressed_state = decompressor.decompress(
-            compressed_data, &mut out_vec, FlushDecompress::None)?;
-        let num_bytes_out = decompressor.total_out() as usize;
-        if num_bytes_out != decompressed_size {
-            return ioerre!("Failed to decompress {} bytes in one go. Only was able to decompress {} bytes. This is a bug on our end, please report this.", decompressed_size, num_bytes_out);
+        // a single call usually decompresses the entire object, but
+        // zlib is allowed to stop early, so we keep going until we
+        // have every byte, or the stream ends.
+        let mut input = compressed_data;
+        let mut written = 0;
+        while written < decompressed_size {
+            let before_in = decompressor.total_in();
+            let before_out = decompressor.total_out();
+            let status = decompressor.decompress(input, &mut out_vec[written..], FlushDecompress::None)
+                .map_err(|e| ioerr!("Corrupt deflate stream for object at index {} of pack file\n{}", starts_at, e))?;
+            let consumed = (decompressor.total_in() - before_in) as usize;
+            let produced = (decompressor.total_out() - before_out) as usize;
+            input = &input[consumed..];
+            written += produced;
+            match status {
+                Status::StreamEnd => break,
+                Status::Ok | Status::BufError if consumed != 0 || produced != 0 => continue,
+                Status::Ok | Status::BufError => break,
+            }
+        }
+        if written != decompressed_size {
+            return ioerre!("Object at index {} of pack file decompressed to {} bytes, but its header says it is {} bytes", starts_at, written, decompressed_size);
         }
         Ok(out_vec)
     }
@@ -336,6 +532,10 @@ impl PackFile {
         Ok(unparsed_obj)
     }
 
+    /// resolve an ofs delta by resolving its base first, which
+    /// resolves its own base first, and so on. Every delta in the chain
+    /// adds to the stack, so prefer `resolve_ofs_delta_chain`, which
+    /// gives the same object with a loop instead.
     pub fn resolve_ofs_delta_object(
         &self,
         decompressor: &mut Decompress,
@@ -348,39 +548,83 @@ impl PackFile {
             next_obj_size,
             next_obj_index
         ) = self.get_object_type_and_len_at_index(base_starts_at)?;
-        let next_obj_size: usize = next_obj_size.try_into()
-            .map_err(|_| ioerr!("Failed to convert {} into a usize. Either we failed at parsing this value, or your architecture does not support numbers this large", next_obj_size))?;
+        let next_obj_size = object_size_to_usize(next_obj_size)?;
+        if next_obj_index >= starts_at {
+            return ioerre!("Ofs delta at index {} has a base at index {} that does not come before it", starts_at, base_starts_at);
+        }
         decompressor.reset(true);
-        let unparsed_object = self.resolve_unparsed_object(next_obj_size, next_obj_index, next_obj_type, decompressor)?;
+        let unparsed_object = match next_obj_type {
+            PackFileObjectType::OfsDelta(next_base_starts_at) => self.resolve_ofs_delta_object(
+                decompressor, next_obj_size, next_obj_index, next_base_starts_at)?,
+            _ => self.resolve_unparsed_object(next_obj_size, next_obj_index, next_obj_type, decompressor)?,
+        };
         let this_object_data = self.get_decompressed_data_from_index(decompressed_size, starts_at, decompressor)?;
-        let base_object_data = unparsed_object.payload;
-        let base_object_type = unparsed_object.object_type;
+        let data_out = self.apply_delta_data(&unparsed_object.payload, &this_object_data)?;
+        let unparsed_obj_out = UnparsedObject {
+            object_type: unparsed_object.object_type,
+            payload: data_out
+        };
+        Ok(unparsed_obj_out)
+    }
 
+    /// resolve an ofs delta without recursing: first we walk down the
+    /// chain of bases, remembering where each delta is, until we find an
+    /// object that is not a delta. Then that object is decompressed, and the
+    /// deltas are applied to it in reverse, from the deepest one up to ours.
+    pub fn resolve_ofs_delta_chain(
+        &self,
+        decompressor: &mut Decompress,
+        decompressed_size: usize,
+        starts_at: usize,
+        base_starts_at: usize,
+    ) -> io::Result<UnparsedObject> {
+        // (where the delta data starts, decompressed size of the delta)
+        let mut deltas = vec![(starts_at, decompressed_size)];
+        let mut next_starts_at = base_starts_at;
+        let base = loop {
+            let (obj_type, obj_size, obj_starts_at) = self.get_object_type_and_len_at_index(next_starts_at)?;
+            let obj_size = object_size_to_usize(obj_size)?;
+            // the base has to be before the delta, which also
+            // means a broken pack cant make us loop forever:
+            let (delta_starts_at, _) = deltas[deltas.len() - 1];
+            if obj_starts_at >= delta_starts_at {
+                return ioerre!("Ofs delta at index {} has a base at index {} that does not come before it", delta_starts_at, next_starts_at);
+            }
+            match obj_type {
+                PackFileObjectType::OfsDelta(next) => {
+                    deltas.push((obj_starts_at, obj_size));
+                    next_starts_at = next;
+                }
+                _ => break self.resolve_unparsed_object(obj_size, obj_starts_at, obj_type, decompressor)?,
+            }
+        };
+        let mut payload = base.payload;
+        for (delta_starts_at, delta_size) in deltas.into_iter().rev() {
+            let delta_data = self.get_decompressed_data_from_index(delta_size, delta_starts_at, decompressor)?;
+            payload = self.apply_delta_data(&payload, &delta_data)?;
+        }
+        Ok(UnparsedObject { object_type: base.object_type, payload })
+    }
+
+    /// `delta_data` is the entire decompressed delta, including
+    /// the base size and result size at the start of it.
+    fn apply_delta_data(&self, base_data: &[u8], delta_data: &[u8]) -> io::Result<Vec<u8>> {
         // for our data, we need to extract the length, which
         // is again size encoded like the other cases:
-        let (_base_size, num_read) = find_encoded_length(&this_object_data)
+        let (_base_size, num_read) = find_encoded_length(delta_data)
             .ok_or_else(|| ioerr!("Failed to find size of base object"))?;
-        let this_object_data = &this_object_data[num_read..];
-        let (our_size, num_read) = find_encoded_length(&this_object_data)
+        let delta_data = &delta_data[num_read..];
+        let (our_size, num_read) = find_encoded_length(delta_data)
             .ok_or_else(|| ioerr!("Failed to find size of object"))?;
-        let this_object_data = &this_object_data[num_read..];
-
-        // eprintln!("Going to look for delta data.");
-        // eprintln!("Base object raw: {}", base_object_data.len());
-        // eprintln!("Our delta data: {}", this_object_data.len());
-        // eprintln!("We should be turned into a data of size: {}", our_size);
-        let data_out = apply_delta(&base_object_data, this_object_data, our_size)?;
-        let unparsed_obj_out = UnparsedObject {
-            object_type: base_object_type,
-            payload: data_out
-        };
-        Ok(unparsed_obj_out)
+        let delta_data = &delta_data[num_read..];
+        check_object_size(our_size, self.max_object_size)?;
+        apply_delta(base_data, delta_data, our_size)
     }
 
     /// The continuation of `get_object_type_and_len_at_index`.
     /// Call this to fully resolve an object from a packfile using previously
     /// found information from the `get_object_type_and_len_at_index` call.
-    /// This function will recursively resolve delta offsets (but not reference deltas!)
+    /// This function will resolve delta offsets (but not reference deltas!)
     /// and return an unparsed object that should be either a commit, tree, blob, or tag.
     pub fn resolve_unparsed_object(
         &self,
@@ -407,7 +651,7 @@ impl PackFile {
                     decompressor, decompressed_size, starts_at, UnparsedObjectType::Tag)
             }
             PackFileObjectType::OfsDelta(base_starts_at) => {
-                self.resolve_ofs_delta_object(
+                self.resolve_ofs_delta_chain(
                     decompressor, decompressed_size, starts_at, base_starts_at)
             }
             PackFileObjectType::RefDelta(id) => {
@@ -418,15 +662,123 @@ impl PackFile {
     }
 }
 
+/// how many objects of each type a pack has, see `PackFile::type_histogram`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TypeHistogram {
+    pub commits: u64,
+    pub trees: u64,
+    pub blobs: u64,
+    pub tags: u64,
+    pub ofs_deltas: u64,
+    pub ref_deltas: u64,
+}
+
+impl TypeHistogram {
+    pub fn total(&self) -> u64 {
+        self.commits + self.trees + self.blobs + self.tags + self.ofs_deltas + self.ref_deltas
+    }
+}
+
+/// every oid of one pack, and where it starts in the pack file. Build it
+/// once per pack with `PackOffsetMap::build`, and then use
+/// `PackFile::find_object_offset_by_oid` instead of going back to the idx.
+/// `CachingState` can build and keep these for you, see `use_offset_maps`.
+#[derive(Debug, Default)]
+pub struct PackOffsetMap {
+    pub pack_id: OidFull,
+    /// full oid -> (pack offset, fanout index in the idx file).
+    /// its keyed by the full oid so that 2 objects whose truncated
+    /// `Oid`s are the same can't overwrite each other.
+    offsets: HashMap<OidFull, (u64, usize)>,
+}
+
+impl PackOffsetMap {
+    /// read every oid and offset out of `idx`, in fanout order.
+    /// Errors if `idx` doesn't know the full oids of its objects.
+    pub fn build<I: IDXState>(idx: &mut I) -> io::Result<PackOffsetMap> {
+        let num_objects = idx.num_objects();
+        let mut offsets = HashMap::with_capacity(num_objects);
+        for oid_index in 0..num_objects {
+            let oid = idx.get_full_oid_at_fanout_index(oid_index)
+                .ok_or_else(|| ioerr!("Failed to read the full oid at fanout index {}", oid_index))?;
+            let offset = idx.find_packfile_index_from_fanout_index(oid_index)
+                .ok_or_else(|| ioerr!("Failed to read packfile offset of {} for fanout index {}", oid_full_to_string(oid), oid_index))?;
+            offsets.insert(oid, (offset, oid_index));
+        }
+        Ok(PackOffsetMap { pack_id: idx.id(), offsets })
+    }
+
+    /// (pack offset, fanout index) of this oid, if it is in the pack.
+    pub fn get(&self, oid: OidFull) -> Option<(u64, usize)> {
+        self.offsets.get(&oid).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+}
+
+/// Created from `PackFile::iter_objects`. walks the pack file
+/// from `DATA_STARTS_AT` forward, one object at a time.
+/// If reading an object fails, the error is returned and the
+/// iteration stops.
+pub struct PackFileObjectIter<'a> {
+    pack: &'a PackFile,
+    decompressor: &'a mut Decompress,
+    next_index: usize,
+    objects_remaining: u32,
+}
+
+impl<'a> Iterator for PackFileObjectIter<'a> {
+    type Item = io::Result<(usize, PackFileObjectType, u128)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.objects_remaining == 0 {
+            return None;
+        }
+        self.objects_remaining -= 1;
+        let index = self.next_index;
+        let (obj_type, obj_size, obj_starts_at) = match self.pack.get_object_type_and_len_at_index(index) {
+            Ok(t) => t,
+            Err(e) => {
+                self.objects_remaining = 0;
+                return Some(Err(e));
+            }
+        };
+        let compressed_len = match self.pack.get_compressed_len_at_index(obj_starts_at, self.decompressor) {
+            Ok(l) => l,
+            Err(e) => {
+                self.objects_remaining = 0;
+                return Some(Err(e));
+            }
+        };
+        self.next_index = obj_starts_at + compressed_len;
+        Some(Ok((index, obj_type, obj_size)))
+    }
+}
+
+/// the size from an object header is a u128, but we need a usize to index anything with it.
+fn object_size_to_usize(size: u128) -> io::Result<usize> {
+    size.try_into()
+        .map_err(|_| ioerr!("Failed to convert {} into a usize. Either we failed at parsing this value, or your architecture does not support numbers this large", size))
+}
+
 /// algorithm borrowed from:
 /// https://github.com/speedata/gogit/blob/c5cbd8f9b7205cd5390219b532ca35d0f76b9eab/repository.go#L220
 /// Im not sure how/why this is different from
 /// `find_negative_offset`. I thought they were supposed to do the
 /// same thing but apparently not...
+/// Returns None if `d` ends before the last byte of the length
+/// (one without the MSB set), including if `d` is empty, so it is safe
+/// to call with a slice that was cut short at the end of a pack.
 #[inline(always)]
 pub fn find_encoded_length(d: &[u8]) -> Option<(usize, usize)> {
     let mut num_bytes_read = 1;
-    let first_byte = d[0] as usize;
+    let first_byte = *d.first()? as usize;
     let mut value = first_byte & 0x7f;
     if first_byte & 0b1000_0000 == 0 {
         return Some((value, num_bytes_read))
@@ -460,10 +812,12 @@ pub fn find_encoded_length(d: &[u8]) -> Option<(usize, usize)> {
 
 /// algorithm borrowed from:
 /// https://github.com/Byron/gitoxide/blob/6200ed9ac5609c74de4254ab663c19cfe3591402/git-pack/src/data/entry/decode.rs#L99
-/// Returns length, and number of bytes read
+/// Returns length, and number of bytes read.
+/// Like `find_encoded_length`, returns None instead of panicking
+/// if `d` is empty or ends before the offset does.
 #[inline(always)]
 pub fn find_negative_offset(d: &[u8]) -> Option<(usize, usize)> {
-    let first_byte = d[0];
+    let first_byte = *d.first()?;
     let mut value = first_byte as usize & 0x7f;
     let mut num_bytes_read = 1;
     if first_byte & 0b1000_0000 == 0 {
@@ -508,19 +862,35 @@ pub fn open_pack_file<P: AsRef<Path>>(
     path: P,
     id: OidFull,
 ) -> io::Result<PackFile> {
-    let mmapped = fs_helpers::get_mmapped_file(&path)?;
+    open_pack_file_with(path, id, ReadMode::Mmap)
+}
+
+/// Like `open_pack_file`, but you can choose if the
+/// file gets mmapped, or read entirely into memory.
+pub fn open_pack_file_with<P: AsRef<Path>>(
+    path: P,
+    id: OidFull,
+    read_mode: ReadMode,
+) -> io::Result<PackFile> {
+    let mmapped = fs_helpers::get_file_backing(&path, read_mode)?;
     let pack_size = mmapped.len();
     if pack_size < MINIMAL_PACK_FILE_SIZE {
-        return ioerre!("Pack file {:?} is too small to be a valid pack file", path.as_ref());
+        return Err(GitReaderError::CorruptPack {
+            path: path.as_ref().to_path_buf(),
+            reason: "too small to be a valid pack file".into(),
+        }.into());
     }
     let header = &mmapped[0..PACK_HEADER_SIZE];
     let signature = &header[0..4];
     if signature != PACK_SIGNATURE {
-        return ioerre!("Pack file {:?} did not have valid signature of 'PACK'", path.as_ref());
+        return Err(GitReaderError::CorruptPack {
+            path: path.as_ref().to_path_buf(),
+            reason: "did not have valid signature of 'PACK'".into(),
+        }.into());
     }
     let version_number = BigEndian::read_u32(&header[4..8]);
     if !ACCEPTABLE_VERSION_NUMBERS.contains(&version_number) {
-        return ioerre!("Pack file {:?} version number '{}' is not valid", path.as_ref(), version_number);
+        return Err(GitReaderError::UnsupportedVersion(version_number).into());
     }
 
     let num_objects = BigEndian::read_u32(&header[8..12]);
@@ -528,10 +898,23 @@ pub fn open_pack_file<P: AsRef<Path>>(
         id,
         num_objects,
         mmapped_file: mmapped,
+        max_object_size: DEFAULT_MAX_OBJECT_SIZE,
     };
     Ok(packfile)
 }
 
+/// Like `open_pack_file`, but the id comes from an idx file that
+/// you already opened, and the pack is checked against it.
+/// See `PackFile::check_against_idx`.
+pub fn open_pack_file_checked<I: IDXState, P: AsRef<Path>>(
+    path: P,
+    idx: &I,
+) -> io::Result<PackFile> {
+    let pack = open_pack_file(&path, idx.id())?;
+    pack.check_against_idx(idx, &path)?;
+    Ok(pack)
+}
+
 /// Use this if you don't know the id of the pack file yet
 /// and you wish to parse it from the filename.
 /// Otherwise, use `open_pack_file` if you already
@@ -540,7 +923,253 @@ pub fn open_pack_file_ex<P: AsRef<Path>>(
     path: P
 ) -> io::Result<PackFile> {
     let path = path.as_ref();
-    let pack_id = parse_pack_or_idx_id(&path)
-        .ok_or_else(|| ioerr!("Failed to parse id from pack file: {:?}", path))?;
+    let pack_id = try_parse_pack_or_idx_id(path)?
+        .ok_or_else(|| ioerr!("Failed to parse id from pack file: {:?} is not named like a pack file", path))?;
     open_pack_file(path, pack_id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_database::packed::IDXFileLight;
+    use crate::test_helpers::{PackObject, pack_bytes, pack_bytes_with_level};
+
+    fn pack_from_bytes(data: Vec<u8>) -> PackFile {
+        PackFile {
+            id: [0; 20],
+            num_objects: 1,
+            mmapped_file: Backing::Heap(data),
+            max_object_size: DEFAULT_MAX_OBJECT_SIZE,
+        }
+    }
+
+    #[test]
+    fn object_header_near_eof_works() {
+        let mut data = b"PACK\0\0\0\x02\0\0\0\x01".to_vec();
+        // a blob of size 5, with big enough sizes that it
+        // needs 2 bytes, followed by 1 byte of "data":
+        data.extend_from_slice(&[0b1011_0101, 0b0000_0001, 0]);
+        let pack = pack_from_bytes(data);
+        let (obj_type, len, data_starts) = pack.get_object_type_and_len_at_index(DATA_STARTS_AT).unwrap();
+        assert!(matches!(obj_type, PackFileObjectType::Blob));
+        assert_eq!(len, 5 + (1 << 4));
+        assert_eq!(data_starts, DATA_STARTS_AT + 2);
+
+        // a length that never terminates is still an error:
+        let mut data = b"PACK\0\0\0\x02\0\0\0\x01".to_vec();
+        data.extend_from_slice(&[0b1011_0101, 0b1000_0001]);
+        let pack = pack_from_bytes(data);
+        assert!(pack.get_object_type_and_len_at_index(DATA_STARTS_AT).is_err());
+        assert!(pack.get_object_type_and_len_at_index(DATA_STARTS_AT + 2).is_err());
+    }
+
+    #[test]
+    fn pack_idx_count_mismatch_errors() {
+        let mut data = b"PACK\0\0\0\x02\0\0\0\x01".to_vec();
+        data.extend_from_slice(&[0; 24]);
+        let pack = pack_from_bytes(data);
+        let mut idx_bytes = vec![0xff, b't', b'O', b'c', 0, 0, 0, 2];
+        // an empty v2 idx: 256 fanout entries of 0, then the two checksums
+        idx_bytes.extend_from_slice(&[0; 256 * 4 + 40]);
+        let idx = IDXFileLight::from_backing(Backing::Heap(idx_bytes), [0; 20]).unwrap();
+        let err = pack.check_against_idx(&idx, "pack-0.pack").unwrap_err();
+        assert!(matches!(GitReaderError::from_io_error(&err), Some(GitReaderError::CorruptPack { .. })));
+        assert!(err.to_string().contains("1 objects, but its idx file has 0"));
+
+        let mut other_pack = pack_from_bytes(b"PACK\0\0\0\x02\0\0\0\0".to_vec());
+        other_pack.num_objects = 0;
+        assert!(other_pack.check_against_idx(&idx, "pack-0.pack").is_ok());
+        other_pack.id = [1; 20];
+        assert!(other_pack.check_against_idx(&idx, "pack-0.pack").is_err());
+    }
+
+    #[test]
+    fn pack_trailer_mismatch_errors() {
+        use crate::{object_id::full_oid_from_str, object_database::packed::open_idx_file_light, test_helpers::{copy_packed_fixture_objects, OFS_DELTA_PACK}};
+        let dir = copy_packed_fixture_objects("pack_trailer_mismatch");
+        let pack_path = dir.join("pack").join(format!("pack-{}.pack", OFS_DELTA_PACK));
+        let idx = open_idx_file_light(pack_path.with_extension("idx")).unwrap();
+        assert!(open_pack_file_checked(&pack_path, &idx).is_ok());
+
+        let mut data = std::fs::read(&pack_path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        std::fs::write(&pack_path, data).unwrap();
+        let err = open_pack_file_checked(&pack_path, &idx).err().unwrap();
+        assert!(matches!(GitReaderError::from_io_error(&err), Some(GitReaderError::CorruptPack { .. })));
+        assert!(err.to_string().contains("trailer checksum"));
+        assert_eq!(idx.pack_checksum(), full_oid_from_str(OFS_DELTA_PACK));
+    }
+
+    #[test]
+    fn varint_decoders_handle_short_input() {
+        assert_eq!(find_encoded_length(&[]), None);
+        assert_eq!(find_negative_offset(&[]), None);
+        // the MSB says theres more, but there isnt:
+        assert_eq!(find_encoded_length(&[0x80]), None);
+        assert_eq!(find_negative_offset(&[0x80]), None);
+        assert_eq!(find_encoded_length(&[0xff, 0x80]), None);
+        assert_eq!(find_negative_offset(&[0xff, 0x80]), None);
+
+        assert_eq!(find_encoded_length(&[0x05]), Some((5, 1)));
+        assert_eq!(find_encoded_length(&[0x80, 0x01]), Some((128, 2)));
+        assert_eq!(find_negative_offset(&[0x05]), Some((5, 1)));
+        // offsets are stored with an extra 1 added per continuation byte:
+        assert_eq!(find_negative_offset(&[0x80, 0x00]), Some((128, 2)));
+    }
+
+    #[test]
+    fn huge_object_lengths_dont_overflow() {
+        // a blob header where every one of the 18 bytes has
+        // all of its length bits set:
+        let mut data = b"PACK\0\0\0\x02\0\0\0\x01".to_vec();
+        data.push(0b1011_1111);
+        data.extend_from_slice(&[0xff; 16]);
+        data.push(0x7f);
+        data.extend_from_slice(&[0; 20]);
+        let pack = pack_from_bytes(data.clone());
+        let (_, len, next) = pack.get_object_type_and_len_at_index(DATA_STARTS_AT).unwrap();
+        assert_eq!(len, (1u128 << 123) - 1);
+        assert_eq!(next, DATA_STARTS_AT + 18);
+
+        // and one more continuation byte is an error, not a panic:
+        data[DATA_STARTS_AT + 17] = 0xff;
+        data.insert(DATA_STARTS_AT + 18, 0x7f);
+        let pack = pack_from_bytes(data);
+        assert!(pack.get_object_type_and_len_at_index(DATA_STARTS_AT).is_err());
+    }
+
+    #[test]
+    fn large_objects_decompress_fully() {
+        // stored (uncompressed) blocks make the compressed data
+        // bigger than the object itself:
+        let payload: Vec<u8> = (0..2_000_000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        let (data, object_starts) = pack_bytes_with_level(
+            &[PackObject::Whole(UnparsedObjectType::Blob, &payload)], flate2::Compression::none());
+        assert!(data.len() > payload.len() + 128);
+        let pack = pack_from_bytes(data);
+        let (_, size, data_starts_at) = pack.get_object_type_and_len_at_index(object_starts[0]).unwrap();
+        assert_eq!(size as usize, payload.len());
+        let mut decompressor = Decompress::new(true);
+        let out = pack.get_decompressed_data_from_index(payload.len(), data_starts_at, &mut decompressor).unwrap();
+        assert!(out == payload);
+
+        // a header that claims more than there is, is an error:
+        let err = pack.get_decompressed_data_from_index(payload.len() + 1, data_starts_at, &mut decompressor).unwrap_err();
+        assert!(err.to_string().contains("decompressed to 2000000 bytes"));
+    }
+
+    #[test]
+    fn ofs_delta_chain_matches_recursive_resolve() {
+        use crate::object_database::packed::make_delta;
+        // a blob, and then a chain of deltas that each
+        // have the object right before them as their base:
+        let depth = 200;
+        let mut versions = vec![b"line 0\n".repeat(40)];
+        let mut deltas = vec![];
+        for i in 1..=depth {
+            let mut next = versions[i - 1].clone();
+            next.extend_from_slice(format!("line {}\n", i).as_bytes());
+            deltas.push(make_delta(&versions[i - 1], &next));
+            versions.push(next);
+        }
+        let mut objects = vec![PackObject::Whole(UnparsedObjectType::Blob, &versions[0])];
+        objects.extend(deltas.iter().enumerate().map(|(i, delta)| PackObject::OfsDelta(i, delta)));
+        let (data, object_starts) = pack_bytes(&objects);
+        let mut pack = pack_from_bytes(data);
+        pack.num_objects = depth as u32 + 1;
+
+        let mut decompressor = Decompress::new(true);
+        for i in [1, 2, 50, depth].iter() {
+            let (obj_type, size, data_starts_at) = pack.get_object_type_and_len_at_index(object_starts[*i]).unwrap();
+            let base_starts_at = match obj_type {
+                PackFileObjectType::OfsDelta(b) => b,
+                _ => panic!("expected an ofs delta"),
+            };
+            assert_eq!(base_starts_at, object_starts[i - 1]);
+            let size = size as usize;
+            let looped = pack.resolve_ofs_delta_chain(&mut decompressor, size, data_starts_at, base_starts_at).unwrap();
+            let recursed = pack.resolve_ofs_delta_object(&mut decompressor, size, data_starts_at, base_starts_at).unwrap();
+            assert_eq!(looped.object_type, UnparsedObjectType::Blob);
+            assert_eq!(recursed.object_type, UnparsedObjectType::Blob);
+            assert!(looped.payload == versions[*i]);
+            assert!(recursed.payload == looped.payload);
+            let resolved = pack.resolve_unparsed_object(size, data_starts_at, obj_type, &mut decompressor).unwrap();
+            assert!(resolved.payload == versions[*i]);
+        }
+
+        // a delta whose base is itself is an error, not an endless loop:
+        let (_, size, data_starts_at) = pack.get_object_type_and_len_at_index(object_starts[3]).unwrap();
+        assert!(pack.resolve_ofs_delta_chain(&mut decompressor, size as usize, data_starts_at, object_starts[3]).is_err());
+        assert!(pack.resolve_ofs_delta_object(&mut decompressor, size as usize, data_starts_at, object_starts[3]).is_err());
+    }
+
+    #[test]
+    fn type_histogram_works() {
+        // a blob of 3 bytes, a commit of 3 bytes, and an ofs delta
+        // of 2 bytes whose base is the blob:
+        let (data, _) = pack_bytes(&[
+            PackObject::Whole(UnparsedObjectType::Blob, b"hi\n"),
+            PackObject::Whole(UnparsedObjectType::Commit, b"abc"),
+            PackObject::OfsDelta(0, &[3, 3]),
+        ]);
+        let mut pack = pack_from_bytes(data);
+        pack.num_objects = 3;
+        let histogram = pack.type_histogram().unwrap();
+        assert_eq!(histogram, TypeHistogram { commits: 1, blobs: 1, ofs_deltas: 1, ..Default::default() });
+        assert_eq!(histogram.total(), 3);
+
+        // a pack that claims to have more objects than it does:
+        pack.num_objects = 4;
+        assert!(pack.type_histogram().is_err());
+    }
+
+    #[test]
+    fn offset_map_finds_ref_delta_bases() {
+        use crate::object_database::packed::build_idx_from_pack;
+        let base = b"hello world\n";
+        let base_oid = crate::sha1::hash_object("blob", base);
+        // a ref delta of 7 bytes that makes "hi!\n" out of the base:
+        let (data, object_starts) = pack_bytes(&[
+            PackObject::Whole(UnparsedObjectType::Blob, base),
+            PackObject::RefDelta(base_oid, &[12, 4, 4, b'h', b'i', b'!', b'\n']),
+        ]);
+        let mut pack = pack_from_bytes(data);
+        pack.num_objects = 2;
+
+        let mut idx = build_idx_from_pack(&pack).unwrap();
+        let offsets = PackOffsetMap::build(&mut idx).unwrap();
+        assert_eq!(offsets.len(), 2);
+        assert_eq!(pack.find_object_offset_by_oid(base_oid, &offsets), Some(object_starts[0]));
+        let delta_oid = crate::sha1::hash_object("blob", b"hi!\n");
+        assert_eq!(pack.find_object_offset_by_oid(delta_oid, &offsets), Some(object_starts[1]));
+        assert_eq!(pack.find_object_offset_by_oid([1; 20], &offsets), None);
+
+        // the map of some other pack is never used:
+        pack.id = [1; 20];
+        assert_eq!(pack.find_object_offset_by_oid(base_oid, &offsets), None);
+    }
+
+    #[test]
+    fn offset_map_keeps_oids_with_the_same_truncated_oid() {
+        use crate::{object_database::packed::InMemoryIdx, object_id::full_oid_to_u128_oid};
+        // only the last 4 bytes differ, so their `Oid`s are the same:
+        let first = [7; 20];
+        let mut second = first;
+        second[19] = 8;
+        assert_eq!(full_oid_to_u128_oid(first), full_oid_to_u128_oid(second));
+        let mut fanout_table = [0; 256];
+        for count in fanout_table.iter_mut().skip(7) {
+            *count = 2;
+        }
+        let mut idx = InMemoryIdx {
+            id: [0; 20],
+            fanout_table,
+            entries: vec![(first, 12, 0), (second, 40, 0)],
+        };
+        let offsets = PackOffsetMap::build(&mut idx).unwrap();
+        assert_eq!(offsets.len(), 2);
+        assert_eq!(offsets.get(first), Some((12, 0)));
+        assert_eq!(offsets.get(second), Some((40, 1)));
+    }
+}
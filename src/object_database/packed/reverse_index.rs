@@ -0,0 +1,238 @@
+use std::{io, path::Path};
+use byteorder::{BigEndian, ByteOrder};
+use crate::{ioerr, ioerre, fs_helpers::{self, Backing, ReadMode}, object_id::{Oid, full_oid_to_u128_oid}};
+use super::{IDXFileLight, PackFile};
+
+pub const REV_SIGNATURE: &[u8; 4] = b"RIDX";
+pub const REV_VERSION_NUMBER: u32 = 1;
+/// the hash function id of a .rev file for a sha1 repo
+pub const REV_HASH_ID_SHA1: u32 = 1;
+/// 4 byte signature, 4 byte version, 4 byte hash function id
+pub const REV_HEADER_SIZE: usize = 4 + 4 + 4;
+/// the pack checksum, and the checksum of the .rev file itself
+pub const REV_TRAILER_SIZE: usize = 20 + 20;
+const REV_ENTRY_SIZE: usize = 4;
+
+/// Lets you go from an offset in a pack file to the entry in the
+/// idx file for the object that starts at that offset. The idx file
+/// is sorted by oid, so without this you have to scan every
+/// entry of the idx to find an offset.
+pub enum PackReverseIndex {
+    /// a .rev file that git wrote next to the pack. It contains
+    /// the idx positions of every object, in the order they appear in the pack file.
+    /// so we can binary search it without loading anything into memory.
+    RevFile(Backing),
+    /// (pack offset, idx position) for every object, sorted by pack offset.
+    /// this is what we build ourselves when there is no .rev file.
+    Built(Vec<(u64, usize)>),
+}
+
+impl PackReverseIndex {
+    /// read every offset from the idx file, and sort them.
+    pub fn build(idx: &IDXFileLight) -> io::Result<PackReverseIndex> {
+        let mut entries = Vec::with_capacity(idx.num_objects);
+        for fanout_index in 0..idx.num_objects {
+            let offset = idx.find_packfile_index_from_fanout_index(fanout_index)
+                .ok_or_else(|| ioerr!("Failed to read packfile offset for fanout index {}", fanout_index))?;
+            entries.push((offset, fanout_index));
+        }
+        entries.sort_unstable();
+        Ok(PackReverseIndex::Built(entries))
+    }
+
+    /// open a .rev file. see:
+    /// https://git-scm.com/docs/pack-format#_pack_rev_files_have_the_format
+    pub fn open_rev_file<P: AsRef<Path>>(
+        path: P,
+        idx: &IDXFileLight,
+    ) -> io::Result<PackReverseIndex> {
+        let path = path.as_ref();
+        let backing = fs_helpers::get_file_backing(path, ReadMode::Mmap)?;
+        let expected_size = REV_HEADER_SIZE + idx.num_objects * REV_ENTRY_SIZE + REV_TRAILER_SIZE;
+        if backing.len() != expected_size {
+            return ioerre!("Rev file {:?} has size {}, but expected {} for {} objects", path, backing.len(), expected_size, idx.num_objects);
+        }
+        if &backing[0..4] != REV_SIGNATURE {
+            return ioerre!("Rev file {:?} did not have valid signature of 'RIDX'", path);
+        }
+        let version_number = BigEndian::read_u32(&backing[4..8]);
+        if version_number != REV_VERSION_NUMBER {
+            return ioerre!("Rev file {:?} has unsupported version number {}", path, version_number);
+        }
+        let hash_id = BigEndian::read_u32(&backing[8..12]);
+        if hash_id != REV_HASH_ID_SHA1 {
+            return ioerre!("Rev file {:?} has hash function id {}, but we only support sha1 ({})", path, hash_id, REV_HASH_ID_SHA1);
+        }
+        // the trailer starts with the checksum of the pack, so
+        // we can tell if this .rev file is for some other pack:
+        let trailer_starts_at = backing.len() - REV_TRAILER_SIZE;
+        let pack_checksum = &backing[trailer_starts_at..trailer_starts_at + 20];
+        if idx.pack_checksum().as_ref().map(|c| &c[..]) != Some(pack_checksum) {
+            return ioerre!("Rev file {:?} is for a different pack than its idx file", path);
+        }
+        Ok(PackReverseIndex::RevFile(backing))
+    }
+
+    /// uses the .rev file next to the pack file if it exists,
+    /// otherwise builds the reverse index from the idx file.
+    pub fn open_or_build<P: AsRef<Path>>(
+        pack_path: P,
+        idx: &IDXFileLight,
+    ) -> io::Result<PackReverseIndex> {
+        let rev_path = pack_path.as_ref().with_extension("rev");
+        if rev_path.is_file() {
+            return PackReverseIndex::open_rev_file(rev_path, idx);
+        }
+        PackReverseIndex::build(idx)
+    }
+
+    fn num_entries(&self) -> usize {
+        match self {
+            PackReverseIndex::RevFile(b) => (b.len() - REV_HEADER_SIZE - REV_TRAILER_SIZE) / REV_ENTRY_SIZE,
+            PackReverseIndex::Built(v) => v.len(),
+        }
+    }
+
+    /// the idx position of the nth object in the pack file.
+    fn idx_position_at(&self, n: usize) -> usize {
+        match self {
+            PackReverseIndex::RevFile(b) => {
+                let entry_starts = REV_HEADER_SIZE + n * REV_ENTRY_SIZE;
+                BigEndian::read_u32(&b[entry_starts..entry_starts + REV_ENTRY_SIZE]) as usize
+            }
+            PackReverseIndex::Built(v) => v[n].1,
+        }
+    }
+
+    /// find the idx position (fanout index) of the
+    /// object that starts at `offset` in the pack file.
+    pub fn fanout_index_at_offset(&self, offset: u64, idx: &IDXFileLight) -> Option<usize> {
+        if let PackReverseIndex::Built(v) = self {
+            return v.binary_search_by_key(&offset, |&(o, _)| o).ok()
+                .map(|i| v[i].1);
+        }
+        let mut low = 0;
+        let mut high = self.num_entries();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let fanout_index = self.idx_position_at(mid);
+            let mid_offset = idx.find_packfile_index_from_fanout_index(fanout_index)?;
+            match mid_offset.cmp(&offset) {
+                std::cmp::Ordering::Equal => return Some(fanout_index),
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+            }
+        }
+        None
+    }
+
+    /// the oid of the object that starts at `offset` in the pack file.
+    pub fn oid_at_offset(&self, offset: u64, idx: &IDXFileLight) -> io::Result<Oid> {
+        let fanout_index = self.fanout_index_at_offset(offset, idx)
+            .ok_or_else(|| ioerr!("No object in the idx file starts at pack offset {}", offset))?;
+        let oid_full = idx.get_full_oid_at_fanout_index(fanout_index)
+            .ok_or_else(|| ioerr!("Failed to read oid at fanout index {}", fanout_index))?;
+        Ok(full_oid_to_u128_oid(oid_full))
+    }
+}
+
+impl PackFile {
+    /// find the oid of the object that starts at `offset` in this pack file,
+    /// ie: the base of an `OfsDelta`. `pack_path` is where this pack is, so
+    /// that the first lookup can use the .rev file next to it, or build the
+    /// reverse index from the idx file if it has none (see `PackReverseIndex::open_or_build`).
+    /// That is stored in `reverse_index`, so pass the same
+    /// `reverse_index` back in for the next lookups.
+    pub fn oid_at_pack_offset<P: AsRef<Path>>(
+        &self,
+        offset: u64,
+        idx: &IDXFileLight,
+        pack_path: P,
+        reverse_index: &mut Option<PackReverseIndex>,
+    ) -> io::Result<Oid> {
+        if idx.id != self.id {
+            return ioerre!("Idx file does not belong to this pack file");
+        }
+        let rev = match reverse_index {
+            Some(rev) => rev,
+            None => reverse_index.insert(PackReverseIndex::open_or_build(pack_path, idx)?),
+        };
+        rev.oid_at_offset(offset, idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_database::LightObjectDB;
+    use crate::object_id::full_oid_from_str;
+    use crate::test_helpers::{packed_fixture, copy_packed_fixture_objects, OFS_DELTA_PACK, REF_DELTA_PACK};
+
+    fn pack_path(objects_dir: &Path, id: &str) -> std::path::PathBuf {
+        objects_dir.join("pack").join(format!("pack-{}.pack", id))
+    }
+
+    #[test]
+    fn rev_file_matches_built() {
+        let objects_dir = packed_fixture().join("objects");
+        let odb = LightObjectDB::new(objects_dir.to_str().unwrap()).unwrap();
+        let idx = odb.open_idx(full_oid_from_str(OFS_DELTA_PACK).unwrap()).unwrap();
+        let path = pack_path(&objects_dir, OFS_DELTA_PACK);
+        let rev_file = PackReverseIndex::open_or_build(&path, &idx).unwrap();
+        assert!(matches!(rev_file, PackReverseIndex::RevFile(_)));
+        let built = PackReverseIndex::build(&idx).unwrap();
+        assert_eq!(rev_file.num_entries(), idx.num_objects);
+        assert_eq!(built.num_entries(), idx.num_objects);
+        for n in 0..idx.num_objects {
+            assert_eq!(rev_file.idx_position_at(n), built.idx_position_at(n));
+        }
+        for fanout_index in 0..idx.num_objects {
+            let offset = idx.find_packfile_index_from_fanout_index(fanout_index).unwrap();
+            assert_eq!(rev_file.fanout_index_at_offset(offset, &idx), Some(fanout_index));
+            assert_eq!(built.fanout_index_at_offset(offset, &idx), Some(fanout_index));
+        }
+        assert_eq!(rev_file.fanout_index_at_offset(13, &idx), None);
+    }
+
+    #[test]
+    fn oid_at_pack_offset_uses_rev_file() {
+        let objects_dir = packed_fixture().join("objects");
+        let odb = LightObjectDB::new(objects_dir.to_str().unwrap()).unwrap();
+        for (id, has_rev) in [(OFS_DELTA_PACK, true), (REF_DELTA_PACK, false)].iter() {
+            let pack_id = full_oid_from_str(id).unwrap();
+            let idx = odb.open_idx(pack_id).unwrap();
+            let pack = odb.open_pack(pack_id).unwrap();
+            let path = pack_path(&objects_dir, id);
+            let mut reverse_index = None;
+            for fanout_index in 0..idx.num_objects {
+                let offset = idx.find_packfile_index_from_fanout_index(fanout_index).unwrap();
+                let oid = pack.oid_at_pack_offset(offset, &idx, &path, &mut reverse_index).unwrap();
+                assert_eq!(oid, full_oid_to_u128_oid(idx.get_full_oid_at_fanout_index(fanout_index).unwrap()));
+            }
+            assert_eq!(matches!(reverse_index, Some(PackReverseIndex::RevFile(_))), *has_rev);
+        }
+    }
+
+    #[test]
+    fn rev_file_for_another_pack_errors() {
+        let objects_dir = copy_packed_fixture_objects("rev_file_checks");
+        let odb = LightObjectDB::new(objects_dir.path_str()).unwrap();
+        let idx = odb.open_idx(full_oid_from_str(OFS_DELTA_PACK).unwrap()).unwrap();
+        let rev_path = pack_path(objects_dir.path(), OFS_DELTA_PACK).with_extension("rev");
+        let original = std::fs::read(&rev_path).unwrap();
+        assert!(PackReverseIndex::open_rev_file(&rev_path, &idx).is_ok());
+
+        let mut sha256 = original.clone();
+        sha256[11] = 2;
+        std::fs::write(&rev_path, &sha256).unwrap();
+        let err = PackReverseIndex::open_rev_file(&rev_path, &idx).err().unwrap();
+        assert!(err.to_string().contains("hash function id 2"), "{}", err);
+
+        let mut other_pack = original;
+        let trailer_starts_at = other_pack.len() - REV_TRAILER_SIZE;
+        other_pack[trailer_starts_at] ^= 0xff;
+        std::fs::write(&rev_path, &other_pack).unwrap();
+        let err = PackReverseIndex::open_rev_file(&rev_path, &idx).err().unwrap();
+        assert!(err.to_string().contains("different pack"), "{}", err);
+    }
+}
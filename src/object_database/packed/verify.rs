@@ -0,0 +1,215 @@
+use std::{io, collections::HashMap, convert::TryInto};
+use byteorder::{BigEndian, ByteOrder};
+use flate2::{Crc, Decompress};
+use crate::{ioerr, object_id::OidFull, sha1::{sha1, hash_object}};
+use super::{PackFile, IDXFileLight, IDXVersion, PACK_SIGNATURE, ACCEPTABLE_VERSION_NUMBERS, PACK_HEADER_SIZE, MINIMAL_PACK_FILE_SIZE, resolve_for_idx};
+
+const TRAILER_SIZE: usize = 20;
+
+/// something that is wrong with a pack file. see `PackFile::verify`
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyProblem {
+    /// the pack does not start with 'PACK', or has a version we dont support
+    BadHeader(String),
+    /// the pack and idx disagree about how many objects there are
+    ObjectCountMismatch { in_pack: u32, in_idx: usize },
+    /// the sha1 at the end of the pack is not the sha1 of the rest of the pack
+    TrailerMismatch { expected: OidFull, actual: OidFull },
+    /// the idx says an object is somewhere that isn't in the pack
+    BadOffset { oid: OidFull, offset: u64 },
+    /// the crc32 of the object's raw (compressed) bytes is not the one in the idx
+    CrcMismatch { oid: OidFull, expected: u32, actual: u32 },
+    /// the object resolves, but hashes to something other than its oid
+    ShaMismatch { oid: OidFull, actual: OidFull },
+    /// we failed to read or resolve the object
+    BadObject { oid: OidFull, error: String },
+}
+
+/// the result of `PackFile::verify`.
+/// the pack is valid if `problems` is empty.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct VerifyReport {
+    /// how many objects the idx file says this pack has
+    pub num_objects: usize,
+    /// size of the pack file in bytes
+    pub pack_size: usize,
+    /// the sum of the sizes of every object after deltas are applied
+    pub total_object_size: usize,
+    pub problems: Vec<VerifyProblem>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl PackFile {
+    /// fully check this pack file against its idx file, like
+    /// `git verify-pack`. This checks the header, the trailing checksum,
+    /// the crc32 of every object (only for V2 idx files, V1 does
+    /// not have a crc table), and that every object in the idx
+    /// resolves to data that hashes to its oid.
+    /// Problems are collected in the report instead of returned
+    /// as errors, so you see all of them, not just the first one.
+    /// This reads and resolves every object, so its slow.
+    pub fn verify(&self, idx: &IDXFileLight) -> io::Result<VerifyReport> {
+        let data = &self.mmapped_file[..];
+        let mut report = VerifyReport {
+            num_objects: idx.num_objects,
+            pack_size: data.len(),
+            ..Default::default()
+        };
+        if data.len() < MINIMAL_PACK_FILE_SIZE || &data[0..4] != PACK_SIGNATURE {
+            report.problems.push(VerifyProblem::BadHeader("did not have valid signature of 'PACK'".into()));
+            return Ok(report);
+        }
+        let version_number = BigEndian::read_u32(&data[4..8]);
+        if !ACCEPTABLE_VERSION_NUMBERS.contains(&version_number) {
+            report.problems.push(VerifyProblem::BadHeader(format!("unsupported version {}", version_number)));
+            return Ok(report);
+        }
+        if self.num_objects as usize != idx.num_objects {
+            report.problems.push(VerifyProblem::ObjectCountMismatch {
+                in_pack: self.num_objects,
+                in_idx: idx.num_objects,
+            });
+        }
+
+        let trailer_starts_at = data.len() - TRAILER_SIZE;
+        let actual = sha1(&data[0..trailer_starts_at]);
+        let expected: OidFull = data[trailer_starts_at..].try_into()
+            .map_err(|_| ioerr!("Failed to read pack trailer"))?;
+        if expected != actual {
+            report.problems.push(VerifyProblem::TrailerMismatch { expected, actual });
+        }
+
+        // (oid, offset, fanout index) of every object with a valid offset
+        let mut objects = Vec::with_capacity(idx.num_objects);
+        for fanout_index in 0..idx.num_objects {
+            let oid = idx.get_full_oid_at_fanout_index(fanout_index)
+                .ok_or_else(|| ioerr!("Failed to read oid at index {} of idx file", fanout_index))?;
+            let offset = idx.find_packfile_index_from_fanout_index(fanout_index)
+                .ok_or_else(|| ioerr!("Failed to read pack offset at index {} of idx file", fanout_index))?;
+            let in_bounds = offset >= PACK_HEADER_SIZE as u64 && offset < trailer_starts_at as u64;
+            if !in_bounds {
+                report.problems.push(VerifyProblem::BadOffset { oid, offset });
+                continue;
+            }
+            objects.push((oid, offset as usize, fanout_index));
+        }
+
+        // an object's raw bytes go until the next object starts,
+        // or until the trailer for the last object:
+        let mut offsets: Vec<usize> = objects.iter().map(|o| o.1).collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        offsets.push(trailer_starts_at);
+        let check_crc = idx.version == IDXVersion::V2;
+
+        let oid_to_offset: HashMap<OidFull, usize> = objects.iter().map(|o| (o.0, o.1)).collect();
+        let mut decompressor = Decompress::new(true);
+        for (oid, offset, fanout_index) in objects {
+            if check_crc {
+                // the offsets are unique, so this is where our
+                // offset is, and the next one is where we end:
+                let ends_at = match offsets.binary_search(&offset) {
+                    Ok(position) => offsets[position + 1],
+                    Err(_) => {
+                        report.problems.push(VerifyProblem::BadObject { oid, error: format!("failed to find where the object at {} ends", offset) });
+                        continue;
+                    }
+                };
+                let mut crc = Crc::new();
+                crc.update(&data[offset..ends_at]);
+                let expected = idx.get_crc32_from_fanout_index(fanout_index)
+                    .ok_or_else(|| ioerr!("Failed to read crc32 at index {} of idx file", fanout_index))?;
+                if crc.sum() != expected {
+                    report.problems.push(VerifyProblem::CrcMismatch { oid, expected, actual: crc.sum() });
+                }
+            }
+
+            let resolved = match resolve_for_idx(self, &mut decompressor, &oid_to_offset, offset, 0) {
+                Ok(Some(obj)) => obj,
+                Ok(None) => {
+                    report.problems.push(VerifyProblem::BadObject { oid, error: "its delta base is not in this pack".into() });
+                    continue;
+                }
+                Err(e) => {
+                    report.problems.push(VerifyProblem::BadObject { oid, error: e.to_string() });
+                    continue;
+                }
+            };
+            report.total_object_size += resolved.payload.len();
+            let actual = hash_object(resolved.object_type.as_str(), &resolved.payload);
+            if actual != oid {
+                report.problems.push(VerifyProblem::ShaMismatch { oid, actual });
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_database::LightObjectDB;
+    use crate::object_id::full_oid_from_str;
+    use crate::test_helpers::{copy_packed_fixture_objects, OFS_DELTA_PACK, REF_DELTA_PACK, PACKED_COMMITS};
+
+    /// verify the pack `id` of `objects_dir` after changing it with `corrupt`.
+    fn verify_with<F: FnOnce(&mut Vec<u8>)>(name: &str, id: &str, corrupt: F) -> VerifyReport {
+        let objects_dir = copy_packed_fixture_objects(name);
+        let pack_path = objects_dir.join("pack").join(format!("pack-{}.pack", id));
+        let mut data = std::fs::read(&pack_path).unwrap();
+        corrupt(&mut data);
+        std::fs::write(&pack_path, data).unwrap();
+        let odb = LightObjectDB::new(objects_dir.path_str()).unwrap();
+        let id = full_oid_from_str(id).unwrap();
+        let pack = odb.open_pack(id).unwrap();
+        let idx = odb.open_idx(id).unwrap();
+        pack.verify(&idx).unwrap()
+    }
+
+    #[test]
+    fn clean_packs_verify() {
+        for id in [OFS_DELTA_PACK, REF_DELTA_PACK].iter() {
+            let report = verify_with("verify_clean", id, |_| {});
+            assert!(report.is_ok(), "{:?}", report.problems);
+            assert!(report.num_objects > 0);
+            assert!(report.total_object_size > 0);
+        }
+    }
+
+    #[test]
+    fn flipped_byte_is_a_crc_mismatch() {
+        // the newest commit is the first object in the pack,
+        // so this is in the middle of its compressed data:
+        let report = verify_with("verify_flipped", REF_DELTA_PACK, |data| data[PACK_HEADER_SIZE + 20] ^= 0xff);
+        let commit = full_oid_from_str(PACKED_COMMITS[4]).unwrap();
+        assert!(report.problems.iter().any(|p| matches!(p, VerifyProblem::CrcMismatch { oid, .. } if *oid == commit)), "{:?}", report.problems);
+        assert!(report.problems.iter().any(|p| matches!(p, VerifyProblem::TrailerMismatch { .. })));
+        // its the only object that is wrong:
+        assert!(report.problems.iter().all(|p| match p {
+            VerifyProblem::CrcMismatch { oid, .. } | VerifyProblem::BadObject { oid, .. } | VerifyProblem::ShaMismatch { oid, .. } => *oid == commit,
+            VerifyProblem::TrailerMismatch { .. } => true,
+            _ => false,
+        }), "{:?}", report.problems);
+    }
+
+    #[test]
+    fn bad_trailer_is_a_trailer_mismatch() {
+        let report = verify_with("verify_trailer", OFS_DELTA_PACK, |data| {
+            let last = data.len() - 1;
+            data[last] ^= 0xff;
+        });
+        assert_eq!(report.problems.len(), 1, "{:?}", report.problems);
+        match &report.problems[0] {
+            VerifyProblem::TrailerMismatch { expected, actual } => {
+                assert_ne!(expected, actual);
+                assert_eq!(*actual, full_oid_from_str(OFS_DELTA_PACK).unwrap());
+            }
+            other => panic!("expected a trailer mismatch, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::{io, sync::{Mutex, Condvar}, collections::VecDeque, num::NonZeroUsize};
+use crate::{ioerr, object_id::Oid};
+use super::{LightObjectDB, state::State, loose::UnparsedObjectType, oidmap_u128::{OidMap, defaults::B14}};
+
+/// like `LightObjectDB::reachable_objects`, but the objects are read
+/// and parsed on several threads. Use this for big repos where you
+/// want every reachable oid and don't care about the order:
+/// ```no_run
+/// use git_reader::object_database::{LightObjectDB, state::CachingState, reachable::ReachableObjects};
+///
+/// let odb = LightObjectDB::new("../.git/objects/").unwrap();
+/// let root = 0;
+/// let oids = ReachableObjects::new(&odb, vec![root])
+///     .threads(4)
+///     .collect_with(|| CachingState::new("../.git/objects/"))
+///     .unwrap();
+/// ```
+/// A `State` can't be shared between threads, so every thread makes
+/// its own with the function given to `collect_with`. A `CachingState`
+/// is a good choice, since each thread will read from the same packs
+/// over and over.
+pub struct ReachableObjects<'o, 'a> {
+    odb: &'o LightObjectDB<'a>,
+    roots: Vec<Oid>,
+    threads: usize,
+}
+
+/// what the threads share. The mutex and condvar are only used
+/// to hand out work, the objects are read without holding them.
+struct WorkQueue {
+    /// objects to read. None means we dont know what type
+    /// it is yet (a root, or the target of a tag)
+    items: VecDeque<(Oid, Option<UnparsedObjectType>)>,
+    /// how many objects are being read right now. If there's
+    /// nothing in the queue, and nothing being read, we are done.
+    in_flight: usize,
+    error: Option<io::Error>,
+}
+
+impl<'o, 'a> ReachableObjects<'o, 'a> {
+    /// walk everything reachable from any of the `roots`. Defaults
+    /// to as many threads as `std::thread::available_parallelism`.
+    pub fn new<I: IntoIterator<Item = Oid>>(odb: &'o LightObjectDB<'a>, roots: I) -> ReachableObjects<'o, 'a> {
+        let threads = std::thread::available_parallelism().map(NonZeroUsize::get).unwrap_or(1);
+        ReachableObjects { odb, roots: roots.into_iter().collect(), threads }
+    }
+
+    /// how many threads to read objects with. 0 is the same as 1.
+    pub fn threads(mut self, threads: usize) -> ReachableObjects<'o, 'a> {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// walk and return every reachable oid, each one only once, in
+    /// no particular order. `make_state` is called once per thread.
+    /// If reading any object fails, the walk stops and you get that error.
+    pub fn collect_with<S, F>(self, make_state: F) -> io::Result<Vec<Oid>>
+        where S: State,
+              F: Fn() -> io::Result<S> + Sync,
+    {
+        // the map is big, so we make it right where it will
+        // stay instead of moving it into the mutex:
+        let seen: Mutex<OidMap<(), B14>> = Mutex::new(OidMap::default());
+        let mut items = VecDeque::new();
+        {
+            let mut seen = seen.lock().unwrap();
+            for root in self.roots.iter() {
+                if !seen.contains_key(root) {
+                    seen.insert(*root, ());
+                    items.push_back((*root, None));
+                }
+            }
+        }
+        let queue = Mutex::new(WorkQueue { items, in_flight: 0, error: None });
+        let work_available = Condvar::new();
+
+        let per_thread: Vec<Vec<Oid>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..self.threads).map(|_| scope.spawn(|| {
+                let mut found = vec![];
+                let mut state = match make_state() {
+                    Ok(s) => s,
+                    Err(e) => {
+                        queue.lock().unwrap().error.get_or_insert(e);
+                        work_available.notify_all();
+                        return found;
+                    }
+                };
+                while let Some((oid, known_type)) = next_item(&queue, &work_available) {
+                    // if reading this object panics, the guard still takes it out
+                    // of flight, otherwise the other threads would wait forever:
+                    let in_flight = InFlight { queue: &queue, work_available: &work_available };
+                    found.push(oid);
+                    let mut links = vec![];
+                    let res = self.odb.visit_object_links(oid, known_type, &mut state, |next, next_type| {
+                        links.push((next, next_type));
+                    });
+                    let mut to_read = vec![];
+                    if res.is_ok() {
+                        let mut seen = seen.lock().unwrap();
+                        for (next, next_type) in links {
+                            if seen.contains_key(&next) {
+                                continue;
+                            }
+                            seen.insert(next, ());
+                            // we never read blobs, so theres no
+                            // reason to make them wait in the queue:
+                            if next_type == Some(UnparsedObjectType::Blob) {
+                                found.push(next);
+                            } else {
+                                to_read.push((next, next_type));
+                            }
+                        }
+                    }
+                    {
+                        let mut queue = queue.lock().unwrap();
+                        match res {
+                            Ok(()) => queue.items.extend(to_read),
+                            Err(e) => { queue.error.get_or_insert(e); }
+                        }
+                    }
+                    drop(in_flight);
+                }
+                found
+            })).collect();
+            handles.into_iter().map(|h| h.join().expect("reachable objects thread panicked")).collect()
+        });
+
+        if let Some(e) = queue.into_inner().unwrap().error {
+            return Err(e);
+        }
+        Ok(per_thread.into_iter().flatten().collect())
+    }
+}
+
+/// an object that one of the threads is reading. When this is dropped
+/// (even by a panic) the object is no longer in flight, and the other
+/// threads are woken up so they can see if the walk is done.
+struct InFlight<'q> {
+    queue: &'q Mutex<WorkQueue>,
+    work_available: &'q Condvar,
+}
+
+impl<'q> Drop for InFlight<'q> {
+    fn drop(&mut self) {
+        // we might be panicking while the lock is poisoned,
+        // and panicking again here would abort:
+        let mut queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        queue.in_flight -= 1;
+        if std::thread::panicking() {
+            // the panic itself is what the caller sees (from the join),
+            // this is only there to stop the other threads:
+            queue.error.get_or_insert_with(|| ioerr!("A reachable objects thread panicked"));
+        }
+        self.work_available.notify_all();
+    }
+}
+
+/// wait until there is an object to read, and mark it as in flight.
+/// None once the walk is done, or once a thread had an error.
+fn next_item(
+    queue: &Mutex<WorkQueue>,
+    work_available: &Condvar,
+) -> Option<(Oid, Option<UnparsedObjectType>)> {
+    let mut queue = queue.lock().unwrap();
+    loop {
+        if queue.error.is_some() {
+            return None;
+        }
+        if let Some(item) = queue.items.pop_front() {
+            queue.in_flight += 1;
+            return Some(item);
+        }
+        if queue.in_flight == 0 {
+            return None;
+        }
+        queue = work_available.wait(queue).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_database::{MAX_PATH_TO_DB_LEN, packed::IDXFileLight, state::{MinState, OwnedOrBorrowedMut}, loose::write_raw_object};
+    use crate::object_id::OidFull;
+    use flate2::Decompress;
+    use crate::object_id::{full_oid_to_u128_oid, oid_full_to_string};
+    use crate::test_helpers::{TempDir, CommitFixture};
+
+    #[test]
+    fn parallel_reachable_matches_sequential() {
+        let objects_dir = TempDir::objects_dir("reachable");
+        let objects_dir_str = objects_dir.path_str();
+
+        // a chain of commits, each with a tree of a few blobs and a subtree:
+        let mut parent: Option<String> = None;
+        let mut tips = vec![];
+        for i in 0..20 {
+            let mut subtree = vec![];
+            let blob = write_raw_object(&objects_dir, &UnparsedObjectType::Blob, format!("sub {}\n", i % 3).as_bytes()).unwrap();
+            subtree.extend_from_slice(b"100644 s\0");
+            subtree.extend_from_slice(&blob);
+            let subtree = write_raw_object(&objects_dir, &UnparsedObjectType::Tree, &subtree).unwrap();
+            let mut tree = vec![];
+            for name in ["a", "b", "c"].iter() {
+                let blob = write_raw_object(&objects_dir, &UnparsedObjectType::Blob, format!("{} {}\n", name, i).as_bytes()).unwrap();
+                tree.extend_from_slice(format!("100644 {}\0", name).as_bytes());
+                tree.extend_from_slice(&blob);
+            }
+            tree.extend_from_slice(b"40000 d\0");
+            tree.extend_from_slice(&subtree);
+            let tree = write_raw_object(&objects_dir, &UnparsedObjectType::Tree, &tree).unwrap();
+            let commit = CommitFixture::new().tree(oid_full_to_string(tree)).parents(parent).to_bytes();
+            let commit = write_raw_object(&objects_dir, &UnparsedObjectType::Commit, &commit).unwrap();
+            parent = Some(oid_full_to_string(commit));
+            tips.push(full_oid_to_u128_oid(commit));
+        }
+
+        let odb = LightObjectDB::new(objects_dir_str).unwrap();
+        let tip = *tips.last().unwrap();
+        let mut state = MinState::new(objects_dir_str).unwrap();
+        let mut expected: Vec<Oid> = odb.reachable_objects(tip, &mut state).unwrap().collect();
+        expected.sort_unstable();
+        // 20 commits, 20 trees, 3 subtrees, 60 + 3 blobs:
+        assert_eq!(expected.len(), 106);
+
+        for threads in [1, 4].iter() {
+            // the extra roots are already reachable, so they dont add anything:
+            let roots = vec![tip, tips[3], tips[3]];
+            let mut found = ReachableObjects::new(&odb, roots)
+                .threads(*threads)
+                .collect_with(|| MinState::new(objects_dir_str))
+                .unwrap();
+            found.sort_unstable();
+            assert_eq!(found, expected);
+        }
+
+        // an object that doesnt exist is an error, not a hang:
+        let res = ReachableObjects::new(&odb, vec![tip, 1])
+            .threads(4)
+            .collect_with(|| MinState::new(objects_dir_str));
+        assert!(res.is_err());
+    }
+
+    /// a state that panics when something tries to read an object with it.
+    struct PanicState {
+        inner: MinState,
+    }
+
+    impl State for PanicState {
+        type Idx = IDXFileLight;
+
+        fn get_decompressor(&mut self) -> &mut Decompress {
+            panic!("PanicState can't decompress");
+        }
+
+        fn get_idx_file(&mut self, id: OidFull) -> io::Result<OwnedOrBorrowedMut<'_, Self::Idx>> {
+            self.inner.get_idx_file(id)
+        }
+
+        fn iter_loose_folder<F>(&mut self, folder_byte: u8, cb: &mut F) -> io::Result<()>
+            where F: FnMut(Oid, &str, &str) -> bool
+        {
+            self.inner.iter_loose_folder(folder_byte, cb)
+        }
+
+        // the test's objects are all loose:
+        fn iter_known_packs<F>(&mut self, _cb: &mut F) -> io::Result<()>
+            where F: FnMut(&mut Self, OidFull) -> bool
+        {
+            Ok(())
+        }
+
+        fn get_path_to_db_as_bytes(&self) -> (usize, [u8; MAX_PATH_TO_DB_LEN]) {
+            self.inner.get_path_to_db_as_bytes()
+        }
+    }
+
+    #[test]
+    fn panicking_thread_doesnt_hang_the_others() {
+        let objects_dir = TempDir::objects_dir("reachable_panic");
+        let objects_dir_str = objects_dir.path_str();
+        let commit = write_raw_object(&objects_dir, &UnparsedObjectType::Commit, &CommitFixture::new().to_bytes()).unwrap();
+        let odb = LightObjectDB::new(objects_dir_str).unwrap();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            ReachableObjects::new(&odb, vec![full_oid_to_u128_oid(commit)])
+                .threads(4)
+                .collect_with(|| Ok(PanicState { inner: MinState::new(objects_dir_str)? }))
+        }));
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,371 @@
+use std::{io, cmp::Reverse, collections::{BTreeMap, BTreeSet, BinaryHeap, VecDeque}};
+use crate::{ioerr, ioerre, grafts::{GraftTable, ShallowSet}, object_id::Oid};
+use super::{object_source::ObjectSource, state::State, tree_walk::{diff_trees, ChangedPath}, loose::{UnparsedObject, UnparsedObjectType, commit_object_parsing::{CommitOnlyTreeAndParents, CommitTreeAndCommitterDate, ParseCommit}}};
+
+/// the order that a `RevWalk` visits commits in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkOrder {
+    /// the start commits, then their parents, then their parents, etc.
+    BreadthFirst,
+    /// newest committer date first, like `git log`. Commits with the
+    /// same date are visited in the order they were found.
+    /// Every commit is read when it is found instead of when it is
+    /// visited, because we need its date to know when to visit it.
+    CommitDate,
+}
+
+/// walks the history of one or more commits, visiting each commit
+/// only once, even if it can be reached from several of the start commits.
+/// By default commits are visited in breadth first order, see `WalkOrder`.
+/// If it has a `GraftTable`, replaced commits are read from their replacement,
+/// and grafted commits have their parents changed, so the walk
+/// matches the history that git shows. In a shallow clone, give it the
+/// `ShallowSet` with `with_shallow` so the walk stops at the shallow
+/// commits instead of failing to read their missing parents.
+pub struct RevWalk<'g> {
+    /// commits we found, but have not read yet.
+    queue: VecDeque<Oid>,
+    seen: BTreeSet<Oid>,
+    grafts: Option<&'g GraftTable>,
+    shallow: Option<&'g ShallowSet>,
+    order: WalkOrder,
+    /// only used for `WalkOrder::CommitDate`: the commits that were
+    /// read, by (date, order they were found in).
+    by_date: BinaryHeap<(i64, Reverse<u64>, Oid)>,
+    read: BTreeMap<Oid, CommitOnlyTreeAndParents>,
+    num_found: u64,
+}
+
+impl<'g> RevWalk<'g> {
+    pub fn new(start: Oid) -> RevWalk<'g> {
+        RevWalk::new_with_grafts(start, None)
+    }
+
+    pub fn new_with_grafts(start: Oid, grafts: Option<&'g GraftTable>) -> RevWalk<'g> {
+        RevWalk::new_multi(Some(start), grafts)
+    }
+
+    /// walk the history of all of `starts` together, ie: `git log main feature`.
+    pub fn new_multi<I: IntoIterator<Item = Oid>>(starts: I, grafts: Option<&'g GraftTable>) -> RevWalk<'g> {
+        let mut walk = RevWalk {
+            queue: VecDeque::new(),
+            seen: BTreeSet::new(),
+            grafts,
+            shallow: None,
+            order: WalkOrder::BreadthFirst,
+            by_date: BinaryHeap::new(),
+            read: BTreeMap::new(),
+            num_found: 0,
+        };
+        for start in starts {
+            walk.push(start);
+        }
+        walk
+    }
+
+    /// visit commits in this order instead. Call this before
+    /// the walk starts, not in the middle of it.
+    pub fn with_order(mut self, order: WalkOrder) -> RevWalk<'g> {
+        self.order = order;
+        self
+    }
+
+    /// treat the commits of this shallow clone as root commits, like
+    /// git does. They are still visited, but without any parents.
+    pub fn with_shallow(mut self, shallow: &'g ShallowSet) -> RevWalk<'g> {
+        self.shallow = Some(shallow);
+        self
+    }
+
+    /// add another commit to start from. Does nothing if
+    /// this commit was already found by the walk.
+    pub fn push(&mut self, oid: Oid) {
+        if self.seen.insert(oid) {
+            self.queue.push_back(oid);
+        }
+    }
+
+    /// turn this walk into an iterator that also gives the files each
+    /// commit changed, like `git log --name-only`. Merge commits are
+    /// compared to their first parent, the same as `git log` does by default,
+    /// and every file of a root commit is shown as added.
+    pub fn with_changed_paths<'o, 's, O: ObjectSource, S: State>(
+        self,
+        odb: &'o O,
+        state: &'s mut S,
+    ) -> ChangedPathsWalk<'g, 'o, 's, O, S> {
+        ChangedPathsWalk { walk: self, odb, state, done: false }
+    }
+
+    /// read a commit, applying our grafts/replacements if we have them.
+    /// `odb` can be a `LightObjectDB`, or a `MemObjectStore`.
+    /// the returned commit has its parents already changed by any grafts,
+    /// and a shallow commit has no parents.
+    pub fn read_commit<O: ObjectSource, S: State>(
+        &self,
+        oid: Oid,
+        odb: &O,
+        state: &mut S,
+    ) -> io::Result<CommitOnlyTreeAndParents> {
+        Ok(self.read_commit_and_date(oid, odb, state, false)?.0)
+    }
+
+    /// `read_commit`, and if `want_date`, the committer date of the commit.
+    fn read_commit_and_date<O: ObjectSource, S: State>(
+        &self,
+        oid: Oid,
+        odb: &O,
+        state: &mut S,
+        want_date: bool,
+    ) -> io::Result<(CommitOnlyTreeAndParents, i64)> {
+        let read_oid = match self.grafts {
+            Some(g) => g.replacement_for(oid),
+            None => oid,
+        };
+        let obj: UnparsedObject = odb.get_object_by_oid(read_oid, state)?;
+        if obj.object_type != UnparsedObjectType::Commit {
+            return ioerre!("Expected {:032x} to be a commit, but it is a {}", read_oid, obj.object_type.as_str());
+        }
+        // if we need the date, we parse it along with the tree and
+        // parents instead of going over the headers twice:
+        let (mut commit, date) = if want_date {
+            let dated = CommitTreeAndCommitterDate::parse(&obj.payload)
+                .map_err(|e| ioerr!("Failed to read the committer date of {:032x}\n{}", read_oid, e))?;
+            let commit = CommitOnlyTreeAndParents {
+                tree: dated.tree,
+                parent_one: dated.parent_one,
+                parent_two: dated.parent_two,
+                extra_parents: dated.extra_parents,
+            };
+            (commit, dated.committer_time)
+        } else {
+            (CommitOnlyTreeAndParents::parse(&obj.payload)?, 0)
+        };
+        // grafts are keyed by the oid that the user sees, not
+        // by the replacement:
+        if let Some(parents) = self.grafts.and_then(|g| g.grafted_parents(oid)) {
+            commit.parent_one = parents.first().copied().unwrap_or(0);
+            commit.parent_two = parents.get(1).copied().unwrap_or(0);
+            commit.extra_parents = parents.iter().skip(2).copied().collect();
+        }
+        // the parents of a shallow commit are not in the object
+        // db, and git pretends it doesn't have any:
+        if self.shallow.is_some_and(|s| s.contains(oid)) {
+            commit.parent_one = 0;
+            commit.parent_two = 0;
+            commit.extra_parents.clear();
+        }
+        Ok((commit, date))
+    }
+
+    /// returns the next commit in the walk, and its oid.
+    /// Ok(None) when there are no commits left.
+    pub fn next_commit<O: ObjectSource, S: State>(
+        &mut self,
+        odb: &O,
+        state: &mut S,
+    ) -> io::Result<Option<(Oid, CommitOnlyTreeAndParents)>> {
+        let (oid, commit) = match self.order {
+            WalkOrder::BreadthFirst => {
+                let oid = match self.queue.pop_front() {
+                    Some(oid) => oid,
+                    None => return Ok(None),
+                };
+                (oid, self.read_commit(oid, odb, state)?)
+            }
+            WalkOrder::CommitDate => {
+                while let Some(found) = self.queue.pop_front() {
+                    let (commit, date) = self.read_commit_and_date(found, odb, state, true)?;
+                    self.by_date.push((date, Reverse(self.num_found), found));
+                    self.num_found += 1;
+                    self.read.insert(found, commit);
+                }
+                let oid = match self.by_date.pop() {
+                    Some((_, _, oid)) => oid,
+                    None => return Ok(None),
+                };
+                // we put every commit we read in here:
+                let commit = self.read.remove(&oid)
+                    .ok_or_else(|| ioerr!("Commit {:032x} was lost during the walk", oid))?;
+                (oid, commit)
+            }
+        };
+        for parent in commit.parents() {
+            self.push(parent);
+        }
+        Ok(Some((oid, commit)))
+    }
+}
+
+/// see `RevWalk::with_changed_paths`. The iteration stops after
+/// the first error.
+pub struct ChangedPathsWalk<'g, 'o, 's, O, S> {
+    walk: RevWalk<'g>,
+    odb: &'o O,
+    state: &'s mut S,
+    done: bool,
+}
+
+impl<'g, 'o, 's, O: ObjectSource, S: State> ChangedPathsWalk<'g, 'o, 's, O, S> {
+    fn next_with_changes(&mut self) -> io::Result<Option<(Oid, CommitOnlyTreeAndParents, Vec<ChangedPath>)>> {
+        let (oid, commit) = match self.walk.next_commit(self.odb, self.state)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        // parent_one is 0 for a root commit. We read the parent
+        // through the walk so that grafts/replacements apply to it too.
+        let parent_tree = if commit.parent_one == 0 {
+            None
+        } else {
+            Some(self.walk.read_commit(commit.parent_one, self.odb, self.state)?.tree)
+        };
+        let changes = diff_trees(self.odb, parent_tree, commit.tree, self.state)
+            .map_err(|e| ioerr!("Failed to find the changed paths of {:032x}\n{}", oid, e))?;
+        Ok(Some((oid, commit, changes)))
+    }
+}
+
+impl<'g, 'o, 's, O: ObjectSource, S: State> Iterator for ChangedPathsWalk<'g, 'o, 's, O, S> {
+    type Item = io::Result<(Oid, CommitOnlyTreeAndParents, Vec<ChangedPath>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = self.next_with_changes();
+        if !matches!(res, Ok(Some(_))) {
+            self.done = true;
+        }
+        res.transpose()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sha1::hash_object;
+    use crate::object_database::{object_source::MemObjectStore, state::MinState};
+    use crate::test_helpers::CommitFixture;
+
+    #[test]
+    fn revwalk_multiple_tips_works() {
+        let mut state = MinState::new("/nonexistent/.git/objects").unwrap();
+        let mut store = MemObjectStore::new();
+        let mut commit = |parents: &[Oid], date: i64| {
+            let parents: Vec<String> = parents.iter()
+                .map(|p| crate::object_id::oid_full_to_string(hash_object("commit", &store.objects[p].1)))
+                .collect();
+            let raw = CommitFixture::new().parents(parents).committer_time(date).to_bytes();
+            store.insert(UnparsedObjectType::Commit, raw)
+        };
+        // root is reachable from both tips:
+        //   root(1) <- main1(2) <- main2(5)
+        //   root(1) <- feature(3)
+        let root = commit(&[], 1);
+        let main1 = commit(&[root], 2);
+        let main2 = commit(&[main1], 5);
+        let feature = commit(&[root], 3);
+
+        let walk_all = |walk: &mut RevWalk, state: &mut MinState| {
+            let mut visited = vec![];
+            while let Some((oid, _)) = walk.next_commit(&store, state).unwrap() {
+                visited.push(oid);
+            }
+            visited
+        };
+        let mut walk = RevWalk::new_multi(vec![main2, feature, main2], None);
+        assert_eq!(walk_all(&mut walk, &mut state), vec![main2, feature, main1, root]);
+
+        let mut walk = RevWalk::new_multi(vec![feature, main2], None).with_order(WalkOrder::CommitDate);
+        assert_eq!(walk_all(&mut walk, &mut state), vec![main2, feature, main1, root]);
+
+        // pushing a commit that was already found does nothing:
+        let mut walk = RevWalk::new(main2).with_order(WalkOrder::CommitDate);
+        walk.push(feature);
+        walk.push(root);
+        assert_eq!(walk_all(&mut walk, &mut state), vec![main2, feature, main1, root]);
+    }
+
+    #[test]
+    fn revwalk_changed_paths_works() {
+        use crate::object_database::tree_walk::{ChangedPath, ChangeKind};
+        let mut state = MinState::new("/nonexistent/.git/objects").unwrap();
+        let mut store = MemObjectStore::new();
+        let full = |store: &MemObjectStore, oid: Oid| hash_object(store.objects[&oid].0.as_str(), &store.objects[&oid].1);
+        let tree = |store: &mut MemObjectStore, entries: &[(&str, Oid)]| {
+            let mut raw = vec![];
+            for (name, oid) in entries {
+                let mode = if store.objects[oid].0 == UnparsedObjectType::Tree { "40000" } else { "100644" };
+                raw.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+                raw.extend_from_slice(&full(store, *oid));
+            }
+            store.insert(UnparsedObjectType::Tree, raw)
+        };
+        let a1 = store.insert(UnparsedObjectType::Blob, b"a1\n".to_vec());
+        let a2 = store.insert(UnparsedObjectType::Blob, b"a2\n".to_vec());
+        let b = store.insert(UnparsedObjectType::Blob, b"b\n".to_vec());
+        let sub1 = tree(&mut store, &[("x", b)]);
+        let sub2 = tree(&mut store, &[("x", b), ("y", a1)]);
+        let root_tree = tree(&mut store, &[("a", a1), ("d", sub1), ("e", b)]);
+        // modify a, add d/y, and replace the file e with a directory:
+        let next_tree = tree(&mut store, &[("a", a2), ("d", sub2), ("e", sub1)]);
+        let mut commit = |tree: Oid, parent: Option<Oid>| {
+            let parents = parent.map(|p| crate::object_id::oid_full_to_string(full(&store, p)));
+            let raw = CommitFixture::new()
+                .tree(crate::object_id::oid_full_to_string(full(&store, tree)))
+                .parents(parents)
+                .to_bytes();
+            store.insert(UnparsedObjectType::Commit, raw)
+        };
+        let first = commit(root_tree, None);
+        let second = commit(next_tree, Some(first));
+
+        let found: Vec<_> = RevWalk::new(second).with_changed_paths(&store, &mut state)
+            .collect::<io::Result<_>>().unwrap();
+        assert_eq!(found.len(), 2);
+        let changed = |path: &str, kind, old_id, new_id| ChangedPath { path: path.to_string(), kind, old_id, new_id };
+        assert_eq!(found[0].0, second);
+        assert_eq!(found[0].2, vec![
+            changed("a", ChangeKind::Modified, Some(a1), Some(a2)),
+            changed("d/y", ChangeKind::Added, None, Some(a1)),
+            changed("e", ChangeKind::Deleted, Some(b), None),
+            changed("e/x", ChangeKind::Added, None, Some(b)),
+        ]);
+        // the root commit shows everything as added:
+        assert_eq!(found[1].0, first);
+        assert_eq!(found[1].2, vec![
+            changed("a", ChangeKind::Added, None, Some(a1)),
+            changed("d/x", ChangeKind::Added, None, Some(b)),
+            changed("e", ChangeKind::Added, None, Some(b)),
+        ]);
+    }
+
+    #[test]
+    fn revwalk_stops_at_shallow_commits() {
+        let mut state = MinState::new("/nonexistent/.git/objects").unwrap();
+        let mut store = MemObjectStore::new();
+        // the parent of the first commit was never fetched:
+        let missing_parent = "1".repeat(40);
+        let first_raw = CommitFixture::new().parent(missing_parent).to_bytes();
+        let first = store.insert(UnparsedObjectType::Commit, first_raw.clone());
+        let first_hex = crate::object_id::oid_full_to_string(hash_object("commit", &first_raw));
+        let second = store.insert(UnparsedObjectType::Commit, CommitFixture::new().parent(first_hex.clone()).to_bytes());
+
+        let mut walk = RevWalk::new(second);
+        assert!(walk.next_commit(&store, &mut state).unwrap().is_some());
+        assert!(walk.next_commit(&store, &mut state).unwrap().is_some());
+        assert!(walk.next_commit(&store, &mut state).is_err());
+
+        let shallow = crate::grafts::ShallowSet::parse(&first_hex).unwrap();
+        let mut walk = RevWalk::new(second).with_shallow(&shallow);
+        let mut visited = vec![];
+        while let Some((oid, c)) = walk.next_commit(&store, &mut state).unwrap() {
+            if oid == first {
+                assert_eq!(c.parents().count(), 0);
+            }
+            visited.push(oid);
+        }
+        assert_eq!(visited, vec![second, first]);
+    }
+}
@@ -1,8 +1,8 @@
 
 use flate2::Decompress;
-use crate::{ioerr, object_id::{Oid, OidFull, oid_full_to_string_no_alloc, get_first_byte_of_oid, HEX_BYTES, hash_object_file_and_folder}, ioerre, fs_helpers};
-use std::{collections::BTreeMap, io};
-use super::{main_sep_byte, MAX_PATH_TO_DB_LEN, packed::{open_idx_file_light, IDXFileLight, parse_pack_or_idx_id}, DoesMatch, FoundPackedLocation, FoundObjectLocation};
+use crate::{ioerr, error::GitReaderError, info_files::read_info_packs, object_id::{Oid, OidFull, oid_full_to_string_no_alloc, get_first_byte_of_oid, HEX_BYTES, hash_object_file_and_folder, hash_object_file_and_folder_full, full_oid_to_u128_oid}, ioerre, fs_helpers};
+use std::{collections::{BTreeMap, HashMap, hash_map::Entry}, io, fs::DirEntry, path::Path, time::SystemTime, sync::Arc};
+use super::{main_sep_byte, MAX_PATH_TO_DB_LEN, DEFAULT_MAX_OBJECT_SIZE, packed::{open_idx_file_light, open_pack_file, IDXFileLight, PackFile, PackOffsetMap, OidBloomFilter, try_parse_pack_or_idx_id, check_pack_offset}, DoesMatch, FoundPackedLocation, FoundObjectLocation};
 
 pub enum OwnedOrBorrowedMut<'a, T> {
     Owned(T),
@@ -30,11 +30,105 @@ pub trait State {
     type Idx: IDXState;
 
     fn get_decompressor(&mut self) -> &mut Decompress;
+    /// objects that claim to be larger than this are not read. See
+    /// `DEFAULT_MAX_OBJECT_SIZE`.
+    fn max_object_size(&self) -> usize {
+        DEFAULT_MAX_OBJECT_SIZE
+    }
     fn get_idx_file(&mut self, id: OidFull) -> io::Result<OwnedOrBorrowedMut<Self::Idx>>;
 
+    /// open the pack file "pack/pack-{id}.pack". By default this opens
+    /// it every time, but a state can keep its pack files open (see `CachingState`).
+    /// Its an `Arc` so that a state can hand out a pack it keeps
+    /// without that pack borrowing the state.
+    fn get_pack_file(&mut self, id: OidFull) -> io::Result<Arc<PackFile>> {
+        let hex_str = oid_full_to_string_no_alloc(id);
+        let (take_to, str_arr) = self.get_idx_file_str_array_from_hash(&hex_str);
+        let idx_path = std::str::from_utf8(&str_arr[0..take_to])
+            .map_err(|_| ioerr!("Failed to load pack file from id: {:32x?}", hex_str))?;
+        let mut pack = open_pack_file(Path::new(idx_path).with_extension("pack"), id)?;
+        pack.max_object_size = self.max_object_size();
+        Ok(Arc::new(pack))
+    }
+
+    /// a blob that this state kept from an earlier `cache_blob`.
+    /// By default a state doesn't keep blobs, see `CachingState`.
+    fn get_cached_blob(&mut self, _oid: Oid) -> Option<Arc<[u8]>> {
+        None
+    }
+
+    /// offer a blob to keep for `get_cached_blob`. A state
+    /// can ignore this, ie: if the blob is too big.
+    fn cache_blob(&mut self, _oid: Oid, _blob: Arc<[u8]>) {}
+
+    /// a filter of every oid in every known pack, so a lookup of an
+    /// oid that isn't packed doesn't need to search any idx file.
+    /// None if this state doesn't keep one, which is the default.
+    fn get_pack_bloom_filter(&mut self) -> io::Result<Option<Arc<OidBloomFilter>>> {
+        Ok(None)
+    }
+
+    /// the `PackOffsetMap` of a pack, used to find the bases of ref
+    /// deltas without searching the idx file. Ok(None) means we should
+    /// just search the idx, which is the default.
+    fn get_pack_offset_map(&mut self, _id: OidFull) -> io::Result<Option<Arc<PackOffsetMap>>> {
+        Ok(None)
+    }
+
     fn iter_loose_folder<F>(&mut self, folder_byte: u8, cb: &mut F) -> io::Result<()>
         where F: FnMut(Oid, &str, &str) -> bool;
 
+    /// like `iter_loose_folder`, but a state that kept the entries of this
+    /// folder from an earlier read can give you those instead of reading it
+    /// again, if the folder was last modified before `changed_since`, and
+    /// before the state read it.
+    /// adding or removing a loose object updates the mtime of its folder, so
+    /// if the folder has not been modified, then it contains the same objects
+    /// as the last time you read it.
+    /// Returns true if the folder was read, false if the entries were the
+    /// ones the state kept. By default a state doesn't keep them, so this
+    /// always reads the folder, see `CachingState`.
+    fn iter_loose_folder_if_changed<F>(
+        &mut self,
+        folder_byte: u8,
+        _changed_since: SystemTime,
+        cb: &mut F
+    ) -> io::Result<bool>
+        where F: FnMut(Oid, &str, &str) -> bool
+    {
+        self.iter_loose_folder(folder_byte, cb)?;
+        Ok(true)
+    }
+
+    /// call `cb` with every loose object in all 256 folders, until it returns true.
+    /// Unlike calling `iter_loose_folder` 256 times, this uses one path buffer
+    /// for every folder, and gives you the full oid as well. Folders that
+    /// dont exist are skipped, and so are files in them that are not
+    /// loose objects (ie: temporary files from a `git` that is writing objects).
+    fn iter_all_loose<F>(&mut self, cb: &mut F) -> io::Result<()>
+        where F: FnMut(Oid, OidFull) -> bool
+    {
+        iter_all_loose_entries(self, &mut |oid, oid_full, _entry| Ok(cb(oid, oid_full)))
+    }
+
+    /// like `iter_all_loose`, but also gives you the modification time of
+    /// each object's file, ie: to find unreachable loose objects that are
+    /// old enough to prune. This is one more syscall per object, so only
+    /// use this if you need the times. An object that is removed while
+    /// we are walking (ie: by a git gc) is skipped.
+    fn iter_all_loose_with_mtime<F>(&mut self, cb: &mut F) -> io::Result<()>
+        where F: FnMut(Oid, OidFull, SystemTime) -> bool
+    {
+        iter_all_loose_entries(self, &mut |oid, oid_full, entry| {
+            let metadata = match entry.metadata() {
+                Ok(m) => m,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+                Err(e) => return Err(e),
+            };
+            Ok(cb(oid, oid_full, metadata.modified()?))
+        })
+    }
+
     fn iter_known_packs<F>(&mut self, cb: &mut F) -> io::Result<()>
         where F: FnMut(&mut Self, OidFull) -> bool;
 
@@ -70,6 +164,19 @@ pub trait State {
         (take_slice_to, stack_arr)
     }
 
+    /// Like `get_static_path_str`, but returns an error instead of
+    /// panicking if the path would not fit in `MAX_PATH_TO_DB_LEN` bytes.
+    fn try_get_static_path_str(&self, extend_by: &[u8]) -> io::Result<(usize, [u8; MAX_PATH_TO_DB_LEN])> {
+        let (path_to_db_bytes_start, stack_arr) = self.get_path_to_db_as_bytes();
+        let take_slice_to = path_to_db_bytes_start + extend_by.len();
+        if take_slice_to > MAX_PATH_TO_DB_LEN {
+            let mut path = String::from_utf8_lossy(&stack_arr[0..path_to_db_bytes_start]).into_owned();
+            path.push_str(&String::from_utf8_lossy(extend_by));
+            return Err(GitReaderError::BadPath(path).into());
+        }
+        Ok(self.get_static_path_str(extend_by))
+    }
+
     #[inline(always)]
     fn get_idx_file_str_array_from_hash(&self, hex_str: &[u8]) -> (usize, [u8; MAX_PATH_TO_DB_LEN]) {
         let mut out: [u8; 54] = [
@@ -107,6 +214,18 @@ pub trait State {
 pub trait IDXState {
     fn find_oid_and_fanout_index(&mut self, oid: Oid) -> io::Result<usize>;
     fn find_packfile_index_from_fanout_index(&mut self, fanout_index: usize) -> Option<u64>;
+    /// the full 20 byte oid of the nth entry. None if `fanout_index`
+    /// is out of range, or if this idx only keeps truncated oids.
+    fn get_full_oid_at_fanout_index(&self, _fanout_index: usize) -> Option<OidFull> {
+        None
+    }
+    /// like `find_packfile_index_from_fanout_index`, but also checks that
+    /// the offset points inside of a pack file that is `pack_size` bytes long.
+    fn find_packfile_index_checked(&mut self, fanout_index: usize, pack_size: usize) -> io::Result<u64> {
+        let offset = self.find_packfile_index_from_fanout_index(fanout_index)
+            .ok_or_else(|| ioerr!("Failed to read packfile offset for fanout index {}", fanout_index))?;
+        check_pack_offset(offset, pack_size)
+    }
     fn walk_all_oids_from<F>(&mut self, start_byte: Option<u8>, cb: F)
         where F: FnMut(Oid) -> bool;
 
@@ -115,6 +234,27 @@ pub trait IDXState {
               P: DoesMatch;
 
     fn id(&self) -> OidFull;
+    /// the checksum of the pack file that this idx file was made for,
+    /// if it knows it. None by default.
+    fn pack_checksum(&self) -> Option<OidFull> {
+        None
+    }
+    /// how many objects this idx file has.
+    fn num_objects(&self) -> usize;
+    /// how many objects have an oid starting with `first_byte`.
+    /// the default walks the oids, but an idx file can just
+    /// read it from its fanout table.
+    fn num_objects_with_first_byte(&mut self, first_byte: u8) -> usize {
+        let mut count = 0;
+        self.walk_all_oids_from(Some(first_byte), |oid| {
+            if get_first_byte_of_oid(oid) != first_byte {
+                return true;
+            }
+            count += 1;
+            false
+        });
+        count
+    }
 }
 
 pub struct IDXMapped {
@@ -127,9 +267,7 @@ impl IDXState for IDXMapped {
     fn find_oid_and_fanout_index(&mut self, oid: Oid) -> io::Result<usize> {
         match self.map.get(&oid) {
             Some((fanout_index, _packfile_offset)) => Ok(*fanout_index),
-            None => {
-                return ioerre!("Failed to find oid {:032x} in idx mapped", oid);
-            }
+            None => Err(GitReaderError::ObjectNotFound(oid).into()),
         }
     }
 
@@ -183,6 +321,10 @@ impl IDXState for IDXMapped {
     fn id(&self) -> OidFull {
         self.id
     }
+
+    fn num_objects(&self) -> usize {
+        self.fanout_map.len()
+    }
 }
 
 impl IDXState for IDXFileLight {
@@ -190,6 +332,16 @@ impl IDXState for IDXFileLight {
         IDXFileLight::find_oid_and_fanout_index(self, oid)
     }
 
+    fn num_objects(&self) -> usize {
+        self.num_objects
+    }
+
+    fn num_objects_with_first_byte(&mut self, first_byte: u8) -> usize {
+        let first_byte = first_byte as usize;
+        let start = if first_byte > 0 { self.fanout_table[first_byte - 1] } else { 0 };
+        self.fanout_table[first_byte].saturating_sub(start) as usize
+    }
+
     fn find_packfile_index_from_fanout_index(&mut self, fanout_index: usize) -> Option<u64> {
         IDXFileLight::find_packfile_index_from_fanout_index(self, fanout_index)
     }
@@ -198,6 +350,14 @@ impl IDXState for IDXFileLight {
         self.id
     }
 
+    fn pack_checksum(&self) -> Option<OidFull> {
+        IDXFileLight::pack_checksum(self)
+    }
+
+    fn get_full_oid_at_fanout_index(&self, fanout_index: usize) -> Option<OidFull> {
+        IDXFileLight::get_full_oid_at_fanout_index(self, fanout_index)
+    }
+
     fn walk_all_oids_from<F>(&mut self, start_byte: Option<u8>, cb: F)
         where F: FnMut(Oid) -> bool
     {
@@ -240,6 +400,21 @@ pub struct MinState {
     pub path_to_db_bytes: [u8; MAX_PATH_TO_DB_LEN],
     pub path_to_db_bytes_start: usize,
     pub decompressor: Decompress,
+    /// if this is set, `iter_known_packs` uses these
+    /// instead of searching the pack directory.
+    pub known_packs: Option<Vec<OidFull>>,
+    /// defaults to `DEFAULT_MAX_OBJECT_SIZE`. increase it
+    /// if your repo has legitimately large objects.
+    pub max_object_size: usize,
+    /// by default, `iter_known_packs` skips files in `pack/` that it doesn't
+    /// understand. If this is set, an .idx file with a malformed name
+    /// (ie: "pack-short.idx") is an error instead.
+    pub strict_pack_names: bool,
+    /// how many .idx files with a malformed name the last search of
+    /// `pack/` skipped, so you can tell that something is off without
+    /// setting `strict_pack_names`. Files that aren't named like
+    /// packs at all are not counted.
+    pub malformed_pack_names: usize,
 }
 
 impl MinState {
@@ -264,9 +439,38 @@ impl MinState {
             path_to_db_bytes,
             path_to_db_bytes_start: p_len + 1,
             decompressor: Decompress::new(true),
+            known_packs: None,
+            max_object_size: DEFAULT_MAX_OBJECT_SIZE,
+            strict_pack_names: false,
+            malformed_pack_names: 0,
         };
         Ok(out)
     }
+
+    /// read `objects/info/packs` (if it exists) and use the packs it lists,
+    /// so `iter_known_packs` doesn't need to search the pack directory.
+    /// Returns Ok(false) if there is no such file. Note that this file
+    /// can be out of date, so only use this if you know
+    /// `git update-server-info` runs after every pack is added.
+    pub fn load_known_packs_from_info(&mut self) -> io::Result<bool> {
+        let objects_dir = std::str::from_utf8(&self.path_to_db_bytes[0..self.path_to_db_bytes_start])
+            .map_err(|e| ioerr!("Failed to convert path string to utf8...\n{}", e))?;
+        if !Path::new(objects_dir).join("info").join("packs").is_file() {
+            return Ok(false);
+        }
+        self.known_packs = Some(read_info_packs(objects_dir)?);
+        Ok(true)
+    }
+
+    /// open "pack/pack-{id}.idx" without keeping it around.
+    pub fn open_idx_file(&self, id: OidFull) -> io::Result<IDXFileLight> {
+        // first form the "pack-{40hex}.idx" string array:
+        let hex_str = oid_full_to_string_no_alloc(id);
+        let (take_to, str_arr) = self.get_idx_file_str_array_from_hash(&hex_str);
+        let idx_path = std::str::from_utf8(&str_arr[0..take_to])
+            .map_err(|_| ioerr!("Failed to load idx file from id: {:32x?}", hex_str))?;
+        open_idx_file_light(idx_path)
+    }
 }
 
 impl State for MinState {
@@ -276,14 +480,12 @@ impl State for MinState {
         &mut self.decompressor
     }
 
+    fn max_object_size(&self) -> usize {
+        self.max_object_size
+    }
+
     fn get_idx_file(&mut self, id: OidFull) -> io::Result<OwnedOrBorrowedMut<Self::Idx>> {
-        // first form the "pack-{40hex}.idx" string array:
-        let hex_str = oid_full_to_string_no_alloc(id);
-        let (take_to, str_arr) = self.get_idx_file_str_array_from_hash(&hex_str);
-        let idx_path = std::str::from_utf8(&str_arr[0..take_to])
-            .map_err(|_| ioerr!("Failed to load idx file from id: {:32x?}", hex_str))?;
-        let file = open_idx_file_light(idx_path)?;
-        Ok(OwnedOrBorrowedMut::Owned(file))
+        Ok(OwnedOrBorrowedMut::Owned(self.open_idx_file(id)?))
     }
 
     fn get_path_to_db_as_bytes(&self) -> (usize, [u8; MAX_PATH_TO_DB_LEN]) {
@@ -322,6 +524,12 @@ impl State for MinState {
     fn iter_known_packs<F>(&mut self, cb: &mut F) -> io::Result<()>
         where F: FnMut(&mut Self, OidFull) -> bool
     {
+        if let Some(known_packs) = self.known_packs.clone() {
+            for idx_id in known_packs {
+                if cb(self, idx_id) { break; }
+            }
+            return Ok(());
+        }
         // first we load every .idx file we find in the database/packs
         // directory
         let packs_dir = b"pack";
@@ -330,6 +538,7 @@ impl State for MinState {
             .map_err(|e| ioerr!("Failed to convert path string to utf8...\n{}", e))?;
         // println!("Searching {}", search_path_str);
         let mut stop_searching = false;
+        self.malformed_pack_names = 0;
         fs_helpers::search_folder_out(&search_path_str, |entry| {
             if stop_searching { return Ok(()); }
             let filename = entry.file_name();
@@ -340,12 +549,690 @@ impl State for MinState {
             if ! filename.ends_with(".idx") {
                 return Ok(());
             }
-            let idx_id = match parse_pack_or_idx_id(filename) {
-                Some(i) => i,
-                None => return Ok(()),
+            let idx_id = match try_parse_pack_or_idx_id(filename) {
+                Ok(Some(i)) => i,
+                Ok(None) => return Ok(()),
+                Err(e) if self.strict_pack_names => return Err(e),
+                Err(_) => {
+                    self.malformed_pack_names += 1;
+                    return Ok(());
+                }
             };
             stop_searching = cb(self, idx_id);
             Ok(())
         })
     }
 }
+
+/// the directory walk of `State::iter_all_loose`, which also gives `cb`
+/// the directory entry of each object. Stops when `cb` returns true.
+fn iter_all_loose_entries<S, F>(state: &S, cb: &mut F) -> io::Result<()>
+    where S: State + ?Sized,
+          F: FnMut(Oid, OidFull, &DirEntry) -> io::Result<bool>
+{
+    let (folder_starts_at, mut path_buf) = state.get_path_to_db_as_bytes();
+    let take_to = folder_starts_at + 2;
+    for hex_first_byte in HEX_BYTES.iter() {
+        path_buf[folder_starts_at..take_to].copy_from_slice(hex_first_byte);
+        let folder_path = std::str::from_utf8(&path_buf[0..take_to])
+            .map_err(|e| ioerr!("Failed to convert path string to utf8...\n{}", e))?;
+        // we know all of these HEX_BYTES are valid utf-8 sequences
+        // so we can unwrap:
+        let hex_str = std::str::from_utf8(hex_first_byte).unwrap();
+        let mut stop_searching = false;
+        fs_helpers::search_folder_out_missing_ok(folder_path, |entry| {
+            if stop_searching { return Ok(()); }
+            let entryname = entry.file_name();
+            let filename = match entryname.to_str() {
+                Some(s) if s.len() == 38 => s,
+                _ => return Ok(()),
+            };
+            let oid_full = match hash_object_file_and_folder_full(hex_str, filename) {
+                Ok(o) => o,
+                Err(_) => return Ok(()),
+            };
+            stop_searching = cb(full_oid_to_u128_oid(oid_full), oid_full, entry)?;
+            Ok(())
+        })?;
+        if stop_searching {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// the default for `CachingState::max_open`. Each open pack
+/// is an mmap and a file descriptor, and we keep one for its idx too.
+pub const DEFAULT_MAX_OPEN_PACKS: usize = 128;
+
+/// the default for `CachingState::max_blob_cache_bytes`: 64MB
+pub const DEFAULT_MAX_BLOB_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// a modification time only tells us that a directory didn't change if
+/// we looked at the directory at least this long after that time. Some
+/// filesystems only keep mtimes to the second (or two), so a pack added
+/// right after we listed them can have the same mtime as before.
+const RACY_MTIME_SECS: u64 = 2;
+
+/// a `MinState` that keeps the idx and pack files it opens, so
+/// reading many objects (ie: a revwalk) doesn't re-open and re-mmap
+/// the same files over and over. At most `max_open` idx files and `max_open`
+/// pack files are kept. When one more is needed, the cache is cleared
+/// and starts over, so a repo with a huge number of packs can't run
+/// us out of file descriptors. Use `clear_cache` if the packs on disk
+/// changed (ie: after a repack).
+/// It also keeps the blobs read by `LightObjectDB::get_blob_bytes`, up to
+/// `max_blob_cache_bytes` in total. Like the files, when a blob doesn't fit
+/// the blob cache is cleared, and a blob bigger than the limit is never kept.
+/// And it keeps the entries of every loose folder read with
+/// `iter_loose_folder_if_changed`.
+pub struct CachingState {
+    pub inner: MinState,
+    pub max_open: usize,
+    pub max_blob_cache_bytes: usize,
+    /// build a `PackOffsetMap` for a pack the first time one of
+    /// its ref deltas is resolved, and keep it. Building it reads the
+    /// whole idx file, so this is off by default. Turn it on if you are
+    /// going to read most of a pack that has a lot of ref deltas.
+    pub use_offset_maps: bool,
+    /// build an `OidBloomFilter` of every packed oid the first time
+    /// `contains_oid` is asked about something, so oids that don't
+    /// exist can be ruled out without searching the idx files.
+    /// Building it reads every idx file, so this is off by default.
+    /// It is built again if the packs change (ie: after a fetch or a repack).
+    pub use_bloom_filter: bool,
+    idx_files: HashMap<OidFull, IDXFileLight>,
+    pack_files: HashMap<OidFull, Arc<PackFile>>,
+    blobs: HashMap<Oid, Arc<[u8]>>,
+    blob_cache_bytes: usize,
+    offset_maps: HashMap<OidFull, Arc<PackOffsetMap>>,
+    /// the oid and file name of every object in a loose folder, by its
+    /// first byte, and when we listed that folder.
+    loose_folders: HashMap<u8, (SystemTime, Vec<(Oid, String)>)>,
+    /// the sorted ids of the packs the filter was built from.
+    bloom_filter: Option<(Vec<OidFull>, Arc<OidBloomFilter>)>,
+    /// the mtime of `pack/` and when we listed the packs in it,
+    /// so the filter can be checked without listing them again.
+    bloom_pack_dir: Option<(SystemTime, SystemTime)>,
+}
+
+impl CachingState {
+    pub fn new(path: &str) -> io::Result<CachingState> {
+        Ok(CachingState::from_min_state(MinState::new(path)?))
+    }
+
+    pub fn from_min_state(inner: MinState) -> CachingState {
+        CachingState {
+            inner,
+            max_open: DEFAULT_MAX_OPEN_PACKS,
+            max_blob_cache_bytes: DEFAULT_MAX_BLOB_CACHE_BYTES,
+            use_offset_maps: false,
+            use_bloom_filter: false,
+            idx_files: HashMap::new(),
+            pack_files: HashMap::new(),
+            blobs: HashMap::new(),
+            blob_cache_bytes: 0,
+            offset_maps: HashMap::new(),
+            loose_folders: HashMap::new(),
+            bloom_filter: None,
+            bloom_pack_dir: None,
+        }
+    }
+
+    /// close every idx and pack file we kept open, and forget every
+    /// blob, offset map, loose folder, and the bloom filter.
+    pub fn clear_cache(&mut self) {
+        self.idx_files.clear();
+        self.pack_files.clear();
+        self.offset_maps.clear();
+        self.loose_folders.clear();
+        self.bloom_filter = None;
+        self.bloom_pack_dir = None;
+        self.clear_blob_cache();
+    }
+
+    pub fn clear_blob_cache(&mut self) {
+        self.blobs.clear();
+        self.blob_cache_bytes = 0;
+    }
+
+    /// how many bytes of blobs are cached right now.
+    pub fn blob_cache_bytes(&self) -> usize {
+        self.blob_cache_bytes
+    }
+
+    /// how many idx and pack files are currently open.
+    pub fn num_open(&self) -> (usize, usize) {
+        (self.idx_files.len(), self.pack_files.len())
+    }
+
+    /// the mtime of the `pack/` directory. Adding or removing
+    /// a pack modifies it. None if there is no `pack/`.
+    fn pack_dir_modified(&self) -> io::Result<Option<SystemTime>> {
+        self.dir_modified(b"pack")
+    }
+
+    /// the mtime of a directory in the objects dir, or None if it doesn't exist.
+    fn dir_modified(&self, dir: &[u8]) -> io::Result<Option<SystemTime>> {
+        let (take_to, str_arr) = self.get_static_path_str(dir);
+        let dir = std::str::from_utf8(&str_arr[0..take_to])
+            .map_err(|e| ioerr!("Failed to convert path string to utf8...\n{}", e))?;
+        match std::fs::metadata(dir) {
+            Ok(m) => Ok(Some(m.modified()?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl State for CachingState {
+    type Idx = IDXFileLight;
+
+    fn get_decompressor(&mut self) -> &mut Decompress {
+        self.inner.get_decompressor()
+    }
+
+    fn max_object_size(&self) -> usize {
+        self.inner.max_object_size()
+    }
+
+    fn get_idx_file(&mut self, id: OidFull) -> io::Result<OwnedOrBorrowedMut<'_, Self::Idx>> {
+        if self.idx_files.len() >= self.max_open && !self.idx_files.contains_key(&id) {
+            self.idx_files.clear();
+        }
+        let idx_file = match self.idx_files.entry(id) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(self.inner.open_idx_file(id)?),
+        };
+        Ok(OwnedOrBorrowedMut::BorrowedMut(idx_file))
+    }
+
+    fn get_pack_file(&mut self, id: OidFull) -> io::Result<Arc<PackFile>> {
+        if let Some(pack) = self.pack_files.get(&id) {
+            return Ok(pack.clone());
+        }
+        if self.pack_files.len() >= self.max_open {
+            self.pack_files.clear();
+        }
+        let pack = self.inner.get_pack_file(id)?;
+        // we only open a pack once, so its cheap to make sure
+        // it is the pack its idx file describes:
+        let hex_str = oid_full_to_string_no_alloc(id);
+        let (take_to, str_arr) = self.get_idx_file_str_array_from_hash(&hex_str);
+        let pack_path = Path::new(std::str::from_utf8(&str_arr[0..take_to]).unwrap_or_default())
+            .with_extension("pack");
+        let mut idx_file = self.get_idx_file(id)?;
+        pack.check_against_idx(idx_file.as_mut(), pack_path)?;
+        self.pack_files.insert(id, pack.clone());
+        Ok(pack)
+    }
+
+    fn get_pack_offset_map(&mut self, id: OidFull) -> io::Result<Option<Arc<PackOffsetMap>>> {
+        if !self.use_offset_maps {
+            return Ok(None);
+        }
+        if let Some(map) = self.offset_maps.get(&id) {
+            return Ok(Some(map.clone()));
+        }
+        if self.offset_maps.len() >= self.max_open {
+            self.offset_maps.clear();
+        }
+        let mut idx_file = self.get_idx_file(id)?;
+        let map = Arc::new(PackOffsetMap::build(idx_file.as_mut())?);
+        self.offset_maps.insert(id, map.clone());
+        Ok(Some(map))
+    }
+
+    fn get_pack_bloom_filter(&mut self) -> io::Result<Option<Arc<OidBloomFilter>>> {
+        if !self.use_bloom_filter {
+            return Ok(None);
+        }
+        // this is called for every `contains_oid`, so if we can, we only
+        // stat `pack/` instead of listing it. That only works if the packs
+        // come from the directory, and not from `known_packs`:
+        let pack_dir_modified = match self.inner.known_packs {
+            Some(_) => None,
+            None => self.pack_dir_modified()?,
+        };
+        if let (Some(modified), Some((last_modified, listed_at)), Some((_, filter))) =
+            (pack_dir_modified, self.bloom_pack_dir, &self.bloom_filter)
+        {
+            let not_racy = listed_at.duration_since(last_modified)
+                .map(|d| d.as_secs() >= RACY_MTIME_SECS)
+                .unwrap_or(false);
+            if modified == last_modified && not_racy {
+                return Ok(Some(filter.clone()));
+            }
+        }
+        let listed_at = SystemTime::now();
+        self.bloom_pack_dir = pack_dir_modified.map(|modified| (modified, listed_at));
+        let mut ids = vec![];
+        self.inner.iter_known_packs(&mut |_, id| {
+            ids.push(id);
+            false
+        })?;
+        ids.sort_unstable();
+        // a pack is named by its contents, so the packs only
+        // changed if there are different ids than before:
+        if let Some((built_from, filter)) = &self.bloom_filter {
+            if *built_from == ids {
+                return Ok(Some(filter.clone()));
+            }
+        }
+        let mut num_oids = 0;
+        for id in ids.iter() {
+            num_oids += self.get_idx_file(*id)?.as_mut().num_objects();
+        }
+        let mut filter = OidBloomFilter::with_capacity(num_oids);
+        for id in ids.iter() {
+            self.get_idx_file(*id)?.as_mut().walk_all_oids_from(None, |oid| {
+                filter.insert(oid);
+                false
+            });
+        }
+        let filter = Arc::new(filter);
+        self.bloom_filter = Some((ids, filter.clone()));
+        Ok(Some(filter))
+    }
+
+    fn get_cached_blob(&mut self, oid: Oid) -> Option<Arc<[u8]>> {
+        self.blobs.get(&oid).cloned()
+    }
+
+    fn cache_blob(&mut self, oid: Oid, blob: Arc<[u8]>) {
+        if blob.len() > self.max_blob_cache_bytes || self.blobs.contains_key(&oid) {
+            return;
+        }
+        if self.blob_cache_bytes + blob.len() > self.max_blob_cache_bytes {
+            self.clear_blob_cache();
+        }
+        self.blob_cache_bytes += blob.len();
+        self.blobs.insert(oid, blob);
+    }
+
+    fn get_path_to_db_as_bytes(&self) -> (usize, [u8; MAX_PATH_TO_DB_LEN]) {
+        self.inner.get_path_to_db_as_bytes()
+    }
+
+    fn iter_loose_folder<F>(&mut self, folder_byte: u8, cb: &mut F) -> io::Result<()>
+        where F: FnMut(Oid, &str, &str) -> bool
+    {
+        self.inner.iter_loose_folder(folder_byte, cb)
+    }
+
+    fn iter_loose_folder_if_changed<F>(
+        &mut self,
+        folder_byte: u8,
+        changed_since: SystemTime,
+        cb: &mut F
+    ) -> io::Result<bool>
+        where F: FnMut(Oid, &str, &str) -> bool
+    {
+        let hex_first_byte: [u8; 2] = HEX_BYTES[folder_byte as usize];
+        let modified = match self.dir_modified(&hex_first_byte)? {
+            Some(m) => m,
+            None => {
+                self.loose_folders.remove(&folder_byte);
+                return Ok(false);
+            }
+        };
+        // like the bloom filter, the entries we kept are only still right if
+        // the folder wasn't modified since (or right around when) we listed it:
+        let kept = self.loose_folders.get(&folder_byte).filter(|(listed_at, _)| {
+            let not_racy = listed_at.duration_since(modified)
+                .map(|d| d.as_secs() >= RACY_MTIME_SECS)
+                .unwrap_or(false);
+            modified <= changed_since && not_racy
+        });
+        if let Some((_, entries)) = kept {
+            let (take_index, big_str_array) = self.get_static_path_str(&hex_first_byte);
+            let search_path_str = std::str::from_utf8(&big_str_array[0..take_index])
+                .map_err(|e| ioerr!("Failed to convert path string to utf8...\n{}", e))?;
+            for (oid, filename) in entries.iter() {
+                if cb(*oid, search_path_str, filename) { break; }
+            }
+            return Ok(false);
+        }
+        // we keep the whole folder, even if the callback
+        // doesn't want all of it:
+        let listed_at = SystemTime::now();
+        let mut entries = vec![];
+        let mut stop_calling = false;
+        self.inner.iter_loose_folder(folder_byte, &mut |oid, folder, filename| {
+            if !stop_calling {
+                stop_calling = cb(oid, folder, filename);
+            }
+            entries.push((oid, filename.to_string()));
+            false
+        })?;
+        self.loose_folders.insert(folder_byte, (listed_at, entries));
+        Ok(true)
+    }
+
+    fn iter_known_packs<F>(&mut self, cb: &mut F) -> io::Result<()>
+        where F: FnMut(&mut Self, OidFull) -> bool
+    {
+        // the callback wants us, not our inner state,
+        // so we find the packs first:
+        let mut ids = vec![];
+        self.inner.iter_known_packs(&mut |_, id| {
+            ids.push(id);
+            false
+        })?;
+        for id in ids {
+            if cb(self, id) { break; }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_database::{LightObjectDB, loose::UnparsedObject};
+    use crate::object_id::{full_oid_from_str, hash_str_to_oid};
+    use crate::sha1::hash_object;
+    use crate::object_database::loose::{write_raw_object, UnparsedObjectType};
+    use crate::test_helpers::{packed_fixture, copy_packed_fixture_objects, TempDir, OFS_DELTA_PACK, REF_DELTA_PACK, PACKED_COMMITS};
+
+    #[test]
+    fn caching_state_reuses_and_evicts_packs() {
+        let objects_dir = packed_fixture().join("objects");
+        let mut state = CachingState::new(objects_dir.to_str().unwrap()).unwrap();
+        state.max_open = 1;
+        let ofs_pack = full_oid_from_str(OFS_DELTA_PACK).unwrap();
+        let ref_pack = full_oid_from_str(REF_DELTA_PACK).unwrap();
+
+        let first = state.get_pack_file(ofs_pack).unwrap();
+        let again = state.get_pack_file(ofs_pack).unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+        assert_eq!(state.num_open(), (1, 1));
+
+        // theres only room for one, so the other pack replaces it:
+        let other = state.get_pack_file(ref_pack).unwrap();
+        assert_eq!(other.id, ref_pack);
+        assert_eq!(state.num_open(), (1, 1));
+        let reopened = state.get_pack_file(ofs_pack).unwrap();
+        assert!(!Arc::ptr_eq(&first, &reopened));
+        assert_eq!(state.num_open(), (1, 1));
+
+        state.clear_cache();
+        assert_eq!(state.num_open(), (0, 0));
+    }
+
+    #[test]
+    fn batch_uses_the_states_packs() {
+        let objects_dir = packed_fixture().join("objects");
+        let objects_dir = objects_dir.to_str().unwrap();
+        let odb = LightObjectDB::new(objects_dir).unwrap();
+        let mut state = CachingState::new(objects_dir).unwrap();
+        // the commits, and file.txt of commit 2 (an ofs delta)
+        // and commit 5 (a ref delta):
+        let mut oids: Vec<Oid> = PACKED_COMMITS.iter().map(|c| hash_str_to_oid(c).unwrap()).collect();
+        oids.push(hash_str_to_oid("1e46c79100606b889ae7ddc719e69004a97b8723").unwrap());
+        oids.push(hash_str_to_oid("e174d2e582b1073ec45f9ff5638e1fd4c633fdfa").unwrap());
+        let mut found = vec![];
+        odb.get_objects_batch(&oids, &mut state, &mut |oid, obj: io::Result<UnparsedObject>| {
+            let obj = obj.unwrap();
+            let hashed = hash_object(obj.object_type.as_str(), &obj.payload);
+            assert_eq!(full_oid_to_u128_oid(hashed), oid);
+            found.push(oid);
+        });
+        found.sort_unstable();
+        oids.sort_unstable();
+        assert_eq!(found, oids);
+        // both packs were opened by the state, and are still open:
+        assert_eq!(state.num_open().1, 2);
+        let pack = state.get_pack_file(full_oid_from_str(OFS_DELTA_PACK).unwrap()).unwrap();
+        assert_eq!(Arc::strong_count(&pack), 2);
+    }
+
+    #[test]
+    fn batch_over_a_corrupt_pack_keeps_the_error() {
+        let objects_dir = copy_packed_fixture_objects("batch_corrupt_pack");
+        let pack_path = objects_dir.join("pack").join(format!("pack-{}.pack", REF_DELTA_PACK));
+        let mut data = std::fs::read(&pack_path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        std::fs::write(&pack_path, data).unwrap();
+
+        let odb = LightObjectDB::new(objects_dir.path_str()).unwrap();
+        let mut state = CachingState::new(objects_dir.path_str()).unwrap();
+        // the first 3 are in the pack that is fine, the last 2 are not:
+        let oids: Vec<Oid> = PACKED_COMMITS.iter().map(|c| hash_str_to_oid(c).unwrap()).collect();
+        let mut failed = vec![];
+        odb.get_objects_batch(&oids, &mut state, &mut |oid, obj: io::Result<UnparsedObject>| {
+            match obj {
+                Ok(_) => assert!(oids[..3].contains(&oid)),
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                    assert!(matches!(GitReaderError::from_io_error(&e), Some(GitReaderError::CorruptPack { .. })), "{}", e);
+                    failed.push(oid);
+                }
+            }
+        });
+        failed.sort_unstable();
+        let mut expected = oids[3..].to_vec();
+        expected.sort_unstable();
+        assert_eq!(failed, expected);
+    }
+
+    #[test]
+    fn blob_cache_keeps_blobs_up_to_its_limit() {
+        let objects_dir = TempDir::objects_dir("blob_cache");
+        let mut state = CachingState::new(objects_dir.path_str()).unwrap();
+        state.max_blob_cache_bytes = 10;
+        let blob = |len: usize| -> Arc<[u8]> { vec![b'x'; len].into() };
+
+        state.cache_blob(1, blob(4));
+        state.cache_blob(2, blob(6));
+        assert_eq!(state.blob_cache_bytes(), 10);
+        assert_eq!(state.get_cached_blob(1).unwrap().len(), 4);
+        // the same oid again isn't counted twice:
+        state.cache_blob(1, blob(4));
+        assert_eq!(state.blob_cache_bytes(), 10);
+        // too big to ever keep, so nothing is evicted for it:
+        state.cache_blob(3, blob(11));
+        assert!(state.get_cached_blob(3).is_none());
+        assert_eq!(state.blob_cache_bytes(), 10);
+        // doesn't fit with the others, so they are evicted:
+        state.cache_blob(4, blob(1));
+        assert!(state.get_cached_blob(1).is_none());
+        assert!(state.get_cached_blob(2).is_none());
+        assert_eq!(state.get_cached_blob(4).unwrap().len(), 1);
+        assert_eq!(state.blob_cache_bytes(), 1);
+
+        state.clear_blob_cache();
+        assert!(state.get_cached_blob(4).is_none());
+        assert_eq!(state.blob_cache_bytes(), 0);
+        state.cache_blob(5, blob(2));
+        state.clear_cache();
+        assert_eq!(state.blob_cache_bytes(), 0);
+
+        // a state that doesn't keep blobs:
+        let mut min_state = MinState::new(objects_dir.path_str()).unwrap();
+        min_state.cache_blob(1, blob(1));
+        assert!(min_state.get_cached_blob(1).is_none());
+    }
+
+    #[test]
+    fn get_blob_bytes_uses_the_blob_cache() {
+        let objects_dir = TempDir::objects_dir("blob_cache_odb");
+        let small = full_oid_to_u128_oid(write_raw_object(objects_dir.path(), &UnparsedObjectType::Blob, b"small").unwrap());
+        let big = full_oid_to_u128_oid(write_raw_object(objects_dir.path(), &UnparsedObjectType::Blob, &[b'x'; 100]).unwrap());
+        let odb = LightObjectDB::new(objects_dir.path_str()).unwrap();
+        let mut state = CachingState::new(objects_dir.path_str()).unwrap();
+        state.max_blob_cache_bytes = 50;
+
+        let first = odb.get_blob_bytes(small, &mut state).unwrap();
+        assert_eq!(&first[..], b"small");
+        assert_eq!(state.blob_cache_bytes(), 5);
+        let again = odb.get_blob_bytes(small, &mut state).unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+
+        let first = odb.get_blob_bytes(big, &mut state).unwrap();
+        assert_eq!(first.len(), 100);
+        let again = odb.get_blob_bytes(big, &mut state).unwrap();
+        assert!(!Arc::ptr_eq(&first, &again));
+        assert_eq!(state.blob_cache_bytes(), 5);
+    }
+
+    fn set_folder_mtime(objects_dir: &TempDir, folder_byte: u8, mtime: SystemTime) {
+        let folder = objects_dir.join(format!("{:02x}", folder_byte));
+        std::fs::File::open(folder).unwrap().set_modified(mtime).unwrap();
+    }
+
+    /// the sorted oids of every object `iter_loose_folder_if_changed`
+    /// called back with, and whether it read the folder.
+    fn loose_folder<S: State>(state: &mut S, folder_byte: u8, changed_since: SystemTime) -> (Vec<Oid>, bool) {
+        let mut oids = vec![];
+        let was_read = state.iter_loose_folder_if_changed(folder_byte, changed_since, &mut |oid, _, _| {
+            oids.push(oid);
+            false
+        }).unwrap();
+        oids.sort_unstable();
+        (oids, was_read)
+    }
+
+    #[test]
+    fn unchanged_loose_folders_are_replayed() {
+        let objects_dir = TempDir::objects_dir("loose_if_changed");
+        let write_blob = |payload: &[u8]| full_oid_to_u128_oid(write_raw_object(objects_dir.path(), &UnparsedObjectType::Blob, payload).unwrap());
+        let first = write_blob(b"first");
+        // a blob in some other folder:
+        let mut n = 0;
+        let other = loop {
+            let payload = format!("other {}", n);
+            let oid = full_oid_to_u128_oid(hash_object("blob", payload.as_bytes()));
+            if get_first_byte_of_oid(oid) != get_first_byte_of_oid(first) {
+                break write_blob(payload.as_bytes());
+            }
+            n += 1;
+        };
+        let (changed_folder, unchanged_folder) = (get_first_byte_of_oid(first), get_first_byte_of_oid(other));
+        // a folder modified right before we list it might change again with
+        // the same mtime, so we make them older than that:
+        let long_ago = SystemTime::now() - std::time::Duration::from_secs(60);
+        for folder in [changed_folder, unchanged_folder].iter() {
+            set_folder_mtime(&objects_dir, *folder, long_ago);
+        }
+
+        let mut state = CachingState::new(objects_dir.path_str()).unwrap();
+        let mut min_state = MinState::new(objects_dir.path_str()).unwrap();
+        let first_scan = SystemTime::UNIX_EPOCH;
+        assert_eq!(loose_folder(&mut state, changed_folder, first_scan), (vec![first], true));
+        assert_eq!(loose_folder(&mut state, unchanged_folder, first_scan), (vec![other], true));
+        let second_scan = SystemTime::now();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+
+        // and a new blob in the same folder as the first one:
+        let mut n = 0;
+        let new = loop {
+            let payload = format!("new {}", n);
+            let oid = full_oid_to_u128_oid(hash_object("blob", payload.as_bytes()));
+            if get_first_byte_of_oid(oid) == changed_folder {
+                break write_blob(payload.as_bytes());
+            }
+            n += 1;
+        };
+        let mut expected = vec![first, new];
+        expected.sort_unstable();
+        assert_eq!(loose_folder(&mut state, changed_folder, second_scan), (expected.clone(), true));
+        assert_eq!(loose_folder(&mut state, unchanged_folder, second_scan), (vec![other], false));
+        // a state that doesn't keep folders always reads them:
+        assert_eq!(loose_folder(&mut min_state, changed_folder, second_scan), (expected, true));
+        assert_eq!(loose_folder(&mut min_state, unchanged_folder, second_scan), (vec![other], true));
+
+        // an unchanged folder we never read has to be read:
+        state.clear_cache();
+        assert_eq!(loose_folder(&mut state, unchanged_folder, SystemTime::now()), (vec![other], true));
+        assert_eq!(loose_folder(&mut state, unchanged_folder, SystemTime::now()), (vec![other], false));
+    }
+
+    #[test]
+    fn loose_objects_removed_while_walking_are_skipped() {
+        let objects_dir = TempDir::objects_dir("loose_mtime_removed");
+        let write_blob = |payload: &[u8]| write_raw_object(objects_dir.path(), &UnparsedObjectType::Blob, payload).unwrap();
+        let first = write_blob(b"first");
+        // another blob in the same folder:
+        let mut n = 0;
+        loop {
+            let payload = format!("second {}", n);
+            if hash_object("blob", payload.as_bytes())[0] == first[0] {
+                write_blob(payload.as_bytes());
+                break;
+            }
+            n += 1;
+        }
+        let folder = objects_dir.join(format!("{:02x}", first[0]));
+
+        // the folder is read before the first callback, so
+        // the second object is still listed after we remove it:
+        let mut state = MinState::new(objects_dir.path_str()).unwrap();
+        let mut seen = 0;
+        state.iter_all_loose_with_mtime(&mut |_, _, _| {
+            seen += 1;
+            for entry in std::fs::read_dir(&folder).unwrap() {
+                std::fs::remove_file(entry.unwrap().path()).unwrap();
+            }
+            false
+        }).unwrap();
+        assert_eq!(seen, 1);
+    }
+
+    #[test]
+    fn loose_folders_written_after_listing_are_read_again() {
+        let objects_dir = TempDir::objects_dir("loose_written_after_listing");
+        let write_blob = |payload: &[u8]| full_oid_to_u128_oid(write_raw_object(objects_dir.path(), &UnparsedObjectType::Blob, payload).unwrap());
+        let first = write_blob(b"first");
+        let folder = get_first_byte_of_oid(first);
+        let mut state = CachingState::new(objects_dir.path_str()).unwrap();
+
+        // it was just modified, so even if the caller thinks it hasn't
+        // changed, we can't tell if it changed again in the same second:
+        let later = SystemTime::now() + std::time::Duration::from_secs(60);
+        assert_eq!(loose_folder(&mut state, folder, later), (vec![first], true));
+        assert_eq!(loose_folder(&mut state, folder, later), (vec![first], true));
+
+        // but once its old enough, what we listed is still right:
+        set_folder_mtime(&objects_dir, folder, SystemTime::now() - std::time::Duration::from_secs(60));
+        assert_eq!(loose_folder(&mut state, folder, later), (vec![first], false));
+
+        // a blob written after we listed the folder, but before `changed_since`:
+        let mut n = 0;
+        let new = loop {
+            let payload = format!("new {}", n);
+            let oid = full_oid_to_u128_oid(hash_object("blob", payload.as_bytes()));
+            if get_first_byte_of_oid(oid) == folder {
+                break write_blob(payload.as_bytes());
+            }
+            n += 1;
+        };
+        let mut expected = vec![first, new];
+        expected.sort_unstable();
+        assert_eq!(loose_folder(&mut state, folder, later), (expected, true));
+    }
+
+    #[test]
+    fn malformed_pack_names_are_counted() {
+        let objects_dir = TempDir::objects_dir("malformed_pack_names");
+        for name in ["pack-short.idx", "pack-.idx", "notes.txt", "other.idx"].iter() {
+            std::fs::write(objects_dir.join("pack").join(name), b"").unwrap();
+        }
+        let mut state = MinState::new(objects_dir.path_str()).unwrap();
+        let mut ids = vec![];
+        state.iter_known_packs(&mut |_, id| {
+            ids.push(id);
+            false
+        }).unwrap();
+        assert!(ids.is_empty());
+        assert_eq!(state.malformed_pack_names, 2);
+        // its the count of the last search, not of every search:
+        state.iter_known_packs(&mut |_, _| false).unwrap();
+        assert_eq!(state.malformed_pack_names, 2);
+
+        state.strict_pack_names = true;
+        let err = state.iter_known_packs(&mut |_, _| false).unwrap_err();
+        assert!(err.to_string().contains("pack-"), "{}", err);
+    }
+}
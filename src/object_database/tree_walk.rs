@@ -0,0 +1,314 @@
+use std::{io, collections::BTreeMap};
+use crate::{ioerre, object_id::Oid, sparse::SparseCone};
+use super::{object_source::ObjectSource, state::State, loose::{UnparsedObject, UnparsedObjectType, tree_object_parsing::{TreeObject, TreeEntry, ParseTree}}};
+
+/// read and parse the tree at `oid`. errors if its not a tree.
+pub fn read_tree<O: ObjectSource, S: State>(
+    odb: &O,
+    oid: Oid,
+    state: &mut S,
+) -> io::Result<TreeObject> {
+    let obj: UnparsedObject = odb.get_object_by_oid(oid, state)?;
+    if obj.object_type != UnparsedObjectType::Tree {
+        return ioerre!("Expected {:032x} to be a tree, but it is a {}", oid, obj.object_type.as_str());
+    }
+    TreeObject::parse(&obj.payload)
+}
+
+/// find the entry at `path` (ie: "src/main.rs") starting from the tree
+/// at `tree_oid`. Empty components are ignored, so "src/" and "src"
+/// are the same. Returns Ok(None) if nothing exists at that path.
+/// An empty path is not an entry of any tree, so that is Ok(None) as well.
+pub fn find_tree_path<O: ObjectSource, S: State>(
+    odb: &O,
+    tree_oid: Oid,
+    path: &str,
+    state: &mut S,
+) -> io::Result<Option<TreeEntry>> {
+    let mut components = path.split('/').filter(|c| !c.is_empty()).peekable();
+    let mut current_tree = tree_oid;
+    while let Some(component) = components.next() {
+        let tree = read_tree(odb, current_tree, state)?;
+        let entry = match tree.entries.into_iter().find(|e| e.path_component == component.as_bytes()) {
+            Some(e) => e,
+            None => return Ok(None),
+        };
+        if components.peek().is_none() {
+            return Ok(Some(entry));
+        }
+        if !entry.entry_mode.is_dir() {
+            // theres more path left, but this is not a directory:
+            return Ok(None);
+        }
+        current_tree = entry.id();
+    }
+    Ok(None)
+}
+
+/// recursively walk the tree at `tree_oid`, calling `cb` with the
+/// full path of every entry (directories too, before their contents)
+/// and the entry itself. Submodules (gitlinks) are yielded but not
+/// descended into, since their commits are not in our object DB.
+/// If `prefix` is given, only the entries under that path are walked,
+/// and their paths include the prefix. If the prefix is a file, just that
+/// one entry is yielded. If the prefix does not exist, nothing is.
+/// Names that are not valid utf8 are in the path as `TreeEntry::display_name`,
+/// use the entry's `path_component` if you need the real bytes.
+pub fn walk_tree<O, S, F>(
+    odb: &O,
+    tree_oid: Oid,
+    prefix: Option<&str>,
+    state: &mut S,
+    cb: F,
+) -> io::Result<()>
+    where O: ObjectSource,
+          S: State,
+          F: FnMut(&str, &TreeEntry) -> io::Result<()>,
+{
+    let mut cb = cb;
+    let prefix = prefix.map(|p| p.trim_matches('/')).unwrap_or("");
+    if prefix.is_empty() {
+        return walk_tree_inner(odb, tree_oid, "", state, &|_, _| true, &mut cb);
+    }
+    let entry = match find_tree_path(odb, tree_oid, prefix, state)? {
+        Some(e) => e,
+        None => return Ok(()),
+    };
+    // normalize the prefix, ie: "src//lib" -> "src/lib"
+    let prefix = prefix.split('/').filter(|c| !c.is_empty()).collect::<Vec<_>>().join("/");
+    if !entry.entry_mode.is_dir() {
+        return cb(&prefix, &entry);
+    }
+    walk_tree_inner(odb, entry.id(), &prefix, state, &|_, _| true, &mut cb)
+}
+
+/// like `walk_tree`, but only yields the entries that are part of
+/// the sparse checkout `cone`, and only descends into directories that
+/// have something in the cone. Directories are yielded if we look inside them,
+/// even if none of their files end up being included.
+pub fn walk_tree_sparse<O, S, F>(
+    odb: &O,
+    tree_oid: Oid,
+    cone: &SparseCone,
+    state: &mut S,
+    cb: F,
+) -> io::Result<()>
+    where O: ObjectSource,
+          S: State,
+          F: FnMut(&str, &TreeEntry) -> io::Result<()>,
+{
+    let mut cb = cb;
+    let include = |path: &str, entry: &TreeEntry| if entry.entry_mode.is_dir() {
+        cone.matches_dir(path)
+    } else {
+        cone.matches(path)
+    };
+    walk_tree_inner(odb, tree_oid, "", state, &include, &mut cb)
+}
+
+fn walk_tree_inner<O, S, F>(
+    odb: &O,
+    tree_oid: Oid,
+    path_so_far: &str,
+    state: &mut S,
+    include: &dyn Fn(&str, &TreeEntry) -> bool,
+    cb: &mut F,
+) -> io::Result<()>
+    where O: ObjectSource,
+          S: State,
+          F: FnMut(&str, &TreeEntry) -> io::Result<()>,
+{
+    let tree = read_tree(odb, tree_oid, state)?;
+    for entry in tree.entries.iter() {
+        let path = if path_so_far.is_empty() {
+            entry.display_name().into_owned()
+        } else {
+            format!("{}/{}", path_so_far, entry.display_name())
+        };
+        if !include(&path, entry) {
+            continue;
+        }
+        cb(&path, entry)?;
+        if entry.entry_mode.is_dir() {
+            walk_tree_inner(odb, entry.id(), &path, state, include, cb)?;
+        }
+    }
+    Ok(())
+}
+
+/// how a path changed between two trees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Deleted,
+    /// the contents or the mode changed
+    Modified,
+}
+
+/// one file that is different between two trees, see `diff_trees`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedPath {
+    /// the full path, ie: "src/main.rs". Lossy if a name is not utf8
+    pub path: String,
+    pub kind: ChangeKind,
+    /// None if the path was added
+    pub old_id: Option<Oid>,
+    /// None if the path was deleted
+    pub new_id: Option<Oid>,
+}
+
+/// the files that are different between `old_tree` and `new_tree`,
+/// sorted by path. Like `git diff --name-only`, only files (and submodules)
+/// are listed, not the directories they are in. If `old_tree` is None,
+/// every file of `new_tree` was added, which is how git shows a root commit.
+/// Subtrees with the same oid are not read, so this only reads
+/// the parts of the trees that actually changed.
+pub fn diff_trees<O: ObjectSource, S: State>(
+    odb: &O,
+    old_tree: Option<Oid>,
+    new_tree: Oid,
+    state: &mut S,
+) -> io::Result<Vec<ChangedPath>> {
+    let mut out = vec![];
+    diff_trees_inner(odb, old_tree, Some(new_tree), "", state, &mut out)?;
+    out.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(out)
+}
+
+fn diff_trees_inner<O: ObjectSource, S: State>(
+    odb: &O,
+    old_tree: Option<Oid>,
+    new_tree: Option<Oid>,
+    path_so_far: &str,
+    state: &mut S,
+    out: &mut Vec<ChangedPath>,
+) -> io::Result<()> {
+    if old_tree == new_tree {
+        return Ok(());
+    }
+    // keyed by the raw names, so two names that are not
+    // utf8 can't end up as the same entry:
+    let mut entries: BTreeMap<Vec<u8>, (Option<TreeEntry>, Option<TreeEntry>)> = BTreeMap::new();
+    if let Some(old_tree) = old_tree {
+        for entry in read_tree(odb, old_tree, state)?.entries {
+            let name = entry.path_component.clone();
+            entries.entry(name).or_default().0 = Some(entry);
+        }
+    }
+    if let Some(new_tree) = new_tree {
+        for entry in read_tree(odb, new_tree, state)?.entries {
+            let name = entry.path_component.clone();
+            entries.entry(name).or_default().1 = Some(entry);
+        }
+    }
+    for (name, (old, new)) in entries {
+        let name = String::from_utf8_lossy(&name);
+        let path = if path_so_far.is_empty() {
+            name.into_owned()
+        } else {
+            format!("{}/{}", path_so_far, name)
+        };
+        // a directory on either side gets descended into, and a
+        // file on either side gets compared. If a file became a
+        // directory (or the other way) its both: the file was
+        // deleted, and everything in the directory was added.
+        let old_dir = old.as_ref().filter(|e| e.entry_mode.is_dir()).map(|e| e.id());
+        let new_dir = new.as_ref().filter(|e| e.entry_mode.is_dir()).map(|e| e.id());
+        if old_dir.is_some() || new_dir.is_some() {
+            diff_trees_inner(odb, old_dir, new_dir, &path, state, out)?;
+        }
+        let old_file = old.filter(|e| !e.entry_mode.is_dir());
+        let new_file = new.filter(|e| !e.entry_mode.is_dir());
+        let kind = match (&old_file, &new_file) {
+            (None, None) => continue,
+            (None, Some(_)) => ChangeKind::Added,
+            (Some(_), None) => ChangeKind::Deleted,
+            (Some(o), Some(n)) => {
+                if o.id() == n.id() && o.entry_mode.mode_bits() == n.entry_mode.mode_bits() {
+                    continue;
+                }
+                ChangeKind::Modified
+            }
+        };
+        out.push(ChangedPath {
+            path,
+            kind,
+            old_id: old_file.map(|e| e.id()),
+            new_id: new_file.map(|e| e.id()),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{object_id::{full_oid_to_u128_oid, OidFull}, sha1::hash_object};
+    use crate::object_database::{object_source::MemObjectStore, state::MinState};
+
+    fn insert(store: &mut MemObjectStore, object_type: UnparsedObjectType, payload: Vec<u8>) -> OidFull {
+        let oid = hash_object(object_type.as_str(), &payload);
+        store.insert(object_type, payload);
+        oid
+    }
+
+    /// `entries` are (mode, name, oid), and should already be sorted like git sorts them.
+    fn insert_tree(store: &mut MemObjectStore, entries: &[(&str, &str, OidFull)]) -> OidFull {
+        let mut payload = vec![];
+        for (mode, name, oid) in entries {
+            payload.extend_from_slice(format!("{} {}\0", mode, name).as_bytes());
+            payload.extend_from_slice(oid);
+        }
+        insert(store, UnparsedObjectType::Tree, payload)
+    }
+
+    /// file.txt, src/lib.rs and src/nested/a.rs
+    fn make_tree(store: &mut MemObjectStore) -> Oid {
+        let blob = insert(store, UnparsedObjectType::Blob, b"hi\n".to_vec());
+        let nested = insert_tree(store, &[("100644", "a.rs", blob)]);
+        let src = insert_tree(store, &[("100644", "lib.rs", blob), ("40000", "nested", nested)]);
+        let root = insert_tree(store, &[("100644", "file.txt", blob), ("40000", "src", src)]);
+        full_oid_to_u128_oid(root)
+    }
+
+    fn walked_paths(store: &MemObjectStore, tree: Oid, prefix: Option<&str>) -> Vec<String> {
+        let mut state = MinState::new("/nonexistent/.git/objects").unwrap();
+        let mut paths = vec![];
+        walk_tree(store, tree, prefix, &mut state, |path, _| {
+            paths.push(path.to_string());
+            Ok(())
+        }).unwrap();
+        paths
+    }
+
+    #[test]
+    fn walk_tree_with_prefix_works() {
+        let mut store = MemObjectStore::new();
+        let tree = make_tree(&mut store);
+        assert_eq!(walked_paths(&store, tree, None), ["file.txt", "src", "src/lib.rs", "src/nested", "src/nested/a.rs"]);
+        assert_eq!(walked_paths(&store, tree, Some("src/")), ["src/lib.rs", "src/nested", "src/nested/a.rs"]);
+        assert_eq!(walked_paths(&store, tree, Some("src//nested")), ["src/nested/a.rs"]);
+        // a prefix that is a blob is just that one entry:
+        assert_eq!(walked_paths(&store, tree, Some("file.txt")), ["file.txt"]);
+        assert_eq!(walked_paths(&store, tree, Some("src/lib.rs")), ["src/lib.rs"]);
+        // and one that doesn't exist is nothing, even if part of it does:
+        assert!(walked_paths(&store, tree, Some("missing")).is_empty());
+        assert!(walked_paths(&store, tree, Some("src/missing")).is_empty());
+        assert!(walked_paths(&store, tree, Some("file.txt/more")).is_empty());
+    }
+
+    #[test]
+    fn find_tree_path_works() {
+        let mut store = MemObjectStore::new();
+        let tree = make_tree(&mut store);
+        let mut state = MinState::new("/nonexistent/.git/objects").unwrap();
+        let mut find = |path: &str| find_tree_path(&store, tree, path, &mut state).unwrap();
+        let lib = find("src/lib.rs").unwrap();
+        assert_eq!(lib.path_component, b"lib.rs");
+        assert!(!lib.entry_mode.is_dir());
+        assert!(find("src/nested/").unwrap().entry_mode.is_dir());
+        assert!(find("file.txt/more").is_none());
+        assert!(find("src/missing").is_none());
+        assert!(find("").is_none());
+    }
+}
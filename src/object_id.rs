@@ -1,4 +1,4 @@
-use std::{convert::{TryInto, TryFrom}, io};
+use std::{convert::{TryInto, TryFrom}, cmp::Ordering, io, fmt, str::FromStr};
 use crate::ioerr;
 
 /// NOTE: we represent sha1 hash keys as u128, when they are really
@@ -49,6 +49,47 @@ impl TryFrom<OidStrFull> for OidFull {
     }
 }
 
+/// A thin wrapper around an `Oid` for printing and parsing.
+/// Because an `Oid` is just a `u128`, printing it with `{}` gives
+/// you a decimal number. `OidHex` always displays as 32 zero-padded
+/// hex chars, and can be parsed from a hash string via `FromStr`.
+/// eg: `let oid: OidHex = "abcd...".parse()?;`
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct OidHex(pub Oid);
+
+impl fmt::Display for OidHex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+impl fmt::Debug for OidHex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OidHex({:032x})", self.0)
+    }
+}
+
+impl FromStr for OidHex {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let oid = hash_str_to_oid(s)?;
+        Ok(OidHex(oid))
+    }
+}
+
+impl From<Oid> for OidHex {
+    fn from(oid: Oid) -> Self {
+        OidHex(oid)
+    }
+}
+
+impl From<OidHex> for Oid {
+    fn from(oid_hex: OidHex) -> Self {
+        oid_hex.0
+    }
+}
+
 #[macro_export]
 macro_rules! printoid {
     ($oid: tt) => {
@@ -117,6 +158,23 @@ pub fn hex_u128_to_str(h: Oid) -> String {
     format!("{}{}", prepend_0s, hash_str)
 }
 
+/// returns an array of 32 bytes (hex characters)
+/// that represents the Oid, zero padded. Like `hex_u128_to_str`
+/// but without allocating a string.
+pub fn oid_to_hex_bytes(h: Oid) -> [u8; 32] {
+    let mut out = [b'0'; 32];
+    for (i, byte) in h.to_be_bytes().iter().enumerate() {
+        out[(i * 2)..(i * 2 + 2)].copy_from_slice(&HEX_BYTES[*byte as usize]);
+    }
+    out
+}
+
+/// write the 32 hex characters of the oid directly into the writer.
+/// useful when printing a lot of oids, since nothing is allocated.
+pub fn write_oid_hex<W: io::Write>(h: Oid, writer: &mut W) -> io::Result<()> {
+    writer.write_all(&oid_to_hex_bytes(h))
+}
+
 pub fn hex_u128_trunc_to_str(h: OidTruncated) -> String {
     let oid = Oid::from_be_bytes(h);
     hex_u128_to_str(oid)
@@ -134,6 +192,15 @@ pub fn oid_full_to_string(h: OidFull) -> String {
     s
 }
 
+/// the path of a loose object relative to the objects directory,
+/// ie: "ab/cdef..." (2 hex chars, a slash, then the other 38 hex chars).
+/// This always uses '/' even on windows.
+pub fn oid_full_to_loose_relpath(h: OidFull) -> String {
+    let mut s = oid_full_to_string(h);
+    s.insert(2, '/');
+    s
+}
+
 /// returns an array of 40 bytes (hex characters)
 /// that represents the OidFull.
 pub fn oid_full_to_string_no_alloc(h: OidFull) -> [u8; 40] {
@@ -148,6 +215,14 @@ pub fn oid_full_to_string_no_alloc(h: OidFull) -> [u8; 40] {
     out
 }
 
+/// compare two full oids the way git sorts them, ie: in idx files.
+/// This looks at all 20 bytes. Comparing the truncated `Oid`s of two
+/// objects that share their first 16 bytes gives `Equal`, even though
+/// git puts them in a specific order.
+pub fn cmp_oid_full(a: &OidFull, b: &OidFull) -> Ordering {
+    a[..].cmp(&b[..])
+}
+
 pub fn oid_str_truncated_to_oid(oid_str: OidStrTruncated) -> io::Result<Oid> {
     let oid_str = std::str::from_utf8(&oid_str.0).map_err(|e| ioerr!("{}", e))?;
     let oid = Oid::from_str_radix(oid_str, 16).map_err(|e| ioerr!("{}", e))?;
@@ -265,6 +340,15 @@ pub fn full_slice_oid_to_u128_oid(full: &[u8]) -> Oid {
     Oid::from_be_bytes(oid_bytes)
 }
 
+/// Like `full_slice_oid_to_u128_oid`, but checks that the slice
+/// is long enough first, instead of panicking. Use this for data
+/// that comes from an untrusted source, like a network pack stream.
+pub fn try_full_slice_oid_to_u128_oid(full: &[u8]) -> io::Result<Oid> {
+    let first_16 = full.get(0..16)
+        .ok_or_else(|| ioerr!("Expected at least 16 bytes to create an Oid, but only found {}", full.len()))?;
+    Ok(full_slice_oid_to_u128_oid(first_16))
+}
+
 /// 256 values of hex bytes. each value is an array
 /// of 2 ascii values that represents that byte in ascii hex.
 /// eg: if we want to find the hex code for the value 11,
@@ -533,6 +617,20 @@ pub const HEX_BYTES: &[[u8; 2]; 256] = &[
 mod tests {
     use super::*;
 
+    #[test]
+    fn oid_hex_bytes_are_zero_padded() {
+        assert_eq!(&oid_to_hex_bytes(1), b"00000000000000000000000000000001");
+        assert_eq!(&oid_to_hex_bytes(Oid::MAX), b"ffffffffffffffffffffffffffffffff");
+        let oid = hash_str_to_oid("0260a46c5c337746960e83b54984bd041f56ee53").unwrap();
+        assert_eq!(oid_to_hex_bytes(oid).to_vec(), hex_u128_to_str(oid).into_bytes());
+
+        let mut out = vec![];
+        write_oid_hex(1, &mut out).unwrap();
+        write_oid_hex(0xab, &mut out).unwrap();
+        assert_eq!(&out[0..32], b"00000000000000000000000000000001");
+        assert_eq!(&out[32..], b"000000000000000000000000000000ab");
+    }
+
     #[test]
     fn hash_parsing_works() {
         let folder = "00";
@@ -556,6 +654,43 @@ mod tests {
         assert_eq!(hex_str, expected_hex_str);
     }
 
+    #[test]
+    fn oid_hex_display_and_parse_works() {
+        let oid_hex = OidHex(1);
+        assert_eq!(oid_hex.to_string(), "00000000000000000000000000000001");
+        let parsed: OidHex = "0000000000000000000000000000000100000000".parse().unwrap();
+        assert_eq!(parsed, oid_hex);
+        assert!("abc".parse::<OidHex>().is_err());
+    }
+
+    #[test]
+    fn checked_slice_to_oid_works() {
+        let mut full = OidFull::default();
+        full[15] = 3;
+        assert_eq!(try_full_slice_oid_to_u128_oid(&full).unwrap(), 3);
+        assert_eq!(try_full_slice_oid_to_u128_oid(&full[0..16]).unwrap(), 3);
+        assert!(try_full_slice_oid_to_u128_oid(&full[0..15]).is_err());
+        assert!(try_full_slice_oid_to_u128_oid(&[]).is_err());
+    }
+
+    #[test]
+    fn full_oid_cmp_uses_all_bytes() {
+        let mut a = OidFull::default();
+        a[0] = 0x12;
+        a[15] = 0x34;
+        let mut b = a;
+        a[16] = 0x01;
+        b[19] = 0xff;
+        // the same first 16 bytes, so the truncated oids cant tell them apart:
+        assert_eq!(full_oid_to_u128_oid(a), full_oid_to_u128_oid(b));
+        assert_eq!(cmp_oid_full(&a, &b), Ordering::Greater);
+        assert_eq!(cmp_oid_full(&b, &a), Ordering::Less);
+        assert_eq!(cmp_oid_full(&a, &a), Ordering::Equal);
+        let mut sorted = vec![a, b];
+        sorted.sort_unstable_by(cmp_oid_full);
+        assert_eq!(sorted, vec![b, a]);
+    }
+
     #[test]
     fn getting_first_byte_works() {
         let oid_str = "aaf00000000000000000000000000000";
@@ -564,4 +699,15 @@ mod tests {
         // aa == 170
         assert_eq!(first_byte, 170);
     }
+
+    #[test]
+    fn loose_relpath_works() {
+        let mut oid_full = OidFull::default();
+        oid_full[0] = 0xab;
+        oid_full[1] = 0xcd;
+        let relpath = oid_full_to_loose_relpath(oid_full);
+        assert_eq!(relpath.len(), 41);
+        assert_eq!(relpath.find('/'), Some(2));
+        assert!(relpath.starts_with("ab/cd00"));
+    }
 }
@@ -1,3 +1,6 @@
+use std::{io, path::{Path, PathBuf}, fs, collections::{BTreeMap, HashMap}};
+use crate::{ioerr, ioerre, get_repository_directory, config::{ObjectConfig, load_config}, object_id::{Oid, hash_str_to_oid}};
+use crate::object_database::{LightObjectDB, DEFAULT_MIN_ABBREV, MAX_PEEL_DEPTH, state::State, loose::{UnparsedObject, UnparsedObjectType, TagObject, commit_object_parsing::{ParseCommit, CommitOnlyTreeAndParents}}};
 
 /// contains the filepaths that are needed
 /// for future operations on this repository.
@@ -11,5 +14,748 @@
 /// to contain other folders/files if we need them. See:
 /// https://git-scm.com/docs/gitrepository-layout
 pub struct Repo {
-    
-}
\ No newline at end of file
+    /// absolute path to the .git/ folder (or the repo
+    /// itself if its a bare repo)
+    pub git_dir: PathBuf,
+    /// absolute path to .git/objects/
+    pub objects_dir: PathBuf,
+}
+
+/// which hash function the objects of a repo are named with.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum HashAlgo {
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgo {
+    /// how many bytes a hash of this type is.
+    pub fn hash_len(&self) -> usize {
+        match self {
+            HashAlgo::Sha1 => 20,
+            HashAlgo::Sha256 => 32,
+        }
+    }
+
+    /// how many hex characters a hash of this type is.
+    pub fn hex_len(&self) -> usize {
+        self.hash_len() * 2
+    }
+
+    pub fn from_name(name: &str) -> Option<HashAlgo> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha1" => Some(HashAlgo::Sha1),
+            "sha256" => Some(HashAlgo::Sha256),
+            _ => None,
+        }
+    }
+}
+
+/// what a ref (like HEAD) points to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ref {
+    /// points to another ref, ie: "refs/heads/master"
+    Symbolic(String),
+    /// points directly to an object, ie: a detached HEAD
+    Direct(Oid),
+}
+
+impl Repo {
+    /// find the repository from the search path. the search path
+    /// can be the worktree, or the .git/ dir itself.
+    pub fn open<P: AsRef<Path>>(search_path: P) -> io::Result<Repo> {
+        let git_dir = get_repository_directory(search_path)?;
+        let objects_dir = git_dir.join("objects");
+        Ok(Repo {
+            git_dir,
+            objects_dir,
+        })
+    }
+
+    /// the settings of .git/config that matter for reading objects. See `config::load_config`
+    pub fn config(&self) -> io::Result<ObjectConfig> {
+        load_config(&self.git_dir)
+    }
+
+    /// true if this repo doesn't have a worktree, like git
+    /// decides it: `core.bare` if the config has it, and otherwise
+    /// if the git dir is not a folder named .git/
+    pub fn is_bare(&self) -> io::Result<bool> {
+        match self.config()?.bare {
+            Some(bare) => Ok(bare),
+            None => Ok(self.git_dir.file_name().is_none_or(|name| name != ".git")),
+        }
+    }
+
+    /// See `detect_object_format`
+    pub fn object_format(&self) -> io::Result<HashAlgo> {
+        detect_object_format(&self.git_dir)
+    }
+
+    /// parse .git/HEAD. Note that if HEAD is symbolic,
+    /// the branch it points to does not necessarily exist yet,
+    /// ie: a freshly initialized repo with no commits.
+    pub fn head(&self) -> io::Result<Ref> {
+        let head_path = self.git_dir.join("HEAD");
+        let contents = fs::read_to_string(&head_path)
+            .map_err(|e| ioerr!("Failed to read {:?}\n{}", head_path, e))?;
+        parse_ref_contents(&contents)
+            .map_err(|e| ioerr!("Failed to parse {:?}\n{}", head_path, e))
+    }
+
+    /// the name of the branch that HEAD points to, ie: "master".
+    /// None if HEAD is detached, or if it points to something
+    /// other than a branch.
+    pub fn current_branch(&self) -> Option<String> {
+        match self.head().ok()? {
+            Ref::Symbolic(name) => name.strip_prefix("refs/heads/").map(|b| b.to_string()),
+            Ref::Direct(_) => None,
+        }
+    }
+
+    /// the oid that HEAD eventually points to. the ref HEAD points to
+    /// can either be a loose ref file, or be in packed-refs.
+    /// Returns Ok(None) if HEAD points to a branch that doesn't
+    /// exist yet (an unborn branch).
+    pub fn head_oid(&self) -> io::Result<Option<Oid>> {
+        match self.head()? {
+            Ref::Direct(oid) => Ok(Some(oid)),
+            Ref::Symbolic(name) => self.resolve_ref(&name),
+        }
+    }
+
+    /// the oid that a ref, ie: "refs/heads/master", eventually points to,
+    /// following symbolic refs. Loose refs take priority over packed ones.
+    /// Ok(None) if the ref (or a ref it points to) doesn't exist.
+    pub fn resolve_ref(&self, name: &str) -> io::Result<Option<Oid>> {
+        let mut name = name.to_string();
+        // refs can point to other refs. git limits this to 5 levels deep:
+        for _ in 0..5 {
+            let loose_path = self.git_dir.join(&name);
+            if !loose_path.is_file() {
+                return self.find_packed_ref(&name);
+            }
+            let contents = fs::read_to_string(&loose_path)?;
+            match parse_ref_contents(&contents)
+                .map_err(|e| ioerr!("Failed to parse {:?}\n{}", loose_path, e))?
+            {
+                Ref::Direct(oid) => return Ok(Some(oid)),
+                Ref::Symbolic(target) => name = target,
+            }
+        }
+        ioerre!("Too many levels of symbolic refs while resolving {}", name)
+    }
+
+    /// look up a ref by its full name in the packed-refs file.
+    /// Ok(None) if there is no packed-refs file, or the ref is not in it.
+    pub fn find_packed_ref(&self, name: &str) -> io::Result<Option<Oid>> {
+        Ok(self.packed_refs()?.into_iter()
+            .find(|packed| packed.name == name)
+            .map(|packed| packed.oid))
+    }
+
+    /// every entry of the packed-refs file, in the order of the file.
+    /// Empty if there is no packed-refs file.
+    pub fn packed_refs(&self) -> io::Result<Vec<PackedRef>> {
+        let packed_refs_path = self.git_dir.join("packed-refs");
+        match fs::read_to_string(&packed_refs_path) {
+            Ok(contents) => parse_packed_refs(&contents)
+                .map_err(|e| ioerr!("Failed to parse {:?}\n{}", packed_refs_path, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// every ref under `refs/` (loose and packed), sorted by name,
+    /// like `git show-ref`. Symbolic refs are resolved to the oid they
+    /// eventually point to, and ones that point to a ref that doesn't
+    /// exist are left out. If a ref is both loose and packed, the
+    /// loose one wins.
+    pub fn all_refs(&self) -> io::Result<Vec<(String, Oid)>> {
+        let mut refs: BTreeMap<String, Oid> = self.packed_refs()?.into_iter()
+            .map(|packed| (packed.name, packed.oid))
+            .collect();
+        let mut loose_names = vec![];
+        collect_loose_ref_names(&self.git_dir.join("refs"), "refs", &mut loose_names)?;
+        for name in loose_names {
+            match self.resolve_ref(&name)? {
+                Some(oid) => { refs.insert(name, oid); }
+                None => { refs.remove(&name); }
+            }
+        }
+        Ok(refs.into_iter().collect())
+    }
+
+    /// what an annotated tag ref eventually points to (ie: the commit), if
+    /// packed-refs knows it. This is the `^` line after the ref in
+    /// packed-refs. None if the ref isn't packed, wasn't peeled, or if a
+    /// loose ref with the same name has replaced the packed one since.
+    pub fn peeled(&self, ref_name: &str) -> Option<Oid> {
+        let packed = self.packed_refs().ok()?.into_iter()
+            .find(|packed| packed.name == ref_name)?;
+        if self.resolve_ref(ref_name).ok()? != Some(packed.oid) {
+            return None;
+        }
+        packed.peeled
+    }
+
+    /// every tag under `refs/tags/`, sorted by name like `git tag`, as
+    /// (name without the "refs/tags/", target, annotated). A tag is annotated if
+    /// its ref points to a tag object, and then the target is the first object
+    /// that isn't a tag when following the tag (or chain of tags), which is
+    /// usually a commit, but can be a tree or a blob. For a lightweight tag,
+    /// the target is what the ref points to. The peeled lines of packed-refs are
+    /// used when we have them, so most packed tags don't need to be read at all.
+    pub fn tags<S: State>(
+        &self,
+        odb: &LightObjectDB,
+        state: &mut S,
+    ) -> io::Result<Vec<(String, Oid, bool)>> {
+        // (oid, peeled) of each packed tag:
+        let packed_tags: HashMap<String, (Oid, Option<Oid>)> = self.packed_refs()?.into_iter()
+            .filter(|packed| packed.name.starts_with("refs/tags/"))
+            .map(|packed| (packed.name, (packed.oid, packed.peeled)))
+            .collect();
+        let mut out = vec![];
+        for (name, oid) in self.all_refs()? {
+            let tag_name = match name.strip_prefix("refs/tags/") {
+                Some(tag_name) => tag_name.to_string(),
+                None => continue,
+            };
+            // only if the packed ref wasn't replaced by a loose one:
+            let peeled = packed_tags.get(&name)
+                .filter(|(packed_oid, _)| *packed_oid == oid)
+                .and_then(|(_, peeled)| *peeled);
+            if let Some(peeled) = peeled {
+                out.push((tag_name, peeled, true));
+                continue;
+            }
+            let obj: UnparsedObject = odb.get_object_by_oid(oid, state)
+                .map_err(|e| ioerr!("Failed to read the object of tag {}\n{}", tag_name, e))?;
+            if obj.object_type != UnparsedObjectType::Tag {
+                out.push((tag_name, oid, false));
+                continue;
+            }
+            let target = peel_tag(odb, TagObject::parse(&obj.payload)?, state)
+                .map_err(|e| ioerr!("Failed to peel tag {}\n{}", tag_name, e))?;
+            out.push((tag_name, target, true));
+        }
+        Ok(out)
+    }
+
+    /// `git rev-parse <spec>` for the part of git's revision syntax that
+    /// most tools need: a ref or an oid, followed by any number of `~N` (go to
+    /// the first parent N times) and `^N` (the Nth parent, `^0` is the commit
+    /// itself), ie: "HEAD~2^2" or "v1.0^". Without a number, `~` and `^` mean 1.
+    /// A short ref name is looked for like git does, ie: "master" is
+    /// refs/heads/master unless there's a tag called master. A short oid needs
+    /// at least `DEFAULT_MIN_ABBREV` hex characters. Tags are peeled to their
+    /// commit before each step, but a spec without any steps is not peeled,
+    /// just like git. If nothing has that name, the error has a kind
+    /// of `io::ErrorKind::NotFound`.
+    pub fn resolve_revision<S: State>(
+        &self,
+        spec: &str,
+        odb: &LightObjectDB,
+        state: &mut S,
+    ) -> io::Result<Oid> {
+        let base_end = spec.find(['~', '^']).unwrap_or(spec.len());
+        let mut current = self.resolve_revision_base(&spec[0..base_end], odb, state)?;
+        let mut steps = &spec[base_end..];
+        while let Some(op) = steps.chars().next() {
+            if op != '~' && op != '^' {
+                return ioerre!("Invalid revision '{}': only ~N and ^N can come after the name, found '{}'", spec, steps);
+            }
+            let digits_end = steps[1..].find(|c: char| !c.is_ascii_digit()).map(|i| i + 1).unwrap_or(steps.len());
+            let n: usize = match &steps[1..digits_end] {
+                "" => 1,
+                digits => digits.parse()
+                    .map_err(|_| ioerr!("Invalid revision '{}': '{}' is too big", spec, digits))?,
+            };
+            steps = &steps[digits_end..];
+            let (commit_oid, commit) = read_commit_parents(odb, current, state)?;
+            current = match (op, n) {
+                ('^', 0) => commit_oid,
+                ('^', n) => match commit.parents().nth(n - 1) {
+                    Some(parent) => parent,
+                    None => return ioerre!("Invalid revision '{}': {:032x} has {} parent(s), so it has no parent {}", spec, commit_oid, commit.parents().count(), n),
+                },
+                _ => {
+                    let mut oid = commit_oid;
+                    let mut commit = commit;
+                    for i in 0..n {
+                        if commit.parent_one == 0 {
+                            return ioerre!("Invalid revision '{}': {:032x} has no parents, but we needed to go back {} more", spec, oid, n - i);
+                        }
+                        oid = commit.parent_one;
+                        if i + 1 < n {
+                            commit = read_commit_parents(odb, oid, state)?.1;
+                        }
+                    }
+                    oid
+                }
+            };
+        }
+        Ok(current)
+    }
+
+    /// the part of a revision before any ~ or ^. Like git, a full oid
+    /// wins over a ref, but a ref wins over a short oid.
+    fn resolve_revision_base<S: State>(
+        &self,
+        name: &str,
+        odb: &LightObjectDB,
+        state: &mut S,
+    ) -> io::Result<Oid> {
+        let is_hex = !name.is_empty() && name.chars().all(|c| c.is_ascii_hexdigit());
+        if is_hex && name.len() == 40 {
+            return hash_str_to_oid(name);
+        }
+        let name = if name == "@" { "HEAD" } else { name };
+        if name.is_empty() {
+            return ioerre!("Invalid revision: it needs a name before the ~ or ^");
+        }
+        // outside of refs/, git only looks at names like HEAD or ORIG_HEAD,
+        // so it doesn't try to read something like .git/config as a ref:
+        let is_top_level = name.chars().all(|c| c.is_ascii_uppercase() || c == '_');
+        let candidates = [
+            if is_top_level || name.starts_with("refs/") { Some(name.to_string()) } else { None },
+            Some(format!("refs/{}", name)),
+            Some(format!("refs/tags/{}", name)),
+            Some(format!("refs/heads/{}", name)),
+            Some(format!("refs/remotes/{}", name)),
+            Some(format!("refs/remotes/{}/HEAD", name)),
+        ];
+        for candidate in candidates.iter().flatten() {
+            if let Some(oid) = self.resolve_ref(candidate)? {
+                return Ok(oid);
+            }
+        }
+        if is_hex {
+            return Ok(odb.resolve_short(name, DEFAULT_MIN_ABBREV, state)?.0);
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("Unknown revision '{}': no ref or object has that name", name)))
+    }
+}
+
+/// read the commit at `oid`, peeling it first if its a tag.
+/// Returns the oid of the commit too.
+fn read_commit_parents<S: State>(
+    odb: &LightObjectDB,
+    oid: Oid,
+    state: &mut S,
+) -> io::Result<(Oid, CommitOnlyTreeAndParents)> {
+    let mut oid = oid;
+    let mut obj: UnparsedObject = odb.get_object_by_oid(oid, state)?;
+    if obj.object_type == UnparsedObjectType::Tag {
+        oid = odb.peel_to_commit(oid, state)?;
+        obj = odb.get_object_by_oid(oid, state)?;
+    }
+    if obj.object_type != UnparsedObjectType::Commit {
+        return ioerre!("Expected {:032x} to be a commit, but it is a {}", oid, obj.object_type.as_str());
+    }
+    Ok((oid, CommitOnlyTreeAndParents::parse(&obj.payload)?))
+}
+
+/// one ref of the packed-refs file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackedRef {
+    /// the full name, ie: "refs/tags/v1.0"
+    pub name: String,
+    pub oid: Oid,
+    /// for an annotated tag, the object the tag eventually points to.
+    pub peeled: Option<Oid>,
+}
+
+/// follow a chain of tags, starting at `tag`, to the first object that is
+/// not a tag, which is usually a commit, but can be a tree or a blob.
+fn peel_tag<S: State>(odb: &LightObjectDB, mut tag: TagObject, state: &mut S) -> io::Result<Oid> {
+    for _ in 0..MAX_PEEL_DEPTH {
+        // the tag says what it points to, so if its not
+        // another tag, we dont need to read it:
+        if !matches!(tag.object_type, Some(UnparsedObjectType::Tag) | None) {
+            return Ok(tag.object);
+        }
+        let obj: UnparsedObject = odb.get_object_by_oid(tag.object, state)?;
+        if obj.object_type != UnparsedObjectType::Tag {
+            return Ok(tag.object);
+        }
+        tag = TagObject::parse(&obj.payload)?;
+    }
+    ioerre!("Exceeded max depth of {} while following tags", MAX_PEEL_DEPTH)
+}
+
+/// parse the contents of a packed-refs file, ie:
+/// ```text
+/// # pack-refs with: peeled fully-peeled sorted
+/// 1111111111111111111111111111111111111111 refs/heads/master
+/// 2222222222222222222222222222222222222222 refs/tags/v1.0
+/// ^3333333333333333333333333333333333333333
+/// ```
+pub fn parse_packed_refs(contents: &str) -> io::Result<Vec<PackedRef>> {
+    let mut out: Vec<PackedRef> = vec![];
+    for line in contents.lines() {
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        // a peeled line belongs to the ref right before it:
+        if let Some(peeled) = line.strip_prefix('^') {
+            let last = out.last_mut()
+                .ok_or_else(|| ioerr!("Found a peeled line '{}' before any ref", line))?;
+            last.peeled = Some(hash_str_to_oid(peeled)?);
+            continue;
+        }
+        let (hash, name) = line.split_once(' ')
+            .ok_or_else(|| ioerr!("Expected a hash and a ref name, instead found '{}'", line))?;
+        out.push(PackedRef { name: name.to_string(), oid: hash_str_to_oid(hash)?, peeled: None });
+    }
+    Ok(out)
+}
+
+/// add the name of every loose ref inside `dir` (which is the ref
+/// folder named `prefix`, ie: "refs/heads") to `out`, going into
+/// every subfolder.
+fn collect_loose_ref_names(dir: &Path, prefix: &str, out: &mut Vec<String>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        // git doesn't allow refs that aren't valid utf8, and .lock files
+        // are refs that are in the middle of being written:
+        let file_name = match file_name.to_str() {
+            Some(name) if !name.ends_with(".lock") => name,
+            _ => continue,
+        };
+        let name = format!("{}/{}", prefix, file_name);
+        if entry.file_type()?.is_dir() {
+            collect_loose_ref_names(&entry.path(), &name, out)?;
+        } else {
+            out.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// parses the contents of a ref file, ie: "ref: refs/heads/master\n"
+/// or a 40 hex char hash.
+pub fn parse_ref_contents(contents: &str) -> io::Result<Ref> {
+    let contents = contents.trim_end();
+    if let Some(target) = contents.strip_prefix("ref:") {
+        return Ok(Ref::Symbolic(target.trim().to_string()));
+    }
+    if contents.len() != 40 {
+        return ioerre!("Expected a ref or a 40 character hash, instead found '{}'", contents);
+    }
+    Ok(Ref::Direct(hash_str_to_oid(contents)?))
+}
+
+/// find out if the repo at `git_dir` uses sha1 or sha256 object ids.
+/// This reads the `[extensions] objectformat` of the repo's config.
+/// If the config does not declare one, we look at the length of a
+/// loose object's file name, or the size of an idx file's entries.
+/// If none of those tell us anything, its sha1, just like git defaults to.
+pub fn detect_object_format<P: AsRef<Path>>(git_dir: P) -> io::Result<HashAlgo> {
+    let git_dir = git_dir.as_ref();
+    if let Some(algo) = load_config(git_dir)?.object_format {
+        return Ok(algo);
+    }
+
+    let objects_dir = git_dir.join("objects");
+    if let Some(algo) = infer_object_format_from_loose(&objects_dir)? {
+        return Ok(algo);
+    }
+    if let Some(algo) = infer_object_format_from_idx(&objects_dir)? {
+        return Ok(algo);
+    }
+    Ok(HashAlgo::Sha1)
+}
+
+/// a loose object is at objects/xx/<rest of hex>, so the length
+/// of the rest tells us how long the hash is.
+fn infer_object_format_from_loose(objects_dir: &Path) -> io::Result<Option<HashAlgo>> {
+    let entries = match fs::read_dir(objects_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let is_loose_folder = name.len() == 2 && name.to_str().map(|n| n.chars().all(|c| c.is_ascii_hexdigit())).unwrap_or(false);
+        if !is_loose_folder {
+            continue;
+        }
+        for obj in fs::read_dir(entry.path())? {
+            let obj_name_len = obj?.file_name().len();
+            for algo in [HashAlgo::Sha1, HashAlgo::Sha256].iter() {
+                if obj_name_len == algo.hex_len() - 2 {
+                    return Ok(Some(*algo));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// a V2 idx file is: 8 byte header, 256 * 4 byte fanout table,
+/// then for N objects: N hashes, N crc32s, N 4 byte offsets, then
+/// 8 bytes per large offset, then the pack and idx hashes. Only one of
+/// the hash lengths should make that add up to the size of the file.
+// `is_multiple_of` is newer than the compilers we support:
+#[allow(unknown_lints, clippy::manual_is_multiple_of)]
+fn infer_object_format_from_idx(objects_dir: &Path) -> io::Result<Option<HashAlgo>> {
+    let pack_dir = objects_dir.join("pack");
+    let entries = match fs::read_dir(&pack_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().map(|e| e != "idx").unwrap_or(true) {
+            continue;
+        }
+        let data = fs::read(&path)?;
+        let header_size = 8 + 256 * 4;
+        if data.len() < header_size || data[0..4] != [255, b't', b'O', b'c'] {
+            continue;
+        }
+        let num_objects = u32::from_be_bytes([
+            data[header_size - 4], data[header_size - 3], data[header_size - 2], data[header_size - 1]
+        ]) as usize;
+        let mut matching = [HashAlgo::Sha1, HashAlgo::Sha256].iter().filter(|algo| {
+            let fixed = header_size + num_objects * (algo.hash_len() + 8) + 2 * algo.hash_len();
+            data.len() >= fixed && (data.len() - fixed) % 8 == 0
+        });
+        if let (Some(algo), None) = (matching.next(), matching.next()) {
+            return Ok(Some(*algo));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_database::{state::MinState, loose::write_raw_object};
+    use crate::object_id::{OidFull, full_oid_to_u128_oid, oid_full_to_string};
+    use crate::test_helpers::{TempDir, CommitFixture, packed_fixture};
+
+    /// a worktree with an empty .git/ whose HEAD is the unborn master.
+    fn make_repo(name: &str) -> (TempDir, Repo) {
+        let dir = TempDir::new(name);
+        let git_dir = dir.join(".git");
+        fs::create_dir_all(git_dir.join("objects").join("pack")).unwrap();
+        fs::create_dir_all(git_dir.join("refs").join("heads")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n").unwrap();
+        let repo = Repo::open(dir.path()).unwrap();
+        (dir, repo)
+    }
+
+    fn write_object(repo: &Repo, object_type: UnparsedObjectType, payload: &[u8]) -> OidFull {
+        write_raw_object(&repo.objects_dir, &object_type, payload).unwrap()
+    }
+
+    /// `contents` is the oid, or "ref: <name>" for a symbolic ref.
+    fn write_ref(repo: &Repo, name: &str, contents: &str) {
+        let path = repo.git_dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, format!("{}\n", contents)).unwrap();
+    }
+
+    fn tag_bytes(object: OidFull, object_type: &str, name: &str) -> Vec<u8> {
+        format!("object {}\ntype {}\ntag {}\ntagger a <a> 0 +0000\n\nmsg\n", oid_full_to_string(object), object_type, name)
+            .into_bytes()
+    }
+
+    fn odb_of(repo: &Repo) -> (LightObjectDB<'_>, MinState) {
+        let objects_dir = repo.objects_dir.to_str().unwrap();
+        (LightObjectDB::new(objects_dir).unwrap(), MinState::new(objects_dir).unwrap())
+    }
+
+    #[test]
+    fn detect_object_format_works() {
+        let git_dir = TempDir::new("object_format");
+        assert_eq!(detect_object_format(git_dir.path()).unwrap(), HashAlgo::Sha1);
+        assert_eq!(detect_object_format(packed_fixture()).unwrap(), HashAlgo::Sha1);
+
+        // the name of a loose object is the rest of its hex:
+        let loose = git_dir.join("objects").join("ab");
+        fs::create_dir_all(&loose).unwrap();
+        fs::write(loose.join("c".repeat(62)), b"").unwrap();
+        assert_eq!(detect_object_format(git_dir.path()).unwrap(), HashAlgo::Sha256);
+        // but the config wins:
+        fs::write(git_dir.join("config"), "[extensions]\n\tobjectformat = sha1\n").unwrap();
+        assert_eq!(detect_object_format(git_dir.path()).unwrap(), HashAlgo::Sha1);
+
+        // a sha256 idx with one object: the header, the fanout table,
+        // the hash, crc and offset, then the 2 trailing hashes:
+        let git_dir = TempDir::new("object_format_idx");
+        let pack_dir = git_dir.join("objects").join("pack");
+        fs::create_dir_all(&pack_dir).unwrap();
+        let mut idx = vec![255, b't', b'O', b'c', 0, 0, 0, 2];
+        for _ in 0..256 {
+            idx.extend_from_slice(&1u32.to_be_bytes());
+        }
+        idx.extend_from_slice(&[0; 32 + 4 + 4 + 32 * 2]);
+        fs::write(pack_dir.join("pack-a.idx"), &idx).unwrap();
+        assert_eq!(detect_object_format(git_dir.path()).unwrap(), HashAlgo::Sha256);
+    }
+
+    #[test]
+    fn head_works() {
+        let (_dir, repo) = make_repo("head");
+        let master = "a".repeat(40);
+        let master_oid = hash_str_to_oid(&master).unwrap();
+        // a new repo's HEAD points to a branch that doesn't exist yet:
+        assert_eq!(repo.head().unwrap(), Ref::Symbolic("refs/heads/master".into()));
+        assert_eq!(repo.head_oid().unwrap(), None);
+        assert_eq!(repo.current_branch(), Some("master".into()));
+
+        write_ref(&repo, "refs/heads/master", &master);
+        assert_eq!(repo.head_oid().unwrap(), Some(master_oid));
+        // or in packed-refs:
+        fs::remove_file(repo.git_dir.join("refs/heads/master")).unwrap();
+        fs::write(repo.git_dir.join("packed-refs"), format!("{} refs/heads/master\n", master)).unwrap();
+        assert_eq!(repo.head_oid().unwrap(), Some(master_oid));
+
+        let detached = "b".repeat(40);
+        write_ref(&repo, "HEAD", &detached);
+        let detached_oid = hash_str_to_oid(&detached).unwrap();
+        assert_eq!(repo.head().unwrap(), Ref::Direct(detached_oid));
+        assert_eq!(repo.head_oid().unwrap(), Some(detached_oid));
+        assert_eq!(repo.current_branch(), None);
+
+        write_ref(&repo, "HEAD", "not a ref");
+        assert!(repo.head().is_err());
+        assert!(repo.head_oid().is_err());
+    }
+
+    #[test]
+    fn all_refs_works() {
+        let (_dir, repo) = make_repo("all_refs");
+        let oid = |c: &str| c.repeat(40);
+        fs::write(repo.git_dir.join("packed-refs"), format!(
+            "# pack-refs with: peeled fully-peeled sorted\n{a} refs/heads/master\n{b} refs/remotes/origin/feature/x\n{c} refs/tags/v1\n^{d}\n{e} refs/tags/v2\n",
+            a = oid("a"), b = oid("b"), c = oid("c"), d = oid("d"), e = oid("e"),
+        )).unwrap();
+        // the loose ref replaces the packed one:
+        write_ref(&repo, "refs/heads/master", &oid("f"));
+        write_ref(&repo, "refs/remotes/origin/feature/y", &oid("1"));
+        write_ref(&repo, "refs/remotes/origin/HEAD", "ref: refs/remotes/origin/feature/x");
+        fs::write(repo.git_dir.join("refs/heads/master.lock"), oid("2")).unwrap();
+
+        let packed = repo.packed_refs().unwrap();
+        assert_eq!(packed.len(), 4);
+        assert_eq!(packed[2], PackedRef { name: "refs/tags/v1".into(), oid: hash_str_to_oid(&oid("c")).unwrap(), peeled: Some(hash_str_to_oid(&oid("d")).unwrap()) });
+        assert_eq!(packed[3].peeled, None);
+        assert_eq!(packed[1].name, "refs/remotes/origin/feature/x");
+        assert!(parse_packed_refs(&format!("^{}\n", oid("d"))).is_err());
+
+        let expected: Vec<(String, Oid)> = vec![
+            ("refs/heads/master", "f"),
+            ("refs/remotes/origin/HEAD", "b"),
+            ("refs/remotes/origin/feature/x", "b"),
+            ("refs/remotes/origin/feature/y", "1"),
+            ("refs/tags/v1", "c"),
+            ("refs/tags/v2", "e"),
+        ].into_iter().map(|(name, c)| (name.to_string(), hash_str_to_oid(&oid(c)).unwrap())).collect();
+        assert_eq!(repo.all_refs().unwrap(), expected);
+        assert_eq!(repo.find_packed_ref("refs/heads/master").unwrap(), Some(hash_str_to_oid(&oid("a")).unwrap()));
+        assert_eq!(repo.resolve_ref("refs/heads/master").unwrap(), Some(hash_str_to_oid(&oid("f")).unwrap()));
+        assert_eq!(repo.peeled("refs/tags/v1"), Some(hash_str_to_oid(&oid("d")).unwrap()));
+        assert_eq!(repo.peeled("refs/tags/v2"), None);
+    }
+
+    #[test]
+    fn resolve_revision_works() {
+        let (_dir, repo) = make_repo("resolve_revision");
+        let hex = oid_full_to_string;
+        let commit = |fixture: CommitFixture| write_object(&repo, UnparsedObjectType::Commit, &fixture.to_bytes());
+        // root <- one <- merge <- three <- four, and merge's 2nd parent is side:
+        let root = commit(CommitFixture::new().message("root"));
+        let one = commit(CommitFixture::new().parent(hex(root)).message("one"));
+        let side = commit(CommitFixture::new().parent(hex(root)).message("side"));
+        let merge = commit(CommitFixture::new().parents(vec![hex(one), hex(side)]).message("merge"));
+        let three = commit(CommitFixture::new().parent(hex(merge)).message("three"));
+        let four = commit(CommitFixture::new().parent(hex(three)).message("four"));
+        write_ref(&repo, "refs/heads/master", &hex(four));
+        // a tag and a branch with the same name, the tag should win:
+        write_ref(&repo, "refs/tags/same", &hex(root));
+        write_ref(&repo, "refs/heads/same", &hex(one));
+
+        let (odb, mut state) = odb_of(&repo);
+        let mut resolve = |spec: &str| repo.resolve_revision(spec, &odb, &mut state);
+        let cases = [
+            ("HEAD", four),
+            ("@", four),
+            ("master", four),
+            ("HEAD~", three),
+            ("HEAD~3", one),
+            ("HEAD^", three),
+            ("HEAD^0", four),
+            ("HEAD~2^2", side),
+            ("HEAD~2^2~", root),
+            ("same", root),
+            ("refs/heads/same", one),
+        ];
+        for (spec, expected) in cases.iter() {
+            assert_eq!(resolve(spec).unwrap(), full_oid_to_u128_oid(*expected), "{}", spec);
+        }
+        let short = hex(side)[0..7].to_string();
+        assert_eq!(resolve(&short).unwrap(), full_oid_to_u128_oid(side));
+        assert_eq!(resolve(&hex(merge)).unwrap(), full_oid_to_u128_oid(merge));
+
+        let err = resolve("HEAD~2^3").unwrap_err().to_string();
+        assert!(err.contains("has 2 parent(s), so it has no parent 3"), "{}", err);
+        let err = resolve("HEAD^2").unwrap_err().to_string();
+        assert!(err.contains("has 1 parent(s), so it has no parent 2"), "{}", err);
+        let err = resolve("HEAD~10").unwrap_err().to_string();
+        assert!(err.contains("has no parents, but we needed to go back 6 more"), "{}", err);
+        let err = resolve("HEAD~x").unwrap_err().to_string();
+        assert!(err.contains("only ~N and ^N can come after the name"), "{}", err);
+        assert!(resolve("~1").is_err());
+        assert_eq!(resolve("nope").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn tags_works() {
+        let (_dir, repo) = make_repo("tags");
+        let commit = write_object(&repo, UnparsedObjectType::Commit, &CommitFixture::new().to_bytes());
+        let tree = write_object(&repo, UnparsedObjectType::Tree, b"");
+        let annotated = write_object(&repo, UnparsedObjectType::Tag, &tag_bytes(commit, "commit", "annotated"));
+        let nested = write_object(&repo, UnparsedObjectType::Tag, &tag_bytes(annotated, "tag", "nested"));
+        let of_tree = write_object(&repo, UnparsedObjectType::Tag, &tag_bytes(tree, "tree", "of-tree"));
+        let nested_tree = write_object(&repo, UnparsedObjectType::Tag, &tag_bytes(of_tree, "tag", "nested-tree"));
+        write_ref(&repo, "refs/heads/master", &oid_full_to_string(commit));
+        write_ref(&repo, "refs/tags/light", &oid_full_to_string(commit));
+        write_ref(&repo, "refs/tags/annotated", &oid_full_to_string(annotated));
+        write_ref(&repo, "refs/tags/nested", &oid_full_to_string(nested));
+        write_ref(&repo, "refs/tags/nested-tree", &oid_full_to_string(nested_tree));
+        // the packed tag's object doesn't exist, so this only works if its
+        // peeled line is used. The other one was replaced by a loose ref,
+        // so its peeled line is out of date:
+        let missing = "1".repeat(40);
+        fs::write(repo.git_dir.join("packed-refs"), format!(
+            "# pack-refs with: peeled fully-peeled sorted\n{m} refs/tags/packed\n^{c}\n{m} refs/tags/replaced\n^{m}\n",
+            m = missing, c = oid_full_to_string(commit),
+        )).unwrap();
+        write_ref(&repo, "refs/tags/replaced", &oid_full_to_string(annotated));
+
+        let (odb, mut state) = odb_of(&repo);
+        let tags = repo.tags(&odb, &mut state).unwrap();
+        let expected: Vec<(String, Oid, bool)> = vec![
+            ("annotated", commit, true),
+            ("light", commit, false),
+            ("nested", commit, true),
+            ("nested-tree", tree, true),
+            ("packed", commit, true),
+            ("replaced", commit, true),
+        ].into_iter().map(|(name, oid, annotated)| (name.to_string(), full_oid_to_u128_oid(oid), annotated)).collect();
+        assert_eq!(tags, expected);
+    }
+}
@@ -0,0 +1,168 @@
+//! a small sha1 implementation. we only need this to compute
+//! object ids of data we reconstruct ourselves (ie: building an idx
+//! from a pack file), so it is not optimized at all.
+//! its written based off of the pseudocode from:
+//! https://en.wikipedia.org/wiki/SHA-1#SHA-1_pseudocode
+
+use crate::object_id::OidFull;
+
+const H_INIT: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+pub struct Sha1 {
+    state: [u32; 5],
+    /// bytes that have been given to us, but dont
+    /// fill up an entire 64 byte block yet.
+    buffer: [u8; 64],
+    buffer_len: usize,
+    /// total number of bytes we have been given
+    total_len: u64,
+}
+
+impl Default for Sha1 {
+    fn default() -> Self {
+        Sha1::new()
+    }
+}
+
+impl Sha1 {
+    pub fn new() -> Sha1 {
+        Sha1 {
+            state: H_INIT,
+            buffer: [0; 64],
+            buffer_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.total_len += data.len() as u64;
+        let mut data = data;
+        // first fill up whatever is left in our buffer:
+        if self.buffer_len > 0 {
+            let take = (64 - self.buffer_len).min(data.len());
+            self.buffer[self.buffer_len..self.buffer_len + take].copy_from_slice(&data[0..take]);
+            self.buffer_len += take;
+            data = &data[take..];
+            if self.buffer_len < 64 {
+                return;
+            }
+            let block = self.buffer;
+            self.process_block(&block);
+            self.buffer_len = 0;
+        }
+        // then process as many full blocks as we can
+        // without copying them:
+        let mut chunks = data.chunks_exact(64);
+        for block in &mut chunks {
+            self.process_block(block);
+        }
+        let remainder = chunks.remainder();
+        self.buffer[0..remainder.len()].copy_from_slice(remainder);
+        self.buffer_len = remainder.len();
+    }
+
+    pub fn finalize(mut self) -> OidFull {
+        let bit_len = self.total_len.wrapping_mul(8);
+        // pad with a single 1 bit, then 0s until we are
+        // 8 bytes short of a full block, then the length:
+        let mut padding = [0u8; 72];
+        padding[0] = 0x80;
+        let pad_len = if self.buffer_len < 56 {
+            56 - self.buffer_len
+        } else {
+            120 - self.buffer_len
+        };
+        padding[pad_len..pad_len + 8].copy_from_slice(&bit_len.to_be_bytes());
+        // update() would change our total_len, but we already
+        // computed the bit length so that doesnt matter.
+        self.update(&padding[0..pad_len + 8]);
+
+        let mut out = OidFull::default();
+        for (i, word) in self.state.iter().enumerate() {
+            out[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+
+    fn process_block(&mut self, block: &[u8]) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a.rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        self.state[0] = self.state[0].wrapping_add(a);
+        self.state[1] = self.state[1].wrapping_add(b);
+        self.state[2] = self.state[2].wrapping_add(c);
+        self.state[3] = self.state[3].wrapping_add(d);
+        self.state[4] = self.state[4].wrapping_add(e);
+    }
+}
+
+/// sha1 of some bytes all at once.
+pub fn sha1(data: &[u8]) -> OidFull {
+    let mut hasher = Sha1::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// computes the id of an object the same way git does, ie:
+/// the sha1 of "{object_type} {payload_len}\0{payload}".
+/// object_type should be one of "commit", "tree", "blob", "tag".
+pub fn hash_object(object_type: &str, payload: &[u8]) -> OidFull {
+    let mut hasher = Sha1::new();
+    hasher.update(object_type.as_bytes());
+    hasher.update(b" ");
+    hasher.update(payload.len().to_string().as_bytes());
+    hasher.update(&[0]);
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_id::oid_full_to_string;
+
+    #[test]
+    fn sha1_known_values_work() {
+        assert_eq!(oid_full_to_string(sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(oid_full_to_string(sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        // longer than a single block, and fed in uneven pieces:
+        let data = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        let mut hasher = Sha1::new();
+        hasher.update(&data[0..3]);
+        hasher.update(&data[3..]);
+        assert_eq!(oid_full_to_string(hasher.finalize()), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+    }
+
+    #[test]
+    fn hash_object_matches_git() {
+        // `git hash-object` of an empty file:
+        assert_eq!(oid_full_to_string(hash_object("blob", b"")), "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        // the empty tree:
+        assert_eq!(oid_full_to_string(hash_object("tree", b"")), "4b825dc642cb6eb9a060e54bf8d69288fbee4904");
+    }
+}
@@ -0,0 +1,181 @@
+//! reader for `.git/info/sparse-checkout`. Only cone mode
+//! patterns are supported, see:
+//! https://git-scm.com/docs/git-sparse-checkout#_internalscone_pattern_set
+
+use std::{io, fs, path::Path, collections::BTreeSet};
+use crate::{ioerr, ioerre};
+
+/// the directories of a cone mode sparse checkout. Files at the root are
+/// always included. A recursive directory includes everything under it, and
+/// a parent directory (any directory that leads to a recursive one) only
+/// includes the files directly inside of it.
+/// Paths are relative to the root of the repository, without leading or
+/// trailing slashes, ie: "src/object_database".
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SparseCone {
+    pub recursive: BTreeSet<String>,
+    pub parents: BTreeSet<String>,
+}
+
+/// the directory that `path` is in. "" for the root
+fn parent_dir(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(i) => &path[0..i],
+        None => "",
+    }
+}
+
+/// turn `/a/b/` into `a/b`, undoing git's escaping of special characters
+fn cone_dir_from_pattern(pattern: &str, line: &str) -> io::Result<String> {
+    let inner = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/'))
+        .ok_or_else(|| ioerr!("Unsupported sparse-checkout pattern '{}': cone mode patterns look like /dir/", line))?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) => out.push(escaped),
+                None => return ioerre!("Unsupported sparse-checkout pattern '{}': it ends with a \\", line),
+            },
+            '*' | '?' | '[' => {
+                return ioerre!("Unsupported sparse-checkout pattern '{}': only cone mode patterns are supported, not wildcards", line);
+            }
+            _ => out.push(c),
+        }
+    }
+    if out.is_empty() || out.split('/').any(|c| c.is_empty()) {
+        return ioerre!("Unsupported sparse-checkout pattern '{}': it has an empty path component", line);
+    }
+    Ok(out)
+}
+
+impl SparseCone {
+    /// parse the contents of a sparse-checkout file that was written
+    /// in cone mode, ie:
+    /// ```text
+    /// /*
+    /// !/*/
+    /// /docs/
+    /// !/docs/*/
+    /// /docs/api/
+    /// ```
+    /// Anything that isn't one of these forms is an error, since
+    /// we don't know gitignore style pattern matching.
+    pub fn parse(contents: &str) -> io::Result<SparseCone> {
+        let mut cone = SparseCone::default();
+        for line in contents.lines() {
+            let pattern = line.trim_end();
+            if pattern.is_empty() || pattern.starts_with('#') {
+                continue;
+            }
+            // the root files are always included, and
+            // root directories are only included if listed:
+            if pattern == "/*" || pattern == "!/*/" {
+                continue;
+            }
+            if let Some(negated) = pattern.strip_prefix('!') {
+                // !/dir/*/ turns a previous /dir/ into a parent
+                let dir = negated.strip_suffix("*/")
+                    .ok_or_else(|| ioerr!("Unsupported sparse-checkout pattern '{}': cone mode negations look like !/dir/*/", line))?;
+                let dir = cone_dir_from_pattern(dir, line)?;
+                if !cone.recursive.remove(&dir) {
+                    return ioerre!("Unsupported sparse-checkout pattern '{}': it does not come after a /{}/ pattern", line, dir);
+                }
+                cone.parents.insert(dir);
+                continue;
+            }
+            let dir = cone_dir_from_pattern(pattern, line)?;
+            cone.recursive.insert(dir);
+        }
+        // git writes the parents of every directory, but
+        // we dont rely on that:
+        let listed: Vec<String> = cone.recursive.iter().chain(cone.parents.iter()).cloned().collect();
+        for dir in listed {
+            let mut current = parent_dir(&dir);
+            while !current.is_empty() {
+                cone.parents.insert(current.to_string());
+                current = parent_dir(current);
+            }
+        }
+        Ok(cone)
+    }
+
+    /// true if `dir` is one of our recursive directories, or is inside one.
+    fn in_recursive(&self, dir: &str) -> bool {
+        let mut current = dir;
+        while !current.is_empty() {
+            if self.recursive.contains(current) {
+                return true;
+            }
+            current = parent_dir(current);
+        }
+        false
+    }
+
+    /// true if the file at `path` is part of the sparse checkout.
+    pub fn matches(&self, path: &str) -> bool {
+        let path = path.trim_matches('/');
+        let dir = parent_dir(path);
+        dir.is_empty() || self.parents.contains(dir) || self.in_recursive(dir)
+    }
+
+    /// true if the directory at `path` has anything in the sparse checkout,
+    /// ie: if you should look inside of it.
+    pub fn matches_dir(&self, path: &str) -> bool {
+        let path = path.trim_matches('/');
+        path.is_empty() || self.parents.contains(path) || self.in_recursive(path)
+    }
+}
+
+/// load `info/sparse-checkout`. Returns Ok(None) if it doesn't
+/// exist, which means theres no sparse checkout.
+pub fn load_sparse_checkout<P: AsRef<Path>>(git_dir: P) -> io::Result<Option<SparseCone>> {
+    let sparse_path = git_dir.as_ref().join("info").join("sparse-checkout");
+    match fs::read_to_string(&sparse_path) {
+        Ok(contents) => SparseCone::parse(&contents)
+            .map(Some)
+            .map_err(|e| ioerr!("Failed to parse {:?}\n{}", sparse_path, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparse_cone_parsing_works() {
+        let contents = "/*\n!/*/\n/docs/\n!/docs/*/\n/docs/api/\n/src/a\\*b/\n";
+        let cone = SparseCone::parse(contents).unwrap();
+        assert!(cone.recursive.contains("docs/api"));
+        assert!(cone.recursive.contains("src/a*b"));
+        assert!(cone.parents.contains("docs"));
+        // implied by src/a*b:
+        assert!(cone.parents.contains("src"));
+
+        assert!(cone.matches("README.md"));
+        assert!(cone.matches("docs/index.md"));
+        assert!(!cone.matches("docs/other/x.md"));
+        assert!(cone.matches("docs/api/deep/x.md"));
+        assert!(cone.matches("src/a*b/lib.rs"));
+        assert!(cone.matches("src/main.rs"));
+        assert!(!cone.matches("src/other/main.rs"));
+        assert!(!cone.matches("tests/t.rs"));
+
+        assert!(cone.matches_dir("docs"));
+        assert!(cone.matches_dir("docs/api/deep"));
+        assert!(!cone.matches_dir("docs/other"));
+        assert!(!cone.matches_dir("tests"));
+    }
+
+    #[test]
+    fn sparse_non_cone_patterns_error() {
+        assert!(SparseCone::parse("*.rs\n").is_err());
+        assert!(SparseCone::parse("/src/*.rs\n").is_err());
+        assert!(SparseCone::parse("docs/\n").is_err());
+        // negation of something that wasnt included:
+        assert!(SparseCone::parse("!/docs/*/\n").is_err());
+        assert!(SparseCone::parse("# only a comment\n\n").unwrap().recursive.is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+//! things that a lot of the tests need, ie: an empty objects dir
+//! to write objects into, and the raw bytes of a commit.
+
+use std::{io::Write, path::{Path, PathBuf}, sync::atomic::{AtomicUsize, Ordering}};
+use flate2::Compression;
+use crate::{object_id::OidFull, object_database::loose::UnparsedObjectType};
+
+/// the oid of the tree with no entries.
+pub const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+static NEXT_TEMP_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// a new empty directory in the system's temp dir. Its removed when
+/// this is dropped, so a test that fails doesn't leave it behind.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// `name` is only there so you can tell which test made it. Tests
+    /// run in parallel, so every dir gets a number that is never reused.
+    pub fn new(name: &str) -> TempDir {
+        let n = NEXT_TEMP_DIR.fetch_add(1, Ordering::SeqCst);
+        let path = std::env::temp_dir()
+            .join(format!("git_reader_{}_test_{}_{}", name, std::process::id(), n));
+        // left over from a run that was killed:
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    /// a new dir that looks like an empty objects dir, ie: it has a `pack/` folder.
+    pub fn objects_dir(name: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        std::fs::create_dir_all(dir.join("pack")).unwrap();
+        dir
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// the path as a str, for the things that want one (ie: `MinState::new`).
+    pub fn path_str(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// the raw bytes of a commit. By default its of the empty tree with no
+/// parents, and both the author and committer are "a <a>" at time 0.
+/// ie: `CommitFixture::new().parent(&first).message("second").to_bytes()`
+#[derive(Debug, Clone)]
+pub struct CommitFixture {
+    tree: String,
+    parents: Vec<String>,
+    committer_time: i64,
+    message: String,
+}
+
+impl Default for CommitFixture {
+    fn default() -> Self {
+        CommitFixture::new()
+    }
+}
+
+impl CommitFixture {
+    pub fn new() -> CommitFixture {
+        CommitFixture {
+            tree: EMPTY_TREE.to_string(),
+            parents: vec![],
+            committer_time: 0,
+            message: "msg".to_string(),
+        }
+    }
+
+    pub fn tree<S: Into<String>>(mut self, tree: S) -> CommitFixture {
+        self.tree = tree.into();
+        self
+    }
+
+    /// `parent` is the 40 character hex oid.
+    pub fn parent<S: Into<String>>(mut self, parent: S) -> CommitFixture {
+        self.parents.push(parent.into());
+        self
+    }
+
+    pub fn parents<S: Into<String>, I: IntoIterator<Item = S>>(mut self, parents: I) -> CommitFixture {
+        self.parents.extend(parents.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn committer_time(mut self, committer_time: i64) -> CommitFixture {
+        self.committer_time = committer_time;
+        self
+    }
+
+    /// the message, without the trailing newline.
+    pub fn message<S: Into<String>>(mut self, message: S) -> CommitFixture {
+        self.message = message.into();
+        self
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut raw = format!("tree {}\n", self.tree);
+        for parent in self.parents.iter() {
+            raw.push_str(&format!("parent {}\n", parent));
+        }
+        raw.push_str(&format!("author a <a> 0 +0000\ncommitter a <a> {} +0000\n\n{}\n", self.committer_time, self.message));
+        raw.into_bytes()
+    }
+}
+
+/// the id of the pack in `packed_fixture` with commits 1 to 3. Its
+/// deltas are ofs deltas, and it has a `.rev` file.
+pub const OFS_DELTA_PACK: &str = "1b0b46268509b737e811d28dc6f99b19f35bbfde";
+/// the id of the pack in `packed_fixture` with commits 4 and 5,
+/// made with `--no-delta-base-offset`, so its deltas are ref deltas.
+pub const REF_DELTA_PACK: &str = "f87933dd82fa98afcb2e5cc0c3e84461aec8f0d7";
+/// the commits of `packed_fixture`, oldest first. Each one adds
+/// a line to file.txt, so the file.txt blobs are deltas of each other.
+pub const PACKED_COMMITS: [&str; 5] = [
+    "099958a240d1a4d149383fecdc9e08d008266d7d",
+    "4499daaa967f0d191b8791f75886994d72689db8",
+    "2dd1d820b71fa668e970db6b7ce1c4631e89de45",
+    "e41f80838dc0c087c2ab84f4d574a5b0eb0503b2",
+    "6e02e075f930b16d17a9f55dc0aca6a60c430e0e",
+];
+
+/// `test_fixtures/packed.git`, a bare repo made by git where
+/// every object is in one of 2 packs, see `OFS_DELTA_PACK`
+/// and `REF_DELTA_PACK`.
+pub fn packed_fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("test_fixtures").join("packed.git")
+}
+
+/// a copy of the objects dir of `packed_fixture`, for tests that
+/// need to change it (ie: to corrupt a pack).
+pub fn copy_packed_fixture_objects(name: &str) -> TempDir {
+    let dir = TempDir::objects_dir(name);
+    let pack_dir = packed_fixture().join("objects").join("pack");
+    for entry in std::fs::read_dir(pack_dir).unwrap() {
+        let entry = entry.unwrap();
+        std::fs::copy(entry.path(), dir.join("pack").join(entry.file_name())).unwrap();
+    }
+    dir
+}
+
+/// the bytes of a v1 idx file, which is just: the fanout table, then
+/// [4 byte offset][20 byte oid] for each object, then the trailer.
+/// `entries` are the oids and their offset in the pack, in oid order.
+pub fn v1_idx_bytes(entries: &[(OidFull, u32)]) -> Vec<u8> {
+    let mut fanout = [0u32; 256];
+    for (oid, _) in entries {
+        for count in fanout[oid[0] as usize..].iter_mut() {
+            *count += 1;
+        }
+    }
+    let mut out = vec![];
+    for count in fanout.iter() {
+        out.extend_from_slice(&count.to_be_bytes());
+    }
+    for (oid, offset) in entries {
+        out.extend_from_slice(&offset.to_be_bytes());
+        out.extend_from_slice(oid);
+    }
+    out.extend_from_slice(&[0; 40]);
+    out
+}
+
+fn compress_with(data: &[u8], level: Compression) -> Vec<u8> {
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), level);
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// zlib `data` like git does for loose and packed objects.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    compress_with(data, Compression::default())
+}
+
+/// type and size, 4 bits of size then 7 bits at a time.
+fn push_object_header(data: &mut Vec<u8>, obj_type: u8, size: usize) {
+    let mut byte = (obj_type << 4) | (size & 0x0f) as u8;
+    let mut size = size >> 4;
+    while size > 0 {
+        data.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    data.push(byte);
+}
+
+/// the ofs delta offset is most significant first, with 1
+/// subtracted each time we shift (see `find_negative_offset`).
+fn push_ofs_delta_offset(data: &mut Vec<u8>, offset: usize) {
+    let mut bytes = vec![(offset & 0x7f) as u8];
+    let mut offset = offset >> 7;
+    while offset > 0 {
+        offset -= 1;
+        bytes.push(0x80 | (offset & 0x7f) as u8);
+        offset >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
+
+/// an object for `pack_bytes` to write.
+pub enum PackObject<'a> {
+    /// an object that is not a delta.
+    Whole(UnparsedObjectType, &'a [u8]),
+    /// a delta, and the index in `objects` of its base, which
+    /// has to come before it.
+    OfsDelta(usize, &'a [u8]),
+    /// a delta, and the oid of its base.
+    RefDelta(OidFull, &'a [u8]),
+}
+
+/// the bytes of a v2 pack of `objects`, and the offset that each
+/// object starts at. The trailer is all 0s, not the sha1 of the pack.
+pub fn pack_bytes(objects: &[PackObject]) -> (Vec<u8>, Vec<usize>) {
+    pack_bytes_with(objects, compress)
+}
+
+/// `pack_bytes`, but every object is compressed at `level`.
+pub fn pack_bytes_with_level(objects: &[PackObject], level: Compression) -> (Vec<u8>, Vec<usize>) {
+    pack_bytes_with(objects, |data| compress_with(data, level))
+}
+
+fn pack_bytes_with<F: Fn(&[u8]) -> Vec<u8>>(objects: &[PackObject], compress: F) -> (Vec<u8>, Vec<usize>) {
+    let mut data = b"PACK\0\0\0\x02".to_vec();
+    data.extend_from_slice(&(objects.len() as u32).to_be_bytes());
+    let mut offsets = vec![];
+    for object in objects {
+        let starts_at = data.len();
+        let payload = match object {
+            PackObject::Whole(object_type, payload) => {
+                let obj_type = match object_type {
+                    UnparsedObjectType::Commit => 1,
+                    UnparsedObjectType::Tree => 2,
+                    UnparsedObjectType::Blob => 3,
+                    UnparsedObjectType::Tag => 4,
+                };
+                push_object_header(&mut data, obj_type, payload.len());
+                payload
+            }
+            PackObject::OfsDelta(base, delta) => {
+                push_object_header(&mut data, 6, delta.len());
+                push_ofs_delta_offset(&mut data, starts_at - offsets[*base]);
+                delta
+            }
+            PackObject::RefDelta(base, delta) => {
+                push_object_header(&mut data, 7, delta.len());
+                data.extend_from_slice(base);
+                delta
+            }
+        };
+        data.extend_from_slice(&compress(payload));
+        offsets.push(starts_at);
+    }
+    data.extend_from_slice(&[0; 20]);
+    (data, offsets)
+}